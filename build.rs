@@ -0,0 +1,37 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_c_header();
+
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+// generates the gRPC messages and service of src/grpc.rs. uses a vendored protoc, so none needs to be installed
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let protoc =
+        protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    tonic_prost_build::compile_protos("proto/payments_engine.proto")
+        .expect("failed to compile proto/payments_engine.proto");
+    println!("cargo:rerun-if-changed=proto/payments_engine.proto");
+}
+
+// writes include/payments_engine.h for the C API in src/ffi.rs
+#[cfg(feature = "ffi")]
+fn generate_c_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("failed to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("failed to generate C header")
+        .write_to_file(format!("{}/include/payments_engine.h", crate_dir));
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
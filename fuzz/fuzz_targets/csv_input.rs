@@ -0,0 +1,12 @@
+#![no_main]
+// arbitrary bytes through the CSV reader and processor. invalid rows must be skipped, not crash
+use libfuzzer_sys::fuzz_target;
+use payments_engine::transaction_processor::TransactionProcessor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut processor = TransactionProcessor::in_memory();
+    if let Err(e) = processor.process_csv(data) {
+        // storage failures are reported, never panics
+        payments_engine::errors::print_report(e);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+// arbitrary bytes deserialized as a JSON transaction and fed to the processor
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{model::RawTxnInput, transaction_processor::TransactionProcessor};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(txn) = serde_json::from_slice::<RawTxnInput>(data) {
+        let mut processor = TransactionProcessor::in_memory();
+        if let Err(e) = processor.process(txn) {
+            payments_engine::errors::print_report(e);
+        }
+    }
+});
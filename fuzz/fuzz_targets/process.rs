@@ -0,0 +1,18 @@
+#![no_main]
+// arbitrary transaction sequences. besides not panicking, every balance must stay consistent
+use libfuzzer_sys::fuzz_target;
+use payments_engine::{model::RawTxnInput, transaction_processor::TransactionProcessor};
+
+fuzz_target!(|txns: Vec<RawTxnInput>| {
+    let mut processor = TransactionProcessor::in_memory();
+    for txn in txns {
+        if let Err(e) = processor.process(txn) {
+            payments_engine::errors::print_report(e);
+            return;
+        }
+    }
+
+    for state in processor.client_states().unwrap() {
+        assert_eq!(state.total, state.available + state.held);
+    }
+});
@@ -0,0 +1,131 @@
+#ifndef PAYMENTS_ENGINE_H
+#define PAYMENTS_ENGINE_H
+
+/* generated by cbindgen from src/ffi.rs. do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * values for `PeTransaction::txn_type`. these match `TxnType::to_u8`
+ */
+#define PE_DEPOSIT 1
+
+#define PE_WITHDRAWAL 2
+
+#define PE_DISPUTE 3
+
+#define PE_RESOLVE 4
+
+#define PE_CHARGEBACK 5
+
+/**
+ * return codes
+ */
+#define PE_OK 0
+
+#define PE_NOT_FOUND 1
+
+#define PE_NULL_ARGUMENT -1
+
+#define PE_INVALID_TRANSACTION -2
+
+#define PE_ENGINE_ERROR -3
+
+/**
+ * amounts are kept to this many decimal places
+ */
+#define DECIMAL_PLACES 4
+
+/**
+ * opaque handle to a transaction processor
+ */
+typedef struct PeEngine PeEngine;
+
+#if !defined(PE_WIDE_IDS)
+typedef uint16_t ClientId;
+#endif
+
+#if defined(PE_WIDE_IDS)
+typedef uint32_t ClientId;
+#endif
+
+#if !defined(PE_WIDE_IDS)
+typedef uint32_t TransactionId;
+#endif
+
+#if defined(PE_WIDE_IDS)
+/**
+ * SQLite stores integers as i64, so transaction ids above i64::MAX are rejected as invalid input
+ */
+typedef uint64_t TransactionId;
+#endif
+
+/**
+ * a transaction submitted by the caller. `amount` is ignored unless `has_amount` is set
+ */
+typedef struct PeTransaction {
+  uint8_t txn_type;
+  ClientId client;
+  TransactionId tx;
+  bool has_amount;
+  double amount;
+} PeTransaction;
+
+/**
+ * the state of a client account
+ */
+typedef struct PeAccount {
+  ClientId client;
+  double available;
+  double held;
+  double total;
+  bool locked;
+} PeAccount;
+
+
+
+
+
+
+
+
+
+
+
+/**
+ * create an engine. uses an SQLite database unless `in_memory` is set (or the crate was built without the sqlite feature).
+ * returns NULL on failure. free the engine with `pe_engine_free`
+ */
+struct PeEngine *pe_engine_new(bool in_memory);
+
+/**
+ * free an engine created by `pe_engine_new`. passing NULL is a no-op
+ *
+ * # Safety
+ * `engine` must be NULL or a pointer returned by `pe_engine_new` that hasn't been freed
+ */
+void pe_engine_free(struct PeEngine *engine);
+
+/**
+ * process one transaction. like the executable, transactions that fail validation or business rules are ignored and return PE_OK.
+ * returns PE_INVALID_TRANSACTION for an unknown `txn_type` and PE_ENGINE_ERROR if the storage failed
+ *
+ * # Safety
+ * `engine` must come from `pe_engine_new`. `txn` must point to a valid `PeTransaction`
+ */
+int32_t pe_engine_submit(struct PeEngine *engine,
+                         const struct PeTransaction *txn);
+
+/**
+ * copy the state of `client` into `out`. returns PE_NOT_FOUND if the engine hasn't seen the client
+ *
+ * # Safety
+ * `engine` must come from `pe_engine_new`. `out` must point to writable memory for a `PeAccount`
+ */
+int32_t pe_engine_get_account(struct PeEngine *engine, ClientId client, struct PeAccount *out);
+
+#endif /* PAYMENTS_ENGINE_H */
@@ -0,0 +1,89 @@
+//! manual balance adjustments: the back office's escape hatch for corrections the input can't express.
+//! each one needs a reason code and the operator who made it, and is posted against its own ledger account
+//! (`LedgerAccount::Adjustments`) so it stays separate from the client transactions in the reports
+use crate::{amount::Amount, errors::*, model::*};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjustmentReason {
+    /// fixes an error in an earlier transaction
+    Correction,
+    /// a courtesy credit
+    Goodwill,
+    /// a fee charged outside the input
+    Fee,
+    /// an unrecoverable negative balance written off
+    WriteOff,
+    /// a balance carried over from another system
+    Migration,
+}
+
+impl FromStr for AdjustmentReason {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let reason = match s {
+            "correction" => AdjustmentReason::Correction,
+            "goodwill" => AdjustmentReason::Goodwill,
+            "fee" => AdjustmentReason::Fee,
+            "write-off" => AdjustmentReason::WriteOff,
+            "migration" => AdjustmentReason::Migration,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(reason)
+    }
+}
+
+impl fmt::Display for AdjustmentReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            AdjustmentReason::Correction => "correction",
+            AdjustmentReason::Goodwill => "goodwill",
+            AdjustmentReason::Fee => "fee",
+            AdjustmentReason::WriteOff => "write-off",
+            AdjustmentReason::Migration => "migration",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustment {
+    pub client_id: ClientId,
+    /// positive amounts credit the client's available funds, negative amounts debit them
+    pub amount: Amount,
+    pub reason: AdjustmentReason,
+    /// who made the adjustment
+    pub operator: String,
+}
+
+impl fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {:+} ({} by {})",
+            self.client_id, self.amount, self.reason, self.operator
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reason_round_trip() {
+        for reason in [
+            AdjustmentReason::Correction,
+            AdjustmentReason::Goodwill,
+            AdjustmentReason::Fee,
+            AdjustmentReason::WriteOff,
+            AdjustmentReason::Migration,
+        ] {
+            assert_eq!(
+                reason.to_string().parse::<AdjustmentReason>().unwrap(),
+                reason
+            );
+        }
+        assert!("oops".parse::<AdjustmentReason>().is_err());
+    }
+}
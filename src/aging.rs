@@ -0,0 +1,121 @@
+//! open-dispute aging: how long the open disputes have been waiting, grouped into buckets, and the ones past the SLA
+use crate::model::*;
+use std::fmt;
+
+/// an open dispute and the number of whole days since it was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenDisputeAge {
+    pub client_id: ClientId,
+    pub txn_id: TransactionId,
+    /// None for disputes recorded before the open time was stored
+    pub age_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgingBucket {
+    /// 0-7 days
+    Week,
+    /// 8-30 days
+    Month,
+    /// more than 30 days
+    Older,
+}
+
+impl AgingBucket {
+    pub const ALL: [AgingBucket; 3] = [AgingBucket::Week, AgingBucket::Month, AgingBucket::Older];
+
+    pub fn of(age_days: u64) -> Self {
+        match age_days {
+            0..=7 => AgingBucket::Week,
+            8..=30 => AgingBucket::Month,
+            _ => AgingBucket::Older,
+        }
+    }
+}
+
+impl fmt::Display for AgingBucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            AgingBucket::Week => "0-7 days",
+            AgingBucket::Month => "8-30 days",
+            AgingBucket::Older => "30+ days",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AgingReport {
+    /// the number of open disputes per bucket, in the order of `AgingBucket::ALL`
+    pub buckets: [u64; 3],
+    /// open disputes without an open time
+    pub unknown: u64,
+    /// open disputes older than the SLA, oldest first
+    pub breaches: Vec<OpenDisputeAge>,
+}
+
+impl AgingReport {
+    /// `sla_days`: disputes open for longer than this breach the SLA
+    pub fn new(disputes: &[OpenDisputeAge], sla_days: u64) -> Self {
+        let mut report = AgingReport::default();
+        for dispute in disputes {
+            match dispute.age_days {
+                Some(age) => {
+                    report.buckets[AgingBucket::of(age) as usize] += 1;
+                    if age > sla_days {
+                        report.breaches.push(*dispute);
+                    }
+                }
+                None => report.unknown += 1,
+            }
+        }
+        report
+            .breaches
+            .sort_by_key(|d| std::cmp::Reverse(d.age_days));
+        report
+    }
+
+    pub fn count(&self, bucket: AgingBucket) -> u64 {
+        self.buckets[bucket as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dispute(txn_id: TransactionId, age_days: Option<u64>) -> OpenDisputeAge {
+        OpenDisputeAge {
+            client_id: 1,
+            txn_id,
+            age_days,
+        }
+    }
+
+    #[test]
+    fn test_buckets() {
+        assert_eq!(AgingBucket::of(0), AgingBucket::Week);
+        assert_eq!(AgingBucket::of(7), AgingBucket::Week);
+        assert_eq!(AgingBucket::of(8), AgingBucket::Month);
+        assert_eq!(AgingBucket::of(30), AgingBucket::Month);
+        assert_eq!(AgingBucket::of(31), AgingBucket::Older);
+    }
+
+    #[test]
+    fn test_report() {
+        let disputes = [
+            dispute(1, Some(2)),
+            dispute(2, Some(12)),
+            dispute(3, Some(45)),
+            dispute(4, Some(20)),
+            dispute(5, None),
+        ];
+        let report = AgingReport::new(&disputes, 14);
+        assert_eq!(report.count(AgingBucket::Week), 1);
+        assert_eq!(report.count(AgingBucket::Month), 2);
+        assert_eq!(report.count(AgingBucket::Older), 1);
+        assert_eq!(report.unknown, 1);
+        let breaches: Vec<TransactionId> = report.breaches.iter().map(|d| d.txn_id).collect();
+        assert_eq!(breaches, vec![3, 4]);
+    }
+}
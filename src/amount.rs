@@ -0,0 +1,464 @@
+//! exact amounts. an `Amount` is a whole number of ten-thousandths (`DECIMAL_PLACES`), so balances can be added and
+//! subtracted any number of times without drifting the way f64 sums do. SQLite stores that integer (minor units).
+//! inputs are rounded once, with the `RoundingPolicy`, when they become an `Amount`. until then they're a `RawAmount`,
+//! the decimal as it was written, so no digits are lost to f64 on the way in
+use crate::{
+    errors::*,
+    rounding::{RoundingPolicy, DECIMAL_PLACES},
+};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    /// minor units per unit
+    pub const SCALE: i64 = 10i64.pow(DECIMAL_PLACES as u32);
+
+    pub const fn from_minor_units(units: i64) -> Self {
+        Amount(units)
+    }
+
+    pub const fn minor_units(&self) -> i64 {
+        self.0
+    }
+
+    /// parse a plain decimal (ex: `-12.34567`), rounding the digits past `DECIMAL_PLACES` with `policy`.
+    /// None if `s` isn't a plain decimal or doesn't fit
+    pub fn parse(s: &str, policy: RoundingPolicy) -> Option<Self> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let (kept, dropped) = frac.split_at(frac.len().min(DECIMAL_PLACES));
+
+        // i128 holds 38 digits, so anything that would overflow it is far out of range anyway
+        if int.trim_start_matches('0').len() > 30 {
+            return None;
+        }
+        let int: i128 = if int.is_empty() { 0 } else { int.parse().ok()? };
+        let kept: i128 = format!("{:0<width$}", kept, width = DECIMAL_PLACES)
+            .parse()
+            .ok()?;
+        let mut scaled = int * Self::SCALE as i128 + kept;
+        if policy.rounds_up(scaled % 2 == 1, dropped) {
+            scaled += 1;
+        }
+        if negative {
+            scaled = -scaled;
+        }
+        i64::try_from(scaled).ok().map(Amount)
+    }
+
+    /// `value` rounded with `policy`, based on its shortest decimal representation (what gets printed), so 1.00005
+    /// is a tie even though the nearest f64 is slightly below it. None for NaN, infinities, and values that don't fit
+    pub fn from_f64(value: f64, policy: RoundingPolicy) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        // f64's Display never uses an exponent
+        Self::parse(&value.to_string(), policy)
+    }
+
+    /// the nearest f64. for the language bindings and ratios, not for arithmetic
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / Self::SCALE as f64
+    }
+
+    /// rounded to `places` decimal places (at most `DECIMAL_PLACES`) with `policy`. for reports that show fewer digits
+    pub fn round_to(&self, places: usize, policy: RoundingPolicy) -> Self {
+        let dropped_places = DECIMAL_PLACES - places.min(DECIMAL_PLACES);
+        if dropped_places == 0 {
+            return *self;
+        }
+        let step = 10u64.pow(dropped_places as u32);
+        let abs = self.0.unsigned_abs();
+        let mut kept = abs / step;
+        let dropped = format!("{:0width$}", abs % step, width = dropped_places);
+        if policy.rounds_up(kept % 2 == 1, &dropped) {
+            kept += 1;
+        }
+        let units = i64::try_from(kept * step).unwrap_or(i64::MAX);
+        Amount(if self.0 < 0 { -units } else { units })
+    }
+
+    pub fn abs(&self) -> Self {
+        Amount(self.0.saturating_abs())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn checked_add(&self, other: Amount) -> Option<Self> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(&self, other: Amount) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+}
+
+// the operators saturate instead of wrapping. the processor rejects transfers that would overflow a balance, so
+// only sums across many clients can get near the bounds
+impl Add for Amount {
+    type Output = Amount;
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+    fn neg(self) -> Amount {
+        Amount(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Self {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+/// compares with the f64 that prints the same. ex: `Amount::from_minor_units(15_000) == 1.5`
+impl PartialEq<f64> for Amount {
+    fn eq(&self, other: &f64) -> bool {
+        self.to_f64() == *other
+    }
+}
+
+/// the shortest decimal: no trailing zeros, and no decimal point for whole amounts. ex: 1.5, -0.0001, 3
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = Self::SCALE as u64;
+        let (int, frac) = (abs / scale, abs % scale);
+        if frac == 0 {
+            return write!(f, "{}{}", sign, int);
+        }
+        let frac = format!("{:0width$}", frac, width = DECIMAL_PLACES);
+        write!(f, "{}{}.{}", sign, int, frac.trim_end_matches('0'))
+    }
+}
+
+/// exact: fails on digits past `DECIMAL_PLACES` instead of rounding them. for amounts typed by an operator
+impl FromStr for Amount {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let exact = s
+            .split_once('.')
+            .is_none_or(|(_, frac)| frac.bytes().skip(DECIMAL_PLACES).all(|b| b == b'0'));
+        exact
+            .then(|| Amount::parse(s, RoundingPolicy::Truncate))
+            .flatten()
+            .ok_or_else(|| MyError::Conversion(s.to_string()))
+    }
+}
+
+/// an input amount: the decimal as it was written, ex: `12345678901234.5678`, without its formatting (no `+`, and no
+/// leading or trailing zeros), so the same amount compares, prints, and hashes the same however it was written. it
+/// becomes an `Amount` when the transaction is processed, rounded with the `RoundingPolicy` of the processor
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawAmount(String);
+
+impl RawAmount {
+    /// parse a decimal. other float syntax (ex: `1e3`) goes through f64. None for NaN, infinities, and text that
+    /// isn't a number
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return s
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(RawAmount::from);
+        }
+        let int = match int.trim_start_matches('0') {
+            "" => "0",
+            int => int,
+        };
+        let frac = frac.trim_end_matches('0');
+        let sign = if negative && (int != "0" || !frac.is_empty()) {
+            "-"
+        } else {
+            ""
+        };
+        Some(match frac {
+            "" => RawAmount(format!("{}{}", sign, int)),
+            _ => RawAmount(format!("{}{}.{}", sign, int, frac)),
+        })
+    }
+
+    /// the amount rounded to `DECIMAL_PLACES` with `policy`. None if it doesn't fit, or for NaN and infinities from
+    /// the f64 inputs
+    pub fn to_amount(&self, policy: RoundingPolicy) -> Option<Amount> {
+        Amount::parse(&self.0, policy)
+    }
+
+    /// the nearest f64, for the outputs that take floats
+    pub fn to_f64(&self) -> f64 {
+        self.0.parse().unwrap_or(f64::NAN)
+    }
+
+    /// more than zero, before rounding
+    pub fn is_positive(&self) -> bool {
+        self.0.starts_with(|c: char| c.is_ascii_digit()) && self.0 != "0"
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// the shortest decimal that converts back to `value`, as f64's Display prints it. NaN and infinities are kept, to be
+/// rejected when the transaction is processed
+impl From<f64> for RawAmount {
+    fn from(value: f64) -> Self {
+        let s = value.to_string();
+        match value.is_finite() {
+            true => RawAmount::parse(&s).unwrap_or(RawAmount(s)),
+            false => RawAmount(s),
+        }
+    }
+}
+
+impl From<Amount> for RawAmount {
+    fn from(amount: Amount) -> Self {
+        RawAmount(amount.to_string())
+    }
+}
+
+impl fmt::Display for RawAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// a string, or a number. JSON numbers are f64s, so JSON inputs that need more than 15 significant digits send the
+/// amount as a string. CSV fields are inferred as numbers too: `CsvFormat` parses the amount from the field's text
+impl<'de> serde::Deserialize<'de> for RawAmount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = RawAmount;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount")
+            }
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<RawAmount, E> {
+                RawAmount::parse(s)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(s), &self))
+            }
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<RawAmount, E> {
+                Ok(RawAmount::from(value))
+            }
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<RawAmount, E> {
+                Ok(RawAmount(value.to_string()))
+            }
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<RawAmount, E> {
+                Ok(RawAmount(value.to_string()))
+            }
+            fn visit_i128<E: serde::de::Error>(self, value: i128) -> Result<RawAmount, E> {
+                Ok(RawAmount(value.to_string()))
+            }
+            fn visit_u128<E: serde::de::Error>(self, value: u128) -> Result<RawAmount, E> {
+                Ok(RawAmount(value.to_string()))
+            }
+        }
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// as a number, for the JSON outputs
+impl serde::Serialize for RawAmount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for RawAmount {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(RawAmount::from(f64::arbitrary(u)?))
+    }
+}
+
+/// `value` rounded half-even, for literals in tests
+#[cfg(test)]
+pub(crate) fn amt(value: f64) -> Amount {
+    Amount::from_f64(value, RoundingPolicy::HalfEven).unwrap()
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Amount {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.0.into())
+    }
+}
+
+/// columns declared REAL (databases created before amounts were exact) return integral floats
+#[cfg(feature = "sqlite")]
+impl rusqlite::types::FromSql for Amount {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        use rusqlite::types::{FromSqlError, ValueRef};
+        match value {
+            ValueRef::Integer(units) => Ok(Amount(units)),
+            ValueRef::Real(units) if units.fract() == 0.0 && units.abs() < i64::MAX as f64 => {
+                Ok(Amount(units as i64))
+            }
+            ValueRef::Real(_) => Err(FromSqlError::OutOfRange(0)),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Option<i64> {
+        Amount::parse(s, RoundingPolicy::default()).map(|a| a.minor_units())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1.5"), Some(15_000));
+        assert_eq!(parse("-0.0001"), Some(-1));
+        assert_eq!(parse("+2"), Some(20_000));
+        assert_eq!(parse(".25"), Some(2_500));
+        assert_eq!(parse("3."), Some(30_000));
+        assert_eq!(parse("1.00015"), Some(10_002));
+        assert_eq!(parse("1.00025"), Some(10_002));
+        assert_eq!(parse("922337203685477.5807"), Some(i64::MAX));
+        for s in [
+            "",
+            ".",
+            "-",
+            "1e5",
+            "1.2.3",
+            "abc",
+            "922337203685477.5808",
+            "NaN",
+        ] {
+            assert_eq!(parse(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_exact() {
+        let tenth = Amount::from_f64(0.1, RoundingPolicy::default()).unwrap();
+        let mut sum = Amount::ZERO;
+        for _ in 0..10 {
+            sum += tenth;
+        }
+        assert_eq!(sum, Amount::from_minor_units(Amount::SCALE));
+        assert_eq!(tenth + tenth + tenth, 0.3);
+        assert_eq!(Amount::from_f64(f64::NAN, RoundingPolicy::default()), None);
+        assert_eq!(Amount::from_f64(1e20, RoundingPolicy::default()), None);
+    }
+
+    #[test]
+    fn test_display() {
+        for (units, s) in [
+            (0, "0"),
+            (30_000, "3"),
+            (15_000, "1.5"),
+            (-1, "-0.0001"),
+            (-12_345, "-1.2345"),
+        ] {
+            assert_eq!(Amount::from_minor_units(units).to_string(), s);
+            assert_eq!(parse(s), Some(units));
+        }
+    }
+
+    #[test]
+    fn test_raw_amount() {
+        let raw = |s: &str| RawAmount::parse(s).map(|raw| raw.to_string());
+        assert_eq!(raw("+001.500").as_deref(), Some("1.5"));
+        assert_eq!(raw("-0.00").as_deref(), Some("0"));
+        assert_eq!(raw(".25").as_deref(), Some("0.25"));
+        assert_eq!(raw("1e3").as_deref(), Some("1000"));
+        assert_eq!(raw("NaN"), None);
+        assert_eq!(raw("abc"), None);
+        // 18 significant digits: f64 would round it to ...568
+        let large = RawAmount::parse("12345678901234.5678").unwrap();
+        assert_eq!(
+            large.to_amount(RoundingPolicy::HalfEven),
+            Some(Amount::from_minor_units(123_456_789_012_345_678))
+        );
+        assert_eq!(RawAmount::from(1.5), RawAmount::parse("1.50").unwrap());
+        assert_eq!(
+            RawAmount::from(f64::NAN).to_amount(RoundingPolicy::HalfEven),
+            None
+        );
+        assert!(!RawAmount::parse("-1").unwrap().is_positive());
+        assert!(!RawAmount::parse("0.0").unwrap().is_positive());
+        assert!(RawAmount::parse("0.00001").unwrap().is_positive());
+    }
+
+    #[test]
+    fn test_round_to() {
+        let round = |units: i64, places: usize, policy: RoundingPolicy| {
+            Amount::from_minor_units(units)
+                .round_to(places, policy)
+                .to_string()
+        };
+        assert_eq!(round(12_345, 2, RoundingPolicy::HalfEven), "1.23");
+        assert_eq!(round(12_350, 2, RoundingPolicy::HalfEven), "1.24");
+        assert_eq!(round(12_250, 2, RoundingPolicy::HalfEven), "1.22");
+        assert_eq!(round(12_250, 2, RoundingPolicy::HalfUp), "1.23");
+        assert_eq!(round(12_399, 2, RoundingPolicy::Truncate), "1.23");
+        assert_eq!(round(-12_351, 2, RoundingPolicy::HalfEven), "-1.24");
+        assert_eq!(round(19_999, 0, RoundingPolicy::HalfEven), "2");
+        assert_eq!(round(12_345, 4, RoundingPolicy::Truncate), "1.2345");
+        assert_eq!(round(12_345, 9, RoundingPolicy::Truncate), "1.2345");
+    }
+}
@@ -0,0 +1,234 @@
+//! Arrow input (feature "arrow"): record batches with the columns type, client, tx, amount, and optionally timestamp
+//! and original_tx, found by name. each column is cast to the type of its `RawTxnInput` field in one pass, which
+//! borrows the buffers of a column that already has that type. the crate uses arrow-array 54, so callers need a
+//! `RecordBatch` of the same version
+use crate::{amount::RawAmount, errors::*, fmt_error, model::*, transaction_processor::InputRow};
+pub use arrow_array::RecordBatch;
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, Float64Array, Int64Array, StringArray,
+};
+use arrow_schema::{ArrowError, DataType, Schema, TimeUnit};
+use csv::StringRecord;
+use error_stack::{report, IntoReport, Result, ResultExt};
+
+// the columns a batch must have
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+
+/// check that a schema has the required columns
+pub(crate) fn check_schema(schema: &Schema) -> Result<(), MyError> {
+    match REQUIRED
+        .iter()
+        .find(|name| schema.column_with_name(name).is_none())
+    {
+        Some(missing) => Err(report!(MyError::FileReader)
+            .attach_printable(fmt_error!("there's no {} column", missing))),
+        None => Ok(()),
+    }
+}
+
+/// the rows of a record batch, in order. a row with a null or a value that doesn't fit the model is malformed
+pub(crate) fn batch_rows(batch: &RecordBatch) -> Result<impl Iterator<Item = InputRow>, MyError> {
+    check_schema(batch.schema_ref())?;
+    let columns = BatchColumns::new(batch)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read the record batch"))
+        .change_context(MyError::FileReader)?;
+    Ok((0..columns.len).map(move |idx| columns.row(idx)))
+}
+
+// the columns of a record batch, cast to the types of RawTxnInput. the ids are cast to i64 first: a value that
+// doesn't fit is a null
+pub(crate) struct BatchColumns {
+    pub(crate) len: usize,
+    txn_type: StringArray,
+    client: Int64Array,
+    tx: Int64Array,
+    amount: Option<AmountColumn>,
+    timestamp: Option<Int64Array>,
+    original_tx: Option<Int64Array>,
+}
+
+// floats are read like JSON numbers. decimals and strings are read from their text, so no digits are lost to f64
+enum AmountColumn {
+    Float(Float64Array),
+    Text(StringArray),
+}
+
+impl AmountColumn {
+    fn new(array: &ArrayRef) -> std::result::Result<Self, ArrowError> {
+        Ok(match array.data_type() {
+            DataType::Decimal128(_, _)
+            | DataType::Decimal256(_, _)
+            | DataType::Utf8
+            | DataType::LargeUtf8 => AmountColumn::Text(
+                arrow_cast::cast(array, &DataType::Utf8)?
+                    .as_string::<i32>()
+                    .clone(),
+            ),
+            _ => AmountColumn::Float(
+                arrow_cast::cast(array, &DataType::Float64)?
+                    .as_primitive::<Float64Type>()
+                    .clone(),
+            ),
+        })
+    }
+
+    fn array(&self) -> &dyn Array {
+        match self {
+            AmountColumn::Float(array) => array,
+            AmountColumn::Text(array) => array,
+        }
+    }
+
+    // None for a null. Some(None) for text that isn't an amount
+    fn value(&self, idx: usize) -> Option<Option<RawAmount>> {
+        if !self.array().is_valid(idx) {
+            return None;
+        }
+        Some(match self {
+            AmountColumn::Float(array) => Some(RawAmount::from(array.value(idx))),
+            AmountColumn::Text(array) => RawAmount::parse(array.value(idx)),
+        })
+    }
+}
+
+impl BatchColumns {
+    pub(crate) fn new(batch: &RecordBatch) -> std::result::Result<Self, ArrowError> {
+        let column = |name: &str| batch.column_by_name(name);
+        // the schema was checked
+        let required = |name: &str| {
+            column(name).ok_or_else(|| ArrowError::SchemaError(format!("no {} column", name)))
+        };
+        let ints = |array: &ArrayRef| -> std::result::Result<Int64Array, ArrowError> {
+            Ok(arrow_cast::cast(array, &DataType::Int64)?
+                .as_primitive::<Int64Type>()
+                .clone())
+        };
+        // unix seconds, whatever the unit of a timestamp column
+        let seconds = |array: &ArrayRef| match array.data_type() {
+            DataType::Timestamp(_, _) => ints(&arrow_cast::cast(
+                array,
+                &DataType::Timestamp(TimeUnit::Second, None),
+            )?),
+            _ => ints(array),
+        };
+        let txn_type = arrow_cast::cast(required("type")?, &DataType::Utf8)?;
+        Ok(BatchColumns {
+            len: batch.num_rows(),
+            txn_type: txn_type.as_string::<i32>().clone(),
+            client: ints(required("client")?)?,
+            tx: ints(required("tx")?)?,
+            amount: column("amount").map(AmountColumn::new).transpose()?,
+            timestamp: column("timestamp").map(seconds).transpose()?,
+            original_tx: column("original_tx").map(ints).transpose()?,
+        })
+    }
+
+    pub(crate) fn row(&self, idx: usize) -> InputRow {
+        let int = |array: &Int64Array| array.is_valid(idx).then(|| array.value(idx));
+        let txn = (|| {
+            let original_txn_id = match self.original_tx.as_ref().and_then(int) {
+                Some(id) => Some(TransactionId::try_from(id).ok()?),
+                None => None,
+            };
+            Some(RawTxnInput {
+                // an unknown type is invalid, as in CSV input
+                txn_type: self
+                    .txn_type
+                    .is_valid(idx)
+                    .then(|| self.txn_type.value(idx).trim())?
+                    .parse()
+                    .unwrap_or(TxnType::Invalid),
+                client_id: ClientId::try_from(int(&self.client)?).ok()?,
+                txn_id: TransactionId::try_from(int(&self.tx)?).ok()?,
+                amount: match self.amount.as_ref().and_then(|array| array.value(idx)) {
+                    Some(amount) => Some(amount?),
+                    None => None,
+                },
+                timestamp: self.timestamp.as_ref().and_then(int),
+                original_txn_id,
+            })
+        })();
+        match txn {
+            Some(txn) => InputRow::Txn(txn),
+            None => InputRow::Malformed(self.record(idx)),
+        }
+    }
+
+    // the fields of a row as text, for the rejects log. nulls are empty
+    fn record(&self, idx: usize) -> StringRecord {
+        let text = |array: &dyn Array, value: &dyn Fn() -> String| {
+            if array.is_valid(idx) {
+                value()
+            } else {
+                String::new()
+            }
+        };
+        let int = |array: &Option<Int64Array>| match array {
+            Some(array) => text(array, &|| array.value(idx).to_string()),
+            None => String::new(),
+        };
+        StringRecord::from(vec![
+            text(&self.txn_type, &|| self.txn_type.value(idx).to_string()),
+            text(&self.client, &|| self.client.value(idx).to_string()),
+            text(&self.tx, &|| self.tx.value(idx).to_string()),
+            match &self.amount {
+                Some(AmountColumn::Float(array)) => text(array, &|| array.value(idx).to_string()),
+                Some(AmountColumn::Text(array)) => text(array, &|| array.value(idx).to_string()),
+                None => String::new(),
+            },
+            int(&self.timestamp),
+            int(&self.original_tx),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, transaction_processor::TransactionProcessor};
+    use arrow_array::{Decimal128Array, UInt16Array, UInt32Array};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_process_record_batch() {
+        let batch = |types: Vec<&str>, tx: Vec<u32>, amounts: Vec<Option<i128>>| {
+            let client = UInt16Array::from(vec![1; types.len()]);
+            // amounts in hundredths
+            let amount = Decimal128Array::from(amounts)
+                .with_precision_and_scale(10, 2)
+                .unwrap();
+            RecordBatch::try_from_iter(vec![
+                ("tx", Arc::new(UInt32Array::from(tx)) as ArrayRef),
+                ("amount", Arc::new(amount)),
+                ("client", Arc::new(client)),
+                ("type", Arc::new(StringArray::from(types))),
+            ])
+            .unwrap()
+        };
+        let mut tp = TransactionProcessor::in_memory();
+        tp.process_record_batch(&batch(
+            vec!["deposit", "withdrawal"],
+            vec![1, 2],
+            vec![Some(1000), Some(250)],
+        ))
+        .unwrap();
+        // the accounts carry over to the next batch
+        tp.process_record_batch(&batch(vec!["dispute"], vec![1], vec![None]))
+            .unwrap();
+        let state = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(state.available, amt(-2.5));
+        assert_eq!(state.held, amt(10.0));
+        assert_eq!(tp.stats().disputes, 1);
+
+        // a batch without the required columns
+        let batch = RecordBatch::try_from_iter(vec![(
+            "tx",
+            Arc::new(UInt32Array::from(vec![3])) as ArrayRef,
+        )])
+        .unwrap();
+        assert!(tp.process_record_batch(&batch).is_err());
+    }
+}
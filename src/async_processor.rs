@@ -0,0 +1,159 @@
+//! an async front end for `TransactionProcessor`, for embedding the engine in async services (web servers, queue
+//! consumers). every call runs the blocking processor on tokio's blocking thread pool, so SQLite I/O never stalls the
+//! runtime. calls are serialized: transactions are applied one at a time, in the order the calls acquire the processor
+use crate::{
+    errors::*,
+    events::{BatchResult, EngineEvent},
+    fmt_error,
+    model::*,
+    stats::ProcessingStats,
+    transaction_processor::TransactionProcessor,
+};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+/// cheap to clone: the clones share the processor
+#[derive(Clone)]
+pub struct AsyncTransactionProcessor {
+    inner: Arc<Mutex<TransactionProcessor>>,
+}
+
+impl AsyncTransactionProcessor {
+    pub fn new(processor: TransactionProcessor) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(processor)),
+        }
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T, MyError>
+    where
+        F: FnOnce(&mut TransactionProcessor) -> Result<T, MyError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut processor = inner.lock().map_err(|_| {
+                report!(MyError::Db).attach_printable(fmt_error!("processor mutex was poisoned"))
+            })?;
+            f(&mut processor)
+        })
+        .await
+        .report()
+        .attach_printable_lazy(|| fmt_error!("blocking processor task failed"))
+        .change_context(MyError::Db)?
+    }
+
+    /// apply a transaction. returns the events it produced, like `TransactionProcessor::process`
+    pub async fn process_async(&self, txn: RawTxnInput) -> Result<Vec<EngineEvent>, MyError> {
+        self.run(move |p| p.process(txn)).await
+    }
+
+    /// apply many transactions in one store transaction, like `TransactionProcessor::process_batch`
+    pub async fn process_batch_async(
+        &self,
+        txns: Vec<RawTxnInput>,
+    ) -> Result<BatchResult, MyError> {
+        self.run(move |p| p.process_batch(txns)).await
+    }
+
+    /// process a CSV document with a header row, skipping records with invalid formats
+    pub async fn process_csv_async(&self, csv: Vec<u8>) -> Result<(), MyError> {
+        self.run(move |p| p.process_csv(csv.as_slice())).await
+    }
+
+    /// the current state of one client account. None if the client has never been seen
+    pub async fn client_state_async(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Option<ClientState>, MyError> {
+        self.run(move |p| p.client_state(client_id)).await
+    }
+
+    /// the current state of every client account
+    pub async fn client_states_async(&self) -> Result<Vec<ClientState>, MyError> {
+        self.run(|p| p.client_states()).await
+    }
+
+    /// the client report, the same as `TransactionProcessor::write_report`
+    pub async fn report_async(&self) -> Result<Vec<u8>, MyError> {
+        self.run(|p| {
+            let mut report = Vec::new();
+            p.write_report(&mut report)?;
+            Ok(report)
+        })
+        .await
+    }
+
+    /// the stats of the transactions processed so far. blocks the calling thread until the transaction in progress
+    /// is done, so it's meant for threads outside the runtime, ex: a metrics exporter
+    pub fn stats(&self) -> ProcessingStats {
+        match self.inner.lock() {
+            Ok(processor) => processor.stats().clone(),
+            Err(poisoned) => poisoned.into_inner().stats().clone(),
+        }
+    }
+
+    /// print the client report to stdout
+    pub async fn display_async(&self) -> Result<(), MyError> {
+        self.run(|p| p.write_report(io::stdout().lock())).await
+    }
+
+    /// the processor, once no clone is left. None if another clone still shares it
+    pub fn into_inner(self) -> Option<TransactionProcessor> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .and_then(|inner| inner.into_inner().ok())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[tokio::test]
+    async fn test_process_async() {
+        let processor = AsyncTransactionProcessor::new(TransactionProcessor::in_memory());
+        let deposit = |client_id, txn_id| RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id,
+            txn_id,
+            amount: Some(2.5.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+
+        // concurrent callers share the processor
+        let handles: Vec<_> = (1..=10)
+            .map(|txn_id| {
+                let processor = processor.clone();
+                tokio::spawn(async move { processor.process_async(deposit(1, txn_id)).await })
+            })
+            .collect();
+        for handle in handles {
+            let events = handle.await.unwrap().unwrap();
+            assert!(matches!(events[0], EngineEvent::FundsDeposited { .. }));
+        }
+        processor
+            .process_csv_async(b"type,client,tx,amount\nwithdrawal,1,11,5.0\n".to_vec())
+            .await
+            .unwrap();
+        let batch = processor
+            .process_batch_async(vec![deposit(2, 12), deposit(2, 12)])
+            .await
+            .unwrap();
+        assert_eq!((batch.applied(), batch.rejected()), (1, 1));
+
+        let client = processor.client_state_async(1).await.unwrap().unwrap();
+        assert_eq!(client.available, amt(20.0));
+        assert_eq!(processor.client_states_async().await.unwrap().len(), 2);
+        assert_eq!(
+            String::from_utf8(processor.report_async().await.unwrap()).unwrap(),
+            "client,available,held,total,locked\n1,20,0,20,false\n2,2.5,0,2.5,false\n"
+        );
+        assert!(processor.into_inner().is_some());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{errors::*, fmt_error, model::*, store::TxnStore};
+use async_trait::async_trait;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use std::sync::{Arc, Mutex};
+
+/// async counterpart of `TxnStore`, for use by the async server modes
+#[async_trait]
+pub trait AsyncTxnStore: Send + Sync {
+    async fn create_client_state(&self, client_id: ClientId) -> Result<ClientState, MyError>;
+    async fn get_client_state(&self, client_id: ClientId) -> Result<Option<ClientState>, MyError>;
+    // returns every client. the blocking stores can't hand an iterator across threads
+    async fn get_all_clients(&self) -> Result<Vec<ClientState>, MyError>;
+    async fn update_client_state(&self, client_state: ClientState) -> Result<(), MyError>;
+    async fn try_insert_balance_transfer(&self, txn: BalanceTransfer) -> Result<bool, MyError>;
+    async fn try_insert_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+    async fn try_resolve_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+    async fn try_chargeback_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+    async fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError>;
+}
+
+/// adapts a blocking `TxnStore` (such as `TxnDb`) to `AsyncTxnStore`.
+/// every call runs on tokio's blocking thread pool so SQLite I/O never stalls the runtime.
+pub struct BlockingStore<S> {
+    inner: Arc<Mutex<S>>,
+}
+
+impl<S> Clone for BlockingStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: TxnStore + Send + 'static> BlockingStore<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T, MyError>
+    where
+        F: FnOnce(&mut S) -> Result<T, MyError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut store = inner.lock().map_err(|_| {
+                report!(MyError::Db).attach_printable(fmt_error!("storage mutex was poisoned"))
+            })?;
+            f(&mut store)
+        })
+        .await
+        .report()
+        .attach_printable_lazy(|| fmt_error!("blocking storage task failed"))
+        .change_context(MyError::Db)?
+    }
+}
+
+#[async_trait]
+impl<S: TxnStore + Send + 'static> AsyncTxnStore for BlockingStore<S> {
+    async fn create_client_state(&self, client_id: ClientId) -> Result<ClientState, MyError> {
+        self.run(move |s| s.create_client_state(client_id)).await
+    }
+
+    async fn get_client_state(&self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        self.run(move |s| s.get_client_state(client_id)).await
+    }
+
+    async fn get_all_clients(&self) -> Result<Vec<ClientState>, MyError> {
+        self.run(|s| {
+            let mut clients = Vec::new();
+            s.process_all_clients(&mut |client| clients.push(client))?;
+            Ok(clients)
+        })
+        .await
+    }
+
+    async fn update_client_state(&self, client_state: ClientState) -> Result<(), MyError> {
+        self.run(move |s| s.update_client_state(&client_state))
+            .await
+    }
+
+    async fn try_insert_balance_transfer(&self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        self.run(move |s| s.try_insert_balance_transfer(txn)).await
+    }
+
+    async fn try_insert_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.run(move |s| s.try_insert_dispute(client_id, txn_id))
+            .await
+    }
+
+    async fn try_resolve_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.run(move |s| s.try_resolve_dispute(client_id, txn_id))
+            .await
+    }
+
+    async fn try_chargeback_dispute(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.run(move |s| s.try_chargeback_dispute(client_id, txn_id))
+            .await
+    }
+
+    async fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        self.run(move |s| s.get_balance_transfer(client_id, txn_id))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+    use crate::memory_db::MemoryDb;
+    use tracing_subscriber::EnvFilter;
+
+    fn init() -> BlockingStore<MemoryDb> {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_test_writer()
+            .try_init();
+        BlockingStore::new(MemoryDb::new())
+    }
+
+    #[tokio::test]
+    async fn test_create_client() {
+        let store = init();
+        let client = store.create_client_state(123).await.unwrap();
+        let retrieved = store.get_client_state(client.client_id).await.unwrap();
+        assert!(retrieved.is_some());
+        assert_eq!(retrieved.unwrap().client_id, 123);
+        assert_eq!(store.get_all_clients().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispute() {
+        let store = init();
+        store.create_client_state(123).await.unwrap();
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+
+        assert!(store.try_insert_balance_transfer(xfer).await.unwrap());
+        assert!(!store.try_insert_balance_transfer(xfer).await.unwrap());
+        assert!(store.try_insert_dispute(123, 1).await.unwrap());
+        assert!(store.try_resolve_dispute(123, 1).await.unwrap());
+        assert!(!store.try_chargeback_dispute(123, 1).await.unwrap());
+    }
+}
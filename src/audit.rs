@@ -0,0 +1,379 @@
+//! an append-only, hash-chained log of every transaction the processor was given and what it did with it.
+//! each entry contains the hash of the previous one, so modifying, removing, or reordering an entry breaks the chain.
+//! removing entries from the end can only be detected by comparing the head hash with a copy kept elsewhere.
+#[cfg(feature = "encryption")]
+use crate::encryption::FieldKey;
+use crate::{amount::RawAmount, errors::*, fmt_error, model::*};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead};
+
+/// the prev_hash of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// starts at 1
+    pub seq: u64,
+    pub txn_type: String,
+    pub client_id: ClientId,
+    pub txn_id: TransactionId,
+    pub amount: Option<f64>,
+    /// "applied" or the reason the transaction was rejected
+    pub outcome: String,
+    pub prev_hash: String,
+    /// sha256 of the other fields, hex encoded
+    pub hash: String,
+    /// the version of the configuration the processor was using (`config::EngineConfig::version`). None without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<String>,
+    /// the input record (`type,client,tx,amount,timestamp,original_tx`), encrypted with the field key. only recorded
+    /// for transactions, when the chain has a key (`AuditChain::set_field_key`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn compute_hash(&self) -> String {
+        let amount = self.amount.map(|a| a.to_string()).unwrap_or_default();
+        let mut fields = format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            self.seq,
+            self.txn_type,
+            self.client_id,
+            self.txn_id,
+            amount,
+            self.outcome,
+            self.prev_hash
+        );
+        // only hashed when set, so entries written before config versions existed still verify
+        if let Some(version) = &self.config_version {
+            fields.push('|');
+            fields.push_str(version);
+        }
+        // the ciphertext is hashed, so the chain can be verified without the key
+        if let Some(input) = &self.input {
+            fields.push('|');
+            fields.push_str(input);
+        }
+        let digest = Sha256::new().chain_update(fields.as_bytes()).finalize();
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        let seq: i64 = row.get(0)?;
+        Ok(AuditEntry {
+            seq: seq as u64,
+            txn_type: row.get(1)?,
+            client_id: row.get(2)?,
+            txn_id: row.get(3)?,
+            amount: row.get(4)?,
+            outcome: row.get(5)?,
+            prev_hash: row.get(6)?,
+            hash: row.get(7)?,
+            config_version: row.get(8)?,
+            input: row.get(9)?,
+        })
+    }
+}
+
+/// the end of the chain: creates the next entry
+#[derive(Debug, Clone)]
+pub struct AuditChain {
+    next_seq: u64,
+    head: String,
+    config_version: Option<String>,
+    #[cfg(feature = "encryption")]
+    field_key: Option<FieldKey>,
+}
+
+impl Default for AuditChain {
+    fn default() -> Self {
+        AuditChain {
+            next_seq: 1,
+            head: GENESIS_HASH.to_string(),
+            config_version: None,
+            #[cfg(feature = "encryption")]
+            field_key: None,
+        }
+    }
+}
+
+impl AuditChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// continue after `last`, the last entry of an existing log
+    pub fn resume(last: Option<&AuditEntry>) -> Self {
+        match last {
+            Some(entry) => AuditChain {
+                next_seq: entry.seq + 1,
+                head: entry.hash.clone(),
+                ..Self::default()
+            },
+            None => Self::new(),
+        }
+    }
+
+    /// record `version` on the entries created from now on
+    pub fn set_config_version(&mut self, version: Option<String>) {
+        self.config_version = version;
+    }
+
+    /// record the input of the transactions, encrypted with `key`, on the entries created from now on
+    #[cfg(feature = "encryption")]
+    pub fn set_field_key(&mut self, key: Option<FieldKey>) {
+        self.field_key = key;
+    }
+
+    /// the entry that would come next. the chain doesn't move until `advance` is called, so an entry
+    /// that fails to be stored isn't skipped
+    pub fn next_entry(&self, txn: &RawTxnInput, outcome: &str) -> AuditEntry {
+        self.entry(
+            format!("{:?}", txn.txn_type).to_lowercase(),
+            txn.client_id,
+            txn.txn_id,
+            txn.amount.as_ref().map(RawAmount::to_f64),
+            outcome,
+            self.input(txn),
+        )
+    }
+
+    #[cfg(feature = "encryption")]
+    fn input(&self, txn: &RawTxnInput) -> Option<String> {
+        let key = self.field_key.as_ref()?;
+        let fmt = |field: Option<String>| field.unwrap_or_default();
+        let record = format!(
+            "{},{},{},{},{},{}",
+            txn.txn_type,
+            txn.client_id,
+            txn.txn_id,
+            fmt(txn.amount.as_ref().map(|a| a.to_string())),
+            fmt(txn.timestamp.map(|t| t.to_string())),
+            fmt(txn.original_txn_id.map(|t| t.to_string())),
+        );
+        Some(key.encrypt("input", &record))
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn input(&self, _txn: &RawTxnInput) -> Option<String> {
+        None
+    }
+
+    /// the entry that would come next for an administrative action (ex: "forget_client"), rather than a transaction.
+    /// `txn_id` is 0 for actions on the whole client
+    pub fn next_action_entry(
+        &self,
+        action: &str,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        outcome: &str,
+    ) -> AuditEntry {
+        self.entry(action.to_string(), client_id, txn_id, None, outcome, None)
+    }
+
+    fn entry(
+        &self,
+        txn_type: String,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Option<f64>,
+        outcome: &str,
+        input: Option<String>,
+    ) -> AuditEntry {
+        let mut entry = AuditEntry {
+            seq: self.next_seq,
+            txn_type,
+            client_id,
+            txn_id,
+            amount,
+            outcome: outcome.to_string(),
+            prev_hash: self.head.clone(),
+            hash: String::new(),
+            config_version: self.config_version.clone(),
+            input,
+        };
+        entry.hash = entry.compute_hash();
+        entry
+    }
+
+    pub fn advance(&mut self, entry: &AuditEntry) {
+        self.next_seq = entry.seq + 1;
+        self.head = entry.hash.clone();
+    }
+
+    /// the hash of the last entry
+    pub fn head(&self) -> &str {
+        &self.head
+    }
+}
+
+/// checks entries one at a time, in order
+#[derive(Debug, Default)]
+pub struct AuditVerifier {
+    chain: AuditChain,
+    verified: u64,
+}
+
+impl AuditVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        if entry.seq != self.chain.next_seq {
+            return tampered(fmt_error!(
+                "expected entry {} but found entry {}: entries were removed or reordered",
+                self.chain.next_seq,
+                entry.seq
+            ));
+        }
+        if entry.prev_hash != self.chain.head {
+            return tampered(fmt_error!(
+                "entry {} doesn't link to the entry before it",
+                entry.seq
+            ));
+        }
+        if entry.hash != entry.compute_hash() {
+            return tampered(fmt_error!("entry {} was modified", entry.seq));
+        }
+        self.chain.advance(entry);
+        self.verified += 1;
+        Ok(())
+    }
+
+    /// the number of entries verified so far
+    pub fn verified(&self) -> u64 {
+        self.verified
+    }
+
+    /// the hash of the last verified entry
+    pub fn head(&self) -> &str {
+        self.chain.head()
+    }
+}
+
+fn tampered(msg: String) -> Result<(), MyError> {
+    Err(report!(MyError::Audit).attach_printable(msg))
+}
+
+/// write entries as JSON lines
+pub fn write_entries<'a, W: io::Write, I: IntoIterator<Item = &'a AuditEntry>>(
+    mut writer: W,
+    entries: I,
+) -> Result<(), MyError> {
+    for entry in entries {
+        serde_json::to_writer(&mut writer, entry)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to serialize audit entry {}", entry.seq))
+            .change_context(MyError::Output)?;
+        writeln!(writer)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write audit entry {}", entry.seq))
+            .change_context(MyError::Output)?;
+    }
+    Ok(())
+}
+
+/// verify an exported audit file (JSON lines). returns the verifier, which holds the entry count and head hash
+pub fn verify_file<R: io::Read>(reader: R) -> Result<AuditVerifier, MyError> {
+    let mut verifier = AuditVerifier::new();
+    for (idx, line) in io::BufReader::new(reader).lines().enumerate() {
+        let line = line
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read line {}", idx + 1))
+            .change_context(MyError::FileReader)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = serde_json::from_str(&line)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("line {} is not an audit entry", idx + 1))
+            .change_context(MyError::Audit)?;
+        verifier.check(&entry)?;
+    }
+    Ok(verifier)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chain_of(n: TransactionId) -> Vec<AuditEntry> {
+        let mut chain = AuditChain::new();
+        (1..=n)
+            .map(|txn_id| {
+                let txn = RawTxnInput {
+                    txn_type: TxnType::Deposit,
+                    client_id: 1,
+                    txn_id,
+                    amount: Some(1.5.into()),
+                    timestamp: None,
+                    original_txn_id: None,
+                };
+                let entry = chain.next_entry(&txn, "applied");
+                chain.advance(&entry);
+                entry
+            })
+            .collect()
+    }
+
+    fn verify(entries: &[AuditEntry]) -> Result<u64, MyError> {
+        let mut verifier = AuditVerifier::new();
+        for entry in entries {
+            verifier.check(entry)?;
+        }
+        Ok(verifier.verified())
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        let entries = chain_of(3);
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(verify(&entries).unwrap(), 3);
+
+        let mut file = Vec::new();
+        write_entries(&mut file, &entries).unwrap();
+        let verifier = verify_file(file.as_slice()).unwrap();
+        assert_eq!(verifier.verified(), 3);
+        assert_eq!(verifier.head(), entries[2].hash);
+    }
+
+    #[test]
+    fn test_modified_entry() {
+        let mut entries = chain_of(3);
+        entries[1].amount = Some(100.0);
+        assert!(verify(&entries).is_err());
+
+        // recomputing the hash of the modified entry breaks the link to the next one
+        entries[1].hash = entries[1].compute_hash();
+        assert!(verify(&entries).is_err());
+    }
+
+    #[test]
+    fn test_removed_or_reordered_entry() {
+        let mut entries = chain_of(3);
+        entries.remove(1);
+        assert!(verify(&entries).is_err());
+
+        let mut entries = chain_of(3);
+        entries.swap(1, 2);
+        assert!(verify(&entries).is_err());
+    }
+
+    #[test]
+    fn test_modified_file() {
+        let mut file = Vec::new();
+        write_entries(&mut file, &chain_of(2)).unwrap();
+        let tampered =
+            String::from_utf8(file)
+                .unwrap()
+                .replacen("\"applied\"", "\"insufficient_funds\"", 1);
+        assert!(verify_file(tampered.as_bytes()).is_err());
+        assert!(verify_file("not json".as_bytes()).is_err());
+    }
+}
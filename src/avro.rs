@@ -0,0 +1,163 @@
+//! the Avro binary encoding of a transaction. only the one schema below is supported: a payload is a single datum
+//! written with it, without a container file header or a schema registry prefix
+use crate::{amount::RawAmount, errors::*, fmt_error, model::*};
+use error_stack::{report, Result};
+
+/// the schema of a transaction. the fields have the names of the CSV columns
+pub const TRANSACTION_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "Transaction",
+  "namespace": "payments_engine",
+  "fields": [
+    {"name": "type", "type": {"type": "enum", "name": "TxnType", "symbols": ["deposit", "withdrawal", "dispute", "resolve", "chargeback"]}},
+    {"name": "client", "type": "long"},
+    {"name": "tx", "type": "long"},
+    {"name": "amount", "type": ["null", "double"], "default": null}
+  ]
+}"#;
+
+/// decode one datum of `TRANSACTION_SCHEMA`
+pub fn decode_transaction(payload: &[u8]) -> Result<RawTxnInput, MyError> {
+    let mut reader = Reader { bytes: payload };
+    let txn_type = match reader.long()? {
+        0 => TxnType::Deposit,
+        1 => TxnType::Withdrawal,
+        2 => TxnType::Dispute,
+        3 => TxnType::Resolve,
+        4 => TxnType::Chargeback,
+        n => {
+            return Err(report!(MyError::MalformedRecord)
+                .attach_printable(fmt_error!("invalid TxnType symbol {}", n)))
+        }
+    };
+    let client = reader.long()?;
+    let client_id = ClientId::try_from(client).map_err(|_| {
+        report!(MyError::MalformedRecord)
+            .attach_printable(fmt_error!("client {} is out of range", client))
+    })?;
+    let tx = reader.long()?;
+    let txn_id = TransactionId::try_from(tx).map_err(|_| {
+        report!(MyError::MalformedRecord).attach_printable(fmt_error!("tx {} is out of range", tx))
+    })?;
+    let amount = match reader.long()? {
+        0 => None,
+        1 => Some(RawAmount::from(reader.double()?)),
+        n => {
+            return Err(report!(MyError::MalformedRecord)
+                .attach_printable(fmt_error!("invalid amount branch {}", n)))
+        }
+    };
+    if !reader.bytes.is_empty() {
+        return Err(report!(MyError::MalformedRecord)
+            .attach_printable(fmt_error!("{} trailing bytes", reader.bytes.len())));
+    }
+    Ok(RawTxnInput {
+        txn_type,
+        client_id,
+        txn_id,
+        amount,
+        timestamp: None,
+        original_txn_id: None,
+    })
+}
+
+// reads the primitives of the binary encoding from the front of `bytes`
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    // a zigzag-encoded variable-length integer
+    pub(crate) fn long(&mut self) -> Result<i64, MyError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = match self.bytes.split_first() {
+                Some(split) => split,
+                None => {
+                    return Err(report!(MyError::MalformedRecord)
+                        .attach_printable(fmt_error!("truncated long")))
+                }
+            };
+            self.bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+        Err(report!(MyError::MalformedRecord)
+            .attach_printable(fmt_error!("long is more than 10 bytes")))
+    }
+
+    // 8 bytes, little-endian
+    pub(crate) fn double(&mut self) -> Result<f64, MyError> {
+        if self.bytes.len() < 8 {
+            return Err(
+                report!(MyError::MalformedRecord).attach_printable(fmt_error!("truncated double"))
+            );
+        }
+        let (bytes, rest) = self.bytes.split_at(8);
+        self.bytes = rest;
+        let mut le = [0; 8];
+        le.copy_from_slice(bytes);
+        Ok(f64::from_le_bytes(le))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn long(n: i64) -> Vec<u8> {
+        let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    #[test]
+    fn test_decode_transaction() {
+        let deposit = [
+            long(0),
+            long(1),
+            long(300),
+            long(1),
+            2.5f64.to_le_bytes().to_vec(),
+        ]
+        .concat();
+        assert_eq!(
+            decode_transaction(&deposit).unwrap(),
+            RawTxnInput {
+                txn_type: TxnType::Deposit,
+                client_id: 1,
+                txn_id: 300,
+                amount: Some(2.5.into()),
+                timestamp: None,
+                original_txn_id: None,
+            }
+        );
+        let dispute = [long(2), long(7), long(300), long(0)].concat();
+        assert_eq!(
+            decode_transaction(&dispute).unwrap(),
+            RawTxnInput {
+                txn_type: TxnType::Dispute,
+                client_id: 7,
+                txn_id: 300,
+                amount: None,
+                timestamp: None,
+                original_txn_id: None,
+            }
+        );
+
+        assert!(decode_transaction(&[long(5), long(1), long(1), long(0)].concat()).is_err());
+        assert!(decode_transaction(&[long(0), long(-1), long(1), long(0)].concat()).is_err());
+        assert!(decode_transaction(&deposit[..deposit.len() - 1]).is_err());
+        assert!(decode_transaction(&[dispute, vec![0]].concat()).is_err());
+    }
+}
@@ -0,0 +1,852 @@
+//! Avro object container files (feature "avro"), ex: the Kafka archive dumps. the writer's schema, from the file
+//! header, must be a record. its fields are mapped to `RawTxnInput` by name, as the CSV columns are, and the other
+//! fields are skipped. the blocks can be uncompressed, deflate, or snappy
+use crate::{
+    amount::RawAmount, avro::Reader, columns::COLUMNS, errors::*, fmt_error, model::*,
+    transaction_processor::InputRow,
+};
+use csv::StringRecord;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde_json::Value as Json;
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read},
+};
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+// the most items an array or map can have, and the most rows a block can have
+const MAX_ITEMS: u64 = 1 << 24;
+
+// a schema, as far as decoding it goes
+#[derive(Debug, Clone, PartialEq)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    // an int or long with a timestamp logical type, in units per second
+    Timestamp(i64),
+    // a bytes or fixed with the decimal logical type
+    Decimal { scale: i32, size: Option<usize> },
+}
+
+// a decoded datum. only the values a transaction can use are kept
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Boolean(bool),
+    Long(i64),
+    Double(f64),
+    // a decimal logical type, with all of its digits
+    Decimal(RawAmount),
+    String(String),
+    Other,
+}
+
+fn invalid_schema(msg: String) -> error_stack::Report<MyError> {
+    report!(MyError::FileReader).attach_printable(msg)
+}
+
+impl Schema {
+    // `names`: the named types defined so far. recursive types aren't supported
+    fn parse(json: &Json, names: &mut HashMap<String, Schema>) -> Result<Schema, MyError> {
+        let object = match json {
+            Json::String(name) => return Schema::named(name, names),
+            Json::Array(branches) => {
+                let branches = branches
+                    .iter()
+                    .map(|branch| Schema::parse(branch, names))
+                    .collect::<Result<_, _>>()?;
+                return Ok(Schema::Union(branches));
+            }
+            Json::Object(object) => object,
+            _ => return Err(invalid_schema(fmt_error!("invalid schema {}", json))),
+        };
+        let field = |key: &str| object.get(key);
+        let name = field("name").and_then(Json::as_str);
+        let schema = match field("type") {
+            Some(Json::String(kind)) => match kind.as_str() {
+                "record" | "error" => {
+                    let fields = field("fields").and_then(Json::as_array).ok_or_else(|| {
+                        invalid_schema(fmt_error!("record {:?} has no fields", name))
+                    })?;
+                    let fields = fields
+                        .iter()
+                        .map(|f| {
+                            let name = f.get("name").and_then(Json::as_str).unwrap_or_default();
+                            let schema = f.get("type").unwrap_or(&Json::Null);
+                            Ok((name.to_string(), Schema::parse(schema, names)?))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    Schema::Record(fields)
+                }
+                "enum" => {
+                    let symbols = field("symbols").and_then(Json::as_array);
+                    let symbols = symbols.into_iter().flatten();
+                    Schema::Enum(
+                        symbols
+                            .map(|s| s.as_str().unwrap_or_default().to_string())
+                            .collect(),
+                    )
+                }
+                "array" => Schema::Array(Box::new(Schema::parse(
+                    field("items").unwrap_or(&Json::Null),
+                    names,
+                )?)),
+                "map" => Schema::Map(Box::new(Schema::parse(
+                    field("values").unwrap_or(&Json::Null),
+                    names,
+                )?)),
+                "fixed" => {
+                    let size = field("size").and_then(Json::as_u64).unwrap_or_default() as usize;
+                    match Schema::decimal(object) {
+                        Some(scale) => Schema::Decimal {
+                            scale,
+                            size: Some(size),
+                        },
+                        None => Schema::Fixed(size),
+                    }
+                }
+                primitive => {
+                    let schema = Schema::named(primitive, names)?;
+                    let logical = field("logicalType").and_then(Json::as_str);
+                    match (&schema, logical) {
+                        (Schema::Int | Schema::Long, Some("timestamp-millis")) => {
+                            Schema::Timestamp(1_000)
+                        }
+                        (Schema::Int | Schema::Long, Some("timestamp-micros")) => {
+                            Schema::Timestamp(1_000_000)
+                        }
+                        (Schema::Long, Some("timestamp-nanos")) => Schema::Timestamp(1_000_000_000),
+                        (Schema::Bytes, Some("decimal")) => Schema::Decimal {
+                            scale: Schema::decimal(object).unwrap_or_default(),
+                            size: None,
+                        },
+                        _ => schema,
+                    }
+                }
+            },
+            Some(nested) => Schema::parse(nested, names)?,
+            None => return Err(invalid_schema(fmt_error!("schema {} has no type", json))),
+        };
+        if let Some(name) = name {
+            names.insert(name.to_string(), schema.clone());
+            if let Some(namespace) = field("namespace").and_then(Json::as_str) {
+                names.insert(format!("{}.{}", namespace, name), schema.clone());
+            }
+        }
+        Ok(schema)
+    }
+
+    // the scale of a decimal logical type
+    fn decimal(object: &serde_json::Map<String, Json>) -> Option<i32> {
+        if object.get("logicalType").and_then(Json::as_str) != Some("decimal") {
+            return None;
+        }
+        let scale = object
+            .get("scale")
+            .and_then(Json::as_i64)
+            .unwrap_or_default();
+        Some(scale as i32)
+    }
+
+    // encodes to no bytes, so any number of them can be read from nothing
+    fn is_empty(&self) -> bool {
+        match self {
+            Schema::Null => true,
+            Schema::Fixed(size)
+            | Schema::Decimal {
+                size: Some(size), ..
+            } => *size == 0,
+            Schema::Record(fields) => fields.iter().all(|(_, field)| field.is_empty()),
+            _ => false,
+        }
+    }
+
+    fn named(name: &str, names: &HashMap<String, Schema>) -> Result<Schema, MyError> {
+        let schema = match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            _ => match names.get(name) {
+                Some(schema) => schema.clone(),
+                None => return Err(invalid_schema(fmt_error!("unknown type {}", name))),
+            },
+        };
+        Ok(schema)
+    }
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], MyError> {
+        if self.bytes.len() < len {
+            return Err(
+                report!(MyError::MalformedRecord).attach_printable(fmt_error!("truncated datum"))
+            );
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    // a length, as a long
+    fn len(&mut self) -> Result<usize, MyError> {
+        let len = self.long()?;
+        usize::try_from(len).map_err(|_| {
+            report!(MyError::MalformedRecord)
+                .attach_printable(fmt_error!("negative length {}", len))
+        })
+    }
+
+    // the items of an array or map block by block. a negative count is followed by the block size in bytes. the counts
+    // come from the file: an item that isn't `empty` takes at least a byte, so a count past the bytes left is corrupt,
+    // and empty items aren't read at all, since there is nothing to read
+    fn blocks(
+        &mut self,
+        empty: bool,
+        mut item: impl FnMut(&mut Self) -> Result<(), MyError>,
+    ) -> Result<(), MyError> {
+        let mut total: u64 = 0;
+        loop {
+            let count = match self.long()? {
+                0 => return Ok(()),
+                count if count < 0 => {
+                    self.long()?;
+                    count.unsigned_abs()
+                }
+                count => count as u64,
+            };
+            total = total.saturating_add(count);
+            if total > MAX_ITEMS || (!empty && count > self.bytes.len() as u64) {
+                return Err(report!(MyError::MalformedRecord)
+                    .attach_printable(fmt_error!("invalid item count {}", count)));
+            }
+            if empty {
+                continue;
+            }
+            for _ in 0..count {
+                item(self)?;
+            }
+        }
+    }
+
+    fn datum(&mut self, schema: &Schema) -> Result<Value, MyError> {
+        let value = match schema {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Boolean(self.take(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::Long(self.long()?),
+            Schema::Float => {
+                let mut le = [0; 4];
+                le.copy_from_slice(self.take(4)?);
+                Value::Double(f32::from_le_bytes(le).into())
+            }
+            Schema::Double => Value::Double(self.double()?),
+            Schema::Bytes => {
+                let len = self.len()?;
+                self.take(len)?;
+                Value::Other
+            }
+            Schema::String => {
+                let len = self.len()?;
+                let bytes = self.take(len)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => Value::String(s.to_string()),
+                    Err(_) => Value::Other,
+                }
+            }
+            Schema::Record(fields) => {
+                for (_, field) in fields {
+                    self.datum(field)?;
+                }
+                Value::Other
+            }
+            Schema::Enum(symbols) => {
+                let idx = self.long()?;
+                match usize::try_from(idx).ok().and_then(|idx| symbols.get(idx)) {
+                    Some(symbol) => Value::String(symbol.clone()),
+                    None => {
+                        return Err(report!(MyError::MalformedRecord)
+                            .attach_printable(fmt_error!("invalid enum symbol {}", idx)))
+                    }
+                }
+            }
+            Schema::Array(items) => {
+                self.blocks(items.is_empty(), |reader| reader.datum(items).map(|_| ()))?;
+                Value::Other
+            }
+            Schema::Map(values) => {
+                self.blocks(false, |reader| {
+                    reader.datum(&Schema::String)?;
+                    reader.datum(values).map(|_| ())
+                })?;
+                Value::Other
+            }
+            Schema::Union(branches) => {
+                let idx = self.long()?;
+                match usize::try_from(idx).ok().and_then(|idx| branches.get(idx)) {
+                    Some(branch) => self.datum(branch)?,
+                    None => {
+                        return Err(report!(MyError::MalformedRecord)
+                            .attach_printable(fmt_error!("invalid union branch {}", idx)))
+                    }
+                }
+            }
+            Schema::Fixed(size) => {
+                self.take(*size)?;
+                Value::Other
+            }
+            Schema::Timestamp(per_second) => Value::Long(self.long()?.div_euclid(*per_second)),
+            Schema::Decimal { scale, size } => {
+                let len = match size {
+                    Some(size) => *size,
+                    None => self.len()?,
+                };
+                // big-endian two's complement. the scale is at most the precision, and a 16 byte decimal has 38 digits
+                let bytes = self.take(len)?;
+                if bytes.is_empty() || bytes.len() > 16 || !(0..=38).contains(scale) {
+                    return Ok(Value::Other);
+                }
+                let mut unscaled = if bytes[0] & 0x80 != 0 { -1i128 } else { 0 };
+                for byte in bytes {
+                    unscaled = (unscaled << 8) | i128::from(*byte);
+                }
+                match RawAmount::parse(&decimal_text(unscaled, *scale as usize)) {
+                    Some(amount) => Value::Decimal(amount),
+                    None => Value::Other,
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+// unscaled / 10^scale as a decimal, ex: "-1.2300" for -12300 with a scale of 4
+fn decimal_text(unscaled: i128, scale: usize) -> String {
+    let digits = format!("{:0>width$}", unscaled.unsigned_abs(), width = scale + 1);
+    let (int, frac) = digits.split_at(digits.len() - scale);
+    let sign = if unscaled < 0 { "-" } else { "" };
+    format!("{}{}.{}", sign, int, frac)
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Boolean(b) => b.to_string(),
+            Value::Long(n) => n.to_string(),
+            Value::Double(x) => x.to_string(),
+            Value::Decimal(amount) => amount.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Null | Value::Other => String::new(),
+        }
+    }
+}
+
+// the fields of a transaction in the order of COLUMNS. None for a value that doesn't fit the model
+fn transaction(values: &[Value]) -> Option<RawTxnInput> {
+    let id = |value: &Value| match value {
+        Value::Long(n) => Some(*n),
+        _ => None,
+    };
+    let optional = |value: &Value| match value {
+        Value::Null => Some(None),
+        value => id(value).map(Some),
+    };
+    let original_txn_id = match optional(&values[5])? {
+        Some(id) => Some(TransactionId::try_from(id).ok()?),
+        None => None,
+    };
+    Some(RawTxnInput {
+        // an unknown type is invalid, as in CSV input
+        txn_type: match &values[0] {
+            Value::String(s) => s.trim().parse().unwrap_or(TxnType::Invalid),
+            _ => return None,
+        },
+        client_id: ClientId::try_from(id(&values[1])?).ok()?,
+        txn_id: TransactionId::try_from(id(&values[2])?).ok()?,
+        amount: match &values[3] {
+            Value::Null => None,
+            Value::Long(n) => Some(RawAmount::parse(&n.to_string())?),
+            Value::Double(x) => Some(RawAmount::from(*x)),
+            Value::Decimal(amount) => Some(amount.clone()),
+            Value::String(s) => Some(RawAmount::parse(s)?),
+            _ => return None,
+        },
+        timestamp: optional(&values[4])?,
+        original_txn_id,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Null,
+    Deflate,
+    Snappy,
+}
+
+/// the rows of a container file, in order. a row with a value that doesn't fit the model is malformed
+pub(crate) struct AvroRows<R: Read> {
+    reader: BufReader<R>,
+    fields: Vec<Schema>,
+    // the index of each of COLUMNS among the fields
+    columns: [Option<usize>; 6],
+    codec: Codec,
+    sync: [u8; 16],
+    block: Vec<u8>,
+    // the position of the next datum in the block, and the number of data left
+    pos: usize,
+    remaining: u64,
+}
+
+fn read_error(e: io::Error) -> error_stack::Report<MyError> {
+    Err::<(), _>(e)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read the Avro file"))
+        .change_context(MyError::FileReader)
+        .unwrap_err()
+}
+
+// a long from a stream. None at the end of the stream
+fn read_long<R: Read>(reader: &mut R) -> Result<Option<i64>, MyError> {
+    let mut bytes = Vec::new();
+    for _ in 0..10 {
+        let mut byte = [0];
+        if reader.read(&mut byte).map_err(read_error)? == 0 {
+            if bytes.is_empty() {
+                return Ok(None);
+            }
+            break;
+        }
+        bytes.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    Reader { bytes: &bytes }.long().map(Some)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, MyError> {
+    let len = read_long(reader)?.unwrap_or_default();
+    let mut bytes = Vec::new();
+    reader
+        .take(len.max(0) as u64)
+        .read_to_end(&mut bytes)
+        .map_err(read_error)?;
+    if bytes.len() as i64 != len {
+        return Err(
+            report!(MyError::FileReader).attach_printable(fmt_error!("the Avro file is truncated"))
+        );
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn read_rows<R: Read>(reader: R) -> Result<AvroRows<R>, MyError> {
+    // the longs are read a byte at a time
+    let mut reader = BufReader::new(reader);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).map_err(read_error)?;
+    if &magic != MAGIC {
+        return Err(
+            report!(MyError::FileReader).attach_printable(fmt_error!("not an Avro container file"))
+        );
+    }
+    // the metadata: a map of bytes
+    let mut metadata = HashMap::new();
+    loop {
+        let count = match read_long(&mut reader)?.unwrap_or_default() {
+            0 => break,
+            count if count < 0 => {
+                read_long(&mut reader)?;
+                count.unsigned_abs()
+            }
+            count => count as u64,
+        };
+        for _ in 0..count {
+            let key = String::from_utf8_lossy(&read_bytes(&mut reader)?).into_owned();
+            metadata.insert(key, read_bytes(&mut reader)?);
+        }
+    }
+    let mut sync = [0; 16];
+    reader.read_exact(&mut sync).map_err(read_error)?;
+
+    let codec = match metadata.get("avro.codec").map(|codec| codec.as_slice()) {
+        None | Some(b"null") => Codec::Null,
+        Some(b"deflate") => Codec::Deflate,
+        Some(b"snappy") => Codec::Snappy,
+        Some(codec) => {
+            return Err(report!(MyError::FileReader).attach_printable(fmt_error!(
+                "unsupported Avro codec {}",
+                String::from_utf8_lossy(codec)
+            )))
+        }
+    };
+    let schema = metadata.get("avro.schema").ok_or_else(|| {
+        report!(MyError::FileReader).attach_printable(fmt_error!("the Avro file has no schema"))
+    })?;
+    let schema: Json = serde_json::from_slice(schema)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("invalid Avro schema"))
+        .change_context(MyError::FileReader)?;
+    let fields = match Schema::parse(&schema, &mut HashMap::new())? {
+        Schema::Record(fields) => fields,
+        _ => return Err(invalid_schema(fmt_error!("the Avro schema isn't a record"))),
+    };
+    let mut columns = [None; 6];
+    for (column, idx) in COLUMNS.iter().zip(columns.iter_mut()) {
+        *idx = fields.iter().position(|(name, _)| name == column);
+    }
+    if let Some(missing) = COLUMNS[..3]
+        .iter()
+        .zip(columns)
+        .find(|(_, idx)| idx.is_none())
+    {
+        return Err(invalid_schema(fmt_error!(
+            "the Avro schema has no {} field",
+            missing.0
+        )));
+    }
+    Ok(AvroRows {
+        reader,
+        fields: fields.into_iter().map(|(_, schema)| schema).collect(),
+        columns,
+        codec,
+        sync,
+        block: Vec::new(),
+        pos: 0,
+        remaining: 0,
+    })
+}
+
+impl<R: Read> AvroRows<R> {
+    // the next block, decompressed. false at the end of the file
+    fn next_block(&mut self) -> Result<bool, MyError> {
+        let count = match read_long(&mut self.reader)? {
+            Some(count) => count,
+            None => return Ok(false),
+        };
+        let data = read_bytes(&mut self.reader)?;
+        let mut sync = [0; 16];
+        self.reader.read_exact(&mut sync).map_err(read_error)?;
+        if sync != self.sync {
+            return Err(report!(MyError::FileReader)
+                .attach_printable(fmt_error!("the Avro file is corrupt: wrong sync marker")));
+        }
+        self.block = match self.codec {
+            Codec::Null => data,
+            Codec::Deflate => {
+                let mut block = Vec::new();
+                flate2::read::DeflateDecoder::new(data.as_slice())
+                    .read_to_end(&mut block)
+                    .map_err(read_error)?;
+                block
+            }
+            // the block is followed by the CRC32 of the uncompressed data
+            Codec::Snappy => {
+                let corrupt = || {
+                    report!(MyError::FileReader)
+                        .attach_printable(fmt_error!("the Avro file is corrupt: bad snappy block"))
+                };
+                let (compressed, crc) =
+                    data.split_at(data.len().checked_sub(4).ok_or_else(corrupt)?);
+                let block = snap::raw::Decoder::new()
+                    .decompress_vec(compressed)
+                    .map_err(|_| corrupt())?;
+                if crc32fast::hash(&block).to_be_bytes() != crc {
+                    return Err(corrupt());
+                }
+                block
+            }
+        };
+        // as with the items of an array, a row that isn't empty takes at least a byte
+        let rows = count.unsigned_abs();
+        if rows > MAX_ITEMS
+            || (!self.fields.iter().all(Schema::is_empty) && rows > self.block.len() as u64)
+        {
+            return Err(report!(MyError::FileReader).attach_printable(fmt_error!(
+                "the Avro file is corrupt: a block of {} bytes can't have {} rows",
+                self.block.len(),
+                rows
+            )));
+        }
+        self.pos = 0;
+        self.remaining = rows;
+        Ok(true)
+    }
+
+    fn next_row(&mut self) -> Result<Option<InputRow>, MyError> {
+        while self.remaining == 0 {
+            if !self.next_block()? {
+                return Ok(None);
+            }
+        }
+        self.remaining -= 1;
+        let mut reader = Reader {
+            bytes: &self.block[self.pos..],
+        };
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            values.push(reader.datum(field)?);
+        }
+        self.pos = self.block.len() - reader.bytes.len();
+        let values: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|idx| idx.map_or(Value::Null, |idx| values[idx].clone()))
+            .collect();
+        Ok(Some(match transaction(&values) {
+            Some(txn) => InputRow::Txn(txn),
+            None => InputRow::Malformed(values.iter().map(Value::text).collect::<StringRecord>()),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for AvroRows<R> {
+    type Item = Result<InputRow, MyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, transaction_processor::TransactionProcessor};
+    use std::io::Write;
+
+    const SYNC: [u8; 16] = *b"0123456789abcdef";
+
+    // shuffled fields, an unknown field, a timestamp in milliseconds, and an enum type
+    const SCHEMA: &str = r#"{"type": "record", "name": "Txn", "namespace": "payments", "fields": [
+        {"name": "amount", "type": ["null", "double"]},
+        {"name": "memo", "type": {"type": "map", "values": "string"}},
+        {"name": "tx", "type": "long"},
+        {"name": "client", "type": "int"},
+        {"name": "type", "type": {"type": "enum", "name": "TxnType",
+            "symbols": ["deposit", "withdrawal", "dispute", "transfer"]}},
+        {"name": "timestamp", "type": ["null", {"type": "long", "logicalType": "timestamp-millis"}]}
+    ]}"#;
+
+    fn long(n: i64) -> Vec<u8> {
+        let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn bytes(b: &[u8]) -> Vec<u8> {
+        [long(b.len() as i64), b.to_vec()].concat()
+    }
+
+    // (amount, memo, tx, client, type symbol, timestamp)
+    fn datum(row: (Option<f64>, &str, i64, i64, i64, Option<i64>)) -> Vec<u8> {
+        let (amount, memo, tx, client, symbol, timestamp) = row;
+        let amount = match amount {
+            Some(amount) => [long(1), amount.to_le_bytes().to_vec()].concat(),
+            None => long(0),
+        };
+        let memo = match memo {
+            "" => long(0),
+            memo => [long(1), bytes(b"note"), bytes(memo.as_bytes()), long(0)].concat(),
+        };
+        let timestamp = match timestamp {
+            Some(ms) => [long(1), long(ms)].concat(),
+            None => long(0),
+        };
+        [
+            amount,
+            memo,
+            long(tx),
+            long(client),
+            long(symbol),
+            timestamp,
+        ]
+        .concat()
+    }
+
+    // a container file with a block for each slice of rows
+    fn container(schema: &str, codec: &str, blocks: &[&[Vec<u8>]]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend(long(2));
+        file.extend(bytes(b"avro.schema"));
+        file.extend(bytes(schema.as_bytes()));
+        file.extend(bytes(b"avro.codec"));
+        file.extend(bytes(codec.as_bytes()));
+        file.extend(long(0));
+        file.extend(SYNC);
+        for rows in blocks {
+            let data = rows.concat();
+            let data = match codec {
+                "deflate" => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    );
+                    encoder.write_all(&data).unwrap();
+                    encoder.finish().unwrap()
+                }
+                "snappy" => {
+                    let mut compressed = snap::raw::Encoder::new().compress_vec(&data).unwrap();
+                    compressed.extend(crc32fast::hash(&data).to_be_bytes());
+                    compressed
+                }
+                _ => data,
+            };
+            file.extend(long(rows.len() as i64));
+            file.extend(bytes(&data));
+            file.extend(SYNC);
+        }
+        file
+    }
+
+    fn rows() -> Vec<Vec<u8>> {
+        vec![
+            datum((Some(10.0), "salary", 1, 1, 0, Some(1_704_067_200_500))),
+            datum((Some(2.5), "", 2, 1, 1, None)),
+            datum((None, "", 1, 1, 2, None)),
+            // the client is out of range
+            datum((Some(1.0), "", 3, 5_000_000_000, 0, None)),
+            datum((Some(1.0), "", 4, 2, 3, None)),
+        ]
+    }
+
+    #[test]
+    fn test_read_rows() {
+        let rows = rows();
+        let file = container(SCHEMA, "null", &[&rows[..2], &rows[2..]]);
+        let rows: Vec<InputRow> = read_rows(file.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 5);
+        let InputRow::Txn(deposit) = &rows[0] else {
+            panic!("the deposit is malformed");
+        };
+        assert_eq!(deposit.txn_type, TxnType::Deposit);
+        assert_eq!((deposit.client_id, deposit.txn_id), (1, 1));
+        assert_eq!(deposit.amount, Some(10.0.into()));
+        assert_eq!(deposit.timestamp, Some(1704067200));
+        assert!(matches!(&rows[2], InputRow::Txn(txn) if txn.amount.is_none()));
+        let InputRow::Malformed(record) = &rows[3] else {
+            panic!("a client out of range isn't malformed");
+        };
+        assert_eq!(
+            record,
+            &StringRecord::from(vec!["deposit", "5000000000", "3", "1", "", ""])
+        );
+        assert!(matches!(&rows[4], InputRow::Txn(txn) if txn.txn_type == TxnType::Invalid));
+    }
+
+    #[test]
+    fn test_process_avro() {
+        let rows = rows();
+        for codec in ["null", "deflate", "snappy"] {
+            let mut tp = TransactionProcessor::in_memory();
+            tp.process_avro(container(SCHEMA, codec, &[&rows[..]]).as_slice())
+                .unwrap();
+            let state = tp.client_state(1).unwrap().unwrap();
+            assert_eq!(state.available, amt(-2.5), "{}", codec);
+            assert_eq!(state.held, amt(10.0), "{}", codec);
+        }
+
+        let mut tp = TransactionProcessor::in_memory();
+        // a schema without the required fields
+        let schema =
+            r#"{"type": "record", "name": "Txn", "fields": [{"name": "tx", "type": "long"}]}"#;
+        assert!(tp
+            .process_avro(container(schema, "null", &[&[long(1)]]).as_slice())
+            .is_err());
+        // a corrupt block
+        let mut file = container(SCHEMA, "null", &[&rows[..]]);
+        *file.last_mut().unwrap() = b'x';
+        assert!(tp.process_avro(file.as_slice()).is_err());
+        assert!(tp.process_avro(&b"type,client,tx,amount\n"[..]).is_err());
+        // a block that claims more rows than it has bytes
+        let mut file = container(SCHEMA, "null", &[]);
+        file.extend([long(1_000_000), bytes(&rows[0]), SYNC.to_vec()].concat());
+        assert!(tp.process_avro(file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_decimal_amounts() {
+        let schema = r#"{"type": "record", "name": "Txn", "fields": [
+            {"name": "type", "type": "string"},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": {"type": "bytes", "logicalType": "decimal", "precision": 20, "scale": 4}}
+        ]}"#;
+        // more digits than an f64 holds
+        let row = |tx: i64, unscaled: i128| {
+            let be = unscaled.to_be_bytes();
+            [
+                bytes(b"deposit"),
+                long(1),
+                long(tx),
+                bytes(&be[be.len() - 8..]),
+            ]
+            .concat()
+        };
+        let rows = [row(1, 123_456_789_012_345_678), row(2, -5)];
+        let file = container(schema, "null", &[&rows[..]]);
+        let mut values = read_rows(file.as_slice())
+            .unwrap()
+            .map(|row| match row.unwrap() {
+                InputRow::Txn(txn) => txn.amount.unwrap(),
+                InputRow::Malformed(record) => panic!("malformed: {:?}", record),
+            });
+        assert_eq!(values.next().unwrap().as_str(), "12345678901234.5678");
+        assert_eq!(values.next().unwrap().as_str(), "-0.0005");
+        assert_eq!(decimal_text(12300, 0), "12300.");
+        assert_eq!(decimal_text(-12300, 6), "-0.012300");
+    }
+
+    #[test]
+    fn test_item_counts() {
+        let datum = |schema: Schema, bytes: &[u8]| Reader { bytes }.datum(&schema);
+        let longs = || Schema::Array(Box::new(Schema::Long));
+        let nulls = || Schema::Array(Box::new(Schema::Null));
+        // three longs, in two blocks, the second with its size in bytes
+        let array = [
+            long(2),
+            long(1),
+            long(2),
+            long(-1),
+            long(1),
+            long(3),
+            long(0),
+        ]
+        .concat();
+        assert_eq!(datum(longs(), &array).unwrap(), Value::Other);
+        // more longs than there are bytes
+        let array = [long(1_000_000), long(1), long(0)].concat();
+        assert!(datum(longs(), &array).is_err());
+        // nulls take no bytes, but their number is capped
+        let array = [long(1_000_000), long(0)].concat();
+        assert_eq!(datum(nulls(), &array).unwrap(), Value::Other);
+        let array = [long(i64::MAX), long(0)].concat();
+        assert!(datum(nulls(), &array).is_err());
+        let array = [long(MAX_ITEMS as i64), long(1), long(0)].concat();
+        assert!(datum(nulls(), &array).is_err());
+    }
+}
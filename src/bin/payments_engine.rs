@@ -1,68 +1,2173 @@
-use csv::{self, ReaderBuilder};
-use error_stack::Result;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use error_stack::{report, IntoReport, Result, ResultExt};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use payments_engine::async_processor::AsyncTransactionProcessor;
+#[cfg(feature = "grpc")]
+use payments_engine::grpc;
+#[cfg(feature = "kafka")]
+use payments_engine::kafka::{KafkaConfig, KafkaConsumer, PayloadFormat};
+#[cfg(feature = "otlp")]
+use payments_engine::otlp::{self, Telemetry};
+#[cfg(feature = "server")]
+use payments_engine::server;
+#[cfg(feature = "sqlite")]
 use payments_engine::{
-    errors::print_report, errors::*, transaction_processor::TransactionProcessor,
+    adjustment::{Adjustment, AdjustmentReason},
+    aging::{AgingBucket, AgingReport},
+    db::TxnDb,
+    events::EngineEvent,
+    model::{ClientId, TransactionId},
+    policy::DuplicateInputPolicy,
+    report::client_json,
+    schedule::Date,
+    signing::sha256_hex_reader,
+    store::TxnStore,
 };
-use std::{fs, io::BufReader, path::Path, process::ExitCode};
+use payments_engine::{
+    amount::Amount,
+    audit,
+    compression::decompress,
+    config::{ConfigWatcher, EngineConfig},
+    dialect::{parse_char, CsvDialect, InputFormat},
+    encryption::FieldKey,
+    errors::print_report,
+    errors::*,
+    fmt_error,
+    ledger::TrialBalance,
+    limits::Limits,
+    memory::ByteSize,
+    number_format::{AmountUnit, NumberFormat},
+    output,
+    parallel::ParallelProcessor,
+    policy::{CrossClientDisputePolicy, DisputePolicy, LockedAccountPolicy, OverdraftPolicy},
+    rate_limit::{OverloadPolicy, RateLimit, RateLimiter},
+    rejects::{RejectsFormat, RejectsLog},
+    report::ReportFormat,
+    risk::ChargebackThresholds,
+    rounding::RoundingPolicy,
+    schedule::{Rate, RateSchedule, Rates},
+    signing::{sha256_hex, RunManifest, SignatureAlgorithm, SigningKey, VerifyingKey},
+    stats::ProcessingStats,
+    transaction_processor::TransactionProcessor,
+    validate::{validate_csv, Diagnostic},
+};
+#[cfg(feature = "sqlite")]
+use std::io::{Seek, SeekFrom};
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use std::{future::Future, net::SocketAddr};
+#[cfg(feature = "otlp")]
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+#[derive(Parser)]
+#[command(
+    about = "process a CSV file of transactions and print the resulting client accounts",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// check that an audit log hasn't been modified. pass a file written by --audit-log, or --db
+    VerifyAudit {
+        /// an exported audit log
+        file: Option<PathBuf>,
+        /// verify the audit log stored in this SQLite database
+        #[cfg(feature = "sqlite")]
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// check a results file against a signed run manifest written by --manifest
+    Verify {
+        /// the results (the account report) of the run
+        results: PathBuf,
+        /// the run manifest
+        #[arg(long)]
+        manifest: PathBuf,
+        /// the HMAC key, or the hex encoded Ed25519 public key
+        #[arg(long)]
+        key: PathBuf,
+    },
+    /// process the input twice, each time with a fresh scratch store, and check that the sorted reports are identical
+    VerifyDeterminism {
+        /// the CSV files to process, in order
+        #[arg(required = true)]
+        input_files: Vec<PathBuf>,
+        #[arg(long, default_value_t = RoundingPolicy::HalfEven)]
+        rounding: RoundingPolicy,
+    },
+    /// check every record of an input file without processing it. prints each invalid record with its line number,
+    /// its fields and the problem, and fails if there are any
+    Validate {
+        /// the CSV file to check
+        file: PathBuf,
+        #[arg(long, default_value_t = RoundingPolicy::HalfEven)]
+        rounding: RoundingPolicy,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// print the debits and credits per ledger account and check that they net to zero. processes the input files
+    /// with a scratch store, or, with --db, appends them to the database and reports its whole ledger
+    TrialBalance {
+        /// the CSV files to process, in order
+        input_files: Vec<PathBuf>,
+        /// report the ledger stored in this SQLite database
+        #[cfg(feature = "sqlite")]
+        #[arg(long)]
+        db: Option<PathBuf>,
+        /// one line per client account instead of one line for all the available and one for all the held funds
+        #[arg(long)]
+        per_client: bool,
+    },
+    /// data retention: delete deposits and withdrawals older than N days from a database written by --db.
+    /// balances, postings and the audit log are kept
+    #[cfg(feature = "sqlite")]
+    Purge {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        /// keep transactions recorded in the last N days
+        #[arg(long)]
+        older_than_days: u32,
+    },
+    /// report how long the open disputes in a database written by --db have been open, and the ones past the SLA
+    #[cfg(feature = "sqlite")]
+    DisputeAging {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        /// disputes open for more than this many days breach the SLA
+        #[arg(long, default_value_t = 30)]
+        sla_days: u64,
+    },
+    /// erase a client's transaction history from a database written by --db. the balances are kept, and the erasure
+    /// is recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    ForgetClient {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+    },
+    /// reopen a resolved dispute in a database written by --db: the funds are held again. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    ReopenDispute {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+        /// the transaction id of the disputed deposit or withdrawal
+        #[arg(long)]
+        tx: TransactionId,
+    },
+    /// give a client in a database written by --db its own overdraft limit, which takes precedence over --overdraft.
+    /// recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    SetOverdraft {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+        /// how far the client's withdrawals can take its available funds below zero. 0 allows no overdraft
+        #[arg(long, required_unless_present = "clear")]
+        limit: Option<Amount>,
+        /// remove the client's limit: --overdraft applies again
+        #[arg(long, conflicts_with = "limit")]
+        clear: bool,
+    },
+    /// give a client in a database written by --db its own limits, which take precedence over --max-balance,
+    /// --max-deposit, and --max-withdrawal. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    SetLimits {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+        /// the largest total balance a deposit can take the account to
+        #[arg(long, required_unless_present_any = ["max_deposit", "max_withdrawal", "clear"])]
+        max_balance: Option<Amount>,
+        #[arg(long)]
+        max_deposit: Option<Amount>,
+        #[arg(long)]
+        max_withdrawal: Option<Amount>,
+        /// remove the client's limits: the flags of the runs apply again
+        #[arg(long, conflicts_with_all = ["max_balance", "max_deposit", "max_withdrawal"])]
+        clear: bool,
+    },
+    /// unlock an account locked by a chargeback in a database written by --db, and apply the deposits queued by
+    /// --locked-accounts queue-deposits. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    Unlock {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+    },
+    /// manually adjust a client's available funds in a database written by --db. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    Adjust {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        #[arg(long)]
+        client: ClientId,
+        /// positive to credit the client, negative to debit it
+        #[arg(long, allow_hyphen_values = true)]
+        amount: Amount,
+        /// correction, goodwill, fee, write-off or migration
+        #[arg(long)]
+        reason: AdjustmentReason,
+        /// who is making the adjustment
+        #[arg(long)]
+        operator: String,
+        /// allow a negative adjustment to exceed the available funds
+        #[arg(long)]
+        allow_overdraft: bool,
+    },
+    /// list the manual adjustments in a database written by --db
+    #[cfg(feature = "sqlite")]
+    Adjustments {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+    },
+    /// accrue daily interest on the positive available balances of a database written by --db, from the day after
+    /// the last accrual. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    AccrueInterest {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        /// the last day to accrue (YYYY-MM-DD). defaults to today
+        #[arg(long)]
+        through: Option<Date>,
+        /// the yearly interest rate, ex: 0.02 for 2%
+        #[arg(long, required_unless_present = "config")]
+        rate: Option<Rate>,
+        /// take the interest rates with their effective dates from the rate_schedule of a config file
+        #[arg(long, conflicts_with = "rate")]
+        config: Option<PathBuf>,
+    },
+    /// run one read-only SQL statement (ex: a SELECT) against a database written by --db and print the result as
+    /// CSV. the database is opened read-only: statements that write fail
+    #[cfg(feature = "sqlite")]
+    Query {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        sql: String,
+        /// print a JSON array with one object per row instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// print the statement of a client in a database written by --db as CSV: the opening balance, every applied
+    /// deposit, withdrawal, dispute event and adjustment in order with the running balances, and the closing balance
+    #[cfg(feature = "sqlite")]
+    Statement {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        client: ClientId,
+    },
+    /// look up one record of a database written by --db without processing any input
+    #[cfg(feature = "sqlite")]
+    Inspect {
+        #[command(subcommand)]
+        target: InspectTarget,
+    },
+    /// print every deposit, withdrawal, and refund in a database written by --db with its dispute status
+    /// (undisputed, disputed, resolved, or charged_back), sorted by client and transaction id
+    #[cfg(feature = "sqlite")]
+    ExportLedger {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        /// csv, json (an array of objects), or jsonl (one object per line)
+        #[arg(long, default_value_t = ReportFormat::Csv)]
+        format: ReportFormat,
+    },
+    /// recompute every account of a database written by --db from its event log, ex: after the Clients table was
+    /// corrupted, and print the accounts that were corrected. recorded in the audit log
+    #[cfg(feature = "sqlite")]
+    Rebuild {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+    },
+    /// serve an HTTP API: POST /transactions applies one transaction, given as JSON with the names of the CSV columns,
+    /// and returns its outcome. GET /clients and GET /clients/<client> return the accounts
+    #[cfg(feature = "server")]
+    Serve {
+        /// the address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// keep the state in this SQLite database instead of a scratch store
+        #[cfg(feature = "sqlite")]
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// serve the gRPC `Transactions` service of proto/payments_engine.proto: Submit streams transactions in and
+    /// streams back whether each one was accepted or rejected
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        /// the address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        addr: SocketAddr,
+        /// keep the state in this SQLite database instead of a scratch store
+        #[cfg(feature = "sqlite")]
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// consume transactions from a Kafka topic until a transaction fails to process. an offset is committed once its
+    /// transaction has been processed
+    #[cfg(feature = "kafka")]
+    Consume {
+        #[command(flatten)]
+        kafka: KafkaArgs,
+        /// keep the state in this SQLite database instead of a scratch store
+        #[cfg(feature = "sqlite")]
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Subcommand)]
+enum InspectTarget {
+    /// print a client's account, then its open disputes (`tx,type,amount,timestamp,age_days`) as CSV
+    Client {
+        /// the SQLite database
+        #[arg(long)]
+        db: PathBuf,
+        client: ClientId,
+        /// print one JSON object instead: the account with an `open_disputes` array
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[cfg(feature = "kafka")]
+#[derive(clap::Args)]
+struct KafkaArgs {
+    /// the bootstrap servers, ex: localhost:9092
+    #[arg(long)]
+    brokers: String,
+    #[arg(long)]
+    topic: String,
+    /// the consumer group
+    #[arg(long, default_value = "payments_engine")]
+    group: String,
+    /// how the messages are encoded: json or avro
+    #[arg(long, default_value_t = PayloadFormat::Json)]
+    format: PayloadFormat,
+    /// write the report of every account to a numbered file (snapshot-000001.csv, ...) every N seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    snapshot_secs: Option<u64>,
+    /// the directory of the --snapshot-secs files
+    #[arg(long, default_value = "snapshots", requires = "snapshot_secs")]
+    snapshot_dir: PathBuf,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaArgs {
+    fn config(&self) -> KafkaConfig {
+        KafkaConfig {
+            brokers: self.brokers.clone(),
+            topic: self.topic.clone(),
+            group: self.group.clone(),
+            format: self.format,
+            snapshot_interval: self.snapshot_secs.map(Duration::from_secs),
+            snapshot_dir: self.snapshot_dir.clone(),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct DialectArgs {
+    /// the format of the input: auto (the default: comma, tab, or semicolon delimited, detected from the header
+    /// line), csv, tsv, or protobuf (length-delimited Transaction messages of proto/transaction.proto)
+    #[arg(long, default_value_t = InputFormat::Auto)]
+    format: InputFormat,
+    /// the field delimiter of the input: a single character, or tab. ex: ';' for semicolon delimited exports
+    #[arg(long, value_parser = parse_char, conflicts_with = "format")]
+    delimiter: Option<u8>,
+    /// the quote character of the input
+    #[arg(long, default_value = "\"", value_parser = parse_char)]
+    quote: u8,
+    /// read quotes in the input as ordinary characters
+    #[arg(long, conflicts_with = "quote")]
+    no_quoting: bool,
+}
+
+impl DialectArgs {
+    fn dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter.or(self.format.delimiter()),
+            quote: (!self.no_quoting).then_some(self.quote),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct Args {
+    /// the CSV files to process, in order. the accounts carry over from one file to the next. `-` reads standard input
+    input_files: Vec<PathBuf>,
+    /// how amounts are rounded to 4 decimal places: half-even, half-up or truncate
+    #[arg(long, default_value_t = RoundingPolicy::HalfEven)]
+    rounding: RoundingPolicy,
+    /// seed the accounts from the report of a previous run before processing, so daily batches can chain without
+    /// the earlier transactions. every client in it must be new to the store
+    #[arg(long)]
+    initial_balances: Option<PathBuf>,
+    /// report the p50/p95/p99 processing time per transaction to stderr
+    #[arg(long)]
+    latency: bool,
+    /// log a warning for each transaction that takes longer than this many milliseconds, with the time spent in each
+    /// store call. requires RUST_LOG=warn (or lower) to be seen
+    #[arg(long)]
+    slow_txn_ms: Option<u64>,
+    /// report the peak memory usage of the run to stderr (Linux only)
+    #[arg(long)]
+    memory_report: bool,
+    /// stop with an error if the memory usage goes above this, ex: 2G or 512M. close to it, state that can be rebuilt
+    /// is dropped first (Linux only)
+    #[arg(long)]
+    max_memory: Option<ByteSize>,
+    /// write the report of every account to a numbered file (snapshot-000001.csv, ...) after every N transactions,
+    /// applied or rejected. to narrow down where a long run went wrong
+    #[arg(long)]
+    snapshot_every: Option<u64>,
+    /// end of day close: after each input file, write the report of every account to this directory
+    /// (close-001-<file>.csv, ...). the balances carry over to the next file, so one file per business day gives a
+    /// daily close report
+    #[arg(long)]
+    close_dir: Option<PathBuf>,
+    /// the directory of the --snapshot-every files
+    #[arg(long, default_value = "snapshots", requires = "snapshot_every")]
+    snapshot_dir: PathBuf,
+    /// a JSON configuration file, or TOML if its name ends in .toml (rounding, cross_client_disputes, disputes,
+    /// dispute_window_days, overdraft, locked_accounts, max_chargeback_ratio, chargeback_window, rate_schedule, and
+    /// the startup keys db, commit_every, output, number_format, amount_unit). flags given on the command line take precedence over its
+    /// values. the file is checked for changes every second and a new version is applied between two transactions
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// the number format of the amounts in the input and the report: plain (1234.56, the default), en (1,234.56),
+    /// de (1.234,56), fr (1 234,56) or ch (1'234.56). amounts containing a comma must be quoted
+    #[arg(long)]
+    number_format: Option<NumberFormat>,
+    /// the unit of the input amounts: decimal (the default), cents, or ten-thousandths (integer minor units). an
+    /// input whose amount column is named amount_cents is read as cents regardless
+    #[arg(long, default_value_t = AmountUnit::Decimal)]
+    amount_unit: AmountUnit,
+    #[command(flatten)]
+    dialect: DialectArgs,
+    /// what happens to a dispute of another client's deposit or withdrawal: reject (the default) or owner (operator
+    /// mode: apply it to the client that owns the transfer). the number of such disputes is reported to stderr
+    #[arg(long, default_value_t = CrossClientDisputePolicy::Reject)]
+    cross_client_disputes: CrossClientDisputePolicy,
+    /// which transfers can be disputed: both (the default), deposits-only, or withdrawals-only. a dispute of another
+    /// kind is rejected
+    #[arg(long, default_value_t = DisputePolicy::Both)]
+    disputes: DisputePolicy,
+    /// reject the disputes filed more than N days after their deposit or withdrawal, by the timestamp column. a
+    /// dispute without a timestamp is filed when it's processed
+    #[arg(long)]
+    dispute_window_days: Option<u32>,
+    /// how far a withdrawal can take the available funds below zero: reject (the default, no overdraft),
+    /// allow-to-limit:<amount> (ex: allow-to-limit:100), or allow-unlimited. a client's own limit (see set-overdraft)
+    /// takes precedence
+    #[arg(long, default_value_t = OverdraftPolicy::Reject)]
+    overdraft: OverdraftPolicy,
+    /// reject the deposits that would take an account's total balance past this amount. a client's own limit (see
+    /// set-limits) takes precedence. the rejections are appended to the audit log of --db
+    #[arg(long)]
+    max_balance: Option<Amount>,
+    /// reject the deposits larger than this amount
+    #[arg(long)]
+    max_deposit: Option<Amount>,
+    /// reject the withdrawals larger than this amount
+    #[arg(long)]
+    max_withdrawal: Option<Amount>,
+    /// what happens to the transactions of an account locked by a chargeback: reject (the default), audit (reject
+    /// them and append them to the audit log of --db even without --audit-log), or queue-deposits (keep the deposits
+    /// in --db until the account is unlocked, see unlock)
+    #[arg(long, default_value_t = LockedAccountPolicy::Reject)]
+    locked_accounts: LockedAccountPolicy,
+    /// charge this flat fee on every deposit and withdrawal, ex: 0.25. the fees are taken from the available funds,
+    /// refunded if the transfer is charged back, and reported to stderr. replaces the rate_schedule of --config
+    #[arg(long)]
+    flat_fee: Option<Amount>,
+    /// charge this fraction of the amount on every deposit and withdrawal, ex: 0.001 for 0.1%. see --flat-fee
+    #[arg(long)]
+    percent_fee: Option<Rate>,
+    /// limit the transactions of all clients to this many per second: `<rate>` or `<rate>:<burst>`. ex: 1000:5000
+    #[arg(long)]
+    rate_limit: Option<RateLimit>,
+    /// limit the transactions of each client to this many per second: `<rate>` or `<rate>:<burst>`
+    #[arg(long)]
+    client_rate_limit: Option<RateLimit>,
+    /// what happens to a transaction over --rate-limit or --client-rate-limit: shed (reject it, the default) or
+    /// queue (wait until it's allowed). the number of limited transactions per client is reported to stderr
+    #[arg(long, default_value_t = OverloadPolicy::Shed)]
+    on_overload: OverloadPolicy,
+    /// report gaps in the txn_id sequence of deposits and withdrawals to stderr
+    #[arg(long)]
+    check_sequence: bool,
+    /// report clients whose chargebacks exceed this fraction of their deposits (by count or value) to stderr. ex: 0.01
+    #[arg(long)]
+    max_chargeback_ratio: Option<f64>,
+    /// the number of most recent transactions considered by --max-chargeback-ratio
+    #[arg(long, default_value_t = 1000, requires = "max_chargeback_ratio")]
+    chargeback_window: u64,
+    /// report deposits and withdrawals rejected for reusing a txn_id to stderr, highlighting the ones whose amount
+    /// or client differs from the transfer that was applied
+    #[arg(long)]
+    report_duplicates: bool,
+    /// print a summary of the run to stderr: the transactions applied by type, the rejections by reason, the clients
+    /// created, the accounts locked, and the throughput
+    #[arg(long)]
+    summary: bool,
+    /// write every rejected transaction and every input row that fails to parse to this file, with a reason code and
+    /// the original input. JSON lines if the file name ends in .jsonl, CSV otherwise
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+    /// stop with an error at the first input row that fails to parse or transaction that's rejected, naming its
+    /// line, instead of skipping it
+    #[arg(long)]
+    strict: bool,
+    /// at the end of the run, check that the change in the sum of the client totals matches the applied deposits,
+    /// withdrawals, disputes, and chargebacks. warn: report a mismatch on stderr. fail: also exit with an error
+    #[arg(long, value_enum)]
+    reconcile: Option<ReconcileMode>,
+    /// re-verify the account after every applied transaction and abort on the first inconsistency
+    #[arg(long)]
+    check_invariants: bool,
+    /// keep state in this SQLite database across runs. if an earlier run over the same input stopped part way, it's
+    /// resumed
+    #[cfg(feature = "sqlite")]
+    #[arg(long, visible_alias = "db-path")]
+    db: Option<PathBuf>,
+    /// persistent ledger mode: a deposit, withdrawal, or refund whose txn_id is already in --db was applied by an
+    /// earlier run, so it's skipped instead of being rejected as a duplicate. for incremental runs over overlapping
+    /// exports
+    #[cfg(feature = "sqlite")]
+    #[arg(long, requires = "db")]
+    persist: bool,
+    /// process the input on this many threads, with the clients sharded across them (client % threads). each thread
+    /// has its own scratch store and the report rows are sorted by client. a dispute of another shard's transaction
+    /// is rejected as invalid. can't be combined with the options that need the whole run in one store
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "initial_balances", "latency", "slow_txn_ms", "memory_report", "max_memory", "snapshot_every",
+            "close_dir", "config", "rate_limit", "client_rate_limit", "check_sequence", "max_chargeback_ratio",
+            "report_duplicates", "reconcile", "audit_log", "manifest", "rejects", "strict",
+        ]
+    )]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "db"))]
+    threads: u64,
+    /// apply the input rows in store transactions of this many rows. larger batches are faster; with --db an
+    /// interrupted run resumes from the last committed batch
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+    commit_every: u64,
+    /// what happens to an input file whose content was already processed against --db: reject (refuse to run, the
+    /// default), warn (process it again), or skip (warn and go on with the next file). files are recognized by the
+    /// sha256 of their content, not by their path
+    #[cfg(feature = "sqlite")]
+    #[arg(long, default_value_t = DuplicateInputPolicy::Reject, requires = "db")]
+    on_duplicate_input: DuplicateInputPolicy,
+    /// skip, with a warning, an input record whose content (type, client, tx, amount, timestamp, original_tx) was
+    /// already processed. the hashes are kept in --db across runs, so a file that repeats records of an earlier one
+    /// only applies the new ones. the skipped records are rejected as DuplicateRecord
+    #[arg(long)]
+    record_hashes: bool,
+    /// the format of the client report: csv (the default), json (an array of client objects) or jsonl (one client
+    /// object per line). the snapshot and close reports use it too
+    #[arg(long, default_value_t = ReportFormat::Csv)]
+    output_format: ReportFormat,
+    /// round the amounts of the client report to this many decimal places (0 to 4) with --rounding. the balances
+    /// keep 4 decimal places, and so does the report by default
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=4))]
+    report_precision: Option<u8>,
+    /// write the client report to this file instead of stdout. it's written to a temporary file and renamed into
+    /// place, so the file is never left half-written
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// record every transaction in a hash-chained audit log and export it to this file (JSON lines)
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// write a run manifest, with the sha256 of the input and the results, to this file
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// process the input and roll everything back: --db is left unchanged. prints the transactions that would be
+    /// rejected and the malformed rows, as a rejects log (see --rejects), to stdout instead of the client report, and
+    /// the summary of the run (see --summary) to stderr
+    #[arg(long, conflicts_with_all = [
+        "output", "audit_log", "manifest", "rejects", "close_dir", "snapshot_every", "reconcile", "threads",
+    ])]
+    dry_run: bool,
+    /// sign the manifest with the key in this file: an HMAC key, or a hex encoded Ed25519 secret key
+    #[arg(long, requires = "manifest")]
+    sign_key: Option<PathBuf>,
+    /// hmac-sha256 or ed25519
+    #[arg(long, default_value_t = SignatureAlgorithm::HmacSha256)]
+    key_type: SignatureAlgorithm,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ReconcileMode {
+    Warn,
+    Fail,
+}
 
 fn main() -> ExitCode {
-    env_logger::init();
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("error: no input file specified");
-        return ExitCode::FAILURE;
+    // a span is logged when it closes, with the time spent in it
+    let logs = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .with_filter(EnvFilter::from_default_env());
+    let subscriber = tracing_subscriber::registry().with(logs);
+    #[cfg(feature = "otlp")]
+    let telemetry = match Telemetry::from_env() {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("error: failed to set up the OTLP export");
+            print_report(e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // RUST_LOG only filters the logs. the transactions are exported, but not each store call
+    #[cfg(feature = "otlp")]
+    let subscriber = subscriber.with(telemetry.as_ref().map(|telemetry| {
+        telemetry
+            .layer()
+            .with_filter(Targets::new().with_target("payments_engine", tracing::Level::DEBUG))
+    }));
+    subscriber.init();
+
+    let code = run();
+    #[cfg(feature = "otlp")]
+    if let Some(telemetry) = telemetry {
+        if let Err(e) = telemetry.shutdown() {
+            print_report(e);
+        }
     }
+    code
+}
 
-    let input_file = &args[1];
+fn run() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
+    };
 
-    // ensure the item exists
-    let path = Path::new(input_file);
-    if !path.exists() {
-        eprintln!("error: \"{}\" does not exist", input_file);
-        return ExitCode::FAILURE;
+    if let Some(command) = &cli.command {
+        return match command {
+            #[cfg(feature = "sqlite")]
+            Command::VerifyAudit { file, db } => verify_audit(file.as_deref(), db.as_deref()),
+            #[cfg(not(feature = "sqlite"))]
+            Command::VerifyAudit { file } => verify_audit(file.as_deref(), None),
+            Command::Verify {
+                results,
+                manifest,
+                key,
+            } => verify(results, manifest, key),
+            Command::VerifyDeterminism {
+                input_files,
+                rounding,
+            } => verify_determinism(input_files, *rounding),
+            Command::Validate {
+                file,
+                rounding,
+                dialect,
+            } if dialect.format == InputFormat::Protobuf => {
+                eprintln!("error: validate reads CSV input, not {}", dialect.format);
+                ExitCode::FAILURE
+            }
+            Command::Validate {
+                file,
+                rounding,
+                dialect,
+            } => validate(file, dialect.dialect(), *rounding),
+            #[cfg(feature = "sqlite")]
+            Command::TrialBalance {
+                input_files,
+                db,
+                per_client,
+            } => trial_balance(input_files, db.as_deref(), *per_client),
+            #[cfg(not(feature = "sqlite"))]
+            Command::TrialBalance {
+                input_files,
+                per_client,
+            } => trial_balance(input_files, None, *per_client),
+            #[cfg(feature = "sqlite")]
+            Command::Purge {
+                db,
+                older_than_days,
+            } => purge(db, *older_than_days),
+            #[cfg(feature = "sqlite")]
+            Command::DisputeAging { db, sla_days } => dispute_aging(db, *sla_days),
+            #[cfg(feature = "sqlite")]
+            Command::ForgetClient { db, client } => forget_client(db, *client),
+            #[cfg(feature = "sqlite")]
+            Command::ReopenDispute { db, client, tx } => reopen_dispute(db, *client, *tx),
+            #[cfg(feature = "sqlite")]
+            Command::SetOverdraft {
+                db, client, limit, ..
+            } => set_overdraft(db, *client, *limit),
+            #[cfg(feature = "sqlite")]
+            Command::SetLimits {
+                db,
+                client,
+                max_balance,
+                max_deposit,
+                max_withdrawal,
+                clear,
+            } => set_limits(
+                db,
+                *client,
+                (!clear).then_some(Limits {
+                    max_balance: *max_balance,
+                    max_deposit: *max_deposit,
+                    max_withdrawal: *max_withdrawal,
+                }),
+            ),
+            #[cfg(feature = "sqlite")]
+            Command::Unlock { db, client } => unlock(db, *client),
+            #[cfg(feature = "sqlite")]
+            Command::Adjust {
+                db,
+                client,
+                amount,
+                reason,
+                operator,
+                allow_overdraft,
+            } => adjust(
+                db,
+                Adjustment {
+                    client_id: *client,
+                    amount: *amount,
+                    reason: *reason,
+                    operator: operator.clone(),
+                },
+                *allow_overdraft,
+            ),
+            #[cfg(feature = "sqlite")]
+            Command::Adjustments { db } => adjustments(db),
+            #[cfg(feature = "sqlite")]
+            Command::AccrueInterest {
+                db,
+                through,
+                rate,
+                config,
+            } => accrue_interest(
+                db,
+                through.unwrap_or_else(Date::today),
+                *rate,
+                config.as_deref(),
+            ),
+            #[cfg(feature = "sqlite")]
+            Command::Query { db, sql, json } => query(db, sql, *json),
+            #[cfg(feature = "sqlite")]
+            Command::Statement { db, client } => statement(db, *client),
+            #[cfg(feature = "sqlite")]
+            Command::Inspect {
+                target: InspectTarget::Client { db, client, json },
+            } => inspect_client(db, *client, *json),
+            #[cfg(feature = "sqlite")]
+            Command::ExportLedger { db, format } => export_ledger(db, *format),
+            #[cfg(feature = "sqlite")]
+            Command::Rebuild { db } => rebuild(db),
+            #[cfg(all(feature = "server", feature = "sqlite"))]
+            Command::Serve { addr, db } => run_server(db.as_deref(), |p| server::serve(*addr, p)),
+            #[cfg(all(feature = "server", not(feature = "sqlite")))]
+            Command::Serve { addr } => run_server(None, |p| server::serve(*addr, p)),
+            #[cfg(all(feature = "grpc", feature = "sqlite"))]
+            Command::ServeGrpc { addr, db } => run_server(db.as_deref(), |p| grpc::serve(*addr, p)),
+            #[cfg(all(feature = "grpc", not(feature = "sqlite")))]
+            Command::ServeGrpc { addr } => run_server(None, |p| grpc::serve(*addr, p)),
+            #[cfg(all(feature = "kafka", feature = "sqlite"))]
+            Command::Consume { kafka, db } => consume(kafka.config(), db.as_deref()),
+            #[cfg(all(feature = "kafka", not(feature = "sqlite")))]
+            Command::Consume { kafka } => consume(kafka.config(), None),
+        };
     }
 
-    // ensure the item is a file
-    if !path.is_file() {
-        eprintln!("error: {} is not a file", input_file);
+    let mut args = cli.args;
+    if args.input_files.is_empty() {
+        eprintln!("error: no input file specified");
         return ExitCode::FAILURE;
     }
+    if args.manifest.is_some() && args.input_files.len() > 1 {
+        eprintln!("error: --manifest takes a single input file");
+        return ExitCode::FAILURE;
+    }
+    let config = match load_config(&mut args, &matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: invalid configuration");
+            print_report(e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    // attempt to open the file
-    let open_res = fs::OpenOptions::new()
-        .read(true)
-        .write(false)
-        .create(false)
-        .open(input_file);
+    let mut inputs = Vec::new();
+    for input_file in &args.input_files {
+        if input_file.as_os_str() == "-" {
+            match stdin_file() {
+                Ok(file) => inputs.push((input_file.as_path(), file)),
+                Err(e) => {
+                    eprintln!("failed to open standard input: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            continue;
+        }
+
+        // ensure the item exists
+        if !input_file.exists() {
+            eprintln!("error: \"{}\" does not exist", input_file.display());
+            return ExitCode::FAILURE;
+        }
 
-    match open_res {
-        Ok(input_file) => match process_transactions(input_file) {
+        // ensure the item is a file
+        if !input_file.is_file() {
+            eprintln!("error: {} is not a file", input_file.display());
+            return ExitCode::FAILURE;
+        }
+
+        // attempt to open the file
+        let open_res = fs::OpenOptions::new()
+            .read(true)
+            .write(false)
+            .create(false)
+            .open(input_file);
+
+        match open_res {
+            Ok(file) => inputs.push((input_file.as_path(), file)),
             Err(e) => {
-                print_report(e);
-                ExitCode::FAILURE
+                eprintln!("failed to open file: {}", e);
+                return ExitCode::FAILURE;
             }
-            Ok(_) => ExitCode::SUCCESS,
-        },
+        }
+    }
+
+    match process_transactions(inputs, &args, config) {
         Err(e) => {
-            eprintln!("failed to open file: {}", e);
+            print_report(e);
             ExitCode::FAILURE
         }
+        Ok(_) => ExitCode::SUCCESS,
+    }
+}
+
+// --config: the file fills in the flags that weren't given on the command line. the policies given on the command
+// line also override every reload of the file
+fn load_config(
+    args: &mut Args,
+    matches: &ArgMatches,
+) -> Result<Option<(ConfigWatcher, EngineConfig)>, MyError> {
+    let path = match &args.config {
+        Some(path) => path.clone(),
+        None => return Ok(None),
+    };
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let (mut watcher, mut config) = ConfigWatcher::new(&path, Duration::from_secs(1))?;
+    if let Some(rows) = config.commit_every.filter(|_| !given("commit_every")) {
+        args.commit_every = rows;
+    }
+    if let Some(unit) = config.amount_unit.filter(|_| !given("amount_unit")) {
+        args.amount_unit = unit;
+    }
+    if args.number_format.is_none() {
+        args.number_format = config.number_format;
+    }
+    if args.output.is_none() {
+        args.output.clone_from(&config.output);
+    }
+    #[cfg(feature = "sqlite")]
+    if args.db.is_none() {
+        args.db.clone_from(&config.db);
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if config.db.is_some() {
+        return Err(report!(MyError::Config).attach_printable(fmt_error!(
+            "the db key of {} requires the sqlite feature",
+            path.display()
+        )));
+    }
+
+    let overrides = EngineConfig {
+        rounding: given("rounding").then_some(args.rounding),
+        cross_client_disputes: given("cross_client_disputes").then_some(args.cross_client_disputes),
+        disputes: given("disputes").then_some(args.disputes),
+        dispute_window_days: args.dispute_window_days,
+        overdraft: given("overdraft").then_some(args.overdraft),
+        limits: limits(args),
+        locked_accounts: given("locked_accounts").then_some(args.locked_accounts),
+        rate_schedule: fee_schedule(args)?,
+        chargeback_thresholds: args.max_chargeback_ratio.map(|ratio| ChargebackThresholds {
+            window: args.chargeback_window,
+            max_count_ratio: ratio,
+            max_value_ratio: ratio,
+        }),
+        ..Default::default()
+    };
+    config.merge(&overrides);
+    watcher.set_overrides(overrides);
+    Ok(Some((watcher, config)))
+}
+
+fn process_transactions(
+    inputs: Vec<(&Path, fs::File)>,
+    args: &Args,
+    config: Option<(ConfigWatcher, EngineConfig)>,
+) -> Result<(), MyError> {
+    let start = Instant::now();
+    if args.threads > 1 {
+        return process_parallel(inputs, args, start);
+    }
+    #[cfg(feature = "sqlite")]
+    let mut processor = match &args.db {
+        Some(path) => TransactionProcessor::with_store(open_db(path)?),
+        None => scratch_processor()?,
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let mut processor = scratch_processor()?;
+    configure(&mut processor, args)?;
+    #[cfg(feature = "sqlite")]
+    processor.set_duplicate_input_policy(args.on_duplicate_input);
+    #[cfg(feature = "sqlite")]
+    processor.set_skip_seen_txns(args.persist);
+    if args.check_sequence {
+        processor.enable_sequence_check();
+    }
+    if args.report_duplicates {
+        processor.enable_duplicate_report();
+    }
+    if let Some(path) = &args.rejects {
+        processor.set_rejects_log(RejectsLog::create(path)?);
+    }
+    if let Some(ratio) = args.max_chargeback_ratio {
+        processor.enable_chargeback_monitor(ChargebackThresholds {
+            window: args.chargeback_window,
+            max_count_ratio: ratio,
+            max_value_ratio: ratio,
+        });
+    }
+    if args.rate_limit.is_some() || args.client_rate_limit.is_some() {
+        let mut limiter = RateLimiter::new(args.on_overload);
+        if let Some(limit) = args.rate_limit {
+            limiter.set_global_limit(limit);
+        }
+        if let Some(limit) = args.client_rate_limit {
+            limiter.set_client_limit(limit);
+        }
+        processor.set_rate_limiter(limiter);
+    }
+    if let Some((watcher, config)) = config {
+        processor.apply_config(&config);
+        processor.watch_config(watcher);
+    }
+    processor.set_field_key(FieldKey::from_env()?);
+    if args.audit_log.is_some() {
+        processor.enable_audit_log()?;
+    }
+    if args.latency || args.slow_txn_ms.is_some() {
+        processor.enable_latency_tracking(args.slow_txn_ms.map(Duration::from_millis));
+    }
+    if args.memory_report || args.max_memory.is_some() {
+        processor.enable_memory_tracking(args.max_memory);
+    }
+    if let Some(every) = args.snapshot_every {
+        processor.enable_snapshots(&args.snapshot_dir, every)?;
+    }
+    if args.dry_run {
+        return dry_run(processor, &inputs, args, start);
+    }
+    if let Some(path) = &args.initial_balances {
+        load_initial_balances(&mut processor, path)?;
+    }
+    if args.reconcile.is_some() {
+        processor.enable_reconciliation()?;
+    }
+
+    // process the input files, skipping records with invalid formats unless --strict
+    if let Some(dir) = &args.close_dir {
+        fs::create_dir_all(dir)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create {}", dir.display()))
+            .change_context(MyError::Output)?;
+    }
+    for (idx, (input_path, input_file)) in inputs.iter().enumerate() {
+        tracing::debug!(input = %input_path.display(), "processing");
+        process_input(&mut processor, input_path, input_file, args)
+            .attach_printable_lazy(|| fmt_error!("while processing {}", input_path.display()))?;
+        // the balances carry over to the next file: each close report is the opening of the next day
+        if let Some(dir) = &args.close_dir {
+            let stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = format!(
+                "close-{:03}-{}.{}",
+                idx + 1,
+                stem,
+                args.output_format.extension()
+            );
+            processor.write_report_file(&dir.join(name))?;
+        }
+    }
+    processor.flush_rejects_log()?;
+    #[cfg(feature = "otlp")]
+    {
+        let stats = processor.stats().clone();
+        otlp::observe_stats(move || stats.clone());
+    }
+    match &args.manifest {
+        Some(path) => {
+            // the manifest needs the exact bytes that were written
+            let mut results = Vec::new();
+            processor.write_report(&mut results)?;
+            let write_results = |writer: &mut dyn Write| {
+                writer
+                    .write_all(&results)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to write the results"))
+                    .change_context(MyError::Output)
+            };
+            match &args.output {
+                Some(file) => output::write_atomically(file, |w| write_results(w))?,
+                None => write_results(&mut io::stdout().lock())?,
+            }
+            write_manifest(inputs[0].0, &results, path, args)?;
+        }
+        None => match &args.output {
+            Some(file) => processor.write_report_file(file)?,
+            None => processor.display()?,
+        },
+    }
+
+    if let Some(path) = &args.audit_log {
+        let file = fs::File::create(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create {}", path.display()))
+            .change_context(MyError::Output)?;
+        processor.write_audit_log(BufWriter::new(file))?;
+    }
+
+    report_cross_client_disputes(processor.cross_client_disputes(), args);
+    report_fees(processor.stats());
+    if args.summary {
+        eprintln!("{}", processor.stats().summary(start.elapsed()));
+    }
+    if let Some(latency) = processor.latency().filter(|_| args.latency) {
+        eprintln!("latency: {}", latency);
+    }
+    if args.memory_report {
+        match processor.peak_memory() {
+            Some(peak) => eprintln!("memory: peak resident {}", peak),
+            None => eprintln!("memory: not available on this platform"),
+        }
+    }
+    if let Some(limited) = processor.rate_limited() {
+        let outcome = match args.on_overload {
+            OverloadPolicy::Shed => "shed",
+            OverloadPolicy::Queue => "delayed",
+        };
+        for (client_id, count) in limited {
+            eprintln!(
+                "rate limit: client {}: {} transaction(s) {}",
+                client_id, count, outcome
+            );
+        }
     }
+    // the gaps don't affect balances. keep them out of the account report on stdout
+    if let Some(gaps) = processor.sequence_gaps() {
+        let missing: u64 = gaps.iter().map(|gap| gap.missing()).sum();
+        eprintln!(
+            "sequence check: {} gap(s), {} missing txn_id(s)",
+            gaps.len(),
+            missing
+        );
+        for gap in gaps {
+            eprintln!("missing txn_id: {}", gap);
+        }
+    }
+    if let Some(duplicates) = processor.duplicate_report() {
+        let differing = duplicates.iter().filter(|d| d.differs()).count();
+        eprintln!(
+            "duplicates: {} txn_id(s) seen more than once, {} with differing amounts or clients",
+            duplicates.len(),
+            differing
+        );
+        for duplicate in duplicates {
+            eprintln!("{}", duplicate);
+        }
+    }
+    if let Some(reconciliation) = processor.reconcile()? {
+        if reconciliation.is_balanced() {
+            eprintln!("reconciliation ok: {}", reconciliation);
+        } else {
+            eprintln!(
+                "reconciliation MISMATCH (off by {}): {}",
+                reconciliation.difference(),
+                reconciliation
+            );
+            if args.reconcile == Some(ReconcileMode::Fail) {
+                return Err(report!(MyError::Invariant).attach_printable(fmt_error!(
+                    "the run doesn't reconcile: {}",
+                    reconciliation
+                )));
+            }
+        }
+    }
+    if let Some(flagged) = processor.chargeback_risk_report() {
+        eprintln!(
+            "chargeback ratio: {} client(s) above the threshold",
+            flagged.len()
+        );
+        for risk in flagged {
+            eprintln!("{}", risk);
+        }
+    }
+    Ok(())
 }
 
-fn process_transactions(input_file: fs::File) -> Result<(), MyError> {
-    let mut processor = TransactionProcessor::new()?;
+fn limits(args: &Args) -> Limits {
+    Limits {
+        max_balance: args.max_balance,
+        max_deposit: args.max_deposit,
+        max_withdrawal: args.max_withdrawal,
+    }
+}
 
-    // process the input file, skippipping records with invalid formats.
-    let reader = BufReader::new(input_file);
-    let mut csv_reader = ReaderBuilder::new().from_reader(reader);
-    for mut string_record in csv_reader.records().flatten() {
-        string_record.trim();
-        // deserialize it, skip invalid formats
-        if let Ok(txn) = string_record.deserialize(None) {
-            processor.process(txn)?;
+// the settings that a parallel run shares with a single processor
+fn configure(processor: &mut TransactionProcessor, args: &Args) -> Result<(), MyError> {
+    processor.set_rounding_policy(args.rounding);
+    processor.set_cross_client_dispute_policy(args.cross_client_disputes);
+    processor.set_dispute_policy(args.disputes);
+    processor.set_dispute_window(args.dispute_window_days);
+    processor.set_overdraft_policy(args.overdraft);
+    processor.set_limits(limits(args));
+    processor.set_locked_account_policy(args.locked_accounts);
+    processor.set_commit_every(args.commit_every);
+    if let Some(format) = args.number_format {
+        processor.set_number_format(format);
+    }
+    processor.set_amount_unit(args.amount_unit);
+    processor.set_csv_dialect(args.dialect.dialect());
+    processor.set_report_format(args.output_format);
+    if let Some(places) = args.report_precision {
+        processor.set_report_precision(places.into());
+    }
+    processor.set_strict(args.strict);
+    processor.set_record_hashes(args.record_hashes);
+    if args.check_invariants {
+        processor.enable_invariant_checks();
+    }
+    if let Some(schedule) = fee_schedule(args)? {
+        processor.set_rate_schedule(schedule);
+    }
+    Ok(())
+}
+
+// --flat-fee and --percent-fee: the same fees on every day
+fn fee_schedule(args: &Args) -> Result<Option<RateSchedule>, MyError> {
+    if args.flat_fee.is_none() && args.percent_fee.is_none() {
+        return Ok(None);
+    }
+    RateSchedule::constant(Rates {
+        flat_fee: args.flat_fee.unwrap_or_default(),
+        percent_fee: args.percent_fee.unwrap_or_default(),
+        ..Default::default()
+    })
+    .map(Some)
+}
+
+fn load_initial_balances(processor: &mut TransactionProcessor, path: &Path) -> Result<(), MyError> {
+    let file = fs::File::open(path)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to open {}", path.display()))
+        .change_context(MyError::FileReader)?;
+    let seeded = processor.load_initial_balances(BufReader::new(file))?;
+    tracing::debug!(seeded, "loaded the initial balances");
+    Ok(())
+}
+
+// --dry-run: the initial balances and the input files are applied in one store transaction that is rolled back
+fn dry_run(
+    mut processor: TransactionProcessor,
+    inputs: &[(&Path, fs::File)],
+    args: &Args,
+    start: Instant,
+) -> Result<(), MyError> {
+    processor.set_rejects_log(RejectsLog::new(io::stdout(), RejectsFormat::Csv)?);
+    processor.dry_run(|processor| {
+        if let Some(path) = &args.initial_balances {
+            load_initial_balances(processor, path)?;
         }
+        for (input_path, input_file) in inputs {
+            tracing::debug!(input = %input_path.display(), "processing");
+            #[cfg(feature = "parquet")]
+            if let Some(file) = file_with_extension(input_path, input_file, "parquet")? {
+                processor.process_parquet(file)?;
+                continue;
+            }
+            #[cfg(feature = "avro")]
+            if let Some(file) = file_with_extension(input_path, input_file, "avro")? {
+                processor.process_avro(file)?;
+                continue;
+            }
+            if args.dialect.format == InputFormat::Protobuf {
+                processor.process_protobuf(decompress(input_path, input_file)?)?;
+                continue;
+            }
+            processor
+                .process_csv(decompress(input_path, input_file)?)
+                .attach_printable_lazy(|| {
+                    fmt_error!("while processing {}", input_path.display())
+                })?;
+        }
+        processor.flush_rejects_log()
+    })?;
+    eprintln!("{}", processor.stats().summary(start.elapsed()));
+    Ok(())
+}
+
+// --threads: the clients are sharded across worker threads, each with a scratch store
+fn process_parallel(
+    inputs: Vec<(&Path, fs::File)>,
+    args: &Args,
+    start: Instant,
+) -> Result<(), MyError> {
+    let mut parallel = ParallelProcessor::new(args.threads as usize, || {
+        let mut processor = scratch_processor()?;
+        configure(&mut processor, args)?;
+        Ok(processor)
+    })?;
+    for (input_path, input_file) in &inputs {
+        tracing::debug!(input = %input_path.display(), "processing");
+        #[cfg(feature = "parquet")]
+        if let Some(file) = file_with_extension(input_path, input_file, "parquet")? {
+            parallel.process_parquet(file)?;
+            continue;
+        }
+        #[cfg(feature = "avro")]
+        if let Some(file) = file_with_extension(input_path, input_file, "avro")? {
+            parallel.process_avro(file)?;
+            continue;
+        }
+        if args.dialect.format == InputFormat::Protobuf {
+            parallel.process_protobuf(decompress(input_path, input_file)?)?;
+            continue;
+        }
+        parallel.process_csv(decompress(input_path, input_file)?)?;
+    }
+    let shards = parallel.finish()?;
+    #[cfg(feature = "otlp")]
+    {
+        let stats = shards.stats();
+        otlp::observe_stats(move || stats.clone());
+    }
+    match &args.output {
+        Some(file) => shards.write_report_file(file)?,
+        None => shards.display()?,
+    }
+    report_cross_client_disputes(shards.cross_client_disputes(), args);
+    report_fees(&shards.stats());
+    if args.summary {
+        eprintln!("{}", shards.stats().summary(start.elapsed()));
     }
-    processor.display()?;
     Ok(())
 }
+
+fn report_cross_client_disputes(count: u64, args: &Args) {
+    if count > 0 {
+        let outcome = match args.cross_client_disputes {
+            CrossClientDisputePolicy::Reject => "rejected",
+            CrossClientDisputePolicy::Owner => "applied to the owner",
+        };
+        eprintln!(
+            "cross-client disputes: {} dispute(s) referenced another client's transaction ({})",
+            count, outcome
+        );
+    }
+}
+
+// the fees go to the operator's fee account, not to a client: they're reported apart from the account report
+fn report_fees(stats: &ProcessingStats) {
+    if stats.fees_charged.is_positive() {
+        eprintln!(
+            "fees: {} charged, {} refunded on chargebacks, {} collected",
+            stats.fees_charged,
+            stats.fees_refunded,
+            stats.fees_collected()
+        );
+    }
+}
+
+// a persistent database. the adjustments are encrypted with the field key in the environment, if it's set
+#[cfg(feature = "sqlite")]
+fn open_db(path: &Path) -> Result<TxnDb, MyError> {
+    let mut db = TxnDb::open(&path.to_string_lossy())?;
+    db.set_field_key(FieldKey::from_env()?);
+    Ok(db)
+}
+
+// a processor with a new, empty store
+fn scratch_processor() -> Result<TransactionProcessor, MyError> {
+    #[cfg(feature = "sqlite")]
+    return TransactionProcessor::new();
+    #[cfg(not(feature = "sqlite"))]
+    Ok(TransactionProcessor::in_memory())
+}
+
+// the report of one run, with the client rows sorted
+fn sorted_report(input_files: &[PathBuf], rounding: RoundingPolicy) -> Result<Vec<u8>, MyError> {
+    let mut processor = scratch_processor()?;
+    processor.set_rounding_policy(rounding);
+    for path in input_files {
+        let file = fs::File::open(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to open {}", path.display()))
+            .change_context(MyError::FileReader)?;
+        processor.process_csv(decompress(path, file)?)?;
+    }
+    let mut report = Vec::new();
+    processor.write_report(&mut report)?;
+
+    let report = String::from_utf8_lossy(&report).into_owned();
+    let mut lines = report.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_unstable();
+    let mut sorted = String::new();
+    for line in std::iter::once(header).chain(rows) {
+        sorted.push_str(line);
+        sorted.push('\n');
+    }
+    Ok(sorted.into_bytes())
+}
+
+fn verify_determinism(input_files: &[PathBuf], rounding: RoundingPolicy) -> ExitCode {
+    let res = (|| -> Result<(Vec<u8>, Vec<u8>), MyError> {
+        Ok((
+            sorted_report(input_files, rounding)?,
+            sorted_report(input_files, rounding)?,
+        ))
+    })();
+    let (first, second) = match res {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("error: failed to process the input");
+            print_report(e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if first == second {
+        println!(
+            "deterministic: both runs produced the same {} byte report (sha256 {})",
+            first.len(),
+            sha256_hex(&first)
+        );
+        return ExitCode::SUCCESS;
+    }
+    eprintln!("error: the two runs produced different reports");
+    let (first, second) = (
+        String::from_utf8_lossy(&first),
+        String::from_utf8_lossy(&second),
+    );
+    let mismatches = first.lines().zip(second.lines()).filter(|(a, b)| a != b);
+    for (a, b) in mismatches {
+        eprintln!("- {}\n+ {}", a, b);
+    }
+    ExitCode::FAILURE
+}
+
+fn validate(path: &Path, dialect: CsvDialect, rounding: RoundingPolicy) -> ExitCode {
+    let res = (|| -> Result<(u64, Vec<Diagnostic>), MyError> {
+        let file = fs::File::open(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to open {}", path.display()))
+            .change_context(MyError::FileReader)?;
+        validate_csv(decompress(path, file)?, dialect, rounding)
+    })();
+
+    match res {
+        Ok((records, diagnostics)) => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+            eprintln!("{} record(s), {} error(s)", records, diagnostics.len());
+            if diagnostics.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("error: failed to read {}", path.display());
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn trial_balance(input_files: &[PathBuf], db: Option<&Path>, per_client: bool) -> ExitCode {
+    let res = (|| -> Result<TrialBalance, MyError> {
+        let mut processor = match db {
+            #[cfg(feature = "sqlite")]
+            Some(path) => TransactionProcessor::with_store(open_db(path)?),
+            _ => scratch_processor()?,
+        };
+        for path in input_files {
+            let file = fs::File::open(path)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to open {}", path.display()))
+                .change_context(MyError::FileReader)?;
+            processor.process_csv(decompress(path, file)?)?;
+        }
+        Ok(processor.ledger()?.trial_balance(per_client))
+    })();
+
+    match res {
+        Ok(trial_balance) => {
+            print!("{}", trial_balance);
+            if trial_balance.is_balanced() {
+                ExitCode::SUCCESS
+            } else {
+                eprintln!("error: the debits and credits don't net to zero");
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("error: failed to build the trial balance");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// without SQLite every input goes through process_csv
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn process_input(
+    processor: &mut TransactionProcessor,
+    input_path: &Path,
+    input_file: &fs::File,
+    args: &Args,
+) -> Result<(), MyError> {
+    #[cfg(feature = "sqlite")]
+    if args.db.is_some() {
+        let input_sha256 = hash_input(input_path, input_file)?;
+        if let Some(run) = processor.check_input(&input_sha256)? {
+            eprintln!(
+                "duplicate input: {} has the same content as {}, which was already processed",
+                input_path.display(),
+                run.input
+            );
+            if args.on_duplicate_input == DuplicateInputPolicy::Skip {
+                eprintln!("skipping {}", input_path.display());
+                return Ok(());
+            }
+        }
+        // Parquet, Avro, and protobuf input isn't resumable
+        #[cfg(feature = "parquet")]
+        if let Some(file) = file_with_extension(input_path, input_file, "parquet")? {
+            processor.process_parquet(file)?;
+            return processor.record_input(&input_path.display().to_string(), &input_sha256);
+        }
+        #[cfg(feature = "avro")]
+        if let Some(file) = file_with_extension(input_path, input_file, "avro")? {
+            processor.process_avro(file)?;
+            return processor.record_input(&input_path.display().to_string(), &input_sha256);
+        }
+        if args.dialect.format == InputFormat::Protobuf {
+            processor.process_protobuf(decompress(input_path, input_file)?)?;
+            return processor.record_input(&input_path.display().to_string(), &input_sha256);
+        }
+        processor.process_csv_resumable(
+            decompress(input_path, input_file)?,
+            &run_id(input_path, input_file),
+        )?;
+        return processor.record_input(&input_path.display().to_string(), &input_sha256);
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(file) = file_with_extension(input_path, input_file, "parquet")? {
+        return processor.process_parquet(file);
+    }
+    #[cfg(feature = "avro")]
+    if let Some(file) = file_with_extension(input_path, input_file, "avro")? {
+        return processor.process_avro(file);
+    }
+    if args.dialect.format == InputFormat::Protobuf {
+        return processor.process_protobuf(decompress(input_path, input_file)?);
+    }
+    processor.process_csv(decompress(input_path, input_file)?)
+}
+
+// the input file if it has the extension of a columnar format, Parquet or Avro. they have their own compression, so
+// they aren't decompressed
+#[cfg(any(feature = "avro", feature = "parquet"))]
+fn file_with_extension(
+    input_path: &Path,
+    input_file: &fs::File,
+    extension: &str,
+) -> Result<Option<fs::File>, MyError> {
+    let matches = input_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension));
+    if !matches {
+        return Ok(None);
+    }
+    input_file
+        .try_clone()
+        .map(Some)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to open {}", input_path.display()))
+        .change_context(MyError::FileReader)
+}
+
+// standard input as a file, for the input file `-`. it can't be rewound, so it can't be hashed for --db
+fn stdin_file() -> io::Result<fs::File> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        Ok(io::stdin().as_fd().try_clone_to_owned()?.into())
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsHandle;
+        Ok(io::stdin().as_handle().try_clone_to_owned()?.into())
+    }
+    #[cfg(not(any(unix, windows)))]
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// identifies the input across restarts: the same file with the same length is the same run
+#[cfg(feature = "sqlite")]
+fn run_id(input_path: &Path, input_file: &fs::File) -> String {
+    let path = fs::canonicalize(input_path).unwrap_or_else(|_| input_path.to_path_buf());
+    let len = input_file.metadata().map(|m| m.len()).unwrap_or(0);
+    format!("{}:{}", path.display(), len)
+}
+
+// the sha256 of the input's content. the file is rewound so it can be processed next
+#[cfg(feature = "sqlite")]
+fn hash_input(input_path: &Path, mut input_file: &fs::File) -> Result<String, MyError> {
+    sha256_hex_reader(input_file)
+        .and_then(|hash| input_file.seek(SeekFrom::Start(0)).map(|_| hash))
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read {}", input_path.display()))
+        .change_context(MyError::FileReader)
+}
+
+fn verify_audit(file: Option<&Path>, db: Option<&Path>) -> ExitCode {
+    let res = match (file, db) {
+        (Some(path), _) => fs::File::open(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to open {}", path.display()))
+            .change_context(MyError::FileReader)
+            .and_then(|f| audit::verify_file(BufReader::new(f))),
+        #[cfg(feature = "sqlite")]
+        (None, Some(path)) => {
+            open_db(path).and_then(|db| TransactionProcessor::with_store(db).verify_audit_log())
+        }
+        _ => {
+            eprintln!("error: no audit log specified");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match res {
+        Ok(verifier) => {
+            println!(
+                "audit log ok: {} entries, head {}",
+                verifier.verified(),
+                verifier.head()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: audit log verification failed");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn purge(db: &Path, days: u32) -> ExitCode {
+    let res = open_db(db).and_then(|mut db| db.purge_older_than(days));
+    match res {
+        Ok(purged) => {
+            println!("purged {} balance transfer(s)", purged);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: purge failed");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn dispute_aging(db: &Path, sla_days: u64) -> ExitCode {
+    let res = open_db(db).and_then(|db| db.open_dispute_ages());
+    let ages = match res {
+        Ok(ages) => ages,
+        Err(e) => {
+            eprintln!("error: failed to read the open disputes");
+            print_report(e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = AgingReport::new(&ages, sla_days);
+    println!("open disputes: {}", ages.len());
+    for bucket in AgingBucket::ALL {
+        println!("{}: {}", bucket, report.count(bucket));
+    }
+    if report.unknown > 0 {
+        println!("unknown age: {}", report.unknown);
+    }
+    println!(
+        "SLA breaches (> {} days): {}",
+        sla_days,
+        report.breaches.len()
+    );
+    for dispute in &report.breaches {
+        println!(
+            "client {} txn {}: open for {} days",
+            dispute.client_id,
+            dispute.txn_id,
+            dispute.age_days.unwrap_or_default()
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(feature = "sqlite")]
+fn forget_client(db: &Path, client_id: ClientId) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.forget_client(client_id)
+    });
+    match res {
+        Ok(deleted) => {
+            println!(
+                "forgot client {}: {} balance transfer(s) deleted",
+                client_id, deleted
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to forget client {}", client_id);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn reopen_dispute(db: &Path, client_id: ClientId, txn_id: TransactionId) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.reopen_dispute(client_id, txn_id)
+    });
+    match res {
+        Ok(_) => {
+            println!(
+                "reopened the dispute of txn {} for client {}",
+                txn_id, client_id
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!(
+                "error: failed to reopen the dispute of txn {} for client {}",
+                txn_id, client_id
+            );
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn set_overdraft(db: &Path, client_id: ClientId, limit: Option<Amount>) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.set_overdraft_limit(client_id, limit)
+    });
+    match res {
+        Ok(()) => {
+            match limit {
+                Some(limit) => println!("client {}: overdraft limit {}", client_id, limit),
+                None => println!("client {}: overdraft limit removed", client_id),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!(
+                "error: failed to set the overdraft limit of client {}",
+                client_id
+            );
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn set_limits(db: &Path, client_id: ClientId, limits: Option<Limits>) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.set_client_limits(client_id, limits)
+    });
+    match res {
+        Ok(()) => {
+            match limits {
+                Some(limits) => println!("client {}: {}", client_id, limits),
+                None => println!("client {}: limits removed", client_id),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to set the limits of client {}", client_id);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn unlock(db: &Path, client_id: ClientId) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.unlock_account(client_id)
+    });
+    match res {
+        Ok(events) => {
+            let applied = events
+                .iter()
+                .filter(|e| matches!(e, EngineEvent::FundsDeposited { .. }))
+                .count();
+            let rejected = events.iter().filter(|e| e.is_rejection()).count();
+            println!(
+                "unlocked client {}: {} queued deposit(s) applied, {} rejected",
+                client_id, applied, rejected
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to unlock client {}", client_id);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn adjust(db: &Path, adjustment: Adjustment, allow_overdraft: bool) -> ExitCode {
+    let client_id = adjustment.client_id;
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.adjust(adjustment, allow_overdraft)?;
+        processor.client_state(client_id)
+    });
+    match res {
+        Ok(Some(state)) => {
+            println!("client,available,held,total,locked");
+            println!("{}", state);
+            ExitCode::SUCCESS
+        }
+        Ok(None) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: failed to adjust client {}", client_id);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn accrue_interest(
+    db: &Path,
+    through: Date,
+    rate: Option<Rate>,
+    config: Option<&Path>,
+) -> ExitCode {
+    let schedule = match (rate, config) {
+        (Some(rate), _) => RateSchedule::constant(Rates {
+            interest_rate: rate,
+            ..Default::default()
+        }),
+        (None, Some(config)) => {
+            EngineConfig::load(config).map(|config| config.rate_schedule.unwrap_or_default())
+        }
+        (None, None) => Ok(RateSchedule::default()),
+    };
+    let res = schedule.and_then(|schedule| {
+        let db = open_db(db)?;
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.set_rate_schedule(schedule);
+        processor.accrue_interest(through)
+    });
+    match res {
+        Ok(events) => {
+            let mut total = Amount::ZERO;
+            for event in &events {
+                if let EngineEvent::InterestAccrued { amount, .. } = event {
+                    total += *amount;
+                }
+            }
+            println!(
+                "accrued {} of interest through {} for {} client(s)",
+                total,
+                through,
+                events.len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to accrue interest through {}", through);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn adjustments(db: &Path) -> ExitCode {
+    let res = open_db(db).and_then(|db| TransactionProcessor::with_store(db).adjustments());
+    match res {
+        Ok(adjustments) => {
+            // kept apart from the client transactions
+            let net: Amount = adjustments.iter().map(|a| a.amount).sum();
+            println!("{} adjustment(s), net {}", adjustments.len(), net);
+            for adjustment in adjustments {
+                println!("{}", adjustment);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to read the adjustments");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn query(db: &Path, sql: &str, json: bool) -> ExitCode {
+    let res = TxnDb::query_read_only(db, sql).and_then(|result| {
+        if !json {
+            return result.write_csv(io::stdout().lock());
+        }
+        serde_json::to_writer_pretty(io::stdout().lock(), &result.to_json())
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write the query result"))
+            .change_context(MyError::Output)
+            .map(|_| println!())
+    });
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: the query failed");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn statement(db: &Path, client_id: ClientId) -> ExitCode {
+    let res = open_db(db).and_then(|db| TransactionProcessor::with_store(db).statement(client_id));
+    match res {
+        Ok(Some(statement)) => {
+            print!("{}", statement);
+            ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            eprintln!("error: unknown client {}", client_id);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!(
+                "error: failed to read the statement of client {}",
+                client_id
+            );
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn inspect_client(db: &Path, client_id: ClientId, json: bool) -> ExitCode {
+    let res = open_db(db).and_then(|mut db| {
+        let state = match db.get_client_state(client_id)? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let open = db.get_open_disputes(client_id)?;
+        let ages = db.client_open_dispute_ages(client_id)?;
+        Ok(Some((state, open, ages)))
+    });
+    let (state, open, ages) = match res {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            eprintln!("error: unknown client {}", client_id);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("error: failed to read client {}", client_id);
+            print_report(e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let age_days = |txn_id: TransactionId| {
+        ages.iter()
+            .find(|age| age.txn_id == txn_id)
+            .and_then(|age| age.age_days)
+    };
+    let txn_type = |amount: Amount| {
+        if amount.is_negative() {
+            "withdrawal"
+        } else {
+            "deposit"
+        }
+    };
+    if json {
+        let disputes: Vec<serde_json::Value> = open
+            .iter()
+            .map(|transfer| {
+                serde_json::json!({
+                    "tx": transfer.txn_id,
+                    "type": txn_type(transfer.amount),
+                    "amount": transfer.amount.abs().to_f64(),
+                    "timestamp": transfer.timestamp,
+                    "age_days": age_days(transfer.txn_id),
+                })
+            })
+            .collect();
+        let mut client = client_json(&state);
+        client["open_disputes"] = disputes.into();
+        println!("{}", client);
+        return ExitCode::SUCCESS;
+    }
+
+    println!("client,available,held,total,locked");
+    println!("{}", state);
+    println!();
+    println!("tx,type,amount,timestamp,age_days");
+    for transfer in &open {
+        let field = |value: Option<String>| value.unwrap_or_default();
+        println!(
+            "{},{},{},{},{}",
+            transfer.txn_id,
+            txn_type(transfer.amount),
+            transfer.amount.abs(),
+            field(transfer.timestamp.map(|t| t.to_string())),
+            field(age_days(transfer.txn_id).map(|d| d.to_string()))
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(feature = "sqlite")]
+fn export_ledger(db: &Path, format: ReportFormat) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        TransactionProcessor::with_store(db).export_ledger(io::stdout().lock(), format)
+    });
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: failed to export the ledger");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn rebuild(db: &Path) -> ExitCode {
+    let res = open_db(db).and_then(|db| {
+        let mut processor = TransactionProcessor::with_store(db);
+        processor.enable_audit_log()?;
+        processor.rebuild()
+    });
+    match res {
+        Ok(corrected) => {
+            eprintln!("{} account(s) corrected", corrected.len());
+            println!("client,available,held,total,locked");
+            for state in corrected {
+                println!("{}", state);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: failed to rebuild the accounts");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// runs `serve` with a processor on `db`, or on a scratch store, until the server stops
+#[cfg(any(feature = "server", feature = "grpc"))]
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn run_server<F, S>(db: Option<&Path>, serve: S) -> ExitCode
+where
+    S: FnOnce(AsyncTransactionProcessor) -> F,
+    F: Future<Output = Result<(), MyError>>,
+{
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to start the runtime"))
+        .change_context(MyError::Output);
+    #[cfg(feature = "sqlite")]
+    let processor = match db {
+        Some(path) => open_db(path).map(TransactionProcessor::with_store),
+        None => scratch_processor(),
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let processor = scratch_processor();
+    let res = runtime.and_then(|runtime| {
+        let processor = AsyncTransactionProcessor::new(processor?);
+        #[cfg(feature = "otlp")]
+        {
+            let processor = processor.clone();
+            otlp::observe_stats(move || processor.stats());
+        }
+        runtime.block_on(serve(processor))
+    });
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: the server failed");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn consume(config: KafkaConfig, db: Option<&Path>) -> ExitCode {
+    #[cfg(feature = "sqlite")]
+    let processor = match db {
+        Some(path) => open_db(path).map(TransactionProcessor::with_store),
+        None => scratch_processor(),
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let processor = scratch_processor();
+    let res = processor.and_then(|mut processor| {
+        KafkaConsumer::new(&config).and_then(|mut consumer| consumer.run(&mut processor))
+    });
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: consuming {} failed", config.topic);
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, MyError> {
+    fs::read(path)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read {}", path.display()))
+        .change_context(MyError::FileReader)
+}
+
+fn write_manifest(
+    input_path: &Path,
+    results: &[u8],
+    manifest_path: &Path,
+    args: &Args,
+) -> Result<(), MyError> {
+    let input = read_file(input_path)?;
+    let mut manifest = RunManifest::new(&input_path.display().to_string(), &input, results);
+    if let Some(key_path) = &args.sign_key {
+        let key = SigningKey::from_key_file(args.key_type, &read_file(key_path)?)?;
+        manifest.sign(&key);
+    }
+
+    let file = fs::File::create(manifest_path)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to create {}", manifest_path.display()))
+        .change_context(MyError::Output)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &manifest)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to write the manifest"))
+        .change_context(MyError::Output)
+}
+
+fn verify(results_path: &Path, manifest_path: &Path, key_path: &Path) -> ExitCode {
+    let res = (|| -> Result<(), MyError> {
+        let manifest: RunManifest = serde_json::from_slice(&read_file(manifest_path)?)
+            .report()
+            .attach_printable_lazy(|| {
+                fmt_error!("{} is not a run manifest", manifest_path.display())
+            })
+            .change_context(MyError::Signature)?;
+        let algorithm = match manifest.algorithm {
+            Some(a) => a,
+            None => {
+                return Err(report!(MyError::Signature)
+                    .attach_printable(fmt_error!("the manifest isn't signed")))
+            }
+        };
+        let key = VerifyingKey::from_key_file(algorithm, &read_file(key_path)?)?;
+        manifest.verify(&read_file(results_path)?, &key)
+    })();
+
+    match res {
+        Ok(_) => {
+            println!(
+                "verified: {} matches the signed manifest",
+                results_path.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: verification failed");
+            print_report(e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! `TransactionProcessorBuilder`: a processor configured in one expression, with the store it runs on. the
+//! constructors of `TransactionProcessor` stay available: `new` (a scratch SQLite database), `in_memory`, and
+//! `with_store`
+#[cfg(feature = "sqlite")]
+use crate::db::TxnDb;
+use crate::{
+    errors::*, hooks::ProcessorHooks, policy::DisputePolicy, store::TxnStore,
+    transaction_processor::TransactionProcessor,
+};
+use error_stack::Result;
+#[cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+/// where the processor keeps its state
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum StoreBackend {
+    /// nothing outlives the processor. doesn't require SQLite or a file system
+    #[default]
+    Memory,
+    /// a SQLite database at this path, created if it doesn't exist. its tables are reused and the file is kept, so a
+    /// later run continues where this one stopped
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+    /// a SQLite database with a random name in the working directory, deleted when the processor is dropped
+    #[cfg(feature = "sqlite")]
+    SqliteScratch,
+}
+
+/// ex: `TransactionProcessorBuilder::new().sqlite("ledger.db").strict(true).build()?`
+#[derive(Default)]
+pub struct TransactionProcessorBuilder {
+    backend: StoreBackend,
+    // takes precedence over the backend
+    store: Option<Box<dyn TxnStore + Send>>,
+    disputes: Option<DisputePolicy>,
+    report_precision: Option<usize>,
+    commit_every: Option<u64>,
+    hooks: Vec<Box<dyn ProcessorHooks>>,
+    strict: bool,
+}
+
+impl TransactionProcessorBuilder {
+    /// an in-memory processor with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(mut self, backend: StoreBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// shorthand for `backend(StoreBackend::Sqlite(path))`
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite(self, path: impl Into<PathBuf>) -> Self {
+        self.backend(StoreBackend::Sqlite(path.into()))
+    }
+
+    /// run on a store of your own instead of the backend, ex: a `FakeStore` in tests
+    pub fn store<S: TxnStore + Send + 'static>(mut self, store: S) -> Self {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// see `TransactionProcessor::set_dispute_policy`
+    pub fn dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.disputes = Some(policy);
+        self
+    }
+
+    /// the decimal places of the amounts in the report. see `TransactionProcessor::set_report_precision`
+    pub fn report_precision(mut self, places: usize) -> Self {
+        self.report_precision = Some(places);
+        self
+    }
+
+    /// the number of input rows applied per store transaction. see `TransactionProcessor::set_commit_every`
+    pub fn commit_every(mut self, rows: u64) -> Self {
+        self.commit_every = Some(rows);
+        self
+    }
+
+    /// can be called more than once. see `TransactionProcessor::add_hooks`
+    pub fn hooks<H: ProcessorHooks + 'static>(mut self, hooks: H) -> Self {
+        self.hooks.push(Box::new(hooks));
+        self
+    }
+
+    /// see `TransactionProcessor::set_strict`
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// fails if the SQLite database can't be opened
+    pub fn build(self) -> Result<TransactionProcessor, MyError> {
+        let mut processor = match self.store {
+            Some(store) => TransactionProcessor::with_boxed_store(store),
+            None => match self.backend {
+                StoreBackend::Memory => TransactionProcessor::in_memory(),
+                #[cfg(feature = "sqlite")]
+                StoreBackend::Sqlite(path) => {
+                    TransactionProcessor::with_store(TxnDb::open(&path.to_string_lossy())?)
+                }
+                #[cfg(feature = "sqlite")]
+                StoreBackend::SqliteScratch => TransactionProcessor::new()?,
+            },
+        };
+        if let Some(policy) = self.disputes {
+            processor.set_dispute_policy(policy);
+        }
+        if let Some(places) = self.report_precision {
+            processor.set_report_precision(places);
+        }
+        if let Some(rows) = self.commit_every {
+            processor.set_commit_every(rows);
+        }
+        for hooks in self.hooks {
+            processor.add_boxed_hooks(hooks);
+        }
+        processor.set_strict(self.strict);
+        Ok(processor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::Amount, model::*};
+    use std::sync::{Arc, Mutex};
+
+    struct Deposits(Arc<Mutex<u64>>);
+
+    impl ProcessorHooks for Deposits {
+        fn on_deposit(&mut self, _: ClientId, _: TransactionId, _: Amount) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let deposits = Arc::new(Mutex::new(0));
+        let mut tp = TransactionProcessorBuilder::new()
+            .dispute_policy(DisputePolicy::DepositsOnly)
+            .report_precision(2)
+            .commit_every(10)
+            .hooks(Deposits(deposits.clone()))
+            .build()
+            .unwrap();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.1234
+                        withdrawal,1,2,5.0
+                        dispute,1,2,";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(*deposits.lock().unwrap(), 1);
+        let mut report = Vec::new();
+        tp.write_report(&mut report).unwrap();
+        // the dispute of the withdrawal was rejected
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n1,5.12,0,5.12,false\n"
+        );
+
+        // strict mode stops at the malformed row
+        let mut tp = TransactionProcessorBuilder::new()
+            .strict(true)
+            .build()
+            .unwrap();
+        assert!(tp.process_csv(csv.replace("5.0", "x").as_bytes()).is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_backend() {
+        let path = std::env::temp_dir().join(format!("builder-{}.db", std::process::id()));
+        let build = || {
+            TransactionProcessorBuilder::new()
+                .sqlite(&path)
+                .build()
+                .unwrap()
+        };
+        let mut tp = build();
+        tp.process_csv("type,client,tx,amount\ndeposit,1,1,2.0".as_bytes())
+            .unwrap();
+        drop(tp);
+        // the database was kept
+        let tp = build();
+        assert_eq!(tp.client_states().unwrap().len(), 1);
+        drop(tp);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+//! chaos mode: a `TxnStore` wrapper that randomly fails a fraction of the calls to the store it wraps, to check that
+//! the processor retries busy errors and otherwise fails a row atomically. unlike `FakeStore`, which fails chosen
+//! calls, the faults are spread at random (but reproducibly, from a seed) over a whole run.
+//! a fault is injected before the call reaches the wrapped store, so the failed call itself has no effect.
+//! enabled by the "test-util" feature.
+use crate::{
+    adjustment::Adjustment, amount::Amount, audit::AuditEntry, errors::*, event_log::LoggedEvent,
+    fmt_error, ledger::Posting, limits::Limits, model::*, schedule::Date, store::TxnStore,
+    workload::Rng,
+};
+use error_stack::{bail, report, Result};
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// SQLITE_BUSY: another connection holds the lock. returned as `MyError::Busy`, which can be retried
+    Busy,
+    /// a constraint the processor didn't expect to violate. returned as `MyError::Db`
+    Constraint,
+    /// a disk I/O error. returned as `MyError::Db`
+    Io,
+}
+
+/// ex: `ChaosStore::new(TxnDb::new("chaos.db")?, 42).rate(0.01).faults(&[Fault::Busy])`
+pub struct ChaosStore<S> {
+    inner: S,
+    rng: Cell<Rng>,
+    rate: f64,
+    faults: Vec<Fault>,
+    // the number of faults injected so far
+    injected: Cell<u64>,
+}
+
+impl<S: TxnStore> ChaosStore<S> {
+    /// injects every kind of fault into 1% of the calls
+    pub fn new(inner: S, seed: u64) -> Self {
+        ChaosStore {
+            inner,
+            rng: Cell::new(Rng::new(seed)),
+            rate: 0.01,
+            faults: vec![Fault::Busy, Fault::Constraint, Fault::Io],
+            injected: Cell::new(0),
+        }
+    }
+
+    /// the fraction of the calls that fail
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// the kinds of faults to inject, chosen with equal probability
+    pub fn faults(mut self, faults: &[Fault]) -> Self {
+        self.faults = faults.to_vec();
+        self
+    }
+
+    /// stop injecting faults. the wrapped store is used as is from now on
+    pub fn calm(&mut self) {
+        self.rate = 0.0;
+    }
+
+    pub fn injected(&self) -> u64 {
+        self.injected.get()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn chaos(&self, op: &str) -> Result<(), MyError> {
+        if self.faults.is_empty() {
+            return Ok(());
+        }
+        let mut rng = self.rng.get();
+        let fault = (rng.next_f64() < self.rate).then(|| self.faults[rng.below(self.faults.len())]);
+        self.rng.set(rng);
+        let fault = match fault {
+            Some(fault) => fault,
+            None => return Ok(()),
+        };
+        self.injected.set(self.injected.get() + 1);
+        match fault {
+            Fault::Busy => bail!(MyError::Busy),
+            Fault::Constraint => Err(report!(MyError::Db)
+                .attach_printable(fmt_error!("injected constraint violation in {}", op))),
+            Fault::Io => Err(report!(MyError::Db)
+                .attach_printable(fmt_error!("injected disk I/O error in {}", op))),
+        }
+    }
+}
+
+impl<S: TxnStore> TxnStore for ChaosStore<S> {
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
+        self.chaos("create_client_state")?;
+        self.inner.create_client_state(client_id)
+    }
+
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        self.chaos("get_client_state")?;
+        self.inner.get_client_state(client_id)
+    }
+
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError> {
+        self.chaos("process_all_clients")?;
+        self.inner.process_all_clients(f)
+    }
+
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
+        self.chaos("update_client_state")?;
+        self.inner.update_client_state(client_state)
+    }
+
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        self.chaos("try_insert_balance_transfer")?;
+        self.inner.try_insert_balance_transfer(txn)
+    }
+
+    fn try_insert_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.chaos("try_insert_dispute")?;
+        self.inner.try_insert_dispute(client_id, txn_id)
+    }
+
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.chaos("try_resolve_dispute")?;
+        self.inner.try_resolve_dispute(client_id, txn_id)
+    }
+
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.chaos("try_chargeback_dispute")?;
+        self.inner.try_chargeback_dispute(client_id, txn_id)
+    }
+
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.chaos("try_reopen_dispute")?;
+        self.inner.try_reopen_dispute(client_id, txn_id)
+    }
+
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError> {
+        self.chaos("count_dispute_reopens")?;
+        self.inner.count_dispute_reopens(client_id, txn_id)
+    }
+
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        self.chaos("get_balance_transfer")?;
+        self.inner.get_balance_transfer(client_id, txn_id)
+    }
+
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        self.chaos("get_balance_transfer_by_id")?;
+        self.inner.get_balance_transfer_by_id(txn_id)
+    }
+
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError> {
+        self.chaos("get_open_disputes")?;
+        self.inner.get_open_disputes(client_id)
+    }
+
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError> {
+        self.chaos("process_all_balance_transfers")?;
+        self.inner.process_all_balance_transfers(f)
+    }
+
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        self.chaos("get_overdraft_limit")?;
+        self.inner.get_overdraft_limit(client_id)
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        self.chaos("set_overdraft_limit")?;
+        self.inner.set_overdraft_limit(client_id, limit)
+    }
+
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError> {
+        self.chaos("get_client_limits")?;
+        self.inner.get_client_limits(client_id)
+    }
+
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        self.chaos("set_client_limits")?;
+        self.inner.set_client_limits(client_id, limits)
+    }
+
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError> {
+        self.chaos("insert_fee")?;
+        self.inner.insert_fee(client_id, txn_id, fee)
+    }
+
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError> {
+        self.chaos("get_fee")?;
+        self.inner.get_fee(client_id, txn_id)
+    }
+
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError> {
+        self.chaos("try_insert_refund")?;
+        self.inner.try_insert_refund(refund)
+    }
+
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError> {
+        self.chaos("get_refund")?;
+        self.inner.get_refund(client_id, txn_id)
+    }
+
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        self.chaos("try_queue_deposit")?;
+        self.inner.try_queue_deposit(txn)
+    }
+
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError> {
+        self.chaos("take_queued_deposits")?;
+        self.inner.take_queued_deposits(client_id)
+    }
+
+    fn forget_client(
+        &mut self,
+        client_id: ClientId,
+        summary: &[Posting],
+    ) -> Result<usize, MyError> {
+        self.chaos("forget_client")?;
+        self.inner.forget_client(client_id, summary)
+    }
+
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError> {
+        self.chaos("insert_posting")?;
+        self.inner.insert_posting(posting)
+    }
+
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError> {
+        self.chaos("process_all_postings")?;
+        self.inner.process_all_postings(f)
+    }
+
+    fn process_client_postings(
+        &self,
+        client_id: ClientId,
+        f: &mut dyn FnMut(Posting),
+    ) -> Result<(), MyError> {
+        self.chaos("process_client_postings")?;
+        self.inner.process_client_postings(client_id, f)
+    }
+
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError> {
+        self.chaos("insert_adjustment")?;
+        self.inner.insert_adjustment(adjustment)
+    }
+
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError> {
+        self.chaos("process_all_adjustments")?;
+        self.inner.process_all_adjustments(f)
+    }
+
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        self.chaos("append_audit_entry")?;
+        self.inner.append_audit_entry(entry)
+    }
+
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError> {
+        self.chaos("process_all_audit_entries")?;
+        self.inner.process_all_audit_entries(f)
+    }
+
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError> {
+        self.chaos("append_event")?;
+        self.inner.append_event(event)
+    }
+
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError> {
+        self.chaos("process_all_events")?;
+        self.inner.process_all_events(f)
+    }
+
+    fn begin(&mut self) -> Result<(), MyError> {
+        self.chaos("begin")?;
+        self.inner.begin()
+    }
+
+    fn commit(&mut self) -> Result<(), MyError> {
+        self.chaos("commit")?;
+        self.inner.commit()
+    }
+
+    // a rollback has to get through, or the failed row's unit of work would stay open
+    fn rollback(&mut self) -> Result<(), MyError> {
+        self.inner.rollback()
+    }
+
+    fn get_checkpoint(&self, run_id: &str) -> Result<Option<u64>, MyError> {
+        self.chaos("get_checkpoint")?;
+        self.inner.get_checkpoint(run_id)
+    }
+
+    fn set_checkpoint(&mut self, run_id: &str, rows: u64) -> Result<(), MyError> {
+        self.chaos("set_checkpoint")?;
+        self.inner.set_checkpoint(run_id, rows)
+    }
+
+    fn get_input_run(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        self.chaos("get_input_run")?;
+        self.inner.get_input_run(input_sha256)
+    }
+
+    fn insert_input_run(&mut self, run: &InputRun) -> Result<(), MyError> {
+        self.chaos("insert_input_run")?;
+        self.inner.insert_input_run(run)
+    }
+
+    fn try_insert_record_hash(&mut self, sha256: &str) -> Result<bool, MyError> {
+        self.chaos("try_insert_record_hash")?;
+        self.inner.try_insert_record_hash(sha256)
+    }
+
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        self.chaos("get_interest_accrued_through")?;
+        self.inner.get_interest_accrued_through()
+    }
+
+    fn set_interest_accrued_through(&mut self, date: Date) -> Result<(), MyError> {
+        self.chaos("set_interest_accrued_through")?;
+        self.inner.set_interest_accrued_through(date)
+    }
+
+    fn is_purged_txn_id(&self, txn_id: TransactionId) -> Result<bool, MyError> {
+        self.chaos("is_purged_txn_id")?;
+        self.inner.is_purged_txn_id(txn_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db::MemoryDb;
+
+    #[test]
+    fn test_rate() {
+        let mut db = ChaosStore::new(MemoryDb::new(), 1)
+            .rate(0.25)
+            .faults(&[Fault::Busy]);
+        let mut busy = 0;
+        for _ in 0..1000 {
+            if let Err(e) = db.get_client_state(1) {
+                assert!(matches!(e.current_context(), MyError::Busy));
+                busy += 1;
+            }
+        }
+        assert_eq!(db.injected(), busy);
+        assert!((200..300).contains(&busy), "{}", busy);
+
+        db.calm();
+        assert!(db.create_client_state(1).is_ok());
+        assert_eq!(db.injected(), busy);
+    }
+}
@@ -0,0 +1,123 @@
+//! the columns of CSV input, mapped by the names of the header row, so the columns can come in any order and
+//! unknown extra columns are ignored. a header that doesn't name the type, client, and tx columns is read by
+//! position, as `type,client,tx,amount[,timestamp[,original_tx]]`
+use crate::number_format::AmountUnit;
+use csv::StringRecord;
+
+/// the columns of a transaction, in the order of `RawTxnInput`
+pub const COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "original_tx"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns {
+    // the index of each of COLUMNS in a record. None for positional input
+    indices: Option<[Option<usize>; 6]>,
+    unit: Option<AmountUnit>,
+}
+
+impl Columns {
+    /// the columns named by a header row. the amount column can be named after its unit, ex: `amount_cents`
+    pub fn from_header(header: &StringRecord) -> Self {
+        let mut indices = [None; 6];
+        let mut unit = None;
+        for (idx, name) in header.iter().enumerate() {
+            let name = name.trim();
+            let column = match COLUMNS.iter().position(|column| *column == name) {
+                Some(column) => column,
+                None => match AmountUnit::from_header(name) {
+                    Some(named) if indices[3].is_none() => {
+                        unit = Some(named);
+                        3
+                    }
+                    _ => continue,
+                },
+            };
+            // the first of two columns with the same name is used
+            indices[column].get_or_insert(idx);
+        }
+        if indices[..3].iter().all(Option::is_some) {
+            return Columns {
+                indices: Some(indices),
+                unit,
+            };
+        }
+        Columns {
+            indices: None,
+            unit: header.get(3).and_then(AmountUnit::from_header),
+        }
+    }
+
+    /// false if the input is read by position
+    pub fn is_named(&self) -> bool {
+        self.indices.is_some()
+    }
+
+    /// the unit named by the amount column, if any
+    pub fn amount_unit(&self) -> Option<AmountUnit> {
+        self.unit
+    }
+
+    /// the fields of a record in the order of COLUMNS. a column missing from the header is an empty field.
+    /// positional records are returned as they are
+    pub fn arrange(&self, record: StringRecord) -> StringRecord {
+        let Some(indices) = self.indices else {
+            return record;
+        };
+        let mut arranged: StringRecord = indices
+            .iter()
+            .map(|idx| idx.and_then(|idx| record.get(idx)).unwrap_or(""))
+            .collect();
+        arranged.set_position(record.position().cloned());
+        arranged
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shuffled_header() {
+        let columns = Columns::from_header(&StringRecord::from(vec![
+            "memo",
+            " amount",
+            "tx",
+            "type",
+            "client",
+            "timestamp",
+        ]));
+        assert!(columns.is_named());
+        assert_eq!(columns.amount_unit(), None);
+        let record = StringRecord::from(vec!["rent", "1.5", "3", "deposit", "1", "1704067200"]);
+        assert_eq!(
+            columns.arrange(record),
+            StringRecord::from(vec!["deposit", "1", "3", "1.5", "1704067200", ""])
+        );
+
+        // no amount column at all
+        let columns = Columns::from_header(&StringRecord::from(vec!["tx", "client", "type"]));
+        assert_eq!(
+            columns.arrange(StringRecord::from(vec!["3", "1", "dispute"])),
+            StringRecord::from(vec!["dispute", "1", "3", "", "", ""])
+        );
+    }
+
+    #[test]
+    fn test_unit_and_positional() {
+        let columns = Columns::from_header(&StringRecord::from(vec![
+            "client",
+            "type",
+            "amount_cents",
+            "tx",
+        ]));
+        assert!(columns.is_named());
+        assert_eq!(columns.amount_unit(), Some(AmountUnit::Cents));
+
+        // unknown names are read by position
+        let columns =
+            Columns::from_header(&StringRecord::from(vec!["a", "b", "c", "amount_cents"]));
+        assert!(!columns.is_named());
+        assert_eq!(columns.amount_unit(), Some(AmountUnit::Cents));
+        let record = StringRecord::from(vec!["deposit", "1", "1", "150"]);
+        assert_eq!(columns.arrange(record.clone()), record);
+    }
+}
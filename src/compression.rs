@@ -0,0 +1,115 @@
+//! compressed input. gzip and zstd streams are recognized by their magic bytes (or, failing that, the extension of
+//! the file) and decompressed on the fly, so a `.csv.gz` dump is processed without unpacking it first
+use crate::{errors::*, fmt_error};
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// from the first bytes of the input, or from the extension of `path` (`.gz`, `.zst`) when they don't match
+    pub fn detect(path: &Path, head: &[u8]) -> Self {
+        if head.starts_with(GZIP_MAGIC) {
+            return Compression::Gzip;
+        }
+        if head.starts_with(ZSTD_MAGIC) {
+            return Compression::Zstd;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// `reader`, decompressed if it's gzip or zstd. `path` names the input in errors and is used for its extension.
+/// concatenated gzip members (ex: from `cat a.gz b.gz`) are read as one stream
+pub fn decompress<'a, R: Read + 'a>(path: &Path, reader: R) -> Result<Box<dyn Read + 'a>, MyError> {
+    let mut reader = BufReader::new(reader);
+    let head = reader
+        .fill_buf()
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read {}", path.display()))
+        .change_context(MyError::FileReader)?;
+    let compression = Compression::detect(path, head);
+    tracing::debug!(input = %path.display(), ?compression, "opened");
+    let reader: Box<dyn Read + 'a> = match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(
+            zstd::stream::read::Decoder::with_buffer(reader)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to read {}", path.display()))
+                .change_context(MyError::FileReader)?,
+        ),
+    };
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction_processor::TransactionProcessor;
+    use std::io::Write;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+
+    fn read(path: &str, bytes: &[u8]) -> String {
+        let mut out = String::new();
+        decompress(Path::new(path), bytes)
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(CSV.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+
+        assert_eq!(read("input.csv", CSV.as_bytes()), CSV);
+        // the magic bytes win over the extension
+        assert_eq!(read("input.csv", &gzip), CSV);
+        assert_eq!(read("input.csv.gz", &gzip), CSV);
+        assert_eq!(read("input.csv.zst", &zstd), CSV);
+        assert_eq!(read("input", &zstd), CSV);
+        assert_eq!(read("empty.csv", b""), "");
+
+        assert_eq!(
+            Compression::detect(Path::new("input.csv.gz"), b""),
+            Compression::Gzip
+        );
+        let mut out = String::new();
+        let corrupt = decompress(Path::new("input.csv.gz"), CSV.as_bytes())
+            .unwrap()
+            .read_to_string(&mut out);
+        assert!(corrupt.is_err());
+    }
+
+    // a compressed input that ends early fails the run instead of being skipped like a malformed row
+    #[test]
+    fn test_truncated_input() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(CSV.repeat(100).as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut processor = TransactionProcessor::in_memory();
+        let input = decompress(Path::new("input.csv.gz"), &gzip[..gzip.len() / 2]).unwrap();
+        let err = processor.process_csv(input).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::FileReader));
+    }
+}
@@ -0,0 +1,525 @@
+//! the engine configuration file: the policies and thresholds that can change without restarting a long-running
+//! process. the file is JSON, ex: `{"rounding": "half-up", "disputes": "deposits-only", "max_chargeback_ratio": 0.01}`,
+//! or TOML if its name ends in `.toml` (feature "toml"), with the same keys. every key is optional; a missing key
+//! leaves the processor's setting unchanged.
+//! the startup keys (`db`, `commit_every`, `output`, `number_format`, `amount_unit`) are read by the executable when a
+//! run starts; `apply_config` and reloads leave them alone.
+//! `dispute_window_days` is how many days after a deposit or withdrawal it can be disputed.
+//! `max_balance`, `max_deposit`, and `max_withdrawal` are the `Limits` of the clients without their own.
+//! `rate_schedule` lists the fee and interest rates with their effective dates, ex:
+//! `"rate_schedule": [{"from": "2024-01-01", "until": "2024-07-01", "flat_fee": 0.5, "interest_rate": 0.02}]`.
+//! a `ConfigWatcher` reloads the file when it changes, and the processor applies the new configuration between two
+//! transactions, recording its version on every audit entry
+use crate::{
+    amount::Amount,
+    errors::*,
+    fmt_error,
+    limits::Limits,
+    number_format::{AmountUnit, NumberFormat},
+    policy::{CrossClientDisputePolicy, DisputePolicy, LockedAccountPolicy, OverdraftPolicy},
+    risk::ChargebackThresholds,
+    rounding::RoundingPolicy,
+    schedule::{Date, Rate, RatePeriod, RateSchedule, Rates},
+};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EngineConfig {
+    /// identifies the contents of the file: the first 12 hex digits of its sha256
+    pub version: String,
+    pub rounding: Option<RoundingPolicy>,
+    pub cross_client_disputes: Option<CrossClientDisputePolicy>,
+    pub disputes: Option<DisputePolicy>,
+    pub overdraft: Option<OverdraftPolicy>,
+    /// the limits that are set replace the processor's
+    pub limits: Limits,
+    pub locked_accounts: Option<LockedAccountPolicy>,
+    /// how many days after a deposit or withdrawal it can be disputed
+    pub dispute_window_days: Option<u32>,
+    pub chargeback_thresholds: Option<ChargebackThresholds>,
+    pub rate_schedule: Option<RateSchedule>,
+    pub number_format: Option<NumberFormat>,
+    pub amount_unit: Option<AmountUnit>,
+    /// the input rows applied per store transaction
+    pub commit_every: Option<u64>,
+    /// the SQLite database. the executable uses a scratch store without one
+    pub db: Option<PathBuf>,
+    /// the file the client report is written to instead of stdout
+    pub output: Option<PathBuf>,
+}
+
+// the file format. the policies are parsed with their FromStr impls, like the command line flags
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    rounding: Option<String>,
+    cross_client_disputes: Option<String>,
+    disputes: Option<String>,
+    overdraft: Option<String>,
+    max_balance: Option<f64>,
+    max_deposit: Option<f64>,
+    max_withdrawal: Option<f64>,
+    locked_accounts: Option<String>,
+    dispute_window_days: Option<u32>,
+    max_chargeback_ratio: Option<f64>,
+    chargeback_window: Option<u64>,
+    rate_schedule: Option<Vec<RatePeriodFile>>,
+    number_format: Option<String>,
+    amount_unit: Option<String>,
+    commit_every: Option<u64>,
+    db: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RatePeriodFile {
+    from: String,
+    until: Option<String>,
+    #[serde(default)]
+    flat_fee: f64,
+    #[serde(default)]
+    percent_fee: f64,
+    #[serde(default)]
+    interest_rate: f64,
+}
+
+impl EngineConfig {
+    /// parse a JSON configuration
+    pub fn parse(contents: &[u8]) -> Result<Self, MyError> {
+        let file: ConfigFile = serde_json::from_slice(contents)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("invalid configuration"))
+            .change_context(MyError::Config)?;
+        Self::from_file(file, contents)
+    }
+
+    /// parse a TOML configuration
+    #[cfg(feature = "toml")]
+    pub fn parse_toml(contents: &[u8]) -> Result<Self, MyError> {
+        let file: ConfigFile = std::str::from_utf8(contents)
+            .report()
+            .change_context(MyError::Config)
+            .and_then(|contents| {
+                toml::from_str(contents)
+                    .report()
+                    .change_context(MyError::Config)
+            })
+            .attach_printable_lazy(|| fmt_error!("invalid configuration"))?;
+        Self::from_file(file, contents)
+    }
+
+    fn from_file(file: ConfigFile, contents: &[u8]) -> Result<Self, MyError> {
+        let parse_err = |e: MyError| report!(e).change_context(MyError::Config);
+        let rounding = match &file.rounding {
+            Some(s) => Some(s.parse::<RoundingPolicy>().map_err(parse_err)?),
+            None => None,
+        };
+        let cross_client_disputes = match &file.cross_client_disputes {
+            Some(s) => Some(s.parse::<CrossClientDisputePolicy>().map_err(parse_err)?),
+            None => None,
+        };
+        let disputes = match &file.disputes {
+            Some(s) => Some(s.parse::<DisputePolicy>().map_err(parse_err)?),
+            None => None,
+        };
+        let overdraft = match &file.overdraft {
+            Some(s) => Some(s.parse::<OverdraftPolicy>().map_err(parse_err)?),
+            None => None,
+        };
+        let limit = |name: &str, value: Option<f64>| match value {
+            Some(value) => match Amount::from_f64(value, RoundingPolicy::HalfEven) {
+                Some(limit) if !limit.is_negative() => Ok(Some(limit)),
+                _ => Err(report!(MyError::Config).attach_printable(fmt_error!(
+                    "invalid {}: {}",
+                    name,
+                    value
+                ))),
+            },
+            None => Ok(None),
+        };
+        // exact: a rate with digits past Rate::PLACES is refused rather than rounded
+        let rate = |name: &str, value: f64| {
+            Rate::from_f64(value).ok_or_else(|| {
+                report!(MyError::Config).attach_printable(fmt_error!("invalid {}: {}", name, value))
+            })
+        };
+        let limits = Limits {
+            max_balance: limit("max_balance", file.max_balance)?,
+            max_deposit: limit("max_deposit", file.max_deposit)?,
+            max_withdrawal: limit("max_withdrawal", file.max_withdrawal)?,
+        };
+        let locked_accounts = match &file.locked_accounts {
+            Some(s) => Some(s.parse::<LockedAccountPolicy>().map_err(parse_err)?),
+            None => None,
+        };
+        let chargeback_thresholds = match (file.max_chargeback_ratio, file.chargeback_window) {
+            (Some(ratio), window) => Some(ChargebackThresholds {
+                window: window.unwrap_or(ChargebackThresholds::default().window),
+                max_count_ratio: ratio,
+                max_value_ratio: ratio,
+            }),
+            (None, Some(_)) => {
+                return Err(report!(MyError::Config).attach_printable(fmt_error!(
+                    "chargeback_window requires max_chargeback_ratio"
+                )))
+            }
+            (None, None) => None,
+        };
+        let rate_schedule = match file.rate_schedule {
+            Some(periods) => {
+                let periods = periods
+                    .into_iter()
+                    .map(|p| {
+                        Ok(RatePeriod {
+                            from: p.from.parse::<Date>().map_err(parse_err)?,
+                            until: match p.until {
+                                Some(until) => Some(until.parse::<Date>().map_err(parse_err)?),
+                                None => None,
+                            },
+                            rates: Rates {
+                                flat_fee: limit("flat_fee", Some(p.flat_fee))?.unwrap_or_default(),
+                                percent_fee: rate("percent_fee", p.percent_fee)?,
+                                interest_rate: rate("interest_rate", p.interest_rate)?,
+                            },
+                        })
+                    })
+                    .collect::<Result<Vec<RatePeriod>, MyError>>()?;
+                Some(RateSchedule::new(periods)?)
+            }
+            None => None,
+        };
+        let number_format = match &file.number_format {
+            Some(s) => Some(s.parse::<NumberFormat>().map_err(parse_err)?),
+            None => None,
+        };
+        let amount_unit = match &file.amount_unit {
+            Some(s) => Some(s.parse::<AmountUnit>().map_err(parse_err)?),
+            None => None,
+        };
+        if file.commit_every == Some(0) {
+            return Err(report!(MyError::Config)
+                .attach_printable(fmt_error!("commit_every must be at least 1")));
+        }
+
+        let digest = Sha256::digest(contents);
+        Ok(EngineConfig {
+            version: digest
+                .iter()
+                .take(6)
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            rounding,
+            cross_client_disputes,
+            disputes,
+            overdraft,
+            limits,
+            locked_accounts,
+            dispute_window_days: file.dispute_window_days,
+            chargeback_thresholds,
+            rate_schedule,
+            number_format,
+            amount_unit,
+            commit_every: file.commit_every,
+            db: file.db,
+            output: file.output,
+        })
+    }
+
+    /// load a JSON file, or a TOML file if its name ends in `.toml`
+    pub fn load(path: &Path) -> Result<Self, MyError> {
+        let contents = fs::read(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read {}", path.display()))
+            .change_context(MyError::FileReader)?;
+        let res = match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::parse_toml(&contents),
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => Err(report!(MyError::Config)
+                .attach_printable(fmt_error!("TOML configuration requires the toml feature"))),
+            _ => Self::parse(&contents),
+        };
+        res.attach_printable_lazy(|| fmt_error!("in {}", path.display()))
+    }
+
+    /// replace the values of this configuration with those set in `overrides`, ex: the command line flags
+    pub fn merge(&mut self, overrides: &EngineConfig) {
+        fn set<T: Clone>(value: &mut Option<T>, overriding: &Option<T>) {
+            if overriding.is_some() {
+                value.clone_from(overriding);
+            }
+        }
+        set(&mut self.rounding, &overrides.rounding);
+        set(
+            &mut self.cross_client_disputes,
+            &overrides.cross_client_disputes,
+        );
+        set(&mut self.disputes, &overrides.disputes);
+        set(&mut self.overdraft, &overrides.overdraft);
+        self.limits = overrides.limits.or(self.limits);
+        set(&mut self.locked_accounts, &overrides.locked_accounts);
+        set(
+            &mut self.dispute_window_days,
+            &overrides.dispute_window_days,
+        );
+        set(
+            &mut self.chargeback_thresholds,
+            &overrides.chargeback_thresholds,
+        );
+        set(&mut self.rate_schedule, &overrides.rate_schedule);
+        set(&mut self.number_format, &overrides.number_format);
+        set(&mut self.amount_unit, &overrides.amount_unit);
+        set(&mut self.commit_every, &overrides.commit_every);
+        set(&mut self.db, &overrides.db);
+        set(&mut self.output, &overrides.output);
+    }
+}
+
+/// reloads a configuration file when it changes
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    interval: Duration,
+    last_check: Instant,
+    modified: Option<SystemTime>,
+    version: String,
+    overrides: EngineConfig,
+}
+
+impl ConfigWatcher {
+    /// loads the file. `interval`: how often `poll` looks at the file
+    pub fn new(path: &Path, interval: Duration) -> Result<(Self, EngineConfig), MyError> {
+        let config = EngineConfig::load(path)?;
+        let watcher = ConfigWatcher {
+            path: path.to_path_buf(),
+            interval,
+            last_check: Instant::now(),
+            modified: modified(path),
+            version: config.version.clone(),
+            overrides: EngineConfig::default(),
+        };
+        Ok((watcher, config))
+    }
+
+    /// values that take precedence over the file's on every reload (see `EngineConfig::merge`)
+    pub fn set_overrides(&mut self, overrides: EngineConfig) {
+        self.overrides = overrides;
+    }
+
+    /// the new configuration if the file changed. a file that doesn't parse is logged and skipped: the current
+    /// configuration stays in effect until the file is fixed
+    pub fn poll(&mut self) -> Option<EngineConfig> {
+        if self.last_check.elapsed() < self.interval {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        match EngineConfig::load(&self.path) {
+            Ok(mut config) if config.version != self.version => {
+                self.version = config.version.clone();
+                config.merge(&self.overrides);
+                Some(config)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!(path = %self.path.display(), "ignoring the changed configuration: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_parse() {
+        let config = EngineConfig::parse(
+            br#"{"rounding": "truncate", "cross_client_disputes": "owner", "max_chargeback_ratio": 0.5}"#,
+        )
+        .unwrap();
+        assert_eq!(config.rounding, Some(RoundingPolicy::Truncate));
+        assert_eq!(
+            config.cross_client_disputes,
+            Some(CrossClientDisputePolicy::Owner)
+        );
+        assert_eq!(config.chargeback_thresholds.unwrap().window, 1000);
+        assert_eq!(config.version.len(), 12);
+
+        let empty = EngineConfig::parse(b"{}").unwrap();
+        assert_eq!(empty.rounding, None);
+        assert_ne!(empty.version, config.version);
+
+        assert_eq!(
+            EngineConfig::parse(br#"{"disputes": "deposits-only"}"#)
+                .unwrap()
+                .disputes,
+            Some(DisputePolicy::DepositsOnly)
+        );
+        assert_eq!(
+            EngineConfig::parse(br#"{"overdraft": "allow-unlimited"}"#)
+                .unwrap()
+                .overdraft,
+            Some(OverdraftPolicy::AllowUnlimited)
+        );
+        assert_eq!(
+            EngineConfig::parse(br#"{"locked_accounts": "queue-deposits"}"#)
+                .unwrap()
+                .locked_accounts,
+            Some(LockedAccountPolicy::QueueDeposits)
+        );
+        assert_eq!(
+            EngineConfig::parse(br#"{"dispute_window_days": 90}"#)
+                .unwrap()
+                .dispute_window_days,
+            Some(90)
+        );
+        assert!(EngineConfig::parse(br#"{"dispute_window_days": -1}"#).is_err());
+        let limits = EngineConfig::parse(br#"{"max_balance": 1000, "max_withdrawal": 12.5}"#)
+            .unwrap()
+            .limits;
+        assert_eq!(
+            limits,
+            Limits {
+                max_balance: Some(amt(1000.0)),
+                max_deposit: None,
+                max_withdrawal: Some(amt(12.5)),
+            }
+        );
+        assert!(EngineConfig::parse(br#"{"max_deposit": -1}"#).is_err());
+        assert!(EngineConfig::parse(br#"{"rounding": "up"}"#).is_err());
+        assert!(EngineConfig::parse(br#"{"fees": 1}"#).is_err());
+        assert!(EngineConfig::parse(br#"{"chargeback_window": 5}"#).is_err());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_parse_toml() {
+        let config = EngineConfig::parse_toml(
+            br#"
+disputes = "deposits-only"
+commit_every = 500
+db = "ledger.db"
+number_format = "de"
+
+[[rate_schedule]]
+from = "2024-01-01"
+flat_fee = 0.5
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.disputes, Some(DisputePolicy::DepositsOnly));
+        assert_eq!(config.commit_every, Some(500));
+        assert_eq!(config.db, Some(PathBuf::from("ledger.db")));
+        assert_eq!(config.number_format, Some(NumberFormat::DE));
+        assert!(config.rate_schedule.is_some());
+
+        assert!(EngineConfig::parse_toml(b"rounding = \"up\"").is_err());
+        assert!(EngineConfig::parse_toml(b"commit_every = 0").is_err());
+        assert!(EngineConfig::parse_toml(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut config =
+            EngineConfig::parse(br#"{"rounding": "truncate", "disputes": "deposits-only"}"#)
+                .unwrap();
+        config.merge(&EngineConfig {
+            rounding: Some(RoundingPolicy::HalfUp),
+            commit_every: Some(10),
+            ..Default::default()
+        });
+        assert_eq!(config.rounding, Some(RoundingPolicy::HalfUp));
+        assert_eq!(config.disputes, Some(DisputePolicy::DepositsOnly));
+        assert_eq!(config.commit_every, Some(10));
+    }
+
+    #[test]
+    fn test_rate_schedule() {
+        let config = EngineConfig::parse(
+            br#"{"rate_schedule": [
+                {"from": "2024-01-01", "until": "2024-07-01", "flat_fee": 0.5},
+                {"from": "2024-07-01", "flat_fee": 0.75, "interest_rate": 0.02}
+            ]}"#,
+        )
+        .unwrap();
+        let schedule = config.rate_schedule.unwrap();
+        let rates = schedule.rates_at("2024-06-30".parse().unwrap());
+        assert_eq!(
+            (rates.flat_fee, rates.interest_rate),
+            (amt(0.5), Rate::ZERO)
+        );
+        let rates = schedule.rates_at("2025-01-01".parse().unwrap());
+        assert_eq!(
+            (rates.flat_fee, rates.interest_rate),
+            (amt(0.75), "0.02".parse().unwrap())
+        );
+
+        assert!(EngineConfig::parse(br#"{"rate_schedule": [{"from": "2024-02-30"}]}"#).is_err());
+        // a percentage with more digits than a Rate keeps
+        assert!(EngineConfig::parse(
+            br#"{"rate_schedule": [{"from": "2024-01-01", "percent_fee": 0.0000000001}]}"#
+        )
+        .is_err());
+        assert!(EngineConfig::parse(
+            br#"{"rate_schedule": [{"from": "2024-01-01"}, {"from": "2024-02-01"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_watcher() {
+        let path = std::env::temp_dir().join(format!("engine-config-{}.json", std::process::id()));
+        fs::write(&path, r#"{"rounding": "half-up"}"#).unwrap();
+        let (mut watcher, config) = ConfigWatcher::new(&path, Duration::ZERO).unwrap();
+        assert_eq!(config.rounding, Some(RoundingPolicy::HalfUp));
+        assert!(watcher.poll().is_none());
+
+        // force a different modification time: some file systems only have 1s resolution
+        watcher.modified = None;
+        assert!(watcher.poll().is_none(), "same contents, same version");
+        fs::write(&path, r#"{"rounding": "truncate"}"#).unwrap();
+        watcher.modified = None;
+        assert_eq!(
+            watcher.poll().unwrap().rounding,
+            Some(RoundingPolicy::Truncate)
+        );
+
+        // the overrides win on every reload
+        watcher.set_overrides(EngineConfig {
+            rounding: Some(RoundingPolicy::HalfEven),
+            ..Default::default()
+        });
+        fs::write(&path, r#"{"rounding": "half-up"}"#).unwrap();
+        watcher.modified = None;
+        assert_eq!(
+            watcher.poll().unwrap().rounding,
+            Some(RoundingPolicy::HalfEven)
+        );
+
+        // an invalid file is skipped
+        fs::write(&path, "{").unwrap();
+        watcher.modified = None;
+        assert!(watcher.poll().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,17 +1,44 @@
-use crate::{errors::*, fmt_error, model::*};
+#[cfg(feature = "encryption")]
+use crate::encryption::{self, FieldKey};
+use crate::{
+    adjustment::Adjustment,
+    aging::OpenDisputeAge,
+    amount::Amount,
+    audit::AuditEntry,
+    errors::*,
+    event_log::LoggedEvent,
+    fmt_error,
+    ledger::{LedgerAccount, Posting},
+    limits::Limits,
+    model::*,
+    schedule::Date,
+    store::TxnStore,
+};
 use error_stack::{IntoReport, Result, ResultExt};
-use rusqlite::{params, Connection};
-use std::{fs, path::Path};
+use rusqlite::{params, types::ValueRef, Connection, OpenFlags, Params, Row};
+use std::{fs, io, path::Path};
+
+// the store runs about 25 distinct statements. the hot ones are prepared once and reused instead of being compiled
+// for every row
+const STATEMENT_CACHE_CAPACITY: usize = 32;
 
 // todo: take the file name and delete the file on drop.
 pub struct TxnDb {
     file_name: String,
     conn: Connection,
+    // persistent databases keep their file
+    persistent: bool,
+    // encrypts the operators and reasons of the adjustments
+    #[cfg(feature = "encryption")]
+    field_key: Option<FieldKey>,
 }
 
 // clean up the file system. don't want successive runs to interfere with each other.
 impl std::ops::Drop for TxnDb {
     fn drop(&mut self) {
+        if self.persistent {
+            return;
+        }
         let path = Path::new(&self.file_name);
         if fs::remove_file(path).is_err() {
             // todo: error
@@ -20,6 +47,7 @@ impl std::ops::Drop for TxnDb {
 }
 
 impl TxnDb {
+    /// a scratch database. existing tables are dropped and the file is deleted when the TxnDb is dropped
     pub fn new(file_name: &str) -> Result<Self, MyError> {
         let path = Path::new(file_name);
         let should_drop = path.exists();
@@ -29,105 +57,752 @@ impl TxnDb {
             .change_context(MyError::Db)?;
 
         if should_drop {
-            // deletes will cascade
-            conn.execute("DROP TABLE IF EXISTS Clients", [])
+            // children first, because of the foreign keys
+            for table in [
+                "Postings",
+                "Fees",
+                "Refunds",
+                "PendingTransactions",
+                "AuditLog",
+                "Events",
+                "DisputeHistory",
+                "Resolutions",
+                "Disputes",
+                "BalanceTransfers",
+                "PurgedTransfers",
+                "Adjustments",
+                "Limits",
+                "Clients",
+                "Checkpoints",
+                "Runs",
+                "RecordHashes",
+                "Interest",
+            ] {
+                conn.execute(&format!("DROP TABLE IF EXISTS {}", table), [])
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to drop {}", table))
+                    .change_context(MyError::Db)?;
+            }
+        }
+
+        create_tables(&conn)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(Self {
+            file_name: file_name.into(),
+            conn,
+            persistent: false,
+            #[cfg(feature = "encryption")]
+            field_key: None,
+        })
+    }
+
+    /// a persistent database. existing tables are reused and the file is kept, so a later run can continue from its checkpoints
+    pub fn open(file_name: &str) -> Result<Self, MyError> {
+        let conn = Connection::open(Path::new(file_name))
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to open txn db"))
+            .change_context(MyError::Db)?;
+
+        create_tables(&conn)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(Self {
+            file_name: file_name.into(),
+            conn,
+            persistent: true,
+            #[cfg(feature = "encryption")]
+            field_key: None,
+        })
+    }
+
+    /// encrypt the operator and reason of the adjustments stored from now on with `key`, and decrypt the stored ones
+    /// when they're read. adjustments stored before the key was set are read as they are; reading an encrypted one
+    /// without the key is an error
+    #[cfg(feature = "encryption")]
+    pub fn set_field_key(&mut self, key: Option<FieldKey>) {
+        self.field_key = key;
+    }
+
+    // the text stored for the field `name`
+    #[cfg(feature = "encryption")]
+    fn seal_field(&self, name: &str, value: String) -> String {
+        match &self.field_key {
+            Some(key) => key.encrypt(name, &value),
+            None => value,
+        }
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn seal_field(&self, _name: &str, value: String) -> String {
+        value
+    }
+
+    // the value of the field `name` from its stored text
+    #[cfg(feature = "encryption")]
+    fn open_field(&self, name: &str, field: String) -> Result<String, MyError> {
+        match &self.field_key {
+            Some(key) => key.decrypt(name, &field),
+            None if encryption::is_encrypted(&field) => Err(error_stack::report!(
+                MyError::Encryption
+            )
+            .attach_printable(fmt_error!(
+                "the {} is encrypted and no field key is set",
+                name
+            ))),
+            None => Ok(field),
+        }
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn open_field(&self, _name: &str, field: String) -> Result<String, MyError> {
+        Ok(field)
+    }
+
+    // like Connection::execute, with the statement taken from the cache
+    fn execute_cached<P: Params>(&self, sql: &str, params: P) -> rusqlite::Result<usize> {
+        self.conn.prepare_cached(sql)?.execute(params)
+    }
+
+    // like Connection::query_row, with the statement taken from the cache
+    fn query_row_cached<T, P, F>(&self, sql: &str, params: P, f: F) -> rusqlite::Result<T>
+    where
+        P: Params,
+        F: FnOnce(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        self.conn.prepare_cached(sql)?.query_row(params, f)
+    }
+
+    // used for BEGIN/COMMIT, where lock contention shows up. SQLITE_BUSY is reported as MyError::Busy so it can be retried
+    fn execute_batch(&self, sql: &str) -> Result<(), MyError> {
+        let res = self.conn.execute_batch(sql);
+        let busy = matches!(
+            &res,
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::DatabaseBusy
+        );
+        res.report()
+            .attach_printable_lazy(|| fmt_error!("failed to execute {}", sql))
+            .change_context(if busy { MyError::Busy } else { MyError::Db })
+    }
+
+    /// data retention: delete the deposits and withdrawals recorded more than `days` days ago, along with their
+    /// settled disputes, fees, and refunds. transfers with an open dispute are kept so the dispute can still be
+    /// resolved, and a refund and the deposit it refunds are only purged together.
+    /// the client balances, the postings, the event log, and the audit log (and its hashes) are not touched.
+    /// a purged transfer can no longer be disputed. its txn_id and client_id are kept, so the txn_id is still
+    /// rejected as a duplicate. returns the number of transfers deleted
+    pub fn purge_older_than(&mut self, days: u32) -> Result<usize, MyError> {
+        self.begin()?;
+        let res = self.purge(days);
+        match res {
+            Ok(_) => self.commit()?,
+            Err(_) => self.rollback()?,
+        }
+        res
+    }
+
+    fn purge(&mut self, days: u32) -> Result<usize, MyError> {
+        self.conn
+            .execute(
+                "INSERT INTO PurgedTransfers SELECT b.client_id, b.txn_id FROM BalanceTransfers b
+                    WHERE b.recorded_at < CAST(strftime('%s', 'now') AS INTEGER) - (?1) * 86400
+                    AND NOT EXISTS (
+                        SELECT 1 FROM Disputes d
+                        LEFT JOIN Resolutions r ON r.client_id = d.client_id AND r.txn_id = d.txn_id
+                        WHERE d.client_id = b.client_id AND d.txn_id = b.txn_id AND r.txn_id IS NULL
+                    )
+                    AND NOT EXISTS (
+                        SELECT 1 FROM Refunds f
+                        JOIN BalanceTransfers o ON o.txn_id IN (f.txn_id, f.original_txn_id)
+                        WHERE b.txn_id IN (f.txn_id, f.original_txn_id)
+                        AND (o.recorded_at IS NULL
+                            OR o.recorded_at >= CAST(strftime('%s', 'now') AS INTEGER) - (?1) * 86400)
+                    )",
+                params![days],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to record the purged balance transfers"))
+            .change_context(MyError::Db)?;
+
+        // foreign keys aren't enforced, so nothing cascades: the rows that refer to the balance transfers go first.
+        // the ids of earlier purges are no longer stored anywhere else, so matching all of them is harmless
+        for table in ["DisputeHistory", "Resolutions", "Disputes", "Fees"] {
+            self.conn
+                .execute(
+                    &format!(
+                        "DELETE FROM {} WHERE txn_id IN (SELECT txn_id FROM PurgedTransfers)",
+                        table
+                    ),
+                    [],
+                )
                 .report()
-                .attach_printable_lazy(|| fmt_error!("failed to drop Clients"))
+                .attach_printable_lazy(|| fmt_error!("failed to purge the {}", table))
                 .change_context(MyError::Db)?;
         }
+        self.conn
+            .execute(
+                "DELETE FROM Refunds WHERE txn_id IN (SELECT txn_id FROM PurgedTransfers)
+                    OR original_txn_id IN (SELECT txn_id FROM PurgedTransfers)",
+                [],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to purge the Refunds"))
+            .change_context(MyError::Db)?;
+        self.conn
+            .execute(
+                "DELETE FROM BalanceTransfers WHERE txn_id IN (SELECT txn_id FROM PurgedTransfers)",
+                [],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to purge balance transfers"))
+            .change_context(MyError::Db)
+    }
+}
+
+impl TxnDb {
+    /// every open dispute and how long it has been open, oldest first
+    pub fn open_dispute_ages(&self) -> Result<Vec<OpenDisputeAge>, MyError> {
+        self.query_open_dispute_ages(None)
+    }
+
+    /// the open disputes of one client and how long they have been open, oldest first
+    pub fn client_open_dispute_ages(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<OpenDisputeAge>, MyError> {
+        self.query_open_dispute_ages(Some(client_id))
+    }
+
+    // the open disputes of every client if `client_id` is None
+    fn query_open_dispute_ages(
+        &self,
+        client_id: Option<ClientId>,
+    ) -> Result<Vec<OpenDisputeAge>, MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT d.client_id, d.txn_id, (CAST(strftime('%s', 'now') AS INTEGER) - d.opened_at) / 86400
+                    FROM Disputes d
+                    LEFT JOIN Resolutions r ON r.client_id = d.client_id AND r.txn_id = d.txn_id
+                    WHERE r.txn_id IS NULL AND ((?1) IS NULL OR d.client_id = (?1))
+                    ORDER BY d.opened_at, d.client_id, d.txn_id",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![client_id], |row| {
+                let age_days: Option<i64> = row.get(2)?;
+                Ok(OpenDisputeAge {
+                    client_id: row.get(0)?,
+                    txn_id: row.get(1)?,
+                    age_days: age_days.map(|days| days.max(0) as u64),
+                })
+            })
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        let mut ages = Vec::new();
+        for age in iter {
+            ages.push(
+                age.report()
+                    .attach_printable_lazy(|| fmt_error!("failed to get row from Disputes"))
+                    .change_context(MyError::Db)?,
+            );
+        }
+        Ok(ages)
+    }
+}
 
-        conn.execute(
-            "CREATE TABLE Clients (
-                        client_id INTEGER NOT NULL,
-                        available INTEGER NOT NULL,
-                        held REAL NOT NULL,
-                        total REAL NOT NULL,
-                        locked INTEGER NOT NULL,
-                        PRIMARY KEY (client_id)
-                    )",
-            [],
-        )
-        .report()
-        .attach_printable_lazy(|| fmt_error!("failed to create Clients table"))
-        .change_context(MyError::Db)?;
+/// the result of an ad-hoc query: the column names, and the rows as JSON values (a blob is a hex string)
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
 
-        conn.execute(
-            "CREATE TABLE BalanceTransfers (
-                        client_id INTEGER NOT NULL,
-                        txn_id INTEGER NOT NULL UNIQUE,
-                        amount REAL NOT NULL,
-                        PRIMARY KEY (client_id, txn_id),
-                        FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE
-                    )",
-            [],
-        )
+impl QueryResult {
+    /// a header and one line per row. NULL is an empty field
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> Result<(), MyError> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let mut res = writer.write_record(&self.columns);
+        for row in &self.rows {
+            let fields = row.iter().map(|value| match value {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+            res = res.and_then(|_| writer.write_record(fields));
+        }
+        res.and_then(|_| writer.flush().map_err(csv::Error::from))
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write the query result"))
+            .change_context(MyError::Output)
+    }
+
+    /// an array with one object per row, keyed by column name
+    pub fn to_json(&self) -> serde_json::Value {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().cloned())
+                    .collect::<serde_json::Map<String, serde_json::Value>>()
+            })
+            .map(serde_json::Value::Object)
+            .collect()
+    }
+}
+
+impl TxnDb {
+    /// run one ad-hoc SQL statement (ex: a SELECT) against the database at `path` without modifying it. the file is
+    /// opened read-only and with `query_only` set, so a statement that writes fails instead of changing anything
+    pub fn query_read_only(path: &Path, sql: &str) -> Result<QueryResult, MyError> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to open {} read-only", path.display()))
+            .change_context(MyError::Db)?;
+        conn.execute_batch("PRAGMA query_only = ON")
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to set query_only"))
+            .change_context(MyError::Db)?;
+
+        let mut stmt = conn
+            .prepare(sql)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("invalid query"))
+            .change_context(MyError::Db)?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+        let mut rows = Vec::new();
+        let mut iter = stmt
+            .query([])
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to run the query"))
+            .change_context(MyError::Db)?;
+        loop {
+            let row = match iter.next() {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(e) => {
+                    return Err(e)
+                        .report()
+                        .attach_printable_lazy(|| fmt_error!("failed to run the query"))
+                        .change_context(MyError::Db)
+                }
+            };
+            let mut values = Vec::with_capacity(columns.len());
+            for idx in 0..columns.len() {
+                let value = match row.get_ref(idx) {
+                    Ok(ValueRef::Null) | Err(_) => serde_json::Value::Null,
+                    Ok(ValueRef::Integer(i)) => i.into(),
+                    Ok(ValueRef::Real(f)) => f.into(),
+                    Ok(ValueRef::Text(text)) => String::from_utf8_lossy(text).into(),
+                    Ok(ValueRef::Blob(blob)) => blob
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>()
+                        .into(),
+                };
+                values.push(value);
+            }
+            rows.push(values);
+        }
+        Ok(QueryResult { columns, rows })
+    }
+}
+
+fn create_tables(conn: &Connection) -> Result<(), MyError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Clients (
+                    client_id INTEGER NOT NULL,
+                    available INTEGER NOT NULL,
+                    held INTEGER NOT NULL,
+                    total INTEGER NOT NULL,
+                    locked INTEGER NOT NULL,
+                    overdraft_limit INTEGER,
+                    PRIMARY KEY (client_id)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Clients table"))
+    .change_context(MyError::Db)?;
+    // the client's own overdraft limit. NULL: the processor's policy applies
+    add_column_if_missing(conn, "Clients", "overdraft_limit", "INTEGER")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS BalanceTransfers (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL UNIQUE,
+                    amount INTEGER NOT NULL,
+                    recorded_at INTEGER,
+                    timestamp INTEGER,
+                    PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create BalanceTransfers table"))
+    .change_context(MyError::Db)?;
+    // databases created before retention existed don't have the column. their rows are never purged
+    add_column_if_missing(conn, "BalanceTransfers", "recorded_at", "INTEGER")?;
+    // when the transfer happened, from the input. NULL if the input had no timestamp
+    add_column_if_missing(conn, "BalanceTransfers", "timestamp", "INTEGER")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Disputes (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    opened_at INTEGER,
+                    PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id, txn_id) REFERENCES BalanceTransfers(client_id, txn_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Disputes table"))
+    .change_context(MyError::Db)?;
+    add_column_if_missing(conn, "Disputes", "opened_at", "INTEGER")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Resolutions (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    status INTEGER NOT NULL,
+                    PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id, txn_id) REFERENCES Disputes(client_id, txn_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Resolutions table"))
+    .change_context(MyError::Db)?;
+
+    // resolutions undone by reopening the dispute. the Resolutions row is moved here, so Resolutions only holds the
+    // current outcome of a dispute
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS DisputeHistory (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    status INTEGER NOT NULL,
+                    reopened_at INTEGER NOT NULL,
+                    FOREIGN KEY (client_id, txn_id) REFERENCES Disputes(client_id, txn_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create DisputeHistory table"))
+    .change_context(MyError::Db)?;
+
+    // the double-entry ledger. seq preserves the insertion order. the indexes find the postings of a client
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS Postings (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    txn_id INTEGER NOT NULL,
+                    debit TEXT NOT NULL,
+                    credit TEXT NOT NULL,
+                    amount INTEGER NOT NULL
+                );
+        CREATE INDEX IF NOT EXISTS PostingsByDebit ON Postings (debit);
+        CREATE INDEX IF NOT EXISTS PostingsByCredit ON Postings (credit);",
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Postings table"))
+    .change_context(MyError::Db)?;
+
+    // the clients' own limits. a NULL limit falls back to the processor's
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Limits (
+                    client_id INTEGER NOT NULL PRIMARY KEY,
+                    max_balance INTEGER,
+                    max_deposit INTEGER,
+                    max_withdrawal INTEGER,
+                    FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Limits table"))
+    .change_context(MyError::Db)?;
+
+    // the fees charged on balance transfers, refunded if the transfer is charged back
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Fees (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    amount INTEGER NOT NULL,
+                    PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id, txn_id) REFERENCES BalanceTransfers(client_id, txn_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Fees table"))
+    .change_context(MyError::Db)?;
+
+    // the refunds of deposits. the refund itself is a balance transfer too. a deposit can be refunded once
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Refunds (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL UNIQUE,
+                    original_txn_id INTEGER NOT NULL UNIQUE,
+                    PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id, original_txn_id) REFERENCES BalanceTransfers(client_id, txn_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Refunds table"))
+    .change_context(MyError::Db)?;
+
+    // the deposits to locked accounts queued by `LockedAccountPolicy::QueueDeposits`. seq preserves the order they
+    // were queued in
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS PendingTransactions (
+                    seq INTEGER PRIMARY KEY,
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL UNIQUE,
+                    amount INTEGER NOT NULL,
+                    queued_at INTEGER NOT NULL,
+                    timestamp INTEGER,
+                    FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create PendingTransactions table"))
+    .change_context(MyError::Db)?;
+    add_column_if_missing(conn, "PendingTransactions", "timestamp", "INTEGER")?;
+
+    // manual adjustments. seq preserves the insertion order
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Adjustments (
+                    seq INTEGER PRIMARY KEY,
+                    client_id INTEGER NOT NULL,
+                    amount INTEGER NOT NULL,
+                    reason TEXT NOT NULL,
+                    operator TEXT NOT NULL,
+                    recorded_at INTEGER NOT NULL,
+                    FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Adjustments table"))
+    .change_context(MyError::Db)?;
+
+    // the hash-chained audit log. the triggers make it append-only
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS AuditLog (
+                    seq INTEGER NOT NULL,
+                    txn_type TEXT NOT NULL,
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    amount REAL,
+                    outcome TEXT NOT NULL,
+                    prev_hash TEXT NOT NULL,
+                    hash TEXT NOT NULL,
+                    config_version TEXT,
+                    input TEXT,
+                    PRIMARY KEY (seq)
+                );
+        CREATE TRIGGER IF NOT EXISTS AuditLogNoUpdate BEFORE UPDATE ON AuditLog
+            BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+        CREATE TRIGGER IF NOT EXISTS AuditLogNoDelete BEFORE DELETE ON AuditLog
+            BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create AuditLog table"))
+    .change_context(MyError::Db)?;
+    add_column_if_missing(conn, "AuditLog", "config_version", "TEXT")?;
+    add_column_if_missing(conn, "AuditLog", "input", "TEXT")?;
+
+    // the event log the client balances can be rebuilt from. append-only, except for forget_client
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS Events (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    event TEXT NOT NULL,
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    amount INTEGER NOT NULL,
+                    recorded_at INTEGER NOT NULL
+                );
+        CREATE TRIGGER IF NOT EXISTS EventsNoUpdate BEFORE UPDATE ON Events
+            BEGIN SELECT RAISE(ABORT, 'the event log is append-only'); END;",
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Events table"))
+    .change_context(MyError::Db)?;
+
+    // the number of input rows committed per run. used to resume an interrupted run
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Checkpoints (
+                    run_id TEXT NOT NULL,
+                    rows INTEGER NOT NULL,
+                    PRIMARY KEY (run_id)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Checkpoints table"))
+    .change_context(MyError::Db)?;
+
+    // the input files processed to the end, by content. used to refuse a file that was already processed
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Runs (
+                    input_sha256 TEXT NOT NULL,
+                    input TEXT NOT NULL,
+                    processed_at INTEGER NOT NULL,
+                    PRIMARY KEY (input_sha256)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Runs table"))
+    .change_context(MyError::Db)?;
+
+    // the ids of the balance transfers deleted by data retention, so they are still rejected as duplicates
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS PurgedTransfers (
+                    client_id INTEGER NOT NULL,
+                    txn_id INTEGER NOT NULL,
+                    PRIMARY KEY (txn_id)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create PurgedTransfers table"))
+    .change_context(MyError::Db)?;
+
+    // the content hashes of the processed input records. used to skip a record that was already processed
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS RecordHashes (
+                    sha256 TEXT NOT NULL,
+                    recorded_at INTEGER NOT NULL,
+                    PRIMARY KEY (sha256)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create RecordHashes table"))
+    .change_context(MyError::Db)?;
+
+    // a single row: the last day interest was accrued through
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Interest (
+                    id INTEGER NOT NULL CHECK (id = 0),
+                    accrued_through INTEGER NOT NULL,
+                    PRIMARY KEY (id)
+                )",
+        [],
+    )
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to create Interest table"))
+    .change_context(MyError::Db)?;
+
+    migrate_to_minor_units(conn)
+}
+
+// PRAGMA user_version. 1: the amounts are integer minor units (ten-thousandths)
+const SCHEMA_VERSION: i64 = 1;
+
+// databases created before amounts were exact store them as REAL units. the input audit log keeps the amounts as
+// they were read (its hashes cover them), so it isn't converted
+fn migrate_to_minor_units(conn: &Connection) -> Result<(), MyError> {
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
         .report()
-        .attach_printable_lazy(|| fmt_error!("failed to create BalanceTransfers table"))
+        .attach_printable_lazy(|| fmt_error!("failed to read the schema version"))
         .change_context(MyError::Db)?;
-
-        conn.execute(
-            "CREATE TABLE Disputes (
-                        client_id INTEGER NOT NULL,
-                        txn_id INTEGER NOT NULL,
-                        PRIMARY KEY (client_id, txn_id),
-                        FOREIGN KEY (client_id, txn_id) REFERENCES BalanceTransfers(client_id, txn_id) ON DELETE CASCADE
-                    )",
-            [],
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    let minor = |column: &str| {
+        format!(
+            "{0} = CAST(ROUND({0} * {1}) AS INTEGER)",
+            column,
+            Amount::SCALE
         )
+    };
+    conn.execute_batch(&format!(
+        "BEGIN;
+        UPDATE Clients SET {}, {}, {};
+        UPDATE BalanceTransfers SET {3};
+        UPDATE Postings SET {3};
+        UPDATE Adjustments SET {3};
+        PRAGMA user_version = {4};
+        COMMIT;",
+        minor("available"),
+        minor("held"),
+        minor("total"),
+        minor("amount"),
+        SCHEMA_VERSION
+    ))
+    .report()
+    .attach_printable_lazy(|| fmt_error!("failed to convert the amounts to minor units"))
+    .change_context(MyError::Db)
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> Result<(), MyError> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = (?1)",
+            table
+        ))
+        .and_then(|mut stmt| stmt.exists(params![column]))
         .report()
-        .attach_printable_lazy(|| fmt_error!("failed to create Disputes table"))
+        .attach_printable_lazy(|| fmt_error!("failed to read the columns of {}", table))
         .change_context(MyError::Db)?;
-
+    if !exists {
         conn.execute(
-            "CREATE TABLE Resolutions (
-                        client_id INTEGER NOT NULL,
-                        txn_id INTEGER NOT NULL,
-                        status INTEGER NOT NULL,
-                        PRIMARY KEY (client_id, txn_id),
-                        FOREIGN KEY (client_id, txn_id) REFERENCES Disputes(client_id, txn_id) ON DELETE CASCADE
-                    )",
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
             [],
         )
         .report()
-        .attach_printable_lazy(|| fmt_error!("failed to create Resolutions table"))
+        .attach_printable_lazy(|| fmt_error!("failed to add {} to {}", column, table))
         .change_context(MyError::Db)?;
-
-        Ok(Self {
-            file_name: file_name.into(),
-            conn,
-        })
     }
+    Ok(())
+}
 
+// the calls made for a transaction are traced at the trace level, nested in the `process` span of the transaction
+impl TxnStore for TxnDb {
     // call this if get_client_state returns None
-    pub fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
         let client_state = ClientState::new(client_id);
         let locked = client_state.locked.to_u8();
-        self.conn
-            .execute(
-                "INSERT INTO Clients VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![
-                    &client_state.client_id,
-                    &client_state.available,
-                    &client_state.held,
-                    &client_state.total,
-                    &locked,
-                ],
-            )
-            .report()
-            .attach_printable_lazy(|| fmt_error!("failed to create new Client"))
-            .change_context(MyError::Db)?;
+        self.execute_cached(
+            "INSERT INTO Clients (client_id, available, held, total, locked) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                &client_state.client_id,
+                &client_state.available,
+                &client_state.held,
+                &client_state.total,
+                &locked,
+            ],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to create new Client"))
+        .change_context(MyError::Db)?;
         Ok(client_state)
     }
 
     // search for a client state (an account) by client ID
     // return None if not found
-    pub fn get_client_state(
-        &mut self,
-        client_id: ClientId,
-    ) -> Result<Option<ClientState>, MyError> {
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
         let mut stmt = self
             .conn
-            .prepare("SELECT * FROM Clients WHERE client_id=(?1)")
+            .prepare_cached("SELECT * FROM Clients WHERE client_id=(?1)")
             .report()
             .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
             .change_context(MyError::Db)?;
@@ -151,13 +826,12 @@ impl TxnDb {
 
     // used to display client account information
     // it's difficult to return an iterator to a query because the query only lives as long as the Statement. that's why this function accepts a closure
-    pub fn process_all_clients<F>(&self, f: F) -> Result<(), MyError>
-    where
-        F: Fn(ClientState),
-    {
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError> {
         let mut stmt = self
             .conn
-            .prepare("SELECT * FROM Clients")
+            // client_id is the rowid, so the rows are read in order rather than sorted
+            .prepare_cached("SELECT * FROM Clients ORDER BY client_id")
             .report()
             .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
             .change_context(MyError::Db)?;
@@ -175,9 +849,10 @@ impl TxnDb {
         Ok(())
     }
 
-    pub fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
+    #[tracing::instrument(level = "trace", skip_all, fields(client_id = client_state.client_id))]
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
         let locked = client_state.locked.to_u8();
-        self.conn.execute(
+        self.execute_cached(
             "UPDATE Clients SET available=(?1), held=(?2), total=(?3), locked=(?4) WHERE client_id=(?5)",
             params![&client_state.available, &client_state.held, &client_state.total, &locked, &client_state.client_id,],
         ).report()
@@ -189,14 +864,18 @@ impl TxnDb {
     // returns true if the operation succeeded
     // return false if the operation violated a SQL constraint
     // otherwise return an error
-    pub fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
-        let res = self.conn.execute(
-            "INSERT INTO BalanceTransfers VALUES (?1, ?2, ?3)",
-            params![&txn.client_id, txn.txn_id, txn.amount,],
+    #[tracing::instrument(level = "trace", skip_all, fields(client_id = txn.client_id, txn_id = txn.txn_id))]
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        // the id of a purged transfer can't be used again
+        let res = self.execute_cached(
+            "INSERT INTO BalanceTransfers (client_id, txn_id, amount, recorded_at, timestamp)
+                SELECT ?1, ?2, ?3, strftime('%s', 'now'), ?4
+                WHERE NOT EXISTS (SELECT 1 FROM PurgedTransfers WHERE txn_id = ?2)",
+            params![&txn.client_id, txn.txn_id, txn.amount, txn.timestamp],
         );
 
         match res {
-            Ok(_) => Ok(true),
+            Ok(inserted) => Ok(inserted == 1),
             Err(e) => {
                 filter_sql_errors(e)
                     .report()
@@ -210,107 +889,899 @@ impl TxnDb {
     // returns true if the operation succeeded
     // return false if the operation violated a SQL constraint
     // otherwise return an error
-    pub fn try_insert_dispute(
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_insert_dispute(
         &mut self,
         client_id: ClientId,
         txn_id: TransactionId,
     ) -> Result<bool, MyError> {
-        let res = self.conn.execute(
-            "INSERT INTO Disputes VALUES (?1, ?2)",
+        // neither a refund nor a refunded deposit can be disputed
+        let res = self.execute_cached(
+            "INSERT INTO Disputes SELECT ?1, ?2, strftime('%s', 'now')
+                WHERE NOT EXISTS (SELECT 1 FROM Refunds WHERE txn_id = ?2 OR original_txn_id = ?2)",
             params![&client_id, &txn_id,],
         );
         match res {
-            Ok(_) => Ok(true),
-            Err(e) => {
-                filter_sql_errors(e)
-                    .report()
-                    .attach_printable_lazy(|| fmt_error!("failed to add dispute"))
-                    .change_context(MyError::Db)?;
-                Ok(false)
-            }
+            Ok(inserted) => Ok(inserted == 1),
+            Err(e) => {
+                filter_sql_errors(e)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to add dispute"))
+                    .change_context(MyError::Db)?;
+                Ok(false)
+            }
+        }
+    }
+
+    // returns true if the operation succeeded
+    // return false if the operation violated a SQL constraint
+    // otherwise return an error
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        let status = DisputeStatus::Resolved.to_u8();
+        let res = self.execute_cached(
+            "INSERT INTO Resolutions VALUES (?1, ?2, ?3)",
+            params![&client_id, &txn_id, &status,],
+        );
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                filter_sql_errors(e)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to apply resolution"))
+                    .change_context(MyError::Db)?;
+                Ok(false)
+            }
+        }
+    }
+
+    // returns true if the operation succeeded
+    // return false if the operation violated a SQL constraint
+    // otherwise return an error
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        let status = DisputeStatus::Chargeback.to_u8();
+        let res = self.execute_cached(
+            "INSERT INTO Resolutions VALUES (?1, ?2, ?3)",
+            params![&client_id, &txn_id, &status,],
+        );
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                filter_sql_errors(e)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to apply chargeback"))
+                    .change_context(MyError::Db)?;
+                Ok(false)
+            }
+        }
+    }
+
+    // returns false if the dispute isn't resolved. the caller groups the two statements with begin/commit
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        let status = DisputeStatus::Resolved.to_u8();
+        let moved = self
+            .execute_cached(
+                "INSERT INTO DisputeHistory
+                    SELECT client_id, txn_id, status, strftime('%s', 'now') FROM Resolutions
+                    WHERE client_id = ?1 AND txn_id = ?2 AND status = ?3",
+                params![&client_id, &txn_id, &status],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to record dispute history"))
+            .change_context(MyError::Db)?;
+        if moved == 0 {
+            return Ok(false);
+        }
+        self.execute_cached(
+            "DELETE FROM Resolutions WHERE client_id = ?1 AND txn_id = ?2",
+            params![&client_id, &txn_id],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to reopen dispute"))
+        .change_context(MyError::Db)?;
+        Ok(true)
+    }
+
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError> {
+        self.query_row_cached(
+            "SELECT COUNT(*) FROM DisputeHistory WHERE client_id = ?1 AND txn_id = ?2",
+            params![&client_id, &txn_id],
+            |row| row.get(0),
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to count dispute reopens"))
+        .change_context(MyError::Db)
+    }
+
+    // return the balance transfer is it exists in the database
+    // return None if not found
+    // return an error on database failure
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT * FROM BalanceTransfers WHERE client_id = (?1) AND txn_id = (?2)",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let mut txn_iter = stmt
+            .query_map(params![client_id, txn_id], BalanceTransfer::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to execute statement"))
+            .change_context(MyError::Db)?;
+
+        let txn = match txn_iter.next() {
+            Some(r) => r
+                .report()
+                .attach_printable_lazy(|| fmt_error!("somehow failed"))
+                .change_context(MyError::Db)?,
+            None => return Ok(None),
+        };
+        Ok(Some(txn))
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT overdraft_limit FROM Clients WHERE client_id = (?1)",
+            params![client_id],
+            |row| row.get(0),
+        );
+        match res {
+            Ok(limit) => Ok(limit),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| {
+                    fmt_error!("failed to get the overdraft limit of client {}", client_id)
+                })
+                .change_context(MyError::Db),
+        }
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        self.execute_cached(
+            "UPDATE Clients SET overdraft_limit = (?1) WHERE client_id = (?2)",
+            params![limit, client_id],
+        )
+        .report()
+        .attach_printable_lazy(|| {
+            fmt_error!("failed to set the overdraft limit of client {}", client_id)
+        })
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT max_balance, max_deposit, max_withdrawal FROM Limits WHERE client_id = (?1)",
+            params![client_id],
+            |row| {
+                Ok(Limits {
+                    max_balance: row.get(0)?,
+                    max_deposit: row.get(1)?,
+                    max_withdrawal: row.get(2)?,
+                })
+            },
+        );
+        match res {
+            Ok(limits) => Ok(Some(limits)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| {
+                    fmt_error!("failed to get the limits of client {}", client_id)
+                })
+                .change_context(MyError::Db),
+        }
+    }
+
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        let res = match limits {
+            // the client must exist
+            Some(limits) => self.execute_cached(
+                "INSERT OR REPLACE INTO Limits (client_id, max_balance, max_deposit, max_withdrawal)
+                    SELECT client_id, ?2, ?3, ?4 FROM Clients WHERE client_id = (?1)",
+                params![
+                    client_id,
+                    limits.max_balance,
+                    limits.max_deposit,
+                    limits.max_withdrawal
+                ],
+            ),
+            None => self.execute_cached(
+                "DELETE FROM Limits WHERE client_id = (?1)",
+                params![client_id],
+            ),
+        };
+        res.report()
+            .attach_printable_lazy(|| {
+                fmt_error!("failed to set the limits of client {}", client_id)
+            })
+            .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT INTO Fees VALUES (?1, ?2, ?3)",
+            params![client_id, txn_id, fee],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to insert the fee of txn {}", txn_id))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT amount FROM Fees WHERE client_id = (?1) AND txn_id = (?2)",
+            params![client_id, txn_id],
+            |row| row.get(0),
+        );
+        match res {
+            Ok(fee) => Ok(Some(fee)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get the fee of txn {}", txn_id))
+                .change_context(MyError::Db),
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError> {
+        let res = self.execute_cached(
+            "INSERT INTO Refunds SELECT ?1, ?2, ?3
+                WHERE NOT EXISTS (SELECT 1 FROM Disputes WHERE client_id = ?1 AND txn_id = ?3)",
+            params![refund.client_id, refund.txn_id, refund.original_txn_id],
+        );
+        match res {
+            Ok(inserted) => Ok(inserted == 1),
+            Err(e) => {
+                filter_sql_errors(e)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to add refund"))
+                    .change_context(MyError::Db)?;
+                Ok(false)
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT * FROM Refunds WHERE client_id = (?1) AND txn_id = (?2)",
+            params![client_id, txn_id],
+            Refund::from_row,
+        );
+        match res {
+            Ok(refund) => Ok(Some(refund)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get refund {}", txn_id))
+                .change_context(MyError::Db),
+        }
+    }
+
+    // returns false if the transaction id is already queued
+    #[tracing::instrument(level = "trace", skip_all, fields(client_id = txn.client_id, txn_id = txn.txn_id))]
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        let res = self.execute_cached(
+            "INSERT INTO PendingTransactions (client_id, txn_id, amount, queued_at, timestamp)
+                VALUES (?1, ?2, ?3, strftime('%s', 'now'), ?4)",
+            params![txn.client_id, txn.txn_id, txn.amount, txn.timestamp],
+        );
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                filter_sql_errors(e)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to queue deposit"))
+                    .change_context(MyError::Db)?;
+                Ok(false)
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError> {
+        let mut queued = Vec::new();
+        {
+            let mut stmt = self
+                .conn
+                .prepare_cached(
+                    "SELECT client_id, txn_id, amount, timestamp FROM PendingTransactions
+                        WHERE client_id = (?1) ORDER BY seq",
+                )
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+                .change_context(MyError::Db)?;
+            let iter = stmt
+                .query_map(params![client_id], BalanceTransfer::from_row)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+                .change_context(MyError::Db)?;
+            for txn in iter {
+                queued.push(
+                    txn.report()
+                        .attach_printable_lazy(|| {
+                            fmt_error!("failed to get row from PendingTransactions")
+                        })
+                        .change_context(MyError::Db)?,
+                );
+            }
+        }
+        self.execute_cached(
+            "DELETE FROM PendingTransactions WHERE client_id = (?1)",
+            params![client_id],
+        )
+        .report()
+        .attach_printable_lazy(|| {
+            fmt_error!(
+                "failed to delete the queued deposits of client {}",
+                client_id
+            )
+        })
+        .change_context(MyError::Db)?;
+        Ok(queued)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT * FROM BalanceTransfers WHERE txn_id = (?1)",
+            params![txn_id],
+            BalanceTransfer::from_row,
+        );
+        match res {
+            Ok(txn) => Ok(Some(txn)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get balance transfer {}", txn_id))
+                .change_context(MyError::Db),
+        }
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT b.* FROM Disputes d
+                    JOIN BalanceTransfers b ON b.client_id = d.client_id AND b.txn_id = d.txn_id
+                    LEFT JOIN Resolutions r ON r.client_id = d.client_id AND r.txn_id = d.txn_id
+                    WHERE d.client_id = (?1) AND r.txn_id IS NULL
+                    ORDER BY d.txn_id",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![client_id], BalanceTransfer::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        let mut open = Vec::new();
+        for txn in iter {
+            open.push(
+                txn.report()
+                    .attach_printable_lazy(|| fmt_error!("failed to get row from Disputes"))
+                    .change_context(MyError::Db)?,
+            );
+        }
+        Ok(open)
+    }
+
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT b.client_id, b.txn_id, b.amount, b.timestamp, d.txn_id IS NOT NULL AS disputed,
+                        r.status AS resolution, f.original_txn_id
+                    FROM BalanceTransfers b
+                    LEFT JOIN Disputes d ON d.client_id = b.client_id AND d.txn_id = b.txn_id
+                    LEFT JOIN Resolutions r ON r.client_id = b.client_id AND r.txn_id = b.txn_id
+                    LEFT JOIN Refunds f ON f.txn_id = b.txn_id
+                    ORDER BY b.client_id, b.txn_id",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![], TransferRecord::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for record in iter {
+            let record = record
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from BalanceTransfers"))
+                .change_context(MyError::Db)?;
+            f(record);
+        }
+        Ok(())
+    }
+
+    fn forget_client(
+        &mut self,
+        client_id: ClientId,
+        summary: &[Posting],
+    ) -> Result<usize, MyError> {
+        // foreign keys aren't enforced, so nothing cascades: the rows that refer to the balance transfers go first
+        for table in [
+            "DisputeHistory",
+            "Resolutions",
+            "Disputes",
+            "Fees",
+            "Refunds",
+        ] {
+            self.execute_cached(
+                &format!("DELETE FROM {} WHERE client_id = (?1)", table),
+                params![client_id],
+            )
+            .report()
+            .attach_printable_lazy(|| {
+                fmt_error!("failed to delete the {} of client {}", table, client_id)
+            })
+            .change_context(MyError::Db)?;
+        }
+        let deleted = self
+            .execute_cached(
+                "DELETE FROM BalanceTransfers WHERE client_id = (?1)",
+                params![client_id],
+            )
+            .report()
+            .attach_printable_lazy(|| {
+                fmt_error!(
+                    "failed to delete the balance transfers of client {}",
+                    client_id
+                )
+            })
+            .change_context(MyError::Db)?;
+
+        self.execute_cached(
+            "DELETE FROM Adjustments WHERE client_id = (?1)",
+            params![client_id],
+        )
+        .report()
+        .attach_printable_lazy(|| {
+            fmt_error!("failed to delete the adjustments of client {}", client_id)
+        })
+        .change_context(MyError::Db)?;
+
+        self.execute_cached(
+            "DELETE FROM Events WHERE client_id = (?1)",
+            params![client_id],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to delete the events of client {}", client_id))
+        .change_context(MyError::Db)?;
+
+        let accounts = [
+            LedgerAccount::ClientAvailable(client_id).to_string(),
+            LedgerAccount::ClientHeld(client_id).to_string(),
+        ];
+        self.execute_cached(
+            "DELETE FROM Postings WHERE debit IN (?1, ?2) OR credit IN (?1, ?2)",
+            params![accounts[0], accounts[1]],
+        )
+        .report()
+        .attach_printable_lazy(|| {
+            fmt_error!("failed to delete the postings of client {}", client_id)
+        })
+        .change_context(MyError::Db)?;
+        for posting in summary {
+            self.insert_posting(posting)?;
+        }
+        Ok(deleted)
+    }
+
+    #[tracing::instrument(level = "trace", skip_all, fields(txn_id = posting.txn_id))]
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT INTO Postings (txn_id, debit, credit, amount) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                &posting.txn_id,
+                posting.debit.to_string(),
+                posting.credit.to_string(),
+                &posting.amount,
+            ],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to insert posting"))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT txn_id, debit, credit, amount FROM Postings ORDER BY seq")
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![], Posting::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for posting in iter {
+            let posting = posting
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from Postings"))
+                .change_context(MyError::Db)?;
+            f(posting);
+        }
+        Ok(())
+    }
+
+    fn process_client_postings(
+        &self,
+        client_id: ClientId,
+        f: &mut dyn FnMut(Posting),
+    ) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT txn_id, debit, credit, amount FROM Postings
+                    WHERE debit IN (?1, ?2) OR credit IN (?1, ?2)
+                    ORDER BY seq",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let accounts = [
+            LedgerAccount::ClientAvailable(client_id).to_string(),
+            LedgerAccount::ClientHeld(client_id).to_string(),
+        ];
+        let iter = stmt
+            .query_map(params![accounts[0], accounts[1]], Posting::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for posting in iter {
+            let posting = posting
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from Postings"))
+                .change_context(MyError::Db)?;
+            f(posting);
+        }
+        Ok(())
+    }
+
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT INTO Adjustments (client_id, amount, reason, operator, recorded_at)
+                    VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+            params![
+                &adjustment.client_id,
+                &adjustment.amount,
+                self.seal_field("reason", adjustment.reason.to_string()),
+                self.seal_field("operator", adjustment.operator.clone()),
+            ],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to insert adjustment"))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT client_id, amount, reason, operator FROM Adjustments ORDER BY seq",
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for row in iter {
+            let (client_id, amount, reason, operator) = row
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from Adjustments"))
+                .change_context(MyError::Db)?;
+            let reason = self.open_field("reason", reason)?;
+            f(Adjustment {
+                client_id,
+                amount,
+                reason: reason
+                    .parse()
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("invalid adjustment reason {}", reason))
+                    .change_context(MyError::Db)?,
+                operator: self.open_field("operator", operator)?,
+            });
+        }
+        Ok(())
+    }
+
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT INTO AuditLog VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                entry.seq as i64,
+                &entry.txn_type,
+                &entry.client_id,
+                &entry.txn_id,
+                &entry.amount,
+                &entry.outcome,
+                &entry.prev_hash,
+                &entry.hash,
+                &entry.config_version,
+                &entry.input,
+            ],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to append audit entry {}", entry.seq))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT * FROM AuditLog ORDER BY seq")
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![], AuditEntry::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for entry in iter {
+            let entry = entry
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from AuditLog"))
+                .change_context(MyError::Db)?;
+            f(entry);
+        }
+        Ok(())
+    }
+
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT INTO Events (event, client_id, txn_id, amount, recorded_at)
+                    VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+            params![
+                event.kind.to_string(),
+                &event.client_id,
+                &event.txn_id,
+                &event.amount,
+            ],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to append event"))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT event, client_id, txn_id, amount FROM Events ORDER BY seq")
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
+            .change_context(MyError::Db)?;
+
+        let iter = stmt
+            .query_map(params![], LoggedEvent::from_row)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to get query iterator"))
+            .change_context(MyError::Db)?;
+
+        for event in iter {
+            let event = event
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get row from Events"))
+                .change_context(MyError::Db)?;
+            f(event);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn begin(&mut self) -> Result<(), MyError> {
+        self.execute_batch("BEGIN")
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn commit(&mut self) -> Result<(), MyError> {
+        self.execute_batch("COMMIT")
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn rollback(&mut self) -> Result<(), MyError> {
+        self.execute_batch("ROLLBACK")
+    }
+
+    fn get_checkpoint(&self, run_id: &str) -> Result<Option<u64>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT rows FROM Checkpoints WHERE run_id = (?1)",
+            params![run_id],
+            |row| row.get::<_, i64>(0),
+        );
+        match res {
+            Ok(rows) => Ok(Some(rows as u64)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get checkpoint"))
+                .change_context(MyError::Db),
+        }
+    }
+
+    fn set_checkpoint(&mut self, run_id: &str, rows: u64) -> Result<(), MyError> {
+        self.execute_cached(
+                "INSERT INTO Checkpoints VALUES (?1, ?2) ON CONFLICT(run_id) DO UPDATE SET rows = excluded.rows",
+                params![run_id, rows as i64],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to set checkpoint"))
+            .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT accrued_through FROM Interest WHERE id = 0",
+            [],
+            |row| row.get::<_, i64>(0),
+        );
+        match res {
+            Ok(days) => Ok(Some(Date::from_days_since_epoch(days))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get the interest date"))
+                .change_context(MyError::Db),
         }
     }
 
-    // returns true if the operation succeeded
-    // return false if the operation violated a SQL constraint
-    // otherwise return an error
-    pub fn try_resolve_dispute(
-        &mut self,
-        client_id: ClientId,
-        txn_id: TransactionId,
-    ) -> Result<bool, MyError> {
-        let status = DisputeStatus::Resolved.to_u8();
-        let res = self.conn.execute(
-            "INSERT INTO Resolutions VALUES (?1, ?2, ?3)",
-            params![&client_id, &txn_id, &status,],
+    fn set_interest_accrued_through(&mut self, date: Date) -> Result<(), MyError> {
+        self.execute_cached(
+                "INSERT INTO Interest VALUES (0, ?1) ON CONFLICT(id) DO UPDATE SET accrued_through = excluded.accrued_through",
+                params![date.days_since_epoch()],
+            )
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to set the interest date"))
+            .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn is_purged_txn_id(&self, txn_id: TransactionId) -> Result<bool, MyError> {
+        self.query_row_cached(
+            "SELECT EXISTS (SELECT 1 FROM PurgedTransfers WHERE txn_id = (?1))",
+            params![txn_id],
+            |row| row.get(0),
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to look up purged txn {}", txn_id))
+        .change_context(MyError::Db)
+    }
+
+    fn get_input_run(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        let res = self.query_row_cached(
+            "SELECT * FROM Runs WHERE input_sha256 = (?1)",
+            params![input_sha256],
+            InputRun::from_row,
         );
         match res {
-            Ok(_) => Ok(true),
-            Err(e) => {
-                filter_sql_errors(e)
-                    .report()
-                    .attach_printable_lazy(|| fmt_error!("failed to apply resolution"))
-                    .change_context(MyError::Db)?;
-                Ok(false)
-            }
+            Ok(run) => Ok(Some(run)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to get input run"))
+                .change_context(MyError::Db),
         }
     }
 
-    // returns true if the operation succeeded
-    // return false if the operation violated a SQL constraint
-    // otherwise return an error
-    pub fn try_chargeback_dispute(
-        &mut self,
-        client_id: ClientId,
-        txn_id: TransactionId,
-    ) -> Result<bool, MyError> {
-        let status = DisputeStatus::Chargeback.to_u8();
-        let res = self.conn.execute(
-            "INSERT INTO Resolutions VALUES (?1, ?2, ?3)",
-            params![&client_id, &txn_id, &status,],
+    fn insert_input_run(&mut self, run: &InputRun) -> Result<(), MyError> {
+        self.execute_cached(
+            "INSERT OR IGNORE INTO Runs VALUES (?1, ?2, ?3)",
+            params![run.input_sha256, run.input, run.processed_at as i64],
+        )
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to insert input run"))
+        .change_context(MyError::Db)?;
+        Ok(())
+    }
+
+    fn try_insert_record_hash(&mut self, sha256: &str) -> Result<bool, MyError> {
+        let res = self.execute_cached(
+            "INSERT INTO RecordHashes VALUES (?1, strftime('%s', 'now'))",
+            params![sha256],
         );
         match res {
             Ok(_) => Ok(true),
             Err(e) => {
                 filter_sql_errors(e)
                     .report()
-                    .attach_printable_lazy(|| fmt_error!("failed to apply chargeback"))
+                    .attach_printable_lazy(|| fmt_error!("failed to insert record hash"))
                     .change_context(MyError::Db)?;
                 Ok(false)
             }
         }
     }
-
-    // return the balance transfer is it exists in the database
-    // return None if not found
-    // return an error on database failure
-    pub fn get_balance_transfer(
-        &self,
-        client_id: ClientId,
-        txn_id: TransactionId,
-    ) -> Result<Option<BalanceTransfer>, MyError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT * FROM BalanceTransfers WHERE client_id = (?1) AND txn_id = (?2)")
-            .report()
-            .attach_printable_lazy(|| fmt_error!("failed to prepare statement"))
-            .change_context(MyError::Db)?;
-
-        let mut txn_iter = stmt
-            .query_map(params![client_id, txn_id], BalanceTransfer::from_row)
-            .report()
-            .attach_printable_lazy(|| fmt_error!("failed to execute statement"))
-            .change_context(MyError::Db)?;
-
-        let txn = match txn_iter.next() {
-            Some(r) => r
-                .report()
-                .attach_printable_lazy(|| fmt_error!("somehow failed"))
-                .change_context(MyError::Db)?,
-            None => return Ok(None),
-        };
-        Ok(Some(txn))
-    }
 }
 
 // certain operations are expected to fail due to constraint violations. filter these errors out
@@ -325,12 +1796,19 @@ fn filter_sql_errors(e: rusqlite::Error) -> rusqlite::Result<(), rusqlite::Error
 }
 
 #[cfg(test)]
+// the tests fail with assert!(false) after printing the report
+#[allow(clippy::assertions_on_constants)]
 mod test {
     use super::*;
+    use crate::amount::amt;
     use random_string::generate;
+    use tracing_subscriber::EnvFilter;
 
     fn init() -> TxnDb {
-        let _ = env_logger::builder().is_test(true).try_init();
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_test_writer()
+            .try_init();
         let charset = "abcdefghijklmnopqrstuvwxyz";
         TxnDb::new(&format!("{}.db", generate(6, charset)))
             .attach_printable_lazy(|| fmt_error!("database failure"))
@@ -365,6 +1843,18 @@ mod test {
         assert_eq!(retrieved.client_id, client.client_id);
     }
 
+    #[test]
+    fn test_clients_in_order() {
+        let mut db = init();
+        for client_id in [3, 1, 2] {
+            db.create_client_state(client_id).unwrap();
+        }
+        let mut clients = Vec::new();
+        db.process_all_clients(&mut |client| clients.push(client.client_id))
+            .unwrap();
+        assert_eq!(clients, vec![1, 2, 3]);
+    }
+
     #[test]
     fn test_update_client() {
         let mut db = init();
@@ -379,7 +1869,7 @@ mod test {
         };
         assert_eq!(client.available, 0.0);
 
-        client.available = 1.0;
+        client.available = amt(1.0);
         if let Err(e) = db.update_client_state(&client) {
             print_report(e);
             assert!(false);
@@ -421,7 +1911,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -435,7 +1926,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let mut res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -445,6 +1937,23 @@ mod test {
         assert!(!res);
     }
 
+    #[test]
+    fn test_balance_transfer_timestamp() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        for (txn_id, timestamp) in [(1, Some(1_704_067_200)), (2, None)] {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: amt(1.0),
+                timestamp,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+            assert_eq!(db.get_balance_transfer(123, txn_id).unwrap(), Some(xfer));
+            assert_eq!(db.get_balance_transfer_by_id(txn_id).unwrap(), Some(xfer));
+        }
+    }
+
     #[test]
     fn test_get_balance_transfer() {
         let mut db = init();
@@ -452,7 +1961,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -473,7 +1983,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let mut res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -492,7 +2003,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let res = db.try_insert_dispute(xfer.client_id, xfer.txn_id).unwrap();
@@ -506,7 +2018,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let mut res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -534,7 +2047,8 @@ mod test {
         let xfer = BalanceTransfer {
             client_id: 123,
             txn_id: 1,
-            amount: 1.0,
+            amount: amt(1.0),
+            timestamp: None,
         };
 
         let mut res = db.try_insert_balance_transfer(xfer).unwrap();
@@ -555,4 +2069,617 @@ mod test {
             .unwrap();
         assert!(!res);
     }
+
+    #[test]
+    fn test_reopen_dispute() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        assert!(db.try_insert_dispute(123, 1).unwrap());
+        // an open dispute can't be reopened
+        assert!(!db.try_reopen_dispute(123, 1).unwrap());
+
+        for reopens in 1..=2 {
+            assert!(db.try_resolve_dispute(123, 1).unwrap());
+            assert!(db.try_reopen_dispute(123, 1).unwrap());
+            assert_eq!(db.get_open_disputes(123).unwrap(), vec![xfer]);
+            assert_eq!(db.count_dispute_reopens(123, 1).unwrap(), reopens);
+        }
+
+        // a chargeback is final
+        assert!(db.try_chargeback_dispute(123, 1).unwrap());
+        assert!(!db.try_reopen_dispute(123, 1).unwrap());
+        assert_eq!(db.count_dispute_reopens(123, 1).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut db = init();
+        db.begin().unwrap();
+        let _ = db.create_client_state(123);
+        db.set_checkpoint("run", 1).unwrap();
+        db.rollback().unwrap();
+        assert!(db.get_client_state(123).unwrap().is_none());
+        assert!(db.get_checkpoint("run").unwrap().is_none());
+
+        db.begin().unwrap();
+        let _ = db.create_client_state(123);
+        db.set_checkpoint("run", 1).unwrap();
+        db.commit().unwrap();
+        assert!(db.get_client_state(123).unwrap().is_some());
+        assert_eq!(db.get_checkpoint("run").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_open_persists() {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let file_name = format!("{}.db", generate(6, charset));
+        {
+            let mut db = TxnDb::open(&file_name).unwrap();
+            let _ = db.create_client_state(123);
+            db.set_checkpoint("run", 5).unwrap();
+            db.set_checkpoint("run", 6).unwrap();
+        }
+        {
+            let mut db = TxnDb::open(&file_name).unwrap();
+            assert!(db.get_client_state(123).unwrap().is_some());
+            assert_eq!(db.get_checkpoint("run").unwrap(), Some(6));
+        }
+        // new() starts from scratch and deletes the file afterwards
+        let mut db = TxnDb::new(&file_name).unwrap();
+        assert!(db.get_client_state(123).unwrap().is_none());
+        assert!(db.get_checkpoint("run").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        for txn_id in 1..=3 {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: Amount::from_minor_units(txn_id as i64 * Amount::SCALE),
+                timestamp: None,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+            assert!(db.try_insert_dispute(123, txn_id).unwrap());
+        }
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        assert!(db.try_chargeback_dispute(123, 3).unwrap());
+
+        let open = db.get_open_disputes(123).unwrap();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].txn_id, 2);
+        assert!(db.get_open_disputes(124).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_queued_deposits() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        let _ = db.create_client_state(124);
+        for (client_id, txn_id) in [(123, 3), (124, 2), (123, 1)] {
+            let xfer = BalanceTransfer {
+                client_id,
+                txn_id,
+                amount: amt(1.0),
+                timestamp: Some(txn_id as i64 * 60),
+            };
+            assert!(db.try_queue_deposit(xfer).unwrap());
+            assert!(!db.try_queue_deposit(xfer).unwrap());
+        }
+
+        // in the order they were queued, with their timestamps
+        let queued: Vec<(TransactionId, Option<i64>)> = db
+            .take_queued_deposits(123)
+            .unwrap()
+            .iter()
+            .map(|txn| (txn.txn_id, txn.timestamp))
+            .collect();
+        assert_eq!(queued, vec![(3, Some(180)), (1, Some(60))]);
+        assert!(db.take_queued_deposits(123).unwrap().is_empty());
+        assert_eq!(db.take_queued_deposits(124).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_audit_log_is_append_only() {
+        let mut db = init();
+        let chain = crate::audit::AuditChain::new();
+        let txn = RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id: 1,
+            txn_id: 1,
+            amount: Some(1.0.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let entry = chain.next_entry(&txn, "applied");
+        db.append_audit_entry(&entry).unwrap();
+
+        let mut retrieved = Vec::new();
+        db.process_all_audit_entries(&mut |e| retrieved.push(e))
+            .unwrap();
+        assert_eq!(retrieved, vec![entry]);
+
+        assert!(db
+            .conn
+            .execute("UPDATE AuditLog SET outcome = 'malformed'", [])
+            .is_err());
+        assert!(db.conn.execute("DELETE FROM AuditLog", []).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_field_encryption() {
+        use crate::{adjustment::AdjustmentReason, audit::AuditChain};
+        let key =
+            FieldKey::from_hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+                .unwrap();
+        let mut db = init();
+        let _ = db.create_client_state(1);
+        let adjustment = Adjustment {
+            client_id: 1,
+            amount: amt(-0.25),
+            reason: AdjustmentReason::WriteOff,
+            operator: "alice".to_string(),
+        };
+        // the first one is stored before the key is set
+        db.insert_adjustment(&adjustment).unwrap();
+        db.set_field_key(Some(key.clone()));
+        db.insert_adjustment(&adjustment).unwrap();
+
+        let stored: Vec<(String, String)> = db
+            .conn
+            .prepare("SELECT reason, operator FROM Adjustments ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(stored[0], ("write-off".to_string(), "alice".to_string()));
+        let (reason, operator) = &stored[1];
+        assert!(encryption::is_encrypted(reason) && !reason.contains("write-off"));
+        assert!(encryption::is_encrypted(operator) && !operator.contains("alice"));
+
+        let mut retrieved = Vec::new();
+        db.process_all_adjustments(&mut |a| retrieved.push(a))
+            .unwrap();
+        assert_eq!(retrieved, vec![adjustment.clone(), adjustment]);
+        db.set_field_key(None);
+        assert!(db.process_all_adjustments(&mut |_| {}).is_err());
+
+        // the input record of an audit entry
+        let mut chain = AuditChain::new();
+        chain.set_field_key(Some(key.clone()));
+        let txn = RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id: 1,
+            txn_id: 1,
+            amount: Some(1.5.into()),
+            timestamp: Some(1700000000),
+            original_txn_id: None,
+        };
+        let entry = chain.next_entry(&txn, "applied");
+        db.append_audit_entry(&entry).unwrap();
+        let input: String = db
+            .conn
+            .query_row("SELECT input FROM AuditLog", [], |row| row.get(0))
+            .unwrap();
+        assert!(encryption::is_encrypted(&input) && !input.contains("1700000000"));
+        assert_eq!(
+            key.decrypt("input", &input).unwrap(),
+            "deposit,1,1,1.5,1700000000,"
+        );
+        let mut retrieved = Vec::new();
+        db.process_all_audit_entries(&mut |e| retrieved.push(e))
+            .unwrap();
+        assert_eq!(retrieved, vec![entry]);
+    }
+
+    #[test]
+    fn test_postings() {
+        let mut db = init();
+        let deposit = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.5),
+            timestamp: None,
+        };
+        let mut postings = crate::ledger::balance_transfer_postings(&deposit);
+        postings.extend(crate::ledger::dispute_postings(&deposit));
+        for posting in &postings {
+            db.insert_posting(posting).unwrap();
+        }
+
+        let mut retrieved = Vec::new();
+        db.process_all_postings(&mut |p| retrieved.push(p)).unwrap();
+        assert_eq!(retrieved, postings);
+
+        // the postings of one client, in order
+        let other = BalanceTransfer {
+            client_id: 7,
+            txn_id: 2,
+            ..deposit
+        };
+        for posting in crate::ledger::balance_transfer_postings(&other) {
+            db.insert_posting(&posting).unwrap();
+        }
+        retrieved.clear();
+        db.process_client_postings(123, &mut |p| retrieved.push(p))
+            .unwrap();
+        assert_eq!(retrieved, postings);
+    }
+
+    #[test]
+    fn test_process_all_balance_transfers() {
+        let mut db = init();
+        db.create_client_state(1).unwrap();
+        for (txn_id, amount) in [(2, 5.0), (1, 1.5), (3, -1.0), (4, -5.0)] {
+            db.try_insert_balance_transfer(BalanceTransfer {
+                client_id: 1,
+                txn_id,
+                amount: amt(amount),
+                timestamp: None,
+            })
+            .unwrap();
+        }
+        assert!(db.try_insert_dispute(1, 1).unwrap());
+        assert!(db.try_insert_dispute(1, 3).unwrap());
+        assert!(db.try_chargeback_dispute(1, 3).unwrap());
+        assert!(db
+            .try_insert_refund(&Refund {
+                client_id: 1,
+                txn_id: 4,
+                original_txn_id: 2,
+            })
+            .unwrap());
+
+        let mut records = Vec::new();
+        db.process_all_balance_transfers(&mut |r| records.push(r))
+            .unwrap();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.transfer.txn_id, r.status, r.txn_type()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, TransferStatus::Disputed, TxnType::Deposit),
+                (2, TransferStatus::Undisputed, TxnType::Deposit),
+                (3, TransferStatus::ChargedBack, TxnType::Withdrawal),
+                (4, TransferStatus::Undisputed, TxnType::Refund),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        use crate::event_log::{EventKind, LoggedEvent};
+        let mut db = init();
+        let events = [
+            LoggedEvent {
+                kind: EventKind::Deposit,
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(1.5),
+            },
+            LoggedEvent {
+                kind: EventKind::Adjustment,
+                client_id: 2,
+                txn_id: 0,
+                amount: amt(-0.25),
+            },
+        ];
+        for event in &events {
+            db.append_event(event).unwrap();
+        }
+        let mut retrieved = Vec::new();
+        db.process_all_events(&mut |e| retrieved.push(e)).unwrap();
+        assert_eq!(retrieved, events);
+        assert!(db.conn.execute("UPDATE Events SET amount = 0", []).is_err());
+
+        // forgetting a client deletes its events
+        db.forget_client(1, &[]).unwrap();
+        retrieved.clear();
+        db.process_all_events(&mut |e| retrieved.push(e)).unwrap();
+        assert_eq!(retrieved, events[1..]);
+    }
+
+    #[test]
+    fn test_forget_client() {
+        let mut db = init();
+        // a disputed deposit with a fee, and a refunded deposit
+        for (client_id, disputed, refunded, refund) in [(1, 11, 12, 13), (2, 21, 22, 23)] {
+            let _ = db.create_client_state(client_id);
+            for txn_id in [disputed, refunded] {
+                let xfer = BalanceTransfer {
+                    client_id,
+                    txn_id,
+                    amount: amt(1.0),
+                    timestamp: None,
+                };
+                assert!(db.try_insert_balance_transfer(xfer).unwrap());
+            }
+            let txn_id = disputed;
+            assert!(db.try_insert_dispute(client_id, txn_id).unwrap());
+            assert!(db.try_resolve_dispute(client_id, txn_id).unwrap());
+            assert!(db.try_reopen_dispute(client_id, txn_id).unwrap());
+            assert!(db.try_resolve_dispute(client_id, txn_id).unwrap());
+            db.insert_fee(client_id, txn_id, amt(0.1)).unwrap();
+            let refund = Refund {
+                client_id,
+                txn_id: refund,
+                original_txn_id: refunded,
+            };
+            assert!(db.try_insert_refund(&refund).unwrap());
+        }
+        let count = |db: &TxnDb, table: &str, client_id: ClientId| -> i64 {
+            db.conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE client_id = ?1", table),
+                    params![client_id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let tables = [
+            "BalanceTransfers",
+            "Disputes",
+            "Resolutions",
+            "DisputeHistory",
+            "Fees",
+            "Refunds",
+        ];
+        for table in tables {
+            assert!(count(&db, table, 1) > 0, "{}", table);
+        }
+
+        assert_eq!(db.forget_client(1, &[]).unwrap(), 2);
+        for table in tables {
+            assert_eq!(count(&db, table, 1), 0, "{}", table);
+            assert!(count(&db, table, 2) > 0, "{}", table);
+        }
+        assert!(db.get_fee(1, 11).unwrap().is_none());
+        assert!(db.get_refund(1, 13).unwrap().is_none());
+        // the ids can be used again
+        let xfer = BalanceTransfer {
+            client_id: 1,
+            txn_id: 11,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        assert!(db.try_insert_dispute(1, 11).unwrap());
+    }
+
+    #[test]
+    fn test_client_limits() {
+        let mut db = init();
+        let _ = db.create_client_state(1);
+        let limits = Limits {
+            max_balance: Some(amt(100.0)),
+            max_deposit: None,
+            max_withdrawal: Some(amt(2.5)),
+        };
+        assert_eq!(db.get_client_limits(1).unwrap(), None);
+        db.set_client_limits(1, Some(limits)).unwrap();
+        assert_eq!(db.get_client_limits(1).unwrap(), Some(limits));
+        // a client that doesn't exist gets no limits
+        db.set_client_limits(2, Some(limits)).unwrap();
+        assert_eq!(db.get_client_limits(2).unwrap(), None);
+        db.set_client_limits(1, None).unwrap();
+        assert_eq!(db.get_client_limits(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_purge_older_than() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        for txn_id in 1..=8 {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: amt(1.0),
+                timestamp: None,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        }
+        // 1: settled dispute with a fee, 2: open dispute, 3: no dispute, 4: recent, 5: refunded by 6,
+        // 7: refunded by 8, which is recent
+        assert!(db.try_insert_dispute(123, 1).unwrap());
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        assert!(db.try_reopen_dispute(123, 1).unwrap());
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        db.insert_fee(123, 1, amt(0.1)).unwrap();
+        assert!(db.try_insert_dispute(123, 2).unwrap());
+        for (txn_id, original_txn_id) in [(6, 5), (8, 7)] {
+            let refund = Refund {
+                client_id: 123,
+                txn_id,
+                original_txn_id,
+            };
+            assert!(db.try_insert_refund(&refund).unwrap());
+        }
+        db.conn
+            .execute(
+                "UPDATE BalanceTransfers SET recorded_at = recorded_at - 40 * 86400 WHERE txn_id NOT IN (4, 8)",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(db.purge_older_than(30).unwrap(), 4);
+        for (txn_id, kept) in [
+            (1, false),
+            (2, true),
+            (3, false),
+            (4, true),
+            (5, false),
+            (6, false),
+            (7, true),
+            (8, true),
+        ] {
+            assert_eq!(
+                db.get_balance_transfer(123, txn_id).unwrap().is_some(),
+                kept,
+                "{}",
+                txn_id
+            );
+            assert_eq!(db.is_purged_txn_id(txn_id).unwrap(), !kept, "{}", txn_id);
+        }
+        assert_eq!(db.get_open_disputes(123).unwrap().len(), 1);
+        assert!(db.get_client_state(123).unwrap().is_some());
+        // nothing refers to the purged transfers
+        for table in ["Disputes", "Resolutions", "DisputeHistory", "Fees"] {
+            let count: i64 = db
+                .conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE txn_id = 1", table),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, 0, "{}", table);
+        }
+        assert!(db.get_fee(123, 1).unwrap().is_none());
+        assert!(db.get_refund(123, 6).unwrap().is_none());
+        assert!(db.get_refund(123, 8).unwrap().is_some());
+        // a purged txn_id is still a duplicate
+        for client_id in [123, 124] {
+            let xfer = BalanceTransfer {
+                client_id,
+                txn_id: 1,
+                amount: amt(1.0),
+                timestamp: None,
+            };
+            assert!(!db.try_insert_balance_transfer(xfer).unwrap());
+        }
+        assert_eq!(db.purge_older_than(30).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_recorded_at_migration() {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let file_name = format!("{}.db", generate(6, charset));
+        {
+            let conn = Connection::open(&file_name).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE Clients (client_id INTEGER NOT NULL, available INTEGER NOT NULL, held REAL NOT NULL,
+                    total REAL NOT NULL, locked INTEGER NOT NULL, PRIMARY KEY (client_id));
+                CREATE TABLE BalanceTransfers (client_id INTEGER NOT NULL, txn_id INTEGER NOT NULL UNIQUE,
+                    amount REAL NOT NULL, PRIMARY KEY (client_id, txn_id),
+                    FOREIGN KEY (client_id) REFERENCES Clients(client_id) ON DELETE CASCADE);
+                INSERT INTO Clients VALUES (1, 0, 0, 0, 0);
+                INSERT INTO BalanceTransfers VALUES (1, 1, 2.5);",
+            )
+            .unwrap();
+        }
+        let mut db = TxnDb::open(&file_name).unwrap();
+        db.persistent = false;
+        // rows without a recorded_at are kept
+        assert_eq!(db.purge_older_than(0).unwrap(), 0);
+        // the REAL amount was converted to minor units
+        assert_eq!(
+            db.get_balance_transfer(1, 1).unwrap().unwrap().amount,
+            amt(2.5)
+        );
+        assert!(db
+            .try_insert_balance_transfer(BalanceTransfer {
+                client_id: 1,
+                txn_id: 2,
+                amount: amt(1.0),
+                timestamp: None,
+            })
+            .unwrap());
+        // the Clients table gained the overdraft_limit column
+        assert_eq!(db.get_overdraft_limit(1).unwrap(), None);
+        db.set_overdraft_limit(1, Some(amt(5.0))).unwrap();
+        assert_eq!(db.get_overdraft_limit(1).unwrap(), Some(amt(5.0)));
+        assert_eq!(db.get_overdraft_limit(2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_dispute_ages() {
+        let mut db = init();
+        let _ = db.create_client_state(123);
+        for txn_id in 1..=3 {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: amt(1.0),
+                timestamp: None,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+            assert!(db.try_insert_dispute(123, txn_id).unwrap());
+        }
+        assert!(db.try_resolve_dispute(123, 2).unwrap());
+        db.conn
+            .execute(
+                "UPDATE Disputes SET opened_at = opened_at - 10 * 86400 WHERE txn_id = 3",
+                [],
+            )
+            .unwrap();
+
+        let ages = db.open_dispute_ages().unwrap();
+        assert_eq!(
+            ages,
+            vec![
+                OpenDisputeAge {
+                    client_id: 123,
+                    txn_id: 3,
+                    age_days: Some(10)
+                },
+                OpenDisputeAge {
+                    client_id: 123,
+                    txn_id: 1,
+                    age_days: Some(0)
+                },
+            ]
+        );
+        assert_eq!(db.client_open_dispute_ages(123).unwrap(), ages);
+        assert!(db.client_open_dispute_ages(7).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_query_read_only() {
+        let mut db = init();
+        db.create_client_state(7).unwrap();
+        let path = Path::new(&db.file_name).to_path_buf();
+
+        let result = TxnDb::query_read_only(
+            &path,
+            "SELECT client_id, 'a,b' AS name, NULL AS missing, 1.5 AS amount FROM Clients",
+        )
+        .unwrap();
+        assert_eq!(
+            result.columns,
+            vec!["client_id", "name", "missing", "amount"]
+        );
+        assert_eq!(
+            result.to_json(),
+            serde_json::json!([{"client_id": 7, "name": "a,b", "missing": null, "amount": 1.5}])
+        );
+        let mut csv = Vec::new();
+        result.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client_id,name,missing,amount\n7,\"a,b\",,1.5\n"
+        );
+
+        for sql in [
+            "DELETE FROM Clients",
+            "UPDATE Clients SET available = 100",
+            "DROP TABLE Clients",
+        ] {
+            assert!(TxnDb::query_read_only(&path, sql).is_err(), "{}", sql);
+        }
+        assert_eq!(db.get_client_state(7).unwrap().unwrap().available, 0.0);
+        assert!(TxnDb::query_read_only(Path::new("missing.db"), "SELECT 1").is_err());
+    }
 }
@@ -0,0 +1,214 @@
+//! the CSV dialect of the input: the delimiter and how fields are quoted. some upstream systems export semicolon
+//! delimited files, or quote with another character, and spreadsheet tools export tab separated values. unless it's
+//! set, the delimiter is detected from the header line
+use crate::{errors::*, fmt_error};
+use csv::ReaderBuilder;
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader},
+    str::FromStr,
+};
+
+// the delimiters that are detected, the default first
+const DETECTED: [u8; 3] = [b',', b'\t', b';'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+    /// None to detect it from the header line
+    pub delimiter: Option<u8>,
+    /// the quote character. None to read quotes as part of the fields
+    pub quote: Option<u8>,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: None,
+            quote: Some(b'"'),
+        }
+    }
+}
+
+impl CsvDialect {
+    // a reader of the dialect. the other settings are the csv crate's defaults
+    pub(crate) fn reader<R: io::Read>(
+        &self,
+        reader: R,
+    ) -> Result<csv::Reader<BufReader<R>>, MyError> {
+        self.reader_from(ReaderBuilder::new(), reader)
+    }
+
+    // like reader, with the other settings of `builder`
+    pub(crate) fn reader_from<R: io::Read>(
+        &self,
+        mut builder: ReaderBuilder,
+        reader: R,
+    ) -> Result<csv::Reader<BufReader<R>>, MyError> {
+        let mut reader = BufReader::new(reader);
+        let delimiter = match self.delimiter {
+            Some(delimiter) => delimiter,
+            None => detect_delimiter(
+                reader
+                    .fill_buf()
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("failed to read the input"))
+                    .change_context(MyError::FileReader)?,
+            ),
+        };
+        builder.delimiter(delimiter);
+        match self.quote {
+            Some(quote) => builder.quote(quote),
+            None => builder.quoting(false),
+        };
+        Ok(builder.from_reader(reader))
+    }
+}
+
+/// the delimiter of the first line of `input`: the one of comma, tab, and semicolon it contains most often. a comma
+/// if there's none of them
+pub fn detect_delimiter(input: &[u8]) -> u8 {
+    let line = input.split(|b| *b == b'\n').next().unwrap_or_default();
+    let count = |delimiter: u8| line.iter().filter(|b| **b == delimiter).count();
+    // max_by_key returns the last maximum: reversed, ties go to the comma
+    DETECTED
+        .into_iter()
+        .rev()
+        .max_by_key(|delimiter| count(*delimiter))
+        .unwrap_or(b',')
+}
+
+/// the format of file input. `Auto` detects the delimiter from the header line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    #[default]
+    Auto,
+    Csv,
+    /// tab separated values
+    Tsv,
+    /// length-delimited protobuf messages (see protobuf.rs)
+    Protobuf,
+}
+
+impl InputFormat {
+    /// the delimiter of the format. None if it's detected or the format isn't CSV
+    pub fn delimiter(&self) -> Option<u8> {
+        match self {
+            InputFormat::Auto | InputFormat::Protobuf => None,
+            InputFormat::Csv => Some(b','),
+            InputFormat::Tsv => Some(b'\t'),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = MyError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(InputFormat::Auto),
+            "csv" => Ok(InputFormat::Csv),
+            "tsv" => Ok(InputFormat::Tsv),
+            "protobuf" => Ok(InputFormat::Protobuf),
+            _ => Err(MyError::Conversion(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            InputFormat::Auto => "auto",
+            InputFormat::Csv => "csv",
+            InputFormat::Tsv => "tsv",
+            InputFormat::Protobuf => "protobuf",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// parse a delimiter or quote character: a single ASCII character, or `tab` (also `\t`). line breaks can't be used
+pub fn parse_char(s: &str) -> std::result::Result<u8, MyError> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() && s != "\n" && s != "\r" => Ok(s.as_bytes()[0]),
+        _ => Err(MyError::Conversion(s.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dialects() {
+        let read = |dialect: CsvDialect, input: &str| -> Vec<Vec<String>> {
+            dialect
+                .reader(input.as_bytes())
+                .unwrap()
+                .records()
+                .map(|record| record.unwrap().iter().map(str::to_string).collect())
+                .collect()
+        };
+        let semicolon = CsvDialect {
+            delimiter: Some(b';'),
+            ..Default::default()
+        };
+        assert_eq!(
+            read(semicolon, "type;client;tx;amount\ndeposit;1;1;\"1,5\"\n"),
+            [["deposit", "1", "1", "1,5"]]
+        );
+        let single_quotes = CsvDialect {
+            quote: Some(b'\''),
+            ..Default::default()
+        };
+        assert_eq!(
+            read(single_quotes, "type,client,tx,amount\ndeposit,1,1,'1,5'\n"),
+            [["deposit", "1", "1", "1,5"]]
+        );
+        let unquoted = CsvDialect {
+            quote: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            read(unquoted, "type,client,tx,amount\n\"deposit\",1,1,1.5\n"),
+            [["\"deposit\"", "1", "1", "1.5"]]
+        );
+    }
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(
+            detect_delimiter(b"type,client,tx,amount\ndeposit;1;1;1.5"),
+            b','
+        );
+        assert_eq!(detect_delimiter(b"type\tclient\ttx\tamount"), b'\t');
+        assert_eq!(detect_delimiter(b"type;client;tx;amount_cents\n"), b';');
+        assert_eq!(detect_delimiter(b"type;client,tx"), b',');
+        assert_eq!(detect_delimiter(b"type"), b',');
+        assert_eq!(detect_delimiter(b""), b',');
+
+        let tsv = "type\tclient\ttx\tamount\ndeposit\t1\t1\t1,5\n";
+        let read = |dialect: CsvDialect| -> Vec<String> {
+            let mut reader = dialect.reader(tsv.as_bytes()).unwrap();
+            let record = reader.records().next().unwrap().unwrap();
+            record.iter().map(str::to_string).collect()
+        };
+        assert_eq!(read(CsvDialect::default()), ["deposit", "1", "1", "1,5"]);
+        let explicit = CsvDialect {
+            delimiter: InputFormat::Tsv.delimiter(),
+            ..Default::default()
+        };
+        assert_eq!(read(explicit), ["deposit", "1", "1", "1,5"]);
+        assert_eq!("tsv".parse::<InputFormat>().unwrap(), InputFormat::Tsv);
+    }
+
+    #[test]
+    fn test_parse_char() {
+        assert_eq!(parse_char(";").unwrap(), b';');
+        assert_eq!(parse_char("tab").unwrap(), b'\t');
+        assert_eq!(parse_char("\\t").unwrap(), b'\t');
+        assert!(parse_char(";;").is_err());
+        assert!(parse_char("é").is_err());
+        assert!(parse_char("\n").is_err());
+    }
+}
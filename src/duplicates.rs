@@ -0,0 +1,133 @@
+//! duplicate transaction ids. a deposit or withdrawal that reuses an id is rejected, which silently hides an upstream
+//! export that sent the same rows twice. this records each rejected duplicate next to the transfer that was applied
+use crate::model::*;
+use std::{collections::BTreeMap, fmt};
+
+/// a txn_id that appeared more than once
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateTxn {
+    pub txn_id: TransactionId,
+    /// the transfer that was applied. None if it's no longer stored (ex: purged by data retention)
+    pub original: Option<BalanceTransfer>,
+    /// the rejected transfers with the same id, in input order
+    pub duplicates: Vec<BalanceTransfer>,
+}
+
+impl DuplicateTxn {
+    /// true if any occurrence has a different amount, or a different client, than the applied transfer.
+    /// these are not retries of the same transfer
+    pub fn differs(&self) -> bool {
+        match &self.original {
+            Some(original) => self
+                .duplicates
+                .iter()
+                .any(|d| d.amount != original.amount || d.client_id != original.client_id),
+            None => false,
+        }
+    }
+
+    /// the number of times the id appeared
+    pub fn occurrences(&self) -> usize {
+        self.duplicates.len() + 1
+    }
+}
+
+fn describe(f: &mut fmt::Formatter, transfer: &BalanceTransfer) -> fmt::Result {
+    let kind = if transfer.amount.is_negative() {
+        "withdrawal"
+    } else {
+        "deposit"
+    };
+    write!(
+        f,
+        "{} of {} by client {}",
+        kind,
+        transfer.amount.abs(),
+        transfer.client_id
+    )
+}
+
+impl fmt::Display for DuplicateTxn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "txn {} seen {} times: ", self.txn_id, self.occurrences())?;
+        match &self.original {
+            Some(original) => describe(f, original)?,
+            None => write!(f, "unknown")?,
+        }
+        write!(f, " (applied)")?;
+        for duplicate in &self.duplicates {
+            write!(f, ", ")?;
+            describe(f, duplicate)?;
+            write!(f, " (rejected)")?;
+        }
+        if self.differs() {
+            write!(f, " <-- DIFFERS")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DuplicateTracker {
+    txns: BTreeMap<TransactionId, DuplicateTxn>,
+}
+
+impl DuplicateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `duplicate` was rejected because `original` already used its id
+    pub fn record(&mut self, original: Option<BalanceTransfer>, duplicate: BalanceTransfer) {
+        self.txns
+            .entry(duplicate.txn_id)
+            .or_insert_with(|| DuplicateTxn {
+                txn_id: duplicate.txn_id,
+                original,
+                duplicates: Vec::new(),
+            })
+            .duplicates
+            .push(duplicate);
+    }
+
+    /// in txn_id order
+    pub fn report(&self) -> Vec<DuplicateTxn> {
+        self.txns.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn transfer(client_id: ClientId, amount: f64) -> BalanceTransfer {
+        BalanceTransfer {
+            client_id,
+            txn_id: 7,
+            amount: amt(amount),
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let mut tracker = DuplicateTracker::new();
+        tracker.record(Some(transfer(1, 5.0)), transfer(1, 5.0));
+        let report = tracker.report();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].occurrences(), 2);
+        // a resend of the same transfer
+        assert!(!report[0].differs());
+
+        tracker.record(Some(transfer(1, 5.0)), transfer(1, -5.0));
+        let report = tracker.report();
+        assert_eq!(report[0].occurrences(), 3);
+        assert!(report[0].differs());
+        assert_eq!(
+            report[0].to_string(),
+            "txn 7 seen 3 times: deposit of 5 by client 1 (applied), deposit of 5 by client 1 (rejected), \
+             withdrawal of 5 by client 1 (rejected) <-- DIFFERS"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! field-level encryption (feature "encryption") of the free-text fields the engine stores: the operator and reason of
+//! a manual adjustment, and the input record of an audit entry. each field is encrypted on its own with
+//! ChaCha20-Poly1305 and a random nonce, and stored as "enc:" followed by the hex encoded nonce and ciphertext.
+//! the ids and amounts stay in the clear: the balances and the audit chain need them
+use crate::{errors::*, fmt_error};
+use chacha20poly1305::{
+    aead::{Aead, OsRng, Payload},
+    AeadCore, ChaCha20Poly1305, KeyInit, Nonce,
+};
+use error_stack::{report, Result};
+use std::fmt;
+
+/// the environment variable the executable reads the key from: 32 bytes, hex encoded
+pub const FIELD_KEY_VAR: &str = "PAYMENTS_ENGINE_FIELD_KEY";
+
+const PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 12;
+
+/// whether a stored field was encrypted
+pub fn is_encrypted(field: &str) -> bool {
+    field.starts_with(PREFIX)
+}
+
+#[derive(Clone)]
+pub struct FieldKey {
+    cipher: ChaCha20Poly1305,
+}
+
+// the key isn't printed
+impl fmt::Debug for FieldKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FieldKey")
+    }
+}
+
+impl FieldKey {
+    /// a hex encoded 32 byte key
+    pub fn from_hex(key: &str) -> Result<Self, MyError> {
+        let key = decode_hex(key.trim())
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or_else(|| {
+                report!(MyError::Encryption)
+                    .attach_printable(fmt_error!("expected a hex encoded 32 byte key"))
+            })?;
+        Ok(FieldKey {
+            cipher: ChaCha20Poly1305::new_from_slice(&key)
+                .expect("a ChaCha20-Poly1305 key is 32 bytes"),
+        })
+    }
+
+    /// the key in `FIELD_KEY_VAR`. None if it isn't set
+    pub fn from_env() -> Result<Option<Self>, MyError> {
+        match std::env::var(FIELD_KEY_VAR) {
+            Ok(key) => Self::from_hex(&key)
+                .map(Some)
+                .map_err(|e| e.attach_printable(fmt_error!("invalid {}", FIELD_KEY_VAR))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// encrypt the value of the field `name`. the name is authenticated with it, so a value can't be moved to
+    /// another field
+    pub fn encrypt(&self, name: &str, value: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .expect("encrypting into a Vec doesn't fail");
+        format!(
+            "{}{}{}",
+            PREFIX,
+            encode_hex(&nonce),
+            encode_hex(&ciphertext)
+        )
+    }
+
+    /// the value of the field `name`. a value that isn't encrypted (ex: stored before the key was set) is returned as is
+    pub fn decrypt(&self, name: &str, field: &str) -> Result<String, MyError> {
+        let encoded = match field.strip_prefix(PREFIX) {
+            Some(encoded) => encoded,
+            None => return Ok(field.to_string()),
+        };
+        let bytes = decode_hex(encoded)
+            .filter(|bytes| bytes.len() >= NONCE_LEN)
+            .ok_or_else(|| {
+                report!(MyError::Encryption)
+                    .attach_printable(fmt_error!("the {} isn't valid ciphertext", name))
+            })?;
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                report!(MyError::Encryption).attach_printable(fmt_error!(
+                    "failed to decrypt the {}: the key is wrong or the value was modified",
+                    name
+                ))
+            })?;
+        String::from_utf8(plaintext).map_err(|_| {
+            report!(MyError::Encryption)
+                .attach_printable(fmt_error!("the {} isn't valid UTF-8", name))
+        })
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => u8::from_str_radix(std::str::from_utf8(&[*hi, *lo]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_round_trip() {
+        let key = FieldKey::from_hex(KEY).unwrap();
+        let field = key.encrypt("operator", "alice");
+        assert!(is_encrypted(&field));
+        assert!(!field.contains("alice"));
+        assert_eq!(key.decrypt("operator", &field).unwrap(), "alice");
+        // a new nonce every time
+        assert_ne!(key.encrypt("operator", "alice"), field);
+        // a value written in the clear
+        assert_eq!(key.decrypt("operator", "bob").unwrap(), "bob");
+
+        // another field, another key, or a modified value
+        assert!(key.decrypt("reason", &field).is_err());
+        let other = FieldKey::from_hex(&KEY.replace("00", "ff")).unwrap();
+        assert!(other.decrypt("operator", &field).is_err());
+        let mut modified = field.clone();
+        modified.pop();
+        modified.push('0');
+        if modified == field {
+            modified.pop();
+            modified.push('1');
+        }
+        assert!(key.decrypt("operator", &modified).is_err());
+        assert!(key.decrypt("operator", "enc:00").is_err());
+
+        assert!(FieldKey::from_hex("00").is_err());
+        assert!(FieldKey::from_hex(&KEY.replace("1f", "zz")).is_err());
+    }
+}
@@ -21,17 +21,82 @@ pub fn print_report<T>(report: error_stack::Report<T>) {
             )) => format!("- {}", attachment),
             _ => "".to_string(),
         };
-        log::error!("{}", msg);
+        tracing::error!("{}", msg);
     }
 }
 
+/// renders the frames of a report as JSON, source of the error first (same order as print_report):
+/// `{"error": "<outermost context>", "frames": [{"kind": "context" | "attachment", "message": ..., "file": ..., "line": ...}]}`
+pub fn report_to_json<T>(report: &error_stack::Report<T>) -> serde_json::Value {
+    let stack: Vec<&error_stack::Frame> = report.frames().collect();
+    let mut error = None;
+    let mut frames = Vec::new();
+    for frame in stack.iter().rev() {
+        let (kind, message) = match frame.kind() {
+            error_stack::FrameKind::Context(context) => ("context", context.to_string()),
+            error_stack::FrameKind::Attachment(error_stack::AttachmentKind::Printable(
+                attachment,
+            )) => ("attachment", attachment.to_string()),
+            _ => continue,
+        };
+        if kind == "context" {
+            error = Some(message.clone());
+        }
+        let location = frame.location();
+        frames.push(serde_json::json!({
+            "kind": kind,
+            "message": message,
+            "file": location.file(),
+            "line": location.line(),
+        }));
+    }
+    serde_json::json!({
+        "error": error,
+        "frames": frames,
+    })
+}
+
+/// what failed. the details are attached to the report. a rejected transaction isn't an error: `process` returns
+/// it as a `TransactionRejected` event with a `RejectReason`, except in strict mode
 #[derive(Debug)]
 pub enum MyError {
+    /// the account is locked by a chargeback
+    AccountLocked,
+    Audit,
+    /// the store is busy (ex: SQLITE_BUSY). the operation can be retried
+    Busy,
+    /// a client that must be new already has an account
+    ClientExists,
+    Config,
     Conversion(String),
+    /// a storage failure
     Db,
+    /// the input was already processed (see `DuplicateInputPolicy`)
+    DuplicateInput,
+    /// a field couldn't be decrypted, ex: with the wrong key (see `encryption::FieldKey`)
+    Encryption,
     FileReader,
-    Generic(&'static str),
-    GenericFmt(String),
+    /// the available funds don't cover an operator action, ex: an adjustment
+    InsufficientFunds,
+    /// an operator action with invalid arguments, ex: a zero adjustment
+    InvalidRequest,
+    Invariant,
+    /// an input record that doesn't decode, ex: a Kafka message
+    MalformedRecord,
+    /// the memory usage went above the configured ceiling
+    Memory,
+    /// the client has open disputes
+    OpenDisputes,
+    Output,
+    /// a transaction was rejected in strict mode (see `TransactionProcessor::set_strict`)
+    Rejected,
+    Signature,
+    /// the client has never been seen
+    UnknownClient,
+    /// the deposit or withdrawal doesn't exist, or isn't in the dispute state the action needs
+    UnknownTransfer,
+    /// a worker thread of `ParallelProcessor` failed
+    Worker,
 }
 
 impl fmt::Display for MyError {
@@ -41,3 +106,29 @@ impl fmt::Display for MyError {
 }
 
 impl Error for MyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use error_stack::{IntoReport, ResultExt};
+
+    #[test]
+    fn test_report_to_json() {
+        let res: Result<u32, _> = "abc".parse::<u32>();
+        let report = res
+            .report()
+            .attach_printable("while parsing")
+            .change_context(MyError::Db)
+            .unwrap_err();
+
+        let json = report_to_json(&report);
+        assert_eq!(json["error"], "Db");
+        let frames = json["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0]["kind"], "context");
+        assert_eq!(frames[1]["kind"], "attachment");
+        assert_eq!(frames[1]["message"], "while parsing");
+        assert_eq!(frames[2]["message"], "Db");
+        assert_eq!(frames[2]["file"], file!());
+    }
+}
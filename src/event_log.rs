@@ -0,0 +1,294 @@
+//! the append-only log of the balance changes. every applied transaction and administrative action is logged, in
+//! order, alongside the client accounts, so the accounts can be recomputed from the log alone (`rebuild`), ex: to
+//! recover from a corrupted Clients table or to audit the balance logic
+use crate::{amount::Amount, errors::*, events::EngineEvent, model::*};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Deposit,
+    Withdrawal,
+    Refund,
+    Dispute,
+    Resolve,
+    Reopen,
+    Chargeback,
+    Fee,
+    FeeRefund,
+    Interest,
+    Adjustment,
+    Lock,
+    Unlock,
+    /// the available funds an account was seeded with (`--initial-balances`), or kept when its history was erased
+    OpeningAvailable,
+    /// the held funds an account was seeded with, or kept when its history was erased
+    OpeningHeld,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            EventKind::Deposit => "deposit",
+            EventKind::Withdrawal => "withdrawal",
+            EventKind::Refund => "refund",
+            EventKind::Dispute => "dispute",
+            EventKind::Resolve => "resolve",
+            EventKind::Reopen => "reopen",
+            EventKind::Chargeback => "chargeback",
+            EventKind::Fee => "fee",
+            EventKind::FeeRefund => "fee_refund",
+            EventKind::Interest => "interest",
+            EventKind::Adjustment => "adjustment",
+            EventKind::Lock => "lock",
+            EventKind::Unlock => "unlock",
+            EventKind::OpeningAvailable => "opening_available",
+            EventKind::OpeningHeld => "opening_held",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(EventKind::Deposit),
+            "withdrawal" => Ok(EventKind::Withdrawal),
+            "refund" => Ok(EventKind::Refund),
+            "dispute" => Ok(EventKind::Dispute),
+            "resolve" => Ok(EventKind::Resolve),
+            "reopen" => Ok(EventKind::Reopen),
+            "chargeback" => Ok(EventKind::Chargeback),
+            "fee" => Ok(EventKind::Fee),
+            "fee_refund" => Ok(EventKind::FeeRefund),
+            "interest" => Ok(EventKind::Interest),
+            "adjustment" => Ok(EventKind::Adjustment),
+            "lock" => Ok(EventKind::Lock),
+            "unlock" => Ok(EventKind::Unlock),
+            "opening_available" => Ok(EventKind::OpeningAvailable),
+            "opening_held" => Ok(EventKind::OpeningHeld),
+            _ => Err(MyError::Conversion(s.to_string())),
+        }
+    }
+}
+
+/// one balance change of a client account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoggedEvent {
+    pub kind: EventKind,
+    pub client_id: ClientId,
+    /// the transaction that caused the change. 0 for administrative actions
+    pub txn_id: TransactionId,
+    /// as in the `EngineEvent`: disputes, resolves, and chargebacks carry the signed amount of the disputed transfer,
+    /// adjustments and opening balances are signed, everything else is positive. zero for locks and unlocks
+    pub amount: Amount,
+}
+
+impl LoggedEvent {
+    /// the balance change of an event. None for events that don't change a balance: rejections and queued deposits
+    pub fn from_engine_event(event: &EngineEvent) -> Option<Self> {
+        let client_id = event.client_id();
+        let (kind, txn_id, amount) = match *event {
+            EngineEvent::FundsDeposited { txn_id, amount, .. } => {
+                (EventKind::Deposit, txn_id, amount)
+            }
+            EngineEvent::FundsWithdrawn { txn_id, amount, .. } => {
+                (EventKind::Withdrawal, txn_id, amount)
+            }
+            EngineEvent::FundsRefunded { txn_id, amount, .. } => {
+                (EventKind::Refund, txn_id, amount)
+            }
+            EngineEvent::DisputeOpened { txn_id, amount, .. } => {
+                (EventKind::Dispute, txn_id, amount)
+            }
+            EngineEvent::DisputeResolved { txn_id, amount, .. } => {
+                (EventKind::Resolve, txn_id, amount)
+            }
+            EngineEvent::DisputeReopened { txn_id, amount, .. } => {
+                (EventKind::Reopen, txn_id, amount)
+            }
+            EngineEvent::ChargebackApplied { txn_id, amount, .. } => {
+                (EventKind::Chargeback, txn_id, amount)
+            }
+            EngineEvent::FeeCharged { txn_id, amount, .. } => (EventKind::Fee, txn_id, amount),
+            EngineEvent::FeeRefunded { txn_id, amount, .. } => {
+                (EventKind::FeeRefund, txn_id, amount)
+            }
+            EngineEvent::InterestAccrued { amount, .. } => (EventKind::Interest, 0, amount),
+            EngineEvent::BalanceAdjusted { amount, .. } => (EventKind::Adjustment, 0, amount),
+            EngineEvent::AccountLocked { .. } => (EventKind::Lock, 0, Amount::ZERO),
+            EngineEvent::AccountUnlocked { .. } => (EventKind::Unlock, 0, Amount::ZERO),
+            EngineEvent::DepositQueued { .. } | EngineEvent::TransactionRejected { .. } => {
+                return None
+            }
+        };
+        Some(LoggedEvent {
+            kind,
+            client_id,
+            txn_id,
+            amount,
+        })
+    }
+
+    /// the events that open an account in `state`: its nonzero balances, and a lock if it's locked
+    pub fn opening(state: &ClientState) -> Vec<Self> {
+        let event = |kind, amount| LoggedEvent {
+            kind,
+            client_id: state.client_id,
+            txn_id: 0,
+            amount,
+        };
+        let mut events = Vec::new();
+        if !state.available.is_zero() {
+            events.push(event(EventKind::OpeningAvailable, state.available));
+        }
+        if !state.held.is_zero() {
+            events.push(event(EventKind::OpeningHeld, state.held));
+        }
+        if state.is_locked() {
+            events.push(event(EventKind::Lock, Amount::ZERO));
+        }
+        events
+    }
+
+    /// apply the change to `state`, the same way the ledger postings of the event do
+    pub fn apply_to(&self, state: &mut ClientState) {
+        let amount = self.amount;
+        match self.kind {
+            EventKind::Deposit
+            | EventKind::FeeRefund
+            | EventKind::Interest
+            | EventKind::Adjustment
+            | EventKind::OpeningAvailable => state.available += amount,
+            EventKind::Withdrawal | EventKind::Refund | EventKind::Fee => state.available -= amount,
+            EventKind::OpeningHeld => state.held += amount,
+            // a disputed deposit holds its funds. a disputed withdrawal holds a provisional credit
+            EventKind::Dispute | EventKind::Reopen => {
+                if amount.is_positive() {
+                    state.available -= amount;
+                }
+                state.held += amount.abs();
+            }
+            EventKind::Resolve => {
+                if amount.is_positive() {
+                    state.available += amount;
+                }
+                state.held -= amount.abs();
+            }
+            // a charged back deposit leaves. a charged back withdrawal is credited to the client
+            EventKind::Chargeback => {
+                if amount.is_negative() {
+                    state.available += amount.abs();
+                }
+                state.held -= amount.abs();
+            }
+            EventKind::Lock => state.locked = LockedState::Locked,
+            EventKind::Unlock => state.locked = LockedState::Unlocked,
+        }
+        state.total = state.available + state.held;
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        let kind: String = row.get(0)?;
+        let kind = kind.parse().map_err(|e: MyError| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+        })?;
+        Ok(LoggedEvent {
+            kind,
+            client_id: row.get(1)?,
+            txn_id: row.get(2)?,
+            amount: row.get(3)?,
+        })
+    }
+}
+
+/// the client accounts that result from applying `events` in order to empty accounts
+pub fn rebuild(events: impl IntoIterator<Item = LoggedEvent>) -> BTreeMap<ClientId, ClientState> {
+    let mut states = BTreeMap::new();
+    for event in events {
+        let state = states
+            .entry(event.client_id)
+            .or_insert_with(|| ClientState::new(event.client_id));
+        event.apply_to(state);
+    }
+    states
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_kind_names() {
+        for kind in [
+            EventKind::Deposit,
+            EventKind::FeeRefund,
+            EventKind::Unlock,
+            EventKind::OpeningHeld,
+        ] {
+            assert_eq!(kind.to_string().parse::<EventKind>().unwrap(), kind);
+        }
+        assert!("transfer".parse::<EventKind>().is_err());
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let event = |kind, client_id, txn_id, amount: f64| LoggedEvent {
+            kind,
+            client_id,
+            txn_id,
+            amount: amt(amount),
+        };
+        let states = rebuild([
+            event(EventKind::Deposit, 1, 1, 10.0),
+            event(EventKind::Fee, 1, 1, 0.5),
+            event(EventKind::Withdrawal, 1, 2, 4.0),
+            // a disputed withdrawal, charged back
+            event(EventKind::Dispute, 1, 2, -4.0),
+            event(EventKind::Chargeback, 1, 2, -4.0),
+            event(EventKind::Lock, 1, 0, 0.0),
+            event(EventKind::OpeningAvailable, 2, 0, 3.0),
+            event(EventKind::OpeningHeld, 2, 0, 1.0),
+            // a disputed deposit, resolved and reopened
+            event(EventKind::Deposit, 2, 3, 2.0),
+            event(EventKind::Dispute, 2, 3, 2.0),
+            event(EventKind::Resolve, 2, 3, 2.0),
+            event(EventKind::Reopen, 2, 3, 2.0),
+            event(EventKind::Adjustment, 2, 0, -1.0),
+        ]);
+        let one = &states[&1];
+        assert_eq!(one.available, amt(9.5));
+        assert_eq!(one.held, Amount::ZERO);
+        assert_eq!(one.total, amt(9.5));
+        assert!(one.is_locked());
+        let two = &states[&2];
+        assert_eq!(two.available, amt(2.0));
+        assert_eq!(two.held, amt(3.0));
+        assert_eq!(two.total, amt(5.0));
+        assert!(!two.is_locked());
+
+        // the opening events of an account rebuild it
+        let reopened = rebuild(LoggedEvent::opening(one));
+        assert_eq!(reopened[&1].to_string(), one.to_string());
+        assert!(LoggedEvent::opening(&ClientState::new(3)).is_empty());
+    }
+
+    #[test]
+    fn test_from_engine_event() {
+        let event = LoggedEvent::from_engine_event(&EngineEvent::InterestAccrued {
+            client_id: 4,
+            amount: amt(0.1),
+        })
+        .unwrap();
+        assert_eq!(event.kind, EventKind::Interest);
+        assert_eq!((event.client_id, event.txn_id), (4, 0));
+        assert!(LoggedEvent::from_engine_event(&EngineEvent::DepositQueued {
+            client_id: 4,
+            txn_id: 1,
+            amount: amt(1.0),
+        })
+        .is_none());
+    }
+}
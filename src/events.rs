@@ -0,0 +1,168 @@
+use crate::{adjustment::AdjustmentReason, amount::Amount, model::*};
+
+/// why a transaction was not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectReason {
+    /// failed input validation. ex: a deposit without a positive amount, or a dispute with an amount
+    Malformed,
+    /// the client's account was locked by an earlier chargeback
+    AccountLocked,
+    /// a withdrawal exceeded the available funds
+    InsufficientFunds,
+    /// the transaction id was already used by another deposit or withdrawal
+    DuplicateTxnId,
+    /// the disputed transaction doesn't exist, was already disputed, or is a refund or a refunded deposit
+    InvalidDispute,
+    /// the disputed transaction belongs to another client (see `CrossClientDisputePolicy`)
+    CrossClientDispute,
+    /// the `DisputePolicy` doesn't allow disputes of this kind of transaction (deposit or withdrawal)
+    DisputeNotAllowed,
+    /// the dispute was filed after the dispute window of the transfer closed (see `set_dispute_window`)
+    DisputeWindowExpired,
+    /// the refunded transaction isn't a deposit of this client, is disputed, or was already refunded
+    InvalidRefund,
+    /// a resolve or chargeback referenced a transaction without an open dispute
+    NotDisputed,
+    /// shed by the rate limiter (see `RateLimiter`)
+    RateLimited,
+    /// a record with the same content was already processed (see `set_record_hashes`)
+    DuplicateRecord,
+    /// a deposit would take the total balance past the client's limit (see `limits::Limits`)
+    BalanceLimitExceeded,
+    /// a deposit exceeded the largest single deposit allowed
+    DepositLimitExceeded,
+    /// a withdrawal exceeded the largest single withdrawal allowed
+    WithdrawalLimitExceeded,
+}
+
+/// what happened as a result of processing a transaction.
+/// for disputes, resolutions, and chargebacks `amount` is the amount of the original balance transfer: negative for a withdrawal
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    FundsDeposited {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    FundsWithdrawn {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    /// the deposit `original_txn_id` was reversed by the refund `txn_id`: `amount` left the available funds
+    FundsRefunded {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        original_txn_id: TransactionId,
+        amount: Amount,
+    },
+    DisputeOpened {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    DisputeResolved {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    /// a resolved dispute was reopened by an administrator. the funds are held again
+    DisputeReopened {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    ChargebackApplied {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    AccountLocked {
+        client_id: ClientId,
+    },
+    /// the fee of a deposit or withdrawal, taken from the available funds (see `schedule::Rates`)
+    FeeCharged {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    /// the fee of a charged back deposit or withdrawal, returned to the available funds
+    FeeRefunded {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    /// interest accrued on the available funds (see `TransactionProcessor::accrue_interest`)
+    InterestAccrued {
+        client_id: ClientId,
+        amount: Amount,
+    },
+    /// a deposit to a locked account was queued by `LockedAccountPolicy::QueueDeposits`. it isn't applied yet
+    DepositQueued {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Amount,
+    },
+    /// a locked account was unlocked by an administrator
+    AccountUnlocked {
+        client_id: ClientId,
+    },
+    /// a manual adjustment of the available funds
+    BalanceAdjusted {
+        client_id: ClientId,
+        amount: Amount,
+        reason: AdjustmentReason,
+    },
+    TransactionRejected {
+        client_id: ClientId,
+        txn_id: TransactionId,
+        txn_type: TxnType,
+        reason: RejectReason,
+    },
+}
+
+impl EngineEvent {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            EngineEvent::FundsDeposited { client_id, .. }
+            | EngineEvent::FundsWithdrawn { client_id, .. }
+            | EngineEvent::FundsRefunded { client_id, .. }
+            | EngineEvent::DisputeOpened { client_id, .. }
+            | EngineEvent::DisputeResolved { client_id, .. }
+            | EngineEvent::DisputeReopened { client_id, .. }
+            | EngineEvent::ChargebackApplied { client_id, .. }
+            | EngineEvent::AccountLocked { client_id }
+            | EngineEvent::FeeCharged { client_id, .. }
+            | EngineEvent::FeeRefunded { client_id, .. }
+            | EngineEvent::InterestAccrued { client_id, .. }
+            | EngineEvent::DepositQueued { client_id, .. }
+            | EngineEvent::AccountUnlocked { client_id }
+            | EngineEvent::BalanceAdjusted { client_id, .. }
+            | EngineEvent::TransactionRejected { client_id, .. } => *client_id,
+        }
+    }
+
+    pub fn is_rejection(&self) -> bool {
+        matches!(self, EngineEvent::TransactionRejected { .. })
+    }
+}
+
+/// the outcome of `TransactionProcessor::process_batch`: the events of each transaction, in the order they were given
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchResult {
+    pub outcomes: Vec<Vec<EngineEvent>>,
+}
+
+impl BatchResult {
+    /// the number of transactions that weren't rejected: applied, or queued for a locked account
+    pub fn applied(&self) -> usize {
+        self.outcomes.len() - self.rejected()
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|events| events.first().is_some_and(EngineEvent::is_rejection))
+            .count()
+    }
+}
@@ -0,0 +1,143 @@
+//! the export of the stored balance transfers with their dispute status (`export-ledger`), so downstream analytics
+//! don't have to read the store's schema
+use crate::{model::*, report::ReportFormat};
+use serde_json::{json, Value};
+use std::io;
+
+pub const EXPORT_HEADER: &str = "type,client,tx,amount,timestamp,status,original_tx";
+
+/// the JSON object of a transfer, with the names of the CSV columns. the amount is positive, as in the input
+pub fn transfer_json(record: &TransferRecord) -> Value {
+    let transfer = &record.transfer;
+    json!({
+        "type": record.txn_type().to_string(),
+        "client": transfer.client_id,
+        "tx": transfer.txn_id,
+        "amount": transfer.amount.abs().to_f64(),
+        "timestamp": transfer.timestamp,
+        "status": record.status.to_string(),
+        "original_tx": record.refund_of,
+    })
+}
+
+// writes the export one transfer at a time. `finish` must be called after the last one
+pub(crate) struct ExportWriter<W: io::Write> {
+    writer: W,
+    format: ReportFormat,
+    rows: u64,
+}
+
+impl<W: io::Write> ExportWriter<W> {
+    pub(crate) fn new(mut writer: W, format: ReportFormat) -> io::Result<Self> {
+        match format {
+            ReportFormat::Csv => writeln!(writer, "{}", EXPORT_HEADER)?,
+            ReportFormat::Json => write!(writer, "[")?,
+            ReportFormat::Jsonl => {}
+        }
+        Ok(ExportWriter {
+            writer,
+            format,
+            rows: 0,
+        })
+    }
+
+    pub(crate) fn row(&mut self, record: &TransferRecord) -> io::Result<()> {
+        let separator = if self.rows == 0 { "" } else { "," };
+        self.rows += 1;
+        let transfer = &record.transfer;
+        match self.format {
+            ReportFormat::Csv => writeln!(
+                self.writer,
+                "{},{},{},{},{},{},{}",
+                record.txn_type(),
+                transfer.client_id,
+                transfer.txn_id,
+                transfer.amount.abs(),
+                transfer
+                    .timestamp
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+                record.status,
+                record
+                    .refund_of
+                    .map(|id| id.to_string())
+                    .unwrap_or_default()
+            ),
+            ReportFormat::Json => {
+                write!(self.writer, "{}\n{}", separator, transfer_json(record))
+            }
+            ReportFormat::Jsonl => writeln!(self.writer, "{}", transfer_json(record)),
+        }
+    }
+
+    /// returns the number of transfers written
+    pub(crate) fn finish(mut self) -> io::Result<u64> {
+        match self.format {
+            ReportFormat::Json if self.rows > 0 => writeln!(self.writer, "\n]")?,
+            ReportFormat::Json => writeln!(self.writer, "]")?,
+            _ => {}
+        }
+        Ok(self.rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn export(format: ReportFormat, records: &[TransferRecord]) -> String {
+        let mut out = Vec::new();
+        let mut writer = ExportWriter::new(&mut out, format).unwrap();
+        for record in records {
+            writer.row(record).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), records.len() as u64);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let records = [
+            TransferRecord {
+                transfer: BalanceTransfer {
+                    client_id: 1,
+                    txn_id: 1,
+                    amount: amt(1.5),
+                    timestamp: Some(1704067200),
+                },
+                status: TransferStatus::ChargedBack,
+                refund_of: None,
+            },
+            TransferRecord {
+                transfer: BalanceTransfer {
+                    client_id: 1,
+                    txn_id: 2,
+                    amount: amt(-2.0),
+                    timestamp: None,
+                },
+                status: TransferStatus::Undisputed,
+                refund_of: Some(3),
+            },
+        ];
+        assert_eq!(
+            export(ReportFormat::Csv, &records),
+            "type,client,tx,amount,timestamp,status,original_tx
+deposit,1,1,1.5,1704067200,charged_back,
+refund,1,2,2,,undisputed,3
+"
+        );
+        let json: Value = serde_json::from_str(&export(ReportFormat::Json, &records)).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                {"type": "deposit", "client": 1, "tx": 1, "amount": 1.5, "timestamp": 1704067200,
+                    "status": "charged_back", "original_tx": null},
+                {"type": "refund", "client": 1, "tx": 2, "amount": 2.0, "timestamp": null,
+                    "status": "undisputed", "original_tx": 3},
+            ])
+        );
+        assert_eq!(export(ReportFormat::Json, &[]), "[]\n");
+        assert_eq!(export(ReportFormat::Jsonl, &records).lines().count(), 2);
+    }
+}
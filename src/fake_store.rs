@@ -0,0 +1,444 @@
+//! a fake `TxnStore` with failure injection, so integrations can exercise their error paths without SQLite.
+//! enabled by the "test-util" feature.
+use crate::{
+    adjustment::Adjustment, amount::Amount, audit::AuditEntry, errors::*, event_log::LoggedEvent,
+    fmt_error, ledger::Posting, limits::Limits, memory_db::MemoryDb, model::*, schedule::Date,
+    store::TxnStore,
+};
+use error_stack::{report, Result};
+use std::{cell::Cell, collections::HashSet};
+
+/// the operations of `TxnStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StoreOp {
+    CreateClientState,
+    GetClientState,
+    ProcessAllClients,
+    UpdateClientState,
+    InsertBalanceTransfer,
+    InsertDispute,
+    ResolveDispute,
+    ChargebackDispute,
+    ReopenDispute,
+    CountDisputeReopens,
+    GetBalanceTransfer,
+    GetBalanceTransferById,
+    GetOpenDisputes,
+    ProcessAllBalanceTransfers,
+    GetOverdraftLimit,
+    SetOverdraftLimit,
+    GetClientLimits,
+    SetClientLimits,
+    InsertFee,
+    GetFee,
+    InsertRefund,
+    GetRefund,
+    QueueDeposit,
+    TakeQueuedDeposits,
+    ForgetClient,
+    InsertPosting,
+    ProcessAllPostings,
+    ProcessClientPostings,
+    InsertAdjustment,
+    ProcessAllAdjustments,
+    AppendAuditEntry,
+    ProcessAllAuditEntries,
+    AppendEvent,
+    ProcessAllEvents,
+}
+
+/// behaves like `MemoryDb` unless told to fail.
+/// ex: `FakeStore::new().fail_nth_call(3).reject(StoreOp::InsertDispute)`
+#[derive(Default)]
+pub struct FakeStore {
+    inner: MemoryDb,
+    // number of calls made so far, across all operations
+    calls: Cell<usize>,
+    fail_calls: HashSet<usize>,
+    fail_ops: HashSet<StoreOp>,
+    reject_ops: HashSet<StoreOp>,
+}
+
+impl FakeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the nth call (counting from 1, across all operations) returns a storage error
+    pub fn fail_nth_call(mut self, n: usize) -> Self {
+        self.fail_calls.insert(n);
+        self
+    }
+
+    /// every call to `op` returns a storage error
+    pub fn fail(mut self, op: StoreOp) -> Self {
+        self.fail_ops.insert(op);
+        self
+    }
+
+    /// every call to `op` behaves as if it violated a constraint: the try_* operations return Ok(false).
+    /// has no effect on operations that can't violate a constraint
+    pub fn reject(mut self, op: StoreOp) -> Self {
+        self.reject_ops.insert(op);
+        self
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.get()
+    }
+
+    fn check(&self, op: StoreOp) -> Result<(), MyError> {
+        let n = self.calls.get() + 1;
+        self.calls.set(n);
+        if self.fail_calls.contains(&n) || self.fail_ops.contains(&op) {
+            return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                "injected failure for {:?} (call {})",
+                op,
+                n
+            )));
+        }
+        Ok(())
+    }
+
+    fn rejected(&self, op: StoreOp) -> bool {
+        self.reject_ops.contains(&op)
+    }
+}
+
+impl TxnStore for FakeStore {
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
+        self.check(StoreOp::CreateClientState)?;
+        self.inner.create_client_state(client_id)
+    }
+
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        self.check(StoreOp::GetClientState)?;
+        self.inner.get_client_state(client_id)
+    }
+
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllClients)?;
+        self.inner.process_all_clients(f)
+    }
+
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
+        self.check(StoreOp::UpdateClientState)?;
+        self.inner.update_client_state(client_state)
+    }
+
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        self.check(StoreOp::InsertBalanceTransfer)?;
+        if self.rejected(StoreOp::InsertBalanceTransfer) {
+            return Ok(false);
+        }
+        self.inner.try_insert_balance_transfer(txn)
+    }
+
+    fn try_insert_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.check(StoreOp::InsertDispute)?;
+        if self.rejected(StoreOp::InsertDispute) {
+            return Ok(false);
+        }
+        self.inner.try_insert_dispute(client_id, txn_id)
+    }
+
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.check(StoreOp::ResolveDispute)?;
+        if self.rejected(StoreOp::ResolveDispute) {
+            return Ok(false);
+        }
+        self.inner.try_resolve_dispute(client_id, txn_id)
+    }
+
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.check(StoreOp::ChargebackDispute)?;
+        if self.rejected(StoreOp::ChargebackDispute) {
+            return Ok(false);
+        }
+        self.inner.try_chargeback_dispute(client_id, txn_id)
+    }
+
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        self.check(StoreOp::ReopenDispute)?;
+        if self.rejected(StoreOp::ReopenDispute) {
+            return Ok(false);
+        }
+        self.inner.try_reopen_dispute(client_id, txn_id)
+    }
+
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError> {
+        self.check(StoreOp::CountDisputeReopens)?;
+        self.inner.count_dispute_reopens(client_id, txn_id)
+    }
+
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        self.check(StoreOp::GetBalanceTransfer)?;
+        self.inner.get_balance_transfer(client_id, txn_id)
+    }
+
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        self.check(StoreOp::GetBalanceTransferById)?;
+        self.inner.get_balance_transfer_by_id(txn_id)
+    }
+
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError> {
+        self.check(StoreOp::GetOpenDisputes)?;
+        self.inner.get_open_disputes(client_id)
+    }
+
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllBalanceTransfers)?;
+        self.inner.process_all_balance_transfers(f)
+    }
+
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        self.check(StoreOp::GetOverdraftLimit)?;
+        self.inner.get_overdraft_limit(client_id)
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        self.check(StoreOp::SetOverdraftLimit)?;
+        self.inner.set_overdraft_limit(client_id, limit)
+    }
+
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError> {
+        self.check(StoreOp::GetClientLimits)?;
+        self.inner.get_client_limits(client_id)
+    }
+
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        self.check(StoreOp::SetClientLimits)?;
+        self.inner.set_client_limits(client_id, limits)
+    }
+
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError> {
+        self.check(StoreOp::InsertFee)?;
+        self.inner.insert_fee(client_id, txn_id, fee)
+    }
+
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError> {
+        self.check(StoreOp::GetFee)?;
+        self.inner.get_fee(client_id, txn_id)
+    }
+
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError> {
+        self.check(StoreOp::InsertRefund)?;
+        if self.rejected(StoreOp::InsertRefund) {
+            return Ok(false);
+        }
+        self.inner.try_insert_refund(refund)
+    }
+
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError> {
+        self.check(StoreOp::GetRefund)?;
+        self.inner.get_refund(client_id, txn_id)
+    }
+
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        self.check(StoreOp::QueueDeposit)?;
+        if self.rejected(StoreOp::QueueDeposit) {
+            return Ok(false);
+        }
+        self.inner.try_queue_deposit(txn)
+    }
+
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError> {
+        self.check(StoreOp::TakeQueuedDeposits)?;
+        self.inner.take_queued_deposits(client_id)
+    }
+
+    fn forget_client(
+        &mut self,
+        client_id: ClientId,
+        summary: &[Posting],
+    ) -> Result<usize, MyError> {
+        self.check(StoreOp::ForgetClient)?;
+        self.inner.forget_client(client_id, summary)
+    }
+
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError> {
+        self.check(StoreOp::InsertPosting)?;
+        self.inner.insert_posting(posting)
+    }
+
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllPostings)?;
+        self.inner.process_all_postings(f)
+    }
+
+    fn process_client_postings(
+        &self,
+        client_id: ClientId,
+        f: &mut dyn FnMut(Posting),
+    ) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessClientPostings)?;
+        self.inner.process_client_postings(client_id, f)
+    }
+
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError> {
+        self.check(StoreOp::InsertAdjustment)?;
+        self.inner.insert_adjustment(adjustment)
+    }
+
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllAdjustments)?;
+        self.inner.process_all_adjustments(f)
+    }
+
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        self.check(StoreOp::AppendAuditEntry)?;
+        self.inner.append_audit_entry(entry)
+    }
+
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllAuditEntries)?;
+        self.inner.process_all_audit_entries(f)
+    }
+
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError> {
+        self.check(StoreOp::AppendEvent)?;
+        self.inner.append_event(event)
+    }
+
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError> {
+        self.check(StoreOp::ProcessAllEvents)?;
+        self.inner.process_all_events(f)
+    }
+
+    // like the checkpoints, store transactions aren't counted as calls
+    fn begin(&mut self) -> Result<(), MyError> {
+        self.inner.begin()
+    }
+
+    fn commit(&mut self) -> Result<(), MyError> {
+        self.inner.commit()
+    }
+
+    fn rollback(&mut self) -> Result<(), MyError> {
+        self.inner.rollback()
+    }
+
+    // checkpoints, input runs, and the interest date aren't counted as calls, so they don't shift the numbering used by fail_nth_call
+    fn get_checkpoint(&self, run_id: &str) -> Result<Option<u64>, MyError> {
+        self.inner.get_checkpoint(run_id)
+    }
+
+    fn set_checkpoint(&mut self, run_id: &str, rows: u64) -> Result<(), MyError> {
+        self.inner.set_checkpoint(run_id, rows)
+    }
+
+    fn get_input_run(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        self.inner.get_input_run(input_sha256)
+    }
+
+    fn insert_input_run(&mut self, run: &InputRun) -> Result<(), MyError> {
+        self.inner.insert_input_run(run)
+    }
+
+    fn try_insert_record_hash(&mut self, sha256: &str) -> Result<bool, MyError> {
+        self.inner.try_insert_record_hash(sha256)
+    }
+
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        self.inner.get_interest_accrued_through()
+    }
+
+    fn set_interest_accrued_through(&mut self, date: Date) -> Result<(), MyError> {
+        self.inner.set_interest_accrued_through(date)
+    }
+
+    fn is_purged_txn_id(&self, txn_id: TransactionId) -> Result<bool, MyError> {
+        self.inner.is_purged_txn_id(txn_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_fail_nth_call() {
+        let mut db = FakeStore::new().fail_nth_call(2);
+        assert!(db.create_client_state(1).is_ok());
+        assert!(db.get_client_state(1).is_err());
+        assert!(db.get_client_state(1).unwrap().is_some());
+        assert_eq!(db.calls(), 3);
+    }
+
+    #[test]
+    fn test_fail_op() {
+        let mut db = FakeStore::new().fail(StoreOp::UpdateClientState);
+        let state = db.create_client_state(1).unwrap();
+        assert!(db.update_client_state(&state).is_err());
+        assert!(db.update_client_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_reject_op() {
+        let mut db = FakeStore::new().reject(StoreOp::InsertBalanceTransfer);
+        db.create_client_state(1).unwrap();
+        let xfer = BalanceTransfer {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(!db.try_insert_balance_transfer(xfer).unwrap());
+        assert!(db.get_balance_transfer(1, 1).unwrap().is_none());
+    }
+}
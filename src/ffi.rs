@@ -0,0 +1,228 @@
+//! C API for embedding the engine. `include/payments_engine.h` is generated from this file by cbindgen (see build.rs).
+use crate::{
+    amount::RawAmount, errors::print_report, model::*, transaction_processor::TransactionProcessor,
+};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// values for `PeTransaction::txn_type`. these match `TxnType::to_u8`
+pub const PE_DEPOSIT: u8 = 1;
+pub const PE_WITHDRAWAL: u8 = 2;
+pub const PE_DISPUTE: u8 = 3;
+pub const PE_RESOLVE: u8 = 4;
+pub const PE_CHARGEBACK: u8 = 5;
+
+/// return codes
+pub const PE_OK: i32 = 0;
+pub const PE_NOT_FOUND: i32 = 1;
+pub const PE_NULL_ARGUMENT: i32 = -1;
+pub const PE_INVALID_TRANSACTION: i32 = -2;
+pub const PE_ENGINE_ERROR: i32 = -3;
+
+/// opaque handle to a transaction processor
+pub struct PeEngine {
+    processor: TransactionProcessor,
+}
+
+/// a transaction submitted by the caller. `amount` is ignored unless `has_amount` is set
+#[repr(C)]
+pub struct PeTransaction {
+    pub txn_type: u8,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub has_amount: bool,
+    pub amount: f64,
+}
+
+/// the state of a client account
+#[repr(C)]
+pub struct PeAccount {
+    pub client: ClientId,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// create an engine. uses an SQLite database unless `in_memory` is set (or the crate was built without the sqlite feature).
+/// returns NULL on failure. free the engine with `pe_engine_free`
+#[no_mangle]
+pub extern "C" fn pe_engine_new(in_memory: bool) -> *mut PeEngine {
+    #[cfg(feature = "sqlite")]
+    let processor = if in_memory {
+        TransactionProcessor::in_memory()
+    } else {
+        match TransactionProcessor::new() {
+            Ok(p) => p,
+            Err(e) => {
+                print_report(e);
+                return std::ptr::null_mut();
+            }
+        }
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let processor = {
+        let _ = in_memory;
+        TransactionProcessor::in_memory()
+    };
+    Box::into_raw(Box::new(PeEngine { processor }))
+}
+
+/// free an engine created by `pe_engine_new`. passing NULL is a no-op
+///
+/// # Safety
+/// `engine` must be NULL or a pointer returned by `pe_engine_new` that hasn't been freed
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_free(engine: *mut PeEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// process one transaction. like the executable, transactions that fail validation or business rules are ignored and return PE_OK.
+/// returns PE_INVALID_TRANSACTION for an unknown `txn_type` and PE_ENGINE_ERROR if the storage failed
+///
+/// # Safety
+/// `engine` must come from `pe_engine_new`. `txn` must point to a valid `PeTransaction`
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_submit(engine: *mut PeEngine, txn: *const PeTransaction) -> i32 {
+    let (engine, txn) = match (engine.as_mut(), txn.as_ref()) {
+        (Some(engine), Some(txn)) => (engine, txn),
+        _ => return PE_NULL_ARGUMENT,
+    };
+    let txn_type = TxnType::from(txn.txn_type);
+    if txn_type == TxnType::Invalid {
+        return PE_INVALID_TRANSACTION;
+    }
+    let raw = RawTxnInput {
+        txn_type,
+        client_id: txn.client,
+        txn_id: txn.tx,
+        amount: txn.has_amount.then(|| RawAmount::from(txn.amount)),
+        timestamp: None,
+        original_txn_id: None,
+    };
+
+    // don't unwind into C
+    match catch_unwind(AssertUnwindSafe(|| engine.processor.process(raw))) {
+        Ok(Ok(_)) => PE_OK,
+        Ok(Err(e)) => {
+            print_report(e);
+            PE_ENGINE_ERROR
+        }
+        Err(_) => PE_ENGINE_ERROR,
+    }
+}
+
+/// copy the state of `client` into `out`. returns PE_NOT_FOUND if the engine hasn't seen the client
+///
+/// # Safety
+/// `engine` must come from `pe_engine_new`. `out` must point to writable memory for a `PeAccount`
+#[no_mangle]
+pub unsafe extern "C" fn pe_engine_get_account(
+    engine: *mut PeEngine,
+    client: ClientId,
+    out: *mut PeAccount,
+) -> i32 {
+    let (engine, out) = match (engine.as_mut(), out.as_mut()) {
+        (Some(engine), Some(out)) => (engine, out),
+        _ => return PE_NULL_ARGUMENT,
+    };
+
+    match catch_unwind(AssertUnwindSafe(|| engine.processor.client_state(client))) {
+        Ok(Ok(Some(state))) => {
+            *out = PeAccount {
+                client: state.client_id,
+                available: state.available.to_f64(),
+                held: state.held.to_f64(),
+                total: state.total.to_f64(),
+                locked: state.is_locked(),
+            };
+            PE_OK
+        }
+        Ok(Ok(None)) => PE_NOT_FOUND,
+        Ok(Err(e)) => {
+            print_report(e);
+            PE_ENGINE_ERROR
+        }
+        Err(_) => PE_ENGINE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn txn(
+        txn_type: u8,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<f64>,
+    ) -> PeTransaction {
+        PeTransaction {
+            txn_type,
+            client,
+            tx,
+            has_amount: amount.is_some(),
+            amount: amount.unwrap_or(0.0),
+        }
+    }
+
+    #[test]
+    fn test_submit_and_get_account() {
+        unsafe {
+            let engine = pe_engine_new(true);
+            assert!(!engine.is_null());
+
+            assert_eq!(
+                pe_engine_submit(engine, &txn(PE_DEPOSIT, 1, 1, Some(2.0))),
+                PE_OK
+            );
+            assert_eq!(
+                pe_engine_submit(engine, &txn(PE_DISPUTE, 1, 1, None)),
+                PE_OK
+            );
+            assert_eq!(
+                pe_engine_submit(engine, &txn(PE_CHARGEBACK, 1, 1, None)),
+                PE_OK
+            );
+            assert_eq!(
+                pe_engine_submit(engine, &txn(0, 1, 2, None)),
+                PE_INVALID_TRANSACTION
+            );
+
+            let mut account = PeAccount {
+                client: 0,
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                locked: false,
+            };
+            assert_eq!(pe_engine_get_account(engine, 1, &mut account), PE_OK);
+            assert_eq!(account.client, 1);
+            assert_eq!(account.total, 0.0);
+            assert!(account.locked);
+
+            assert_eq!(pe_engine_get_account(engine, 2, &mut account), PE_NOT_FOUND);
+            pe_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        unsafe {
+            let deposit = txn(PE_DEPOSIT, 1, 1, Some(1.0));
+            assert_eq!(
+                pe_engine_submit(std::ptr::null_mut(), &deposit),
+                PE_NULL_ARGUMENT
+            );
+            let engine = pe_engine_new(true);
+            assert_eq!(pe_engine_submit(engine, std::ptr::null()), PE_NULL_ARGUMENT);
+            assert_eq!(
+                pe_engine_get_account(engine, 1, std::ptr::null_mut()),
+                PE_NULL_ARGUMENT
+            );
+            pe_engine_free(engine);
+            pe_engine_free(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! gRPC ingestion. the `Transactions` service (proto/payments_engine.proto) takes a stream of transactions, applies
+//! them to the shared processor in the order they arrive, and streams back the status of each one in the same
+//! order. for internal services that submit at a high rate and can't go through CSV files
+use crate::{
+    amount::RawAmount, async_processor::AsyncTransactionProcessor, errors::*, events::EngineEvent,
+    fmt_error, model::*,
+};
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{net::SocketAddr, pin::Pin};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming};
+
+/// the generated messages and service
+pub mod proto {
+    tonic::include_proto!("payments_engine");
+}
+
+use proto::{
+    transactions_server::{Transactions, TransactionsServer},
+    Transaction, TransactionStatus,
+};
+
+// statuses buffered per stream before processing waits for the client to read them
+const STATUS_BUFFER: usize = 1024;
+
+pub struct TransactionsService {
+    processor: AsyncTransactionProcessor,
+}
+
+impl TransactionsService {
+    pub fn new(processor: AsyncTransactionProcessor) -> Self {
+        Self { processor }
+    }
+
+    /// the service, ready to be added to a tonic server
+    pub fn into_server(self) -> TransactionsServer<Self> {
+        TransactionsServer::new(self)
+    }
+}
+
+/// serve the `Transactions` service on `addr` until the process is stopped
+pub async fn serve(addr: SocketAddr, processor: AsyncTransactionProcessor) -> Result<(), MyError> {
+    tracing::info!(%addr, "listening");
+    tonic::transport::Server::builder()
+        .add_service(TransactionsService::new(processor).into_server())
+        .serve(addr)
+        .await
+        .report()
+        .attach_printable_lazy(|| fmt_error!("the gRPC server on {} stopped", addr))
+        .change_context(MyError::Output)
+}
+
+#[tonic::async_trait]
+impl Transactions for TransactionsService {
+    type SubmitStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<TransactionStatus, Status>> + Send>>;
+
+    async fn submit(
+        &self,
+        request: Request<Streaming<Transaction>>,
+    ) -> std::result::Result<Response<Self::SubmitStream>, Status> {
+        let mut inbound = request.into_inner();
+        let processor = self.processor.clone();
+        let (sender, receiver) = tokio::sync::mpsc::channel(STATUS_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let status = match inbound.message().await {
+                    Ok(Some(txn)) => apply(&processor, txn).await,
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = status.is_err();
+                // the client went away
+                if sender.send(status).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+// a store failure ends the stream with an internal error
+async fn apply(
+    processor: &AsyncTransactionProcessor,
+    txn: Transaction,
+) -> std::result::Result<TransactionStatus, Status> {
+    let rejected = |reason: &str| TransactionStatus {
+        client: txn.client,
+        tx: txn.tx,
+        accepted: false,
+        reason: reason.to_string(),
+    };
+    // ids that don't fit the model are malformed, like in the CSV input
+    let (client_id, txn_id) = match (
+        ClientId::try_from(txn.client),
+        TransactionId::try_from(txn.tx),
+    ) {
+        (Ok(client_id), Ok(txn_id)) => (client_id, txn_id),
+        _ => return Ok(rejected("Malformed")),
+    };
+    let txn_type = u8::try_from(txn.r#type).map_or(TxnType::Invalid, TxnType::from);
+    if txn_type == TxnType::Invalid {
+        return Ok(rejected("Malformed"));
+    }
+    let raw = RawTxnInput {
+        txn_type,
+        client_id,
+        txn_id,
+        amount: txn.amount.map(RawAmount::from),
+        timestamp: None,
+        original_txn_id: None,
+    };
+    match processor.process_async(raw).await {
+        Ok(events) => Ok(match events.first() {
+            Some(EngineEvent::TransactionRejected { reason, .. }) => {
+                rejected(&format!("{:?}", reason))
+            }
+            _ => TransactionStatus {
+                client: txn.client,
+                tx: txn.tx,
+                accepted: true,
+                reason: String::new(),
+            },
+        }),
+        Err(e) => {
+            let message = e.to_string();
+            print_report(e);
+            Err(Status::internal(message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, transaction_processor::TransactionProcessor};
+    use proto::transactions_client::TransactionsClient;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_submit() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let processor = AsyncTransactionProcessor::new(TransactionProcessor::in_memory());
+        let service = TransactionsService::new(processor.clone()).into_server();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+        });
+
+        let mut client = TransactionsClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let txn = |txn_type: proto::TxnType, tx, amount| Transaction {
+            r#type: txn_type as i32,
+            client: 1,
+            tx,
+            amount,
+        };
+        let txns = vec![
+            txn(proto::TxnType::Deposit, 1, Some(2.5)),
+            txn(proto::TxnType::Withdrawal, 2, Some(9.0)),
+            txn(proto::TxnType::Dispute, 1, None),
+            txn(proto::TxnType::Invalid, 3, None),
+        ];
+        let mut statuses = client
+            .submit(tokio_stream::iter(txns))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut outcomes = Vec::new();
+        while let Some(status) = statuses.message().await.unwrap() {
+            outcomes.push((status.tx, status.accepted, status.reason));
+        }
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, true, String::new()),
+                (2, false, "InsufficientFunds".to_string()),
+                (1, true, String::new()),
+                (3, false, "Malformed".to_string()),
+            ]
+        );
+        let account = processor.client_state_async(1).await.unwrap().unwrap();
+        assert_eq!(account.held, amt(2.5));
+    }
+}
@@ -0,0 +1,110 @@
+//! callbacks for library users that want to react to the transactions as they're processed, ex: custom metrics,
+//! notifications, or mirroring the accounts to another system, without forking the processing loop. register them
+//! with `TransactionProcessor::add_hooks`. every method does nothing by default
+use crate::{amount::Amount, events::*, model::*};
+
+pub trait ProcessorHooks: Send {
+    /// a deposit was applied, including a queued deposit applied when its account was unlocked
+    fn on_deposit(&mut self, _client_id: ClientId, _txn_id: TransactionId, _amount: Amount) {}
+
+    /// a withdrawal was applied. `amount` is positive
+    fn on_withdrawal(&mut self, _client_id: ClientId, _txn_id: TransactionId, _amount: Amount) {}
+
+    /// the funds of the transfer `txn_id` are held. `amount` is negative for a disputed withdrawal
+    fn on_dispute_opened(&mut self, _client_id: ClientId, _txn_id: TransactionId, _amount: Amount) {
+    }
+
+    /// a dispute ended in a chargeback. `amount` is negative for a withdrawal
+    fn on_chargeback(&mut self, _client_id: ClientId, _txn_id: TransactionId, _amount: Amount) {}
+
+    fn on_reject(
+        &mut self,
+        _client_id: ClientId,
+        _txn_id: TransactionId,
+        _txn_type: &TxnType,
+        _reason: RejectReason,
+    ) {
+    }
+
+    /// the account was locked by a chargeback. called after `on_chargeback`
+    fn on_account_locked(&mut self, _client_id: ClientId) {}
+}
+
+// calls the hooks of the events of one transaction, in order
+pub(crate) fn notify(hooks: &mut dyn ProcessorHooks, events: &[EngineEvent]) {
+    for event in events {
+        match *event {
+            EngineEvent::FundsDeposited {
+                client_id,
+                txn_id,
+                amount,
+            } => hooks.on_deposit(client_id, txn_id, amount),
+            EngineEvent::FundsWithdrawn {
+                client_id,
+                txn_id,
+                amount,
+            } => hooks.on_withdrawal(client_id, txn_id, amount),
+            EngineEvent::DisputeOpened {
+                client_id,
+                txn_id,
+                amount,
+            } => hooks.on_dispute_opened(client_id, txn_id, amount),
+            EngineEvent::ChargebackApplied {
+                client_id,
+                txn_id,
+                amount,
+            } => hooks.on_chargeback(client_id, txn_id, amount),
+            EngineEvent::AccountLocked { client_id } => hooks.on_account_locked(client_id),
+            EngineEvent::TransactionRejected {
+                client_id,
+                txn_id,
+                ref txn_type,
+                reason,
+            } => hooks.on_reject(client_id, txn_id, txn_type, reason),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl ProcessorHooks for Recorder {
+        fn on_chargeback(&mut self, client_id: ClientId, txn_id: TransactionId, amount: Amount) {
+            self.0
+                .push(format!("chargeback {} {} {}", client_id, txn_id, amount));
+        }
+
+        fn on_account_locked(&mut self, client_id: ClientId) {
+            self.0.push(format!("locked {}", client_id));
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let mut recorder = Recorder::default();
+        notify(
+            &mut recorder,
+            &[
+                EngineEvent::ChargebackApplied {
+                    client_id: 1,
+                    txn_id: 2,
+                    amount: amt(-1.5),
+                },
+                EngineEvent::AccountLocked { client_id: 1 },
+                // no hook
+                EngineEvent::FeeRefunded {
+                    client_id: 1,
+                    txn_id: 2,
+                    amount: amt(0.1),
+                },
+            ],
+        );
+        assert_eq!(recorder.0, ["chargeback 1 2 -1.5", "locked 1"]);
+    }
+}
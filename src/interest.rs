@@ -0,0 +1,114 @@
+//! daily interest on positive available balances. the yearly rate of each day comes from the `RateSchedule`, and the
+//! interest of a day earns interest on the following days (daily compounding). the processor posts the result as a
+//! synthetic transaction: see `TransactionProcessor::accrue_interest`
+use crate::{
+    amount::Amount,
+    rounding::RoundingPolicy,
+    schedule::{Date, RateSchedule},
+};
+
+pub const DAYS_PER_YEAR: i64 = 365;
+
+/// the interest accrued on `balance` from `from` through `through` (both included). each day's interest is the
+/// principal times the yearly rate over `DAYS_PER_YEAR`, computed exactly and rounded once with `policy`. zero if
+/// the balance isn't positive or `from` is after `through`. None if the interest doesn't fit an `Amount`
+pub fn accrue(
+    balance: Amount,
+    schedule: &RateSchedule,
+    from: Date,
+    through: Date,
+    policy: RoundingPolicy,
+) -> Option<Amount> {
+    let mut interest = Amount::ZERO;
+    let mut day = from;
+    while day <= through {
+        let principal = balance.checked_add(interest)?;
+        if principal <= Amount::ZERO {
+            break;
+        }
+        let rate = schedule.rates_at(day).interest_rate;
+        interest = interest.checked_add(rate.apply(principal, DAYS_PER_YEAR, policy)?)?;
+        day = day.succ();
+    }
+    Some(interest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schedule::{RatePeriod, Rates};
+
+    fn date(s: &str) -> Date {
+        s.parse().unwrap()
+    }
+
+    fn rate(interest_rate: &str) -> Rates {
+        Rates {
+            interest_rate: interest_rate.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_accrue() {
+        let schedule = RateSchedule::constant(rate("0.0365")).unwrap();
+        let accrue = |balance: &str, from: &str, through: &str| {
+            accrue(
+                balance.parse().unwrap(),
+                &schedule,
+                date(from),
+                date(through),
+                RoundingPolicy::HalfEven,
+            )
+            .unwrap()
+            .to_string()
+        };
+        // 0.0001 a day on 1000, compounded
+        assert_eq!(accrue("1000", "2024-01-01", "2024-01-01"), "0.1");
+        assert_eq!(accrue("1000", "2024-01-01", "2024-01-02"), "0.2");
+        assert_eq!(accrue("100000", "2024-01-01", "2024-01-02"), "20.001");
+        assert_eq!(accrue("0", "2024-01-01", "2024-12-31"), "0");
+        assert_eq!(accrue("-5", "2024-01-01", "2024-12-31"), "0");
+        assert_eq!(accrue("1000", "2024-01-02", "2024-01-01"), "0");
+
+        // no interest outside the schedule
+        let schedule = RateSchedule::new(vec![RatePeriod {
+            from: date("2024-01-02"),
+            until: Some(date("2024-01-03")),
+            rates: rate("0.0365"),
+        }])
+        .unwrap();
+        let interest = super::accrue(
+            "1000".parse().unwrap(),
+            &schedule,
+            date("2024-01-01"),
+            date("2024-01-05"),
+            RoundingPolicy::HalfEven,
+        );
+        assert_eq!(interest.unwrap().to_string(), "0.1");
+    }
+
+    #[test]
+    fn test_rounding() {
+        // 0.0001 a day on 1: a day's interest on 0.5 and 1.5 is half a minor unit
+        let schedule = RateSchedule::constant(rate("0.0365")).unwrap();
+        let day = date("2024-01-01");
+        let accrue = |balance: &str, policy: RoundingPolicy| {
+            accrue(balance.parse().unwrap(), &schedule, day, day, policy)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(accrue("0.5", RoundingPolicy::HalfEven), "0");
+        assert_eq!(accrue("1.5", RoundingPolicy::HalfEven), "0.0002");
+        assert_eq!(accrue("0.5", RoundingPolicy::HalfUp), "0.0001");
+        assert_eq!(accrue("1.5", RoundingPolicy::Truncate), "0.0001");
+
+        // interest too large for an Amount
+        let schedule = RateSchedule::constant(rate("1000")).unwrap();
+        let balance = Amount::from_minor_units(i64::MAX / 2);
+        assert_eq!(
+            super::accrue(balance, &schedule, day, day, RoundingPolicy::HalfEven),
+            None
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! consistency checks for a client account, run after every applied transaction by `--check-invariants`
+use crate::{amount::Amount, model::*};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// total != available + held
+    Total {
+        available: Amount,
+        held: Amount,
+        total: Amount,
+    },
+    NegativeHeld {
+        held: Amount,
+    },
+    /// held doesn't match the disputes that are still open
+    Held {
+        held: Amount,
+        open_disputes: Amount,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Total {
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "total ({}) != available ({}) + held ({})",
+                total, available, held
+            ),
+            Violation::NegativeHeld { held } => write!(f, "held ({}) is negative", held),
+            Violation::Held {
+                held,
+                open_disputes,
+            } => write!(
+                f,
+                "held ({}) != the sum of the open disputes ({})",
+                held, open_disputes
+            ),
+        }
+    }
+}
+
+/// every invariant of `state` that doesn't hold. `open_disputes` are the client's disputed balance transfers
+/// that haven't been resolved or charged back
+pub fn check_client(state: &ClientState, open_disputes: &[BalanceTransfer]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    if state.total != state.available + state.held {
+        violations.push(Violation::Total {
+            available: state.available,
+            held: state.held,
+            total: state.total,
+        });
+    }
+    if state.held.is_negative() {
+        violations.push(Violation::NegativeHeld { held: state.held });
+    }
+    // a disputed withdrawal holds the withdrawn amount too
+    let disputed: Amount = open_disputes.iter().map(|txn| txn.amount.abs()).sum();
+    if state.held != disputed {
+        violations.push(Violation::Held {
+            held: state.held,
+            open_disputes: disputed,
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn state(available: f64, held: f64, total: f64) -> ClientState {
+        ClientState {
+            available: amt(available),
+            held: amt(held),
+            total: amt(total),
+            ..ClientState::new(1)
+        }
+    }
+
+    fn transfer(txn_id: TransactionId, amount: f64) -> BalanceTransfer {
+        BalanceTransfer {
+            client_id: 1,
+            txn_id,
+            amount: amt(amount),
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_consistent() {
+        assert!(check_client(&state(0.1, 0.2, 0.3), &[]).len() == 1);
+        assert!(check_client(
+            &state(0.1, 0.2, 0.3),
+            &[transfer(1, 0.15), transfer(2, -0.05)]
+        )
+        .is_empty());
+        assert!(check_client(&state(-1.0, 0.0, -1.0), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(
+            check_client(&state(1.0, 0.0, 2.0), &[]),
+            vec![Violation::Total {
+                available: amt(1.0),
+                held: amt(0.0),
+                total: amt(2.0)
+            }]
+        );
+        assert_eq!(
+            check_client(&state(1.0, -1.0, 0.0), &[]),
+            vec![
+                Violation::NegativeHeld { held: amt(-1.0) },
+                Violation::Held {
+                    held: amt(-1.0),
+                    open_disputes: amt(0.0)
+                }
+            ]
+        );
+        assert_eq!(
+            check_client(&state(1.0, 1.0, 2.0), &[transfer(1, 2.0)])[0].to_string(),
+            "held (1) != the sum of the open disputes (2)"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Kafka ingestion. `KafkaConsumer` reads transactions from a topic as a member of a consumer group and applies them
+//! to a processor. an offset is committed only once `process` has returned for its message, so a crash or a store
+//! failure redelivers the messages that weren't applied: delivery is at least once, and a persistent store rejects
+//! the deposits and withdrawals that were already applied as duplicates. a payload that doesn't decode is logged
+//! and skipped, like an invalid CSV row
+use crate::{
+    avro, errors::*, fmt_error, model::*, snapshot::PeriodicSnapshots,
+    transaction_processor::TransactionProcessor,
+};
+use error_stack::{IntoReport, Result, ResultExt};
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    message::Message,
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+// how long `run` waits for a message before checking whether a snapshot is due
+const POLL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// how the messages are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadFormat {
+    /// a JSON object with the names of the CSV columns, ex: `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}`
+    #[default]
+    Json,
+    /// a datum of `avro::TRANSACTION_SCHEMA`
+    Avro,
+}
+
+impl FromStr for PayloadFormat {
+    type Err = MyError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let format = match s {
+            "json" => PayloadFormat::Json,
+            "avro" => PayloadFormat::Avro,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(format)
+    }
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            PayloadFormat::Json => "json",
+            PayloadFormat::Avro => "avro",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// decode one message
+pub fn decode(format: PayloadFormat, payload: &[u8]) -> Result<RawTxnInput, MyError> {
+    match format {
+        PayloadFormat::Json => serde_json::from_slice(payload)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("invalid JSON transaction"))
+            .change_context(MyError::MalformedRecord),
+        PayloadFormat::Avro => avro::decode_transaction(payload),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KafkaConfig {
+    /// the bootstrap servers, ex: `localhost:9092,localhost:9093`
+    pub brokers: String,
+    pub topic: String,
+    /// the consumer group. a new group starts from the earliest offset
+    pub group: String,
+    pub format: PayloadFormat,
+    /// write the report of every account to a numbered file in `snapshot_dir` this often
+    pub snapshot_interval: Option<Duration>,
+    pub snapshot_dir: PathBuf,
+}
+
+pub struct KafkaConsumer {
+    consumer: BaseConsumer,
+    format: PayloadFormat,
+    snapshots: Option<PeriodicSnapshots>,
+}
+
+impl KafkaConsumer {
+    /// join the consumer group and subscribe to the topic. creates the snapshot directory if needed
+    pub fn new(config: &KafkaConfig) -> Result<Self, MyError> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .report()
+            .attach_printable_lazy(|| {
+                fmt_error!("failed to create a consumer for {}", config.brokers)
+            })
+            .change_context(MyError::Config)?;
+        consumer
+            .subscribe(&[&config.topic])
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to subscribe to {}", config.topic))
+            .change_context(MyError::Config)?;
+        let snapshots = match config.snapshot_interval {
+            Some(interval) => Some(periodic_snapshots(&config.snapshot_dir, interval)?),
+            None => None,
+        };
+        Ok(KafkaConsumer {
+            consumer,
+            format: config.format,
+            snapshots,
+        })
+    }
+
+    /// consume until a transaction fails to process or an offset fails to commit
+    pub fn run(&mut self, processor: &mut TransactionProcessor) -> Result<(), MyError> {
+        loop {
+            self.poll(processor, POLL_TIMEOUT)?;
+        }
+    }
+
+    /// wait up to `timeout` for a message and process it, then take a snapshot if one is due. returns whether there
+    /// was a message
+    pub fn poll(
+        &mut self,
+        processor: &mut TransactionProcessor,
+        timeout: Duration,
+    ) -> Result<bool, MyError> {
+        let received = match self.consumer.poll(timeout) {
+            None => false,
+            // the client reconnects by itself
+            Some(Err(e)) => {
+                tracing::warn!("kafka error: {}", e);
+                false
+            }
+            Some(Ok(message)) => {
+                match decode(self.format, message.payload().unwrap_or_default()) {
+                    Ok(txn) => {
+                        processor.process(txn)?;
+                    }
+                    Err(e) => tracing::warn!(
+                        partition = message.partition(),
+                        offset = message.offset(),
+                        "skipping a message: {:?}",
+                        e
+                    ),
+                }
+                self.consumer
+                    .commit_message(&message, CommitMode::Async)
+                    .report()
+                    .attach_printable_lazy(|| {
+                        fmt_error!(
+                            "failed to commit offset {} of partition {}",
+                            message.offset(),
+                            message.partition()
+                        )
+                    })
+                    .change_context(MyError::Output)?;
+                true
+            }
+        };
+        if let Some(path) = self.snapshots.as_mut().and_then(|s| s.due()) {
+            processor
+                .write_report_file(&path.with_extension(processor.report_format().extension()))?;
+        }
+        Ok(received)
+    }
+}
+
+fn periodic_snapshots(dir: &Path, interval: Duration) -> Result<PeriodicSnapshots, MyError> {
+    fs::create_dir_all(dir)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to create {}", dir.display()))
+        .change_context(MyError::Output)?;
+    Ok(PeriodicSnapshots::new(dir, interval))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let deposit = RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id: 1,
+            txn_id: 2,
+            amount: Some(1.5.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let json = br#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1.5}"#;
+        assert_eq!(decode(PayloadFormat::Json, json).unwrap(), deposit);
+        // type 0 (deposit), client 1, tx 2, then the double branch of the amount union
+        let avro = [&[0, 2, 4, 2][..], &1.5f64.to_le_bytes()].concat();
+        assert_eq!(decode(PayloadFormat::Avro, &avro).unwrap(), deposit);
+        assert!(decode(PayloadFormat::Json, b"{").is_err());
+        assert!(decode(PayloadFormat::Avro, json).is_err());
+        assert_eq!(
+            "avro".parse::<PayloadFormat>().unwrap(),
+            PayloadFormat::Avro
+        );
+        assert_eq!(PayloadFormat::Json.to_string(), "json");
+    }
+
+    // without a broker, the consumer is created but receives nothing
+    #[test]
+    fn test_poll_without_broker() {
+        let dir = std::env::temp_dir().join(format!("kafka-snapshots-{}", std::process::id()));
+        let mut consumer = KafkaConsumer::new(&KafkaConfig {
+            brokers: "127.0.0.1:1".to_string(),
+            topic: "transactions".to_string(),
+            group: "test".to_string(),
+            snapshot_interval: Some(Duration::ZERO),
+            snapshot_dir: dir.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut processor = TransactionProcessor::in_memory();
+        assert!(!consumer
+            .poll(&mut processor, Duration::from_millis(10))
+            .unwrap());
+        assert!(dir.join("snapshot-000001.csv").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
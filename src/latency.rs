@@ -0,0 +1,513 @@
+//! per-transaction latency. a `LatencyHistogram` keeps the distribution of the processing times in constant memory,
+//! and `TimedStore` times the store calls of a transaction so a slow one can be logged with its breakdown
+use crate::{
+    adjustment::Adjustment, amount::Amount, audit::AuditEntry, errors::*, event_log::LoggedEvent,
+    ledger::Posting, limits::Limits, model::*, schedule::Date, store::TxnStore,
+};
+use error_stack::Result;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// log-linear buckets of microseconds: 8 per power of two, so a percentile is off by at most 12.5%
+const SUB_BUCKETS: u64 = 8;
+const BUCKETS: usize = 62 * SUB_BUCKETS as usize;
+
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exp = 63 - micros.leading_zeros() as u64;
+    let sub = (micros >> (exp - 3)) & (SUB_BUCKETS - 1);
+    ((exp - 2) * SUB_BUCKETS + sub) as usize
+}
+
+// the largest value that falls into the bucket
+fn bucket_max(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let exp = idx / SUB_BUCKETS + 2;
+    let sub = idx % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub) << (exp - 3)) + (1 << (exp - 3)) - 1
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.counts[bucket(micros)] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// the latency below which a fraction `q` (ex: 0.99) of the transactions fall. rounded up to the bucket,
+    /// so it's at most 12.5% high. zero if nothing was recorded
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(bucket_max(idx)).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} transaction(s), p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.count,
+            self.quantile(0.5),
+            self.quantile(0.95),
+            self.quantile(0.99),
+            self.max
+        )
+    }
+}
+
+/// the store calls made while processing one transaction and how long each took, in order
+pub type StoreTimings = Arc<Mutex<Vec<(&'static str, Duration)>>>;
+
+/// `store calls: get_client_state 120µs, try_insert_balance_transfer 80µs`. repeated calls are added up
+pub fn describe_timings(timings: &[(&'static str, Duration)]) -> String {
+    let mut totals: Vec<(&'static str, Duration)> = Vec::new();
+    for (op, elapsed) in timings {
+        match totals.iter_mut().find(|(name, _)| name == op) {
+            Some((_, total)) => *total += *elapsed,
+            None => totals.push((op, *elapsed)),
+        }
+    }
+    let calls: Vec<String> = totals
+        .iter()
+        .map(|(op, total)| format!("{} {:?}", op, total))
+        .collect();
+    format!("store calls: {}", calls.join(", "))
+}
+
+/// times every call to the store it wraps
+pub(crate) struct TimedStore {
+    inner: Box<dyn TxnStore + Send>,
+    timings: StoreTimings,
+}
+
+impl TimedStore {
+    pub(crate) fn new(inner: Box<dyn TxnStore + Send>, timings: StoreTimings) -> Self {
+        TimedStore { inner, timings }
+    }
+}
+
+fn timed<T>(timings: &StoreTimings, op: &'static str, call: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = call();
+    if let Ok(mut timings) = timings.lock() {
+        timings.push((op, start.elapsed()));
+    }
+    res
+}
+
+impl TxnStore for TimedStore {
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
+        timed(&self.timings, "create_client_state", || {
+            self.inner.create_client_state(client_id)
+        })
+    }
+
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        timed(&self.timings, "get_client_state", || {
+            self.inner.get_client_state(client_id)
+        })
+    }
+
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_clients", || {
+            self.inner.process_all_clients(f)
+        })
+    }
+
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
+        timed(&self.timings, "update_client_state", || {
+            self.inner.update_client_state(client_state)
+        })
+    }
+
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        timed(&self.timings, "try_insert_balance_transfer", || {
+            self.inner.try_insert_balance_transfer(txn)
+        })
+    }
+
+    fn try_insert_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        timed(&self.timings, "try_insert_dispute", || {
+            self.inner.try_insert_dispute(client_id, txn_id)
+        })
+    }
+
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        timed(&self.timings, "try_resolve_dispute", || {
+            self.inner.try_resolve_dispute(client_id, txn_id)
+        })
+    }
+
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        timed(&self.timings, "try_chargeback_dispute", || {
+            self.inner.try_chargeback_dispute(client_id, txn_id)
+        })
+    }
+
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        timed(&self.timings, "try_reopen_dispute", || {
+            self.inner.try_reopen_dispute(client_id, txn_id)
+        })
+    }
+
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError> {
+        timed(&self.timings, "count_dispute_reopens", || {
+            self.inner.count_dispute_reopens(client_id, txn_id)
+        })
+    }
+
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        timed(&self.timings, "get_balance_transfer", || {
+            self.inner.get_balance_transfer(client_id, txn_id)
+        })
+    }
+
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        timed(&self.timings, "get_balance_transfer_by_id", || {
+            self.inner.get_balance_transfer_by_id(txn_id)
+        })
+    }
+
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError> {
+        timed(&self.timings, "get_open_disputes", || {
+            self.inner.get_open_disputes(client_id)
+        })
+    }
+
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_balance_transfers", || {
+            self.inner.process_all_balance_transfers(f)
+        })
+    }
+
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        timed(&self.timings, "get_overdraft_limit", || {
+            self.inner.get_overdraft_limit(client_id)
+        })
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        timed(&self.timings, "set_overdraft_limit", || {
+            self.inner.set_overdraft_limit(client_id, limit)
+        })
+    }
+
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError> {
+        timed(&self.timings, "get_client_limits", || {
+            self.inner.get_client_limits(client_id)
+        })
+    }
+
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        timed(&self.timings, "set_client_limits", || {
+            self.inner.set_client_limits(client_id, limits)
+        })
+    }
+
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError> {
+        timed(&self.timings, "insert_fee", || {
+            self.inner.insert_fee(client_id, txn_id, fee)
+        })
+    }
+
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError> {
+        timed(&self.timings, "get_fee", || {
+            self.inner.get_fee(client_id, txn_id)
+        })
+    }
+
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError> {
+        timed(&self.timings, "try_insert_refund", || {
+            self.inner.try_insert_refund(refund)
+        })
+    }
+
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError> {
+        timed(&self.timings, "get_refund", || {
+            self.inner.get_refund(client_id, txn_id)
+        })
+    }
+
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        timed(&self.timings, "try_queue_deposit", || {
+            self.inner.try_queue_deposit(txn)
+        })
+    }
+
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError> {
+        timed(&self.timings, "take_queued_deposits", || {
+            self.inner.take_queued_deposits(client_id)
+        })
+    }
+
+    fn forget_client(
+        &mut self,
+        client_id: ClientId,
+        summary: &[Posting],
+    ) -> Result<usize, MyError> {
+        timed(&self.timings, "forget_client", || {
+            self.inner.forget_client(client_id, summary)
+        })
+    }
+
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError> {
+        timed(&self.timings, "insert_posting", || {
+            self.inner.insert_posting(posting)
+        })
+    }
+
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_postings", || {
+            self.inner.process_all_postings(f)
+        })
+    }
+
+    fn process_client_postings(
+        &self,
+        client_id: ClientId,
+        f: &mut dyn FnMut(Posting),
+    ) -> Result<(), MyError> {
+        timed(&self.timings, "process_client_postings", || {
+            self.inner.process_client_postings(client_id, f)
+        })
+    }
+
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError> {
+        timed(&self.timings, "insert_adjustment", || {
+            self.inner.insert_adjustment(adjustment)
+        })
+    }
+
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_adjustments", || {
+            self.inner.process_all_adjustments(f)
+        })
+    }
+
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        timed(&self.timings, "append_audit_entry", || {
+            self.inner.append_audit_entry(entry)
+        })
+    }
+
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_audit_entries", || {
+            self.inner.process_all_audit_entries(f)
+        })
+    }
+
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError> {
+        timed(&self.timings, "append_event", || {
+            self.inner.append_event(event)
+        })
+    }
+
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError> {
+        timed(&self.timings, "process_all_events", || {
+            self.inner.process_all_events(f)
+        })
+    }
+
+    fn begin(&mut self) -> Result<(), MyError> {
+        timed(&self.timings, "begin", || self.inner.begin())
+    }
+
+    fn commit(&mut self) -> Result<(), MyError> {
+        timed(&self.timings, "commit", || self.inner.commit())
+    }
+
+    fn rollback(&mut self) -> Result<(), MyError> {
+        timed(&self.timings, "rollback", || self.inner.rollback())
+    }
+
+    fn get_checkpoint(&self, run_id: &str) -> Result<Option<u64>, MyError> {
+        timed(&self.timings, "get_checkpoint", || {
+            self.inner.get_checkpoint(run_id)
+        })
+    }
+
+    fn set_checkpoint(&mut self, run_id: &str, rows: u64) -> Result<(), MyError> {
+        timed(&self.timings, "set_checkpoint", || {
+            self.inner.set_checkpoint(run_id, rows)
+        })
+    }
+
+    fn get_input_run(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        timed(&self.timings, "get_input_run", || {
+            self.inner.get_input_run(input_sha256)
+        })
+    }
+
+    fn insert_input_run(&mut self, run: &InputRun) -> Result<(), MyError> {
+        timed(&self.timings, "insert_input_run", || {
+            self.inner.insert_input_run(run)
+        })
+    }
+
+    fn try_insert_record_hash(&mut self, sha256: &str) -> Result<bool, MyError> {
+        timed(&self.timings, "try_insert_record_hash", || {
+            self.inner.try_insert_record_hash(sha256)
+        })
+    }
+
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        timed(&self.timings, "get_interest_accrued_through", || {
+            self.inner.get_interest_accrued_through()
+        })
+    }
+
+    fn set_interest_accrued_through(&mut self, date: Date) -> Result<(), MyError> {
+        timed(&self.timings, "set_interest_accrued_through", || {
+            self.inner.set_interest_accrued_through(date)
+        })
+    }
+
+    fn is_purged_txn_id(&self, txn_id: TransactionId) -> Result<bool, MyError> {
+        timed(&self.timings, "is_purged_txn_id", || {
+            self.inner.is_purged_txn_id(txn_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for micros in [0, 1, 7, 8, 15, 16, 17, 100, 1_000, 123_456, u64::MAX / 2] {
+            let idx = bucket(micros);
+            assert!(bucket_max(idx) >= micros, "{}", micros);
+            assert!(idx == 0 || bucket_max(idx - 1) < micros, "{}", micros);
+        }
+        assert!(bucket(u64::MAX) < BUCKETS);
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let mut histogram = LatencyHistogram::new();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let p50 = histogram.quantile(0.5).as_micros();
+        assert!((500..=563).contains(&p50), "{}", p50);
+        let p99 = histogram.quantile(0.99).as_micros();
+        assert!((990..=1000).contains(&p99), "{}", p99);
+        assert_eq!(histogram.quantile(1.0), Duration::from_micros(1000));
+        assert_eq!(histogram.count(), 1000);
+    }
+
+    #[test]
+    fn test_describe_timings() {
+        let timings = [
+            ("get_client_state", Duration::from_micros(5)),
+            ("insert_posting", Duration::from_micros(2)),
+            ("insert_posting", Duration::from_micros(3)),
+        ];
+        assert_eq!(
+            describe_timings(&timings),
+            "store calls: get_client_state 5µs, insert_posting 5µs"
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! the double-entry ledger. every applied operation is recorded as balanced postings, and the client account
+//! balances (available, held) are derived from them.
+//! the operator's cash, chargeback expense, adjustment, opening balance, and interest accounts are assets/expenses:
+//! debits increase them.
+//! the client accounts are liabilities (money owed to the client) and the fee account is income: credits increase
+//! them.
+use crate::{adjustment::Adjustment, amount::Amount, errors::*, model::*};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    /// funds the client may spend
+    ClientAvailable(ClientId),
+    /// disputed funds
+    ClientHeld(ClientId),
+    /// money the operator holds on behalf of the clients
+    OperatorCash,
+    /// provisional credits for disputed withdrawals. becomes a loss if the dispute is charged back
+    ChargebackExpense,
+    /// manual adjustments made by operators
+    Adjustments,
+    /// balances carried over from a previous run (`--initial-balances`)
+    OpeningBalances,
+    /// the fees charged on deposits and withdrawals, net of the fees refunded on chargebacks
+    Fees,
+    /// the interest paid on the available funds
+    Interest,
+}
+
+impl LedgerAccount {
+    /// liabilities and income increase with credits. everything else increases with debits
+    pub fn is_credit_normal(&self) -> bool {
+        matches!(
+            self,
+            LedgerAccount::ClientAvailable(_) | LedgerAccount::ClientHeld(_) | LedgerAccount::Fees
+        )
+    }
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::ClientAvailable(id) => write!(f, "available:{}", id),
+            LedgerAccount::ClientHeld(id) => write!(f, "held:{}", id),
+            LedgerAccount::OperatorCash => write!(f, "cash"),
+            LedgerAccount::ChargebackExpense => write!(f, "chargeback_expense"),
+            LedgerAccount::Adjustments => write!(f, "adjustments"),
+            LedgerAccount::OpeningBalances => write!(f, "opening_balances"),
+            LedgerAccount::Fees => write!(f, "fees"),
+            LedgerAccount::Interest => write!(f, "interest"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conversion_error = || MyError::Conversion(s.to_string());
+        match s {
+            "cash" => return Ok(LedgerAccount::OperatorCash),
+            "chargeback_expense" => return Ok(LedgerAccount::ChargebackExpense),
+            "adjustments" => return Ok(LedgerAccount::Adjustments),
+            "opening_balances" => return Ok(LedgerAccount::OpeningBalances),
+            "fees" => return Ok(LedgerAccount::Fees),
+            "interest" => return Ok(LedgerAccount::Interest),
+            _ => {}
+        }
+        let (kind, id) = s.split_once(':').ok_or_else(conversion_error)?;
+        let id: ClientId = id.parse().map_err(|_| conversion_error())?;
+        match kind {
+            "available" => Ok(LedgerAccount::ClientAvailable(id)),
+            "held" => Ok(LedgerAccount::ClientHeld(id)),
+            _ => Err(conversion_error()),
+        }
+    }
+}
+
+/// moves `amount` (always positive) between two accounts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting {
+    /// the transaction that caused the posting
+    pub txn_id: TransactionId,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Amount,
+}
+
+impl Posting {
+    /// update the balances of `state` that this posting touches
+    pub fn apply_to(&self, state: &mut ClientState) {
+        let id = state.client_id;
+        match self.debit {
+            LedgerAccount::ClientAvailable(c) if c == id => state.available -= self.amount,
+            LedgerAccount::ClientHeld(c) if c == id => state.held -= self.amount,
+            _ => {}
+        }
+        match self.credit {
+            LedgerAccount::ClientAvailable(c) if c == id => state.available += self.amount,
+            LedgerAccount::ClientHeld(c) if c == id => state.held += self.amount,
+            _ => {}
+        }
+    }
+
+    /// true if either side is one of the client's accounts
+    pub fn involves_client(&self, client_id: ClientId) -> bool {
+        [self.debit, self.credit].iter().any(|account| {
+            matches!(account, LedgerAccount::ClientAvailable(c) | LedgerAccount::ClientHeld(c) if *c == client_id)
+        })
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        let parse = |idx: usize| -> std::result::Result<LedgerAccount, rusqlite::Error> {
+            let s: String = row.get(idx)?;
+            s.parse().map_err(|e: MyError| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    idx,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })
+        };
+        Ok(Posting {
+            txn_id: row.get(0)?,
+            debit: parse(1)?,
+            credit: parse(2)?,
+            amount: row.get(3)?,
+        })
+    }
+}
+
+/// the postings for a deposit (positive amount) or withdrawal (negative amount)
+pub fn balance_transfer_postings(transfer: &BalanceTransfer) -> Vec<Posting> {
+    let available = LedgerAccount::ClientAvailable(transfer.client_id);
+    let (debit, credit) = if transfer.amount.is_negative() {
+        (available, LedgerAccount::OperatorCash)
+    } else {
+        (LedgerAccount::OperatorCash, available)
+    };
+    vec![Posting {
+        txn_id: transfer.txn_id,
+        debit,
+        credit,
+        amount: transfer.amount.abs(),
+    }]
+}
+
+/// the postings for opening a dispute on `transfer`.
+/// a disputed deposit moves funds from available to held. a disputed withdrawal is provisionally credited to held
+pub fn dispute_postings(transfer: &BalanceTransfer) -> Vec<Posting> {
+    let held = LedgerAccount::ClientHeld(transfer.client_id);
+    let debit = if transfer.amount.is_negative() {
+        LedgerAccount::ChargebackExpense
+    } else {
+        LedgerAccount::ClientAvailable(transfer.client_id)
+    };
+    vec![Posting {
+        txn_id: transfer.txn_id,
+        debit,
+        credit: held,
+        amount: transfer.amount.abs(),
+    }]
+}
+
+/// the postings for resolving a dispute on `transfer`: the reverse of `dispute_postings`
+pub fn resolve_postings(transfer: &BalanceTransfer) -> Vec<Posting> {
+    dispute_postings(transfer)
+        .into_iter()
+        .map(|p| Posting {
+            debit: p.credit,
+            credit: p.debit,
+            ..p
+        })
+        .collect()
+}
+
+/// the postings for charging back a dispute on `transfer`.
+/// a charged back deposit returns the held funds to the payer. a charged back withdrawal releases the held funds to the client
+pub fn chargeback_postings(transfer: &BalanceTransfer) -> Vec<Posting> {
+    let credit = if transfer.amount.is_negative() {
+        LedgerAccount::ClientAvailable(transfer.client_id)
+    } else {
+        LedgerAccount::OperatorCash
+    };
+    vec![Posting {
+        txn_id: transfer.txn_id,
+        debit: LedgerAccount::ClientHeld(transfer.client_id),
+        credit,
+        amount: transfer.amount.abs(),
+    }]
+}
+
+/// the postings for the fee charged on the deposit or withdrawal `txn_id`: it's taken from the available funds
+pub fn fee_postings(client_id: ClientId, txn_id: TransactionId, fee: Amount) -> Vec<Posting> {
+    vec![Posting {
+        txn_id,
+        debit: LedgerAccount::ClientAvailable(client_id),
+        credit: LedgerAccount::Fees,
+        amount: fee,
+    }]
+}
+
+/// the postings for refunding the fee of a charged back deposit or withdrawal: the reverse of `fee_postings`
+pub fn fee_refund_postings(
+    client_id: ClientId,
+    txn_id: TransactionId,
+    fee: Amount,
+) -> Vec<Posting> {
+    vec![Posting {
+        txn_id,
+        debit: LedgerAccount::Fees,
+        credit: LedgerAccount::ClientAvailable(client_id),
+        amount: fee,
+    }]
+}
+
+/// the postings for the interest accrued on a client's available funds. interest isn't an input transaction, so its
+/// txn_id is 0
+pub fn interest_postings(client_id: ClientId, interest: Amount) -> Vec<Posting> {
+    vec![Posting {
+        txn_id: 0,
+        debit: LedgerAccount::Interest,
+        credit: LedgerAccount::ClientAvailable(client_id),
+        amount: interest,
+    }]
+}
+
+/// the postings for a manual adjustment. adjustments aren't input transactions, so their txn_id is 0
+pub fn adjustment_postings(adjustment: &Adjustment) -> Vec<Posting> {
+    let available = LedgerAccount::ClientAvailable(adjustment.client_id);
+    let (debit, credit) = if adjustment.amount.is_negative() {
+        (available, LedgerAccount::Adjustments)
+    } else {
+        (LedgerAccount::Adjustments, available)
+    };
+    vec![Posting {
+        txn_id: 0,
+        debit,
+        credit,
+        amount: adjustment.amount.abs(),
+    }]
+}
+
+/// the postings that carry a client's balances over from a previous run
+pub fn opening_postings(state: &ClientState) -> Vec<Posting> {
+    [
+        (
+            LedgerAccount::ClientAvailable(state.client_id),
+            state.available,
+        ),
+        (LedgerAccount::ClientHeld(state.client_id), state.held),
+    ]
+    .into_iter()
+    .filter(|(_, amount)| !amount.is_zero())
+    .map(|(account, amount)| {
+        let (debit, credit) = if amount.is_negative() {
+            (account, LedgerAccount::OpeningBalances)
+        } else {
+            (LedgerAccount::OpeningBalances, account)
+        };
+        Posting {
+            txn_id: 0,
+            debit,
+            credit,
+            amount: amount.abs(),
+        }
+    })
+    .collect()
+}
+
+/// the txn_id of the postings in a sealed summary
+pub const SEALED_TXN_ID: TransactionId = 0;
+
+/// replace `postings` with a sealed summary: at most one posting per pair of accounts, netting the amounts moved
+/// between them. every account balance stays the same, but the individual transactions can't be recovered
+pub fn seal(postings: &[Posting]) -> Vec<Posting> {
+    // the amount moved from the smaller account to the larger one
+    let mut net: BTreeMap<(LedgerAccount, LedgerAccount), Amount> = BTreeMap::new();
+    for posting in postings {
+        if posting.debit < posting.credit {
+            *net.entry((posting.debit, posting.credit)).or_default() += posting.amount;
+        } else {
+            *net.entry((posting.credit, posting.debit)).or_default() -= posting.amount;
+        }
+    }
+    net.into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|((a, b), amount)| {
+            let (debit, credit) = if amount.is_positive() { (a, b) } else { (b, a) };
+            Posting {
+                txn_id: SEALED_TXN_ID,
+                debit,
+                credit,
+                amount: amount.abs(),
+            }
+        })
+        .collect()
+}
+
+/// debit and credit totals per account
+#[derive(Debug, Default, Clone)]
+pub struct Ledger {
+    accounts: BTreeMap<LedgerAccount, (Amount, Amount)>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn post(&mut self, posting: &Posting) {
+        self.accounts.entry(posting.debit).or_default().0 += posting.amount;
+        self.accounts.entry(posting.credit).or_default().1 += posting.amount;
+    }
+
+    /// the balance of an account in its natural direction: credits - debits for liabilities and income, debits -
+    /// credits otherwise
+    pub fn balance(&self, account: LedgerAccount) -> Amount {
+        let (debits, credits) = self.accounts.get(&account).copied().unwrap_or_default();
+        if account.is_credit_normal() {
+            credits - debits
+        } else {
+            debits - credits
+        }
+    }
+
+    /// every account that has been posted to, in order
+    pub fn accounts(&self) -> impl Iterator<Item = LedgerAccount> + '_ {
+        self.accounts.keys().copied()
+    }
+
+    /// the sum of all debits and the sum of all credits. equal unless a posting was lost
+    pub fn totals(&self) -> (Amount, Amount) {
+        self.accounts
+            .values()
+            .fold((Amount::ZERO, Amount::ZERO), |acc, (d, c)| {
+                (acc.0 + *d, acc.1 + *c)
+            })
+    }
+
+    /// the available and held balances of a client
+    pub fn client_balances(&self, client_id: ClientId) -> (Amount, Amount) {
+        (
+            self.balance(LedgerAccount::ClientAvailable(client_id)),
+            self.balance(LedgerAccount::ClientHeld(client_id)),
+        )
+    }
+
+    /// the debits and credits per account. unless `per_client` is set, the client accounts are summed into two
+    /// lines: "client_available" and "client_held"
+    pub fn trial_balance(&self, per_client: bool) -> TrialBalance {
+        let mut lines: Vec<TrialBalanceLine> = Vec::new();
+        // the accounts are ordered, so the client accounts of a group are next to each other
+        for (account, (debits, credits)) in &self.accounts {
+            let name = match account {
+                LedgerAccount::ClientAvailable(_) if !per_client => "client_available".to_string(),
+                LedgerAccount::ClientHeld(_) if !per_client => "client_held".to_string(),
+                _ => account.to_string(),
+            };
+            match lines.last_mut() {
+                Some(line) if line.account == name => {
+                    line.debits += *debits;
+                    line.credits += *credits;
+                }
+                _ => lines.push(TrialBalanceLine {
+                    account: name,
+                    debits: *debits,
+                    credits: *credits,
+                }),
+            }
+        }
+        TrialBalance { lines }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalanceLine {
+    /// an account, or a group of client accounts
+    pub account: String,
+    pub debits: Amount,
+    pub credits: Amount,
+}
+
+/// the debits and credits per account. the debits and credits net to zero unless a posting was lost
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrialBalance {
+    pub lines: Vec<TrialBalanceLine>,
+}
+
+impl TrialBalance {
+    /// the sum of the debits and the sum of the credits
+    pub fn totals(&self) -> (Amount, Amount) {
+        self.lines
+            .iter()
+            .fold((Amount::ZERO, Amount::ZERO), |acc, line| {
+                (acc.0 + line.debits, acc.1 + line.credits)
+            })
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        let (debits, credits) = self.totals();
+        debits == credits
+    }
+}
+
+/// CSV: one row per line, then the totals. `net` is debits - credits
+impl fmt::Display for TrialBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "account,debits,credits,net")?;
+        for line in &self.lines {
+            writeln!(
+                f,
+                "{},{},{},{}",
+                line.account,
+                line.debits,
+                line.credits,
+                line.debits - line.credits
+            )?;
+        }
+        let (debits, credits) = self.totals();
+        writeln!(f, "total,{},{},{}", debits, credits, debits - credits)
+    }
+}
+
+impl FromIterator<Posting> for Ledger {
+    fn from_iter<I: IntoIterator<Item = Posting>>(iter: I) -> Self {
+        let mut ledger = Ledger::new();
+        for posting in iter {
+            ledger.post(&posting);
+        }
+        ledger
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn apply(postings: &[Posting], state: &mut ClientState, ledger: &mut Ledger) {
+        for posting in postings {
+            posting.apply_to(state);
+            ledger.post(posting);
+        }
+    }
+
+    #[test]
+    fn test_account_round_trip() {
+        for account in [
+            LedgerAccount::ClientAvailable(1),
+            LedgerAccount::ClientHeld(65535),
+            LedgerAccount::OperatorCash,
+            LedgerAccount::ChargebackExpense,
+            LedgerAccount::Adjustments,
+            LedgerAccount::OpeningBalances,
+            LedgerAccount::Fees,
+            LedgerAccount::Interest,
+        ] {
+            assert_eq!(
+                account.to_string().parse::<LedgerAccount>().unwrap(),
+                account
+            );
+        }
+        assert!("held:-1".parse::<LedgerAccount>().is_err());
+        assert!("savings:1".parse::<LedgerAccount>().is_err());
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let mut ledger = Ledger::new();
+        for (client_id, txn_id, amount) in [(1, 1, amt(5.0)), (2, 2, amt(2.5)), (1, 3, amt(-1.0))] {
+            let transfer = BalanceTransfer {
+                client_id,
+                txn_id,
+                amount,
+                timestamp: None,
+            };
+            for posting in balance_transfer_postings(&transfer) {
+                ledger.post(&posting);
+            }
+        }
+        let dispute = BalanceTransfer {
+            client_id: 2,
+            txn_id: 2,
+            amount: amt(2.5),
+            timestamp: None,
+        };
+        for posting in dispute_postings(&dispute) {
+            ledger.post(&posting);
+        }
+
+        let trial_balance = ledger.trial_balance(false);
+        assert!(trial_balance.is_balanced());
+        assert_eq!(
+            trial_balance.to_string(),
+            "account,debits,credits,net
+client_available,3.5,7.5,-4
+client_held,0,2.5,-2.5
+cash,7.5,1,6.5
+total,11,11,0
+"
+        );
+        let per_client = ledger.trial_balance(true);
+        assert_eq!(per_client.lines.len(), 4);
+        assert_eq!(per_client.lines[0].account, "available:1");
+        assert_eq!(per_client.totals(), trial_balance.totals());
+    }
+
+    #[test]
+    fn test_disputed_deposit() {
+        let deposit = BalanceTransfer {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(5.0),
+            timestamp: None,
+        };
+        let mut state = ClientState::new(1);
+        let mut ledger = Ledger::new();
+        apply(
+            &balance_transfer_postings(&deposit),
+            &mut state,
+            &mut ledger,
+        );
+        apply(&dispute_postings(&deposit), &mut state, &mut ledger);
+        assert_eq!((state.available, state.held), (amt(0.0), amt(5.0)));
+        apply(&chargeback_postings(&deposit), &mut state, &mut ledger);
+        assert_eq!((state.available, state.held), (amt(0.0), amt(0.0)));
+
+        assert_eq!(ledger.client_balances(1), (state.available, state.held));
+        assert_eq!(ledger.balance(LedgerAccount::OperatorCash), 0.0);
+        let (debits, credits) = ledger.totals();
+        assert_eq!(debits, credits);
+    }
+
+    #[test]
+    fn test_disputed_withdrawal() {
+        let deposit = BalanceTransfer {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(5.0),
+            timestamp: None,
+        };
+        let withdrawal = BalanceTransfer {
+            client_id: 1,
+            txn_id: 2,
+            amount: amt(-2.0),
+            timestamp: None,
+        };
+        let mut state = ClientState::new(1);
+        let mut ledger = Ledger::new();
+        apply(
+            &balance_transfer_postings(&deposit),
+            &mut state,
+            &mut ledger,
+        );
+        apply(
+            &balance_transfer_postings(&withdrawal),
+            &mut state,
+            &mut ledger,
+        );
+        apply(&dispute_postings(&withdrawal), &mut state, &mut ledger);
+        assert_eq!((state.available, state.held), (amt(3.0), amt(2.0)));
+        apply(&resolve_postings(&withdrawal), &mut state, &mut ledger);
+        assert_eq!((state.available, state.held), (amt(3.0), amt(0.0)));
+        apply(&dispute_postings(&withdrawal), &mut state, &mut ledger);
+        apply(&chargeback_postings(&withdrawal), &mut state, &mut ledger);
+        assert_eq!((state.available, state.held), (amt(5.0), amt(0.0)));
+
+        assert_eq!(ledger.client_balances(1), (state.available, state.held));
+        // the operator paid out the withdrawal and then refunded it
+        assert_eq!(ledger.balance(LedgerAccount::OperatorCash), 3.0);
+        assert_eq!(ledger.balance(LedgerAccount::ChargebackExpense), 2.0);
+        let (debits, credits) = ledger.totals();
+        assert_eq!(debits, credits);
+    }
+
+    #[test]
+    fn test_seal() {
+        let deposit = BalanceTransfer {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(5.0),
+            timestamp: None,
+        };
+        let withdrawal = BalanceTransfer {
+            client_id: 1,
+            txn_id: 2,
+            amount: amt(-2.0),
+            timestamp: None,
+        };
+        let mut postings = balance_transfer_postings(&deposit);
+        postings.extend(balance_transfer_postings(&withdrawal));
+        postings.extend(dispute_postings(&withdrawal));
+        postings.extend(resolve_postings(&withdrawal));
+        postings.extend(dispute_postings(&deposit));
+
+        let sealed = seal(&postings);
+        // cash -> available nets to 3, available -> held is 5, and the resolved dispute cancels out
+        assert_eq!(sealed.len(), 2);
+        assert!(sealed.iter().all(|p| p.txn_id == SEALED_TXN_ID));
+        let before: Ledger = postings.iter().copied().collect();
+        let after: Ledger = sealed.iter().copied().collect();
+        for account in before.accounts() {
+            assert_eq!(before.balance(account), after.balance(account));
+        }
+        assert!(sealed.iter().all(|p| p.involves_client(1)));
+        assert!(!sealed[0].involves_client(2));
+    }
+}
@@ -1,4 +1,82 @@
+pub mod adjustment;
+pub mod aging;
+pub mod amount;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod async_processor;
+#[cfg(feature = "async")]
+pub mod async_store;
+pub mod audit;
+pub mod avro;
+#[cfg(feature = "avro")]
+pub mod avro_container;
+pub mod builder;
+#[cfg(any(test, feature = "test-util"))]
+pub mod chaos_store;
+pub mod columns;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod config;
+#[cfg(feature = "sqlite")]
 pub mod db;
+pub mod dialect;
+pub mod duplicates;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod errors;
+pub mod event_log;
+pub mod events;
+pub mod export;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fake_store;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+pub mod interest;
+pub mod invariants;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod latency;
+pub mod ledger;
+pub mod limits;
+pub mod memory;
+pub mod memory_db;
+#[cfg(feature = "server")]
+pub mod metrics;
 pub mod model;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod number_format;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod output;
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod policy;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod rejects;
+pub mod report;
+pub mod risk;
+pub mod rounding;
+pub mod schedule;
+pub mod sequence;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod snapshot;
+pub mod statement;
+pub mod stats;
+pub mod store;
 pub mod transaction_processor;
+pub mod validate;
+pub mod workload;
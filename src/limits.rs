@@ -0,0 +1,112 @@
+//! transaction limits: the largest total balance an account can hold, and the largest single deposit and withdrawal.
+//! the processor's limits apply to every client; a client's own limits (`TransactionProcessor::set_client_limits`,
+//! the "Limits" table with SQLite) take precedence, one limit at a time
+use crate::{amount::Amount, events::RejectReason};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// the largest total (available + held) after a deposit
+    pub max_balance: Option<Amount>,
+    pub max_deposit: Option<Amount>,
+    pub max_withdrawal: Option<Amount>,
+}
+
+impl Limits {
+    /// true if nothing is limited
+    pub fn is_unlimited(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// each limit of `self` that is set, otherwise the one of `defaults`
+    pub fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            max_balance: self.max_balance.or(defaults.max_balance),
+            max_deposit: self.max_deposit.or(defaults.max_deposit),
+            max_withdrawal: self.max_withdrawal.or(defaults.max_withdrawal),
+        }
+    }
+
+    /// the limit exceeded by a transfer of `amount` (positive for a deposit, negative for a withdrawal) to an account
+    /// with a `total` balance. None if it's within the limits
+    pub fn check(&self, total: Amount, amount: Amount) -> Option<RejectReason> {
+        if amount.is_negative() {
+            return self
+                .max_withdrawal
+                .filter(|max| -amount > *max)
+                .map(|_| RejectReason::WithdrawalLimitExceeded);
+        }
+        if self.max_deposit.is_some_and(|max| amount > max) {
+            return Some(RejectReason::DepositLimitExceeded);
+        }
+        self.max_balance
+            .filter(|max| total + amount > *max)
+            .map(|_| RejectReason::BalanceLimitExceeded)
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = |limit: Option<Amount>| match limit {
+            Some(limit) => limit.to_string(),
+            None => "none".to_string(),
+        };
+        write!(
+            f,
+            "max balance {}, max deposit {}, max withdrawal {}",
+            limit(self.max_balance),
+            limit(self.max_deposit),
+            limit(self.max_withdrawal)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_check() {
+        let limits = Limits {
+            max_balance: Some(amt(100.0)),
+            max_deposit: Some(amt(50.0)),
+            max_withdrawal: Some(amt(20.0)),
+        };
+        let check = |total: f64, amount: f64| limits.check(amt(total), amt(amount));
+        assert_eq!(check(0.0, 50.0), None);
+        assert_eq!(check(0.0, 50.5), Some(RejectReason::DepositLimitExceeded));
+        assert_eq!(check(60.0, 40.0), None);
+        assert_eq!(check(60.0, 40.5), Some(RejectReason::BalanceLimitExceeded));
+        assert_eq!(check(500.0, -20.0), None);
+        assert_eq!(
+            check(500.0, -20.5),
+            Some(RejectReason::WithdrawalLimitExceeded)
+        );
+        // a withdrawal from an account above the balance limit is allowed
+        assert_eq!(check(500.0, -1.0), None);
+        assert_eq!(Limits::default().check(amt(1e9), amt(1e9)), None);
+    }
+
+    #[test]
+    fn test_or() {
+        let own = Limits {
+            max_deposit: Some(amt(5.0)),
+            ..Default::default()
+        };
+        let defaults = Limits {
+            max_balance: Some(amt(100.0)),
+            max_deposit: Some(amt(50.0)),
+            max_withdrawal: None,
+        };
+        let limits = own.or(defaults);
+        assert_eq!(limits.max_balance, Some(amt(100.0)));
+        assert_eq!(limits.max_deposit, Some(amt(5.0)));
+        assert_eq!(limits.max_withdrawal, None);
+        assert!(Limits::default().is_unlimited());
+        assert_eq!(
+            limits.to_string(),
+            "max balance 100, max deposit 5, max withdrawal none"
+        );
+    }
+}
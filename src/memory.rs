@@ -0,0 +1,147 @@
+//! memory usage tracking. the processor samples the resident set size of the process while it runs, records the
+//! peak, and keeps it under a configurable ceiling: when usage gets close, it drops the state that can be rebuilt
+//! (idle rate limiter buckets, chargeback activity that left the window). if that isn't enough the run stops with an
+//! error instead of being killed by the OS part way through a row.
+//! the resident set size is read from /proc, so tracking is only available on Linux
+use crate::errors::*;
+use std::{fmt, str::FromStr};
+
+/// the resident set size of this process in bytes. None where it isn't available
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// a number of bytes. parses `512M`, `2G`, `64k`, or a plain number of bytes (powers of 1024)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MyError::Conversion(s.to_string());
+        let s = s.trim();
+        let (digits, shift) = match s.char_indices().last().ok_or_else(err)? {
+            (idx, 'k' | 'K') => (&s[..idx], 10),
+            (idx, 'm' | 'M') => (&s[..idx], 20),
+            (idx, 'g' | 'G') => (&s[..idx], 30),
+            _ => (s, 0),
+        };
+        let value: u64 = digits.trim().parse().map_err(|_| err())?;
+        value.checked_mul(1 << shift).map(ByteSize).ok_or_else(err)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mib = self.0 as f64 / (1 << 20) as f64;
+        write!(f, "{:.1} MiB", mib)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryPressure {
+    Normal,
+    /// at least 90% of the ceiling
+    High,
+    Exceeded,
+}
+
+#[derive(Debug)]
+pub struct MemoryMonitor {
+    ceiling: Option<u64>,
+    // sample every `every` transactions: reading /proc isn't free
+    every: u64,
+    seen: u64,
+    peak: u64,
+}
+
+impl MemoryMonitor {
+    pub fn new(ceiling: Option<ByteSize>) -> Self {
+        MemoryMonitor {
+            ceiling: ceiling.map(|c| c.0),
+            every: 1000,
+            seen: 0,
+            peak: 0,
+        }
+    }
+
+    /// count a processed transaction. returns the current usage when it's time for a sample
+    pub fn observe(&mut self) -> Option<u64> {
+        self.seen += 1;
+        if self.seen != 1 && !self.seen.is_multiple_of(self.every) {
+            return None;
+        }
+        self.sample()
+    }
+
+    /// measure now, regardless of the sampling interval
+    pub fn sample(&mut self) -> Option<u64> {
+        let bytes = resident_bytes()?;
+        self.peak = self.peak.max(bytes);
+        Some(bytes)
+    }
+
+    pub fn pressure(&self, bytes: u64) -> MemoryPressure {
+        match self.ceiling {
+            Some(ceiling) if bytes > ceiling => MemoryPressure::Exceeded,
+            Some(ceiling) if bytes >= ceiling / 10 * 9 => MemoryPressure::High,
+            _ => MemoryPressure::Normal,
+        }
+    }
+
+    pub fn ceiling(&self) -> Option<ByteSize> {
+        self.ceiling.map(ByteSize)
+    }
+
+    /// the highest usage sampled. None if nothing could be measured
+    pub fn peak(&self) -> Option<ByteSize> {
+        (self.peak > 0).then_some(ByteSize(self.peak))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!("512".parse::<ByteSize>().unwrap(), ByteSize(512));
+        assert_eq!("64k".parse::<ByteSize>().unwrap(), ByteSize(64 << 10));
+        assert_eq!("512M".parse::<ByteSize>().unwrap(), ByteSize(512 << 20));
+        assert_eq!("2 G".parse::<ByteSize>().unwrap(), ByteSize(2 << 30));
+        assert!("".parse::<ByteSize>().is_err());
+        assert!("1.5G".parse::<ByteSize>().is_err());
+        assert!("99999999999999G".parse::<ByteSize>().is_err());
+        assert_eq!(ByteSize(3 << 19).to_string(), "1.5 MiB");
+    }
+
+    #[test]
+    fn test_pressure() {
+        let monitor = MemoryMonitor::new(Some(ByteSize(1000)));
+        assert_eq!(monitor.pressure(500), MemoryPressure::Normal);
+        assert_eq!(monitor.pressure(900), MemoryPressure::High);
+        assert_eq!(monitor.pressure(1001), MemoryPressure::Exceeded);
+        assert_eq!(
+            MemoryMonitor::new(None).pressure(u64::MAX),
+            MemoryPressure::Normal
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sample() {
+        let mut monitor = MemoryMonitor::new(None);
+        assert!(monitor.observe().unwrap() > 0);
+        assert!(monitor.observe().is_none());
+        assert!(monitor.peak().is_some());
+    }
+}
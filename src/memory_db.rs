@@ -0,0 +1,795 @@
+use crate::{
+    adjustment::Adjustment, amount::Amount, audit::AuditEntry, errors::*, event_log::LoggedEvent,
+    ledger::Posting, limits::Limits, model::*, schedule::Date, store::TxnStore,
+};
+use error_stack::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// an in-memory `TxnStore`. enforces the same constraints as the SQLite tables but never touches the file system,
+/// which makes it usable on targets without SQLite (such as wasm32) and in tests. like SQLite, it rolls back every
+/// change made since `begin`.
+#[derive(Clone, Default)]
+pub struct MemoryDb {
+    clients: BTreeMap<ClientId, ClientState>,
+    // transaction ids are globally unique
+    balance_transfers: HashMap<TransactionId, BalanceTransfer>,
+    disputes: HashSet<(ClientId, TransactionId)>,
+    resolutions: HashMap<(ClientId, TransactionId), DisputeStatus>,
+    // the resolutions undone by reopening a dispute
+    dispute_history: Vec<(ClientId, TransactionId, DisputeStatus)>,
+    postings: Vec<Posting>,
+    adjustments: Vec<Adjustment>,
+    audit_log: Vec<AuditEntry>,
+    events: Vec<LoggedEvent>,
+    checkpoints: HashMap<String, u64>,
+    input_runs: HashMap<String, InputRun>,
+    record_hashes: HashSet<String>,
+    overdraft_limits: HashMap<ClientId, Amount>,
+    limits: HashMap<ClientId, Limits>,
+    fees: HashMap<(ClientId, TransactionId), Amount>,
+    // by the transaction id of the refund
+    refunds: HashMap<TransactionId, Refund>,
+    interest_accrued_through: Option<Date>,
+    // the deposits to locked accounts, in the order they were queued
+    queued_deposits: Vec<BalanceTransfer>,
+    // the changes made since begin, undone in reverse order by rollback. None outside of a store transaction
+    journal: Option<Vec<Undo>>,
+}
+
+// how rollback undoes one change. a push is undone by a pop
+#[derive(Clone)]
+enum Undo {
+    Client(ClientId, Option<ClientState>),
+    BalanceTransfer(TransactionId),
+    Dispute(ClientId, TransactionId),
+    Resolution(ClientId, TransactionId),
+    Reopen(ClientId, TransactionId),
+    OverdraftLimit(ClientId, Option<Amount>),
+    Limits(ClientId, Option<Limits>),
+    Fee(ClientId, TransactionId, Option<Amount>),
+    Refund(TransactionId),
+    QueuedDeposit,
+    QueuedDeposits(Vec<BalanceTransfer>),
+    Posting,
+    Adjustment,
+    AuditEntry,
+    Event,
+    Checkpoint(String, Option<u64>),
+    InputRun(String),
+    RecordHash(String),
+    InterestAccruedThrough(Option<Date>),
+    // forget_client changes too much to undo piece by piece
+    Snapshot(Box<MemoryDb>),
+}
+
+impl MemoryDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push(undo);
+        }
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        match undo {
+            Undo::Client(client_id, Some(state)) => {
+                self.clients.insert(client_id, state);
+            }
+            Undo::Client(client_id, None) => {
+                self.clients.remove(&client_id);
+            }
+            Undo::BalanceTransfer(txn_id) => {
+                self.balance_transfers.remove(&txn_id);
+            }
+            Undo::Dispute(client_id, txn_id) => {
+                self.disputes.remove(&(client_id, txn_id));
+            }
+            Undo::Resolution(client_id, txn_id) => {
+                self.resolutions.remove(&(client_id, txn_id));
+            }
+            Undo::Reopen(client_id, txn_id) => {
+                if let Some((_, _, status)) = self.dispute_history.pop() {
+                    self.resolutions.insert((client_id, txn_id), status);
+                }
+            }
+            Undo::OverdraftLimit(client_id, limit) => match limit {
+                Some(limit) => {
+                    self.overdraft_limits.insert(client_id, limit);
+                }
+                None => {
+                    self.overdraft_limits.remove(&client_id);
+                }
+            },
+            Undo::Limits(client_id, limits) => match limits {
+                Some(limits) => {
+                    self.limits.insert(client_id, limits);
+                }
+                None => {
+                    self.limits.remove(&client_id);
+                }
+            },
+            Undo::Fee(client_id, txn_id, fee) => match fee {
+                Some(fee) => {
+                    self.fees.insert((client_id, txn_id), fee);
+                }
+                None => {
+                    self.fees.remove(&(client_id, txn_id));
+                }
+            },
+            Undo::Refund(txn_id) => {
+                self.refunds.remove(&txn_id);
+            }
+            Undo::QueuedDeposit => {
+                self.queued_deposits.pop();
+            }
+            Undo::QueuedDeposits(queued) => self.queued_deposits = queued,
+            Undo::Posting => {
+                self.postings.pop();
+            }
+            Undo::Adjustment => {
+                self.adjustments.pop();
+            }
+            Undo::AuditEntry => {
+                self.audit_log.pop();
+            }
+            Undo::Event => {
+                self.events.pop();
+            }
+            Undo::Checkpoint(run_id, rows) => match rows {
+                Some(rows) => {
+                    self.checkpoints.insert(run_id, rows);
+                }
+                None => {
+                    self.checkpoints.remove(&run_id);
+                }
+            },
+            Undo::InputRun(input_sha256) => {
+                self.input_runs.remove(&input_sha256);
+            }
+            Undo::RecordHash(sha256) => {
+                self.record_hashes.remove(&sha256);
+            }
+            Undo::InterestAccruedThrough(date) => self.interest_accrued_through = date,
+            Undo::Snapshot(snapshot) => {
+                let journal = self.journal.take();
+                *self = *snapshot;
+                self.journal = journal;
+            }
+        }
+    }
+
+    fn try_insert_resolution(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        status: DisputeStatus,
+    ) -> bool {
+        let key = (client_id, txn_id);
+        if !self.disputes.contains(&key) || self.resolutions.contains_key(&key) {
+            return false;
+        }
+        self.resolutions.insert(key, status);
+        self.record(Undo::Resolution(client_id, txn_id));
+        true
+    }
+}
+
+impl TxnStore for MemoryDb {
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError> {
+        let client_state = ClientState::new(client_id);
+        let previous = self.clients.insert(client_id, client_state.clone());
+        self.record(Undo::Client(client_id, previous));
+        Ok(client_state)
+    }
+
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        Ok(self.clients.get(&client_id).cloned())
+    }
+
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError> {
+        // the map is ordered by client_id
+        for state in self.clients.values() {
+            f(state.clone());
+        }
+        Ok(())
+    }
+
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError> {
+        if let Some(state) = self.clients.get_mut(&client_state.client_id) {
+            let previous = std::mem::replace(state, client_state.clone());
+            self.record(Undo::Client(client_state.client_id, Some(previous)));
+        }
+        Ok(())
+    }
+
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        if !self.clients.contains_key(&txn.client_id)
+            || self.balance_transfers.contains_key(&txn.txn_id)
+        {
+            return Ok(false);
+        }
+        self.balance_transfers.insert(txn.txn_id, txn);
+        self.record(Undo::BalanceTransfer(txn.txn_id));
+        Ok(true)
+    }
+
+    fn try_insert_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        // the balance transfer has to exist and belong to this client
+        if self.get_balance_transfer(client_id, txn_id)?.is_none() {
+            return Ok(false);
+        }
+        // neither a refund nor a refunded deposit can be disputed
+        if self
+            .refunds
+            .values()
+            .any(|r| r.txn_id == txn_id || r.original_txn_id == txn_id)
+        {
+            return Ok(false);
+        }
+        if !self.disputes.insert((client_id, txn_id)) {
+            return Ok(false);
+        }
+        self.record(Undo::Dispute(client_id, txn_id));
+        Ok(true)
+    }
+
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        Ok(self.try_insert_resolution(client_id, txn_id, DisputeStatus::Resolved))
+    }
+
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        Ok(self.try_insert_resolution(client_id, txn_id, DisputeStatus::Chargeback))
+    }
+
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError> {
+        let key = (client_id, txn_id);
+        if !matches!(self.resolutions.get(&key), Some(DisputeStatus::Resolved)) {
+            return Ok(false);
+        }
+        if let Some(status) = self.resolutions.remove(&key) {
+            self.dispute_history.push((client_id, txn_id, status));
+            self.record(Undo::Reopen(client_id, txn_id));
+        }
+        Ok(true)
+    }
+
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError> {
+        Ok(self
+            .dispute_history
+            .iter()
+            .filter(|(c, t, _)| (*c, *t) == (client_id, txn_id))
+            .count() as u64)
+    }
+
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        Ok(self
+            .balance_transfers
+            .get(&txn_id)
+            .filter(|txn| txn.client_id == client_id)
+            .copied())
+    }
+
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError> {
+        let mut open: Vec<BalanceTransfer> = self
+            .disputes
+            .iter()
+            .filter(|key| key.0 == client_id && !self.resolutions.contains_key(key))
+            .filter_map(|(_, txn_id)| self.balance_transfers.get(txn_id).copied())
+            .collect();
+        open.sort_by_key(|txn| txn.txn_id);
+        Ok(open)
+    }
+
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError> {
+        let mut transfers: Vec<&BalanceTransfer> = self.balance_transfers.values().collect();
+        transfers.sort_by_key(|txn| (txn.client_id, txn.txn_id));
+        for transfer in transfers {
+            let key = (transfer.client_id, transfer.txn_id);
+            let status = if !self.disputes.contains(&key) {
+                TransferStatus::Undisputed
+            } else {
+                match self.resolutions.get(&key) {
+                    None => TransferStatus::Disputed,
+                    Some(DisputeStatus::Chargeback) => TransferStatus::ChargedBack,
+                    Some(_) => TransferStatus::Resolved,
+                }
+            };
+            f(TransferRecord {
+                transfer: *transfer,
+                status,
+                refund_of: self
+                    .refunds
+                    .get(&transfer.txn_id)
+                    .map(|refund| refund.original_txn_id),
+            });
+        }
+        Ok(())
+    }
+
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        Ok(self.overdraft_limits.get(&client_id).copied())
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        let previous = match limit {
+            Some(limit) if self.clients.contains_key(&client_id) => {
+                self.overdraft_limits.insert(client_id, limit)
+            }
+            Some(_) => return Ok(()),
+            None => self.overdraft_limits.remove(&client_id),
+        };
+        self.record(Undo::OverdraftLimit(client_id, previous));
+        Ok(())
+    }
+
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError> {
+        Ok(self.limits.get(&client_id).copied())
+    }
+
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        let previous = match limits {
+            Some(limits) if self.clients.contains_key(&client_id) => {
+                self.limits.insert(client_id, limits)
+            }
+            Some(_) => return Ok(()),
+            None => self.limits.remove(&client_id),
+        };
+        self.record(Undo::Limits(client_id, previous));
+        Ok(())
+    }
+
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError> {
+        let previous = self.fees.insert((client_id, txn_id), fee);
+        self.record(Undo::Fee(client_id, txn_id, previous));
+        Ok(())
+    }
+
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError> {
+        Ok(self.fees.get(&(client_id, txn_id)).copied())
+    }
+
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError> {
+        let original = (refund.client_id, refund.original_txn_id);
+        if self.get_balance_transfer(original.0, original.1)?.is_none()
+            || self.disputes.contains(&original)
+            || self.refunds.contains_key(&refund.txn_id)
+            || self
+                .refunds
+                .values()
+                .any(|r| r.original_txn_id == refund.original_txn_id)
+        {
+            return Ok(false);
+        }
+        self.refunds.insert(refund.txn_id, *refund);
+        self.record(Undo::Refund(refund.txn_id));
+        Ok(true)
+    }
+
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError> {
+        Ok(self
+            .refunds
+            .get(&txn_id)
+            .filter(|r| r.client_id == client_id)
+            .copied())
+    }
+
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError> {
+        if self.queued_deposits.iter().any(|q| q.txn_id == txn.txn_id) {
+            return Ok(false);
+        }
+        self.queued_deposits.push(txn);
+        self.record(Undo::QueuedDeposit);
+        Ok(true)
+    }
+
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError> {
+        let (taken, kept) = self
+            .queued_deposits
+            .iter()
+            .copied()
+            .partition(|txn| txn.client_id == client_id);
+        let previous = std::mem::replace(&mut self.queued_deposits, kept);
+        self.record(Undo::QueuedDeposits(previous));
+        Ok(taken)
+    }
+
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError> {
+        Ok(self.balance_transfers.get(&txn_id).copied())
+    }
+
+    fn forget_client(
+        &mut self,
+        client_id: ClientId,
+        summary: &[Posting],
+    ) -> Result<usize, MyError> {
+        if self.journal.is_some() {
+            let mut snapshot = self.clone();
+            snapshot.journal = None;
+            self.record(Undo::Snapshot(Box::new(snapshot)));
+        }
+        let before = self.balance_transfers.len();
+        self.balance_transfers
+            .retain(|_, txn| txn.client_id != client_id);
+        self.disputes.retain(|(c, _)| *c != client_id);
+        self.resolutions.retain(|(c, _), _| *c != client_id);
+        self.dispute_history.retain(|(c, _, _)| *c != client_id);
+        self.fees.retain(|(c, _), _| *c != client_id);
+        self.refunds.retain(|_, r| r.client_id != client_id);
+        self.adjustments.retain(|a| a.client_id != client_id);
+        self.events.retain(|e| e.client_id != client_id);
+        self.postings.retain(|p| !p.involves_client(client_id));
+        self.postings.extend_from_slice(summary);
+        Ok(before - self.balance_transfers.len())
+    }
+
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError> {
+        self.postings.push(*posting);
+        self.record(Undo::Posting);
+        Ok(())
+    }
+
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError> {
+        for posting in &self.postings {
+            f(*posting);
+        }
+        Ok(())
+    }
+
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError> {
+        self.adjustments.push(adjustment.clone());
+        self.record(Undo::Adjustment);
+        Ok(())
+    }
+
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError> {
+        for adjustment in &self.adjustments {
+            f(adjustment.clone());
+        }
+        Ok(())
+    }
+
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError> {
+        self.audit_log.push(entry.clone());
+        self.record(Undo::AuditEntry);
+        Ok(())
+    }
+
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError> {
+        for entry in &self.audit_log {
+            f(entry.clone());
+        }
+        Ok(())
+    }
+
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError> {
+        self.events.push(*event);
+        self.record(Undo::Event);
+        Ok(())
+    }
+
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError> {
+        for event in &self.events {
+            f(*event);
+        }
+        Ok(())
+    }
+
+    fn get_checkpoint(&self, run_id: &str) -> Result<Option<u64>, MyError> {
+        Ok(self.checkpoints.get(run_id).copied())
+    }
+
+    fn set_checkpoint(&mut self, run_id: &str, rows: u64) -> Result<(), MyError> {
+        let previous = self.checkpoints.insert(run_id.to_string(), rows);
+        self.record(Undo::Checkpoint(run_id.to_string(), previous));
+        Ok(())
+    }
+
+    fn get_input_run(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        Ok(self.input_runs.get(input_sha256).cloned())
+    }
+
+    fn insert_input_run(&mut self, run: &InputRun) -> Result<(), MyError> {
+        if !self.input_runs.contains_key(&run.input_sha256) {
+            self.input_runs
+                .insert(run.input_sha256.clone(), run.clone());
+            self.record(Undo::InputRun(run.input_sha256.clone()));
+        }
+        Ok(())
+    }
+
+    fn try_insert_record_hash(&mut self, sha256: &str) -> Result<bool, MyError> {
+        if !self.record_hashes.insert(sha256.to_string()) {
+            return Ok(false);
+        }
+        self.record(Undo::RecordHash(sha256.to_string()));
+        Ok(true)
+    }
+
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        Ok(self.interest_accrued_through)
+    }
+
+    fn set_interest_accrued_through(&mut self, date: Date) -> Result<(), MyError> {
+        let previous = self.interest_accrued_through.replace(date);
+        self.record(Undo::InterestAccruedThrough(previous));
+        Ok(())
+    }
+
+    fn begin(&mut self) -> Result<(), MyError> {
+        self.journal.get_or_insert_with(Vec::new);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), MyError> {
+        self.journal = None;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), MyError> {
+        for undo in self.journal.take().into_iter().flatten().rev() {
+            self.undo(undo);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_create_client() {
+        let mut db = MemoryDb::new();
+        let client = db.create_client_state(123).unwrap();
+        let retrieved = db.get_client_state(client.client_id).unwrap();
+        assert!(retrieved.is_some());
+        assert_eq!(retrieved.unwrap().client_id, client.client_id);
+        assert!(db.get_client_state(124).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_balance_transfer_without_client() {
+        let mut db = MemoryDb::new();
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(!db.try_insert_balance_transfer(xfer).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_balance_transfer() {
+        let mut db = MemoryDb::new();
+        db.create_client_state(123).unwrap();
+        db.create_client_state(124).unwrap();
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        assert!(!db.try_insert_balance_transfer(xfer).unwrap());
+
+        // transaction ids are unique across clients
+        let xfer = BalanceTransfer {
+            client_id: 124,
+            ..xfer
+        };
+        assert!(!db.try_insert_balance_transfer(xfer).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_other_client() {
+        let mut db = MemoryDb::new();
+        db.create_client_state(123).unwrap();
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        assert!(!db.try_insert_dispute(124, 1).unwrap());
+        assert!(db.try_insert_dispute(123, 1).unwrap());
+        assert!(!db.try_insert_dispute(123, 1).unwrap());
+    }
+
+    #[test]
+    fn test_refund() {
+        let mut db = MemoryDb::new();
+        db.create_client_state(123).unwrap();
+        for txn_id in [1, 2] {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: amt(1.0),
+                timestamp: None,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+        }
+        let refund = Refund {
+            client_id: 123,
+            txn_id: 3,
+            original_txn_id: 1,
+        };
+        assert!(db.try_insert_refund(&refund).unwrap());
+        assert_eq!(db.get_refund(123, 3).unwrap(), Some(refund));
+        assert!(db.get_refund(124, 3).unwrap().is_none());
+        // a deposit is refunded once, and a refund or a refunded deposit can't be disputed
+        let again = Refund {
+            txn_id: 4,
+            ..refund
+        };
+        assert!(!db.try_insert_refund(&again).unwrap());
+        assert!(!db.try_insert_dispute(123, 1).unwrap());
+        assert!(!db.try_insert_dispute(123, 3).unwrap());
+        // a disputed deposit can't be refunded
+        assert!(db.try_insert_dispute(123, 2).unwrap());
+        let disputed = Refund {
+            original_txn_id: 2,
+            ..again
+        };
+        assert!(!db.try_insert_refund(&disputed).unwrap());
+    }
+
+    #[test]
+    fn test_resolve_dispute() {
+        let mut db = MemoryDb::new();
+        db.create_client_state(123).unwrap();
+        let xfer = BalanceTransfer {
+            client_id: 123,
+            txn_id: 1,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer).unwrap());
+
+        // can't resolve something that isn't disputed
+        assert!(!db.try_resolve_dispute(123, 1).unwrap());
+        assert!(db.try_insert_dispute(123, 1).unwrap());
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        assert!(!db.try_resolve_dispute(123, 1).unwrap());
+        assert!(!db.try_chargeback_dispute(123, 1).unwrap());
+    }
+
+    #[test]
+    fn test_reopen_dispute() {
+        let mut db = MemoryDb::new();
+        db.create_client_state(123).unwrap();
+        for txn_id in [1, 2] {
+            let xfer = BalanceTransfer {
+                client_id: 123,
+                txn_id,
+                amount: amt(1.0),
+                timestamp: None,
+            };
+            assert!(db.try_insert_balance_transfer(xfer).unwrap());
+            assert!(db.try_insert_dispute(123, txn_id).unwrap());
+        }
+
+        // only a resolved dispute can be reopened
+        assert!(!db.try_reopen_dispute(123, 1).unwrap());
+        assert!(db.try_chargeback_dispute(123, 2).unwrap());
+        assert!(!db.try_reopen_dispute(123, 2).unwrap());
+
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        assert!(db.try_reopen_dispute(123, 1).unwrap());
+        assert_eq!(db.get_open_disputes(123).unwrap().len(), 1);
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+        assert!(db.try_reopen_dispute(123, 1).unwrap());
+        assert_eq!(db.count_dispute_reopens(123, 1).unwrap(), 2);
+        assert_eq!(db.count_dispute_reopens(123, 2).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut db = MemoryDb::new();
+        let mut state = db.create_client_state(123).unwrap();
+        let xfer = |txn_id| BalanceTransfer {
+            client_id: 123,
+            txn_id,
+            amount: amt(1.0),
+            timestamp: None,
+        };
+        assert!(db.try_insert_balance_transfer(xfer(1)).unwrap());
+        assert!(db.try_insert_dispute(123, 1).unwrap());
+        assert!(db.try_resolve_dispute(123, 1).unwrap());
+
+        db.begin().unwrap();
+        state.available = amt(1.0);
+        db.update_client_state(&state).unwrap();
+        db.create_client_state(456).unwrap();
+        assert!(db.try_insert_balance_transfer(xfer(2)).unwrap());
+        assert!(db.try_insert_dispute(123, 2).unwrap());
+        assert!(db.try_reopen_dispute(123, 1).unwrap());
+        db.insert_fee(123, 2, amt(0.5)).unwrap();
+        db.set_overdraft_limit(123, Some(amt(2.0))).unwrap();
+        db.set_checkpoint("run", 2).unwrap();
+        assert!(db.try_insert_record_hash("abc").unwrap());
+        db.forget_client(456, &[]).unwrap();
+        db.rollback().unwrap();
+
+        assert_eq!(
+            db.get_client_state(123).unwrap().unwrap().available,
+            Amount::ZERO
+        );
+        assert!(db.get_client_state(456).unwrap().is_none());
+        assert!(db.get_balance_transfer(123, 2).unwrap().is_none());
+        assert!(db.get_open_disputes(123).unwrap().is_empty());
+        assert_eq!(db.count_dispute_reopens(123, 1).unwrap(), 0);
+        assert!(db.get_fee(123, 2).unwrap().is_none());
+        assert!(db.get_overdraft_limit(123).unwrap().is_none());
+        assert!(db.get_checkpoint("run").unwrap().is_none());
+        assert!(db.try_insert_record_hash("abc").unwrap());
+
+        // committed changes stay, and nothing is journaled outside of a store transaction
+        db.begin().unwrap();
+        assert!(db.try_insert_balance_transfer(xfer(2)).unwrap());
+        db.commit().unwrap();
+        assert!(db.try_insert_balance_transfer(xfer(3)).unwrap());
+        db.rollback().unwrap();
+        assert!(db.get_balance_transfer(123, 2).unwrap().is_some());
+        assert!(db.get_balance_transfer(123, 3).unwrap().is_some());
+    }
+}
@@ -0,0 +1,161 @@
+//! Prometheus metrics of the HTTP server, served on `GET /metrics` in the text exposition format
+use crate::{errors::*, events::EngineEvent, fmt_error, model::TxnType};
+use error_stack::{IntoReport, Result, ResultExt};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+// from 50µs to ~1.6s: an in-memory transaction takes microseconds, an SQLite one with fsync milliseconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// the counters and histograms of processed transactions, in their own registry
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    // by type and outcome: applied, queued (a deposit to a locked account), rejected, or failed (a store error)
+    transactions: IntCounterVec,
+    rejections: IntCounterVec,
+    chargebacks: IntCounter,
+    accounts_locked: IntCounter,
+    latency: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, MyError> {
+        Self::register()
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to register the metrics"))
+            .change_context(MyError::Output)
+    }
+
+    fn register() -> prometheus::Result<Self> {
+        let transactions = IntCounterVec::new(
+            Opts::new(
+                "payments_engine_transactions_total",
+                "transactions processed, by type and outcome",
+            ),
+            &["type", "outcome"],
+        )?;
+        let rejections = IntCounterVec::new(
+            Opts::new(
+                "payments_engine_rejections_total",
+                "rejected transactions, by reason",
+            ),
+            &["reason"],
+        )?;
+        let chargebacks =
+            IntCounter::new("payments_engine_chargebacks_total", "disputes charged back")?;
+        let accounts_locked = IntCounter::new(
+            "payments_engine_accounts_locked_total",
+            "accounts locked by a chargeback",
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "payments_engine_transaction_duration_seconds",
+                "the time to process a transaction, by type",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["type"],
+        )?;
+        let registry = Registry::new();
+        registry.register(Box::new(transactions.clone()))?;
+        registry.register(Box::new(rejections.clone()))?;
+        registry.register(Box::new(chargebacks.clone()))?;
+        registry.register(Box::new(accounts_locked.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        Ok(Metrics {
+            registry,
+            transactions,
+            rejections,
+            chargebacks,
+            accounts_locked,
+            latency,
+        })
+    }
+
+    /// count a processed transaction of `txn_type`, from its events (`None` if it failed), and its latency
+    pub fn observe(&self, txn_type: TxnType, events: Option<&[EngineEvent]>, latency: Duration) {
+        let txn_type = txn_type.to_string();
+        let outcome = match events {
+            None => "failed",
+            Some([EngineEvent::TransactionRejected { .. }, ..]) => "rejected",
+            Some([EngineEvent::DepositQueued { .. }, ..]) => "queued",
+            Some(_) => "applied",
+        };
+        self.transactions
+            .with_label_values(&[txn_type.as_str(), outcome])
+            .inc();
+        self.latency
+            .with_label_values(&[txn_type.as_str()])
+            .observe(latency.as_secs_f64());
+        for event in events.unwrap_or_default() {
+            match event {
+                EngineEvent::TransactionRejected { reason, .. } => self
+                    .rejections
+                    .with_label_values(&[format!("{:?}", reason)])
+                    .inc(),
+                EngineEvent::ChargebackApplied { .. } => self.chargebacks.inc(),
+                EngineEvent::AccountLocked { .. } => self.accounts_locked.inc(),
+                _ => {}
+            }
+        }
+    }
+
+    /// the metrics in the Prometheus text format
+    pub fn encode(&self) -> Result<String, MyError> {
+        let mut out = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut out)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to encode the metrics"))
+            .change_context(MyError::Output)?;
+        String::from_utf8(out)
+            .report()
+            .change_context(MyError::Output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, events::RejectReason};
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new().unwrap();
+        let deposit = [EngineEvent::FundsDeposited {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(2.5),
+        }];
+        metrics.observe(TxnType::Deposit, Some(&deposit), Duration::from_micros(30));
+        let rejected = [EngineEvent::TransactionRejected {
+            client_id: 1,
+            txn_id: 2,
+            txn_type: TxnType::Withdrawal,
+            reason: RejectReason::InsufficientFunds,
+        }];
+        metrics.observe(
+            TxnType::Withdrawal,
+            Some(&rejected),
+            Duration::from_millis(2),
+        );
+        metrics.observe(TxnType::Withdrawal, None, Duration::from_millis(2));
+
+        let text = metrics.encode().unwrap();
+        for line in [
+            r#"payments_engine_transactions_total{outcome="applied",type="deposit"} 1"#,
+            r#"payments_engine_transactions_total{outcome="rejected",type="withdrawal"} 1"#,
+            r#"payments_engine_transactions_total{outcome="failed",type="withdrawal"} 1"#,
+            r#"payments_engine_rejections_total{reason="InsufficientFunds"} 1"#,
+            "payments_engine_chargebacks_total 0",
+            r#"payments_engine_transaction_duration_seconds_bucket{type="deposit",le="0.00005"} 1"#,
+            r#"payments_engine_transaction_duration_seconds_count{type="withdrawal"} 2"#,
+        ] {
+            assert!(text.contains(line), "{} not in\n{}", line, text);
+        }
+    }
+}
@@ -1,10 +1,23 @@
-use crate::errors::*;
+use crate::{
+    amount::{Amount, RawAmount},
+    errors::*,
+};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{fmt, str::FromStr};
 
+#[cfg(not(feature = "wide-ids"))]
 pub type ClientId = u16;
+#[cfg(not(feature = "wide-ids"))]
 pub type TransactionId = u32;
 
+// for platforms with more than 65k clients
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u32;
+/// SQLite stores integers as i64, so transaction ids above i64::MAX are rejected as invalid input
+#[cfg(feature = "wide-ids")]
+pub type TransactionId = u64;
+
 #[derive(Clone)]
 pub enum LockedState {
     Invalid,
@@ -47,11 +60,11 @@ impl fmt::Display for LockedState {
 pub struct ClientState {
     pub client_id: ClientId,
     /// liquid funds
-    pub available: f64,
+    pub available: Amount,
     /// disputed funds
-    pub held: f64,
+    pub held: Amount,
     /// avail + held
-    pub total: f64,
+    pub total: Amount,
     /// set to true if the account is frozen. happens in the event of a chargeback
     pub locked: LockedState,
 }
@@ -60,12 +73,13 @@ impl ClientState {
     pub fn new(client_id: ClientId) -> Self {
         ClientState {
             client_id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
             locked: LockedState::Unlocked,
         }
     }
+    #[cfg(feature = "sqlite")]
     pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
         let locked: u8 = row.get(4)?;
         Ok(ClientState {
@@ -95,6 +109,7 @@ impl fmt::Display for ClientState {
 
 /// all possible transaction types
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "snake_case")]
 pub enum TxnType {
     Invalid,
@@ -103,6 +118,7 @@ pub enum TxnType {
     Dispute,
     Resolve,
     Chargeback,
+    Refund,
 }
 
 impl TxnType {
@@ -114,6 +130,7 @@ impl TxnType {
             TxnType::Dispute => 3,
             TxnType::Resolve => 4,
             TxnType::Chargeback => 5,
+            TxnType::Refund => 6,
         }
     }
 }
@@ -126,6 +143,7 @@ impl std::convert::From<u8> for TxnType {
             3 => TxnType::Dispute,
             4 => TxnType::Resolve,
             5 => TxnType::Chargeback,
+            6 => TxnType::Refund,
             _ => TxnType::Invalid,
         }
     }
@@ -140,14 +158,32 @@ impl FromStr for TxnType {
             "dispute" => TxnType::Dispute,
             "resolve" => TxnType::Resolve,
             "chargeback" => TxnType::Chargeback,
+            "refund" => TxnType::Refund,
             _ => return Err(MyError::Conversion(s.to_string())),
         };
         Ok(txn)
     }
 }
 
+/// the name used in the CSV input
+impl fmt::Display for TxnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            TxnType::Invalid => "invalid",
+            TxnType::Deposit => "deposit",
+            TxnType::Withdrawal => "withdrawal",
+            TxnType::Dispute => "dispute",
+            TxnType::Resolve => "resolve",
+            TxnType::Chargeback => "chargeback",
+            TxnType::Refund => "refund",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 /// a deserialized input
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct RawTxnInput {
     #[serde(rename = "type")]
     pub txn_type: TxnType,
@@ -157,24 +193,115 @@ pub struct RawTxnInput {
     /// a globally unique transaction ID
     #[serde(rename = "tx")]
     pub txn_id: TransactionId,
-    pub amount: Option<f64>,
+    /// rounded to an exact `Amount` when the transaction is processed
+    pub amount: Option<RawAmount>,
+    /// when the transaction happened, in unix seconds. from the optional fifth column
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    /// the deposit a refund reverses. from the optional sixth column
+    #[serde(default, rename = "original_tx")]
+    pub original_txn_id: Option<TransactionId>,
+}
+
+impl RawTxnInput {
+    /// the sha256 of the record's content, as hex. the same transaction sent twice has the same hash, whatever the
+    /// formatting of its amount
+    pub fn content_hash(&self) -> String {
+        let fmt = |field: Option<String>| field.unwrap_or_default();
+        let fields = format!(
+            "{}|{}|{}|{}|{}|{}",
+            self.txn_type,
+            self.client_id,
+            self.txn_id,
+            fmt(self.amount.as_ref().map(|a| a.to_string())),
+            fmt(self.timestamp.map(|t| t.to_string())),
+            fmt(self.original_txn_id.map(|t| t.to_string())),
+        );
+        let digest = Sha256::digest(fields.as_bytes());
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
 }
 
 /// either a deposit or withdrawal
 /// for deposits, amount is positive. for withdrawal, amount is negative
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BalanceTransfer {
     pub client_id: ClientId,
     pub txn_id: TransactionId,
-    pub amount: f64,
+    pub amount: Amount,
+    /// when the transfer happened, in unix seconds. None if the input didn't say
+    pub timestamp: Option<i64>,
 }
 
 impl BalanceTransfer {
+    #[cfg(feature = "sqlite")]
     pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
         Ok(BalanceTransfer {
-            client_id: row.get(0)?,
-            txn_id: row.get(1)?,
-            amount: row.get(2)?,
+            client_id: row.get("client_id")?,
+            txn_id: row.get("txn_id")?,
+            amount: row.get("amount")?,
+            timestamp: row.get("timestamp")?,
+        })
+    }
+}
+
+/// where a balance transfer stands in the dispute process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Undisputed,
+    /// disputed and not yet resolved or charged back
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl fmt::Display for TransferStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            TransferStatus::Undisputed => "undisputed",
+            TransferStatus::Disputed => "disputed",
+            TransferStatus::Resolved => "resolved",
+            TransferStatus::ChargedBack => "charged_back",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// a stored balance transfer with its dispute status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferRecord {
+    pub transfer: BalanceTransfer,
+    pub status: TransferStatus,
+    /// the deposit it refunds. None unless the transfer is a refund
+    pub refund_of: Option<TransactionId>,
+}
+
+impl TransferRecord {
+    /// deposit, withdrawal, or refund
+    pub fn txn_type(&self) -> TxnType {
+        if self.refund_of.is_some() {
+            TxnType::Refund
+        } else if self.transfer.amount.is_negative() {
+            TxnType::Withdrawal
+        } else {
+            TxnType::Deposit
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        let disputed: bool = row.get("disputed")?;
+        let resolution: Option<u8> = row.get("resolution")?;
+        let status = match (disputed, resolution.map(DisputeStatus::from)) {
+            (false, _) => TransferStatus::Undisputed,
+            (true, None) => TransferStatus::Disputed,
+            (true, Some(DisputeStatus::Chargeback)) => TransferStatus::ChargedBack,
+            (true, Some(_)) => TransferStatus::Resolved,
+        };
+        Ok(TransferRecord {
+            transfer: BalanceTransfer::from_row(row)?,
+            status,
+            refund_of: row.get("original_txn_id")?,
         })
     }
 }
@@ -194,6 +321,10 @@ pub enum Txn {
         client_id: ClientId,
         txn_id: TransactionId,
     },
+    Refund {
+        refund: Refund,
+        timestamp: Option<i64>,
+    },
 }
 
 pub struct Dispute {
@@ -202,6 +333,7 @@ pub struct Dispute {
 }
 
 impl Dispute {
+    #[cfg(feature = "sqlite")]
     pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
         Ok(Dispute {
             client_id: row.get(0)?,
@@ -210,7 +342,27 @@ impl Dispute {
     }
 }
 
-#[derive(PartialEq, Eq)]
+/// a refund of a deposit. `txn_id` is the refund's own transaction, which withdraws the amount of the deposit
+/// `original_txn_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refund {
+    pub client_id: ClientId,
+    pub txn_id: TransactionId,
+    pub original_txn_id: TransactionId,
+}
+
+impl Refund {
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        Ok(Refund {
+            client_id: row.get("client_id")?,
+            txn_id: row.get("txn_id")?,
+            original_txn_id: row.get("original_txn_id")?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DisputeStatus {
     Invalid,
     Resolved,
@@ -243,6 +395,7 @@ pub struct DisputeResolution {
 }
 
 impl DisputeResolution {
+    #[cfg(feature = "sqlite")]
     pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
         let status: u8 = row.get(2)?;
         Ok(DisputeResolution {
@@ -253,9 +406,32 @@ impl DisputeResolution {
     }
 }
 
+/// an input file that was processed to the end, identified by the sha256 of its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputRun {
+    pub input_sha256: String,
+    /// the path the file was read from
+    pub input: String,
+    /// seconds since the unix epoch
+    pub processed_at: u64,
+}
+
+impl InputRun {
+    #[cfg(feature = "sqlite")]
+    pub fn from_row(row: &rusqlite::Row<'_>) -> std::result::Result<Self, rusqlite::Error> {
+        let processed_at: i64 = row.get(2)?;
+        Ok(InputRun {
+            input_sha256: row.get(0)?,
+            input: row.get(1)?,
+            processed_at: processed_at as u64,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::amount::amt;
 
     #[test]
     fn parse_csv_test1() {
@@ -295,9 +471,9 @@ mod test {
     fn print_client_state() -> Result<(), Box<dyn std::error::Error>> {
         let state = ClientState {
             client_id: 1,
-            available: 2.0,
-            held: 1.7,
-            total: 3.7,
+            available: amt(2.0),
+            held: amt(1.7),
+            total: amt(3.7),
             locked: LockedState::Unlocked,
         };
 
@@ -0,0 +1,120 @@
+//! Node.js bindings. build with `npm run build` (@napi-rs/cli), which enables the "node" feature
+use crate::{
+    amount::RawAmount, errors::print_report, model::*, transaction_processor::TransactionProcessor,
+};
+use napi::{Error, Result};
+use napi_derive::napi;
+use std::{fs, io::BufReader, str::FromStr};
+
+// javascript only sees the message. the full report is logged
+fn to_js_err<C: error_stack::Context>(report: error_stack::Report<C>) -> Error {
+    let msg = format!("{:?}", report);
+    print_report(report);
+    Error::from_reason(msg)
+}
+
+/// a transaction in the same shape as a row of the input file
+#[napi(object)]
+pub struct Transaction {
+    #[napi(js_name = "type")]
+    pub txn_type: String,
+    pub client: u32,
+    /// up to 2^53 (the largest exact integer in javascript) with the "wide-ids" feature
+    pub tx: i64,
+    pub amount: Option<f64>,
+}
+
+#[napi(object)]
+pub struct Account {
+    pub client: u32,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl From<ClientState> for Account {
+    // ClientId is already a u32 with the "wide-ids" feature
+    #[allow(clippy::useless_conversion)]
+    fn from(state: ClientState) -> Self {
+        Account {
+            client: state.client_id.into(),
+            available: state.available.to_f64(),
+            held: state.held.to_f64(),
+            total: state.total.to_f64(),
+            locked: state.is_locked(),
+        }
+    }
+}
+
+/// runs the same validation and balance logic as the payments_engine executable
+#[napi]
+pub struct Engine {
+    processor: TransactionProcessor,
+}
+
+#[napi]
+impl Engine {
+    /// uses an SQLite database unless `inMemory` is set (or the crate was built without the sqlite feature)
+    #[napi(constructor)]
+    pub fn new(in_memory: Option<bool>) -> Result<Self> {
+        #[cfg(feature = "sqlite")]
+        let processor = if in_memory.unwrap_or(false) {
+            TransactionProcessor::in_memory()
+        } else {
+            TransactionProcessor::new().map_err(to_js_err)?
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let processor = {
+            let _ = in_memory;
+            TransactionProcessor::in_memory()
+        };
+        Ok(Engine { processor })
+    }
+
+    /// process a CSV file with the columns `type,client,tx,amount`. invalid records are skipped
+    #[napi]
+    pub fn process_csv(&mut self, path: String) -> Result<()> {
+        let file = fs::File::open(&path).map_err(|e| Error::from_reason(e.to_string()))?;
+        self.processor
+            .process_csv(BufReader::new(file))
+            .map_err(to_js_err)
+    }
+
+    /// process a single transaction. transactions that fail business rules are ignored, like in the executable
+    #[napi]
+    pub fn process(&mut self, txn: Transaction) -> Result<()> {
+        let txn_type = TxnType::from_str(&txn.txn_type)
+            .map_err(|e| Error::from_reason(format!("invalid transaction type: {:?}", e)))?;
+        let client_id = ClientId::try_from(txn.client)
+            .map_err(|_| Error::from_reason(format!("client id out of range: {}", txn.client)))?;
+        let txn_id = TransactionId::try_from(txn.tx)
+            .map_err(|_| Error::from_reason(format!("transaction id out of range: {}", txn.tx)))?;
+        let raw = RawTxnInput {
+            txn_type,
+            client_id,
+            txn_id,
+            amount: txn.amount.map(RawAmount::from),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        self.processor.process(raw).map(|_| ()).map_err(to_js_err)
+    }
+
+    /// the account for one client, or null if the client hasn't been seen
+    #[napi]
+    pub fn account(&mut self, client: u32) -> Result<Option<Account>> {
+        let client_id = match ClientId::try_from(client) {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        let state = self.processor.client_state(client_id).map_err(to_js_err)?;
+        Ok(state.map(Account::from))
+    }
+
+    #[napi]
+    pub fn accounts(&self) -> Result<Vec<Account>> {
+        let states = self.processor.client_states().map_err(to_js_err)?;
+        Ok(states.into_iter().map(Account::from).collect())
+    }
+}
@@ -0,0 +1,299 @@
+//! locale-aware amounts. some partners send `1.234,56`-style numbers. a `NumberFormat` parses amounts in the input
+//! and formats them in the report. without one, amounts use a `.` decimal separator and no grouping.
+//! other exports send integer minor units instead (`amount_cents`): an `AmountUnit` converts those to decimals
+use crate::{amount::Amount, errors::*};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    /// the thousands separator. None: digits aren't grouped
+    pub grouping: Option<char>,
+}
+
+impl NumberFormat {
+    /// 1234.56
+    pub const PLAIN: NumberFormat = NumberFormat {
+        decimal: '.',
+        grouping: None,
+    };
+    /// 1,234.56
+    pub const EN: NumberFormat = NumberFormat {
+        decimal: '.',
+        grouping: Some(','),
+    };
+    /// 1.234,56
+    pub const DE: NumberFormat = NumberFormat {
+        decimal: ',',
+        grouping: Some('.'),
+    };
+    /// 1 234,56
+    pub const FR: NumberFormat = NumberFormat {
+        decimal: ',',
+        grouping: Some(' '),
+    };
+    /// 1'234.56
+    pub const CH: NumberFormat = NumberFormat {
+        decimal: '.',
+        grouping: Some('\''),
+    };
+
+    /// rewrite `s` with a `.` decimal separator and no grouping, so it parses as an f64.
+    /// None if `s` isn't a number in this format. groups after the first must have 3 digits, so `1,5` isn't 15
+    pub fn normalize(&self, s: &str) -> Option<String> {
+        let s = s.trim();
+        let (sign, digits) = match s.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", s),
+        };
+        let (int, frac) = match digits.split_once(self.decimal) {
+            Some((int, frac)) => (int, Some(frac)),
+            None => (digits, None),
+        };
+
+        let mut normalized = sign.to_string();
+        match self.grouping {
+            Some(sep) if int.contains(sep) => {
+                for (idx, group) in int.split(sep).enumerate() {
+                    let valid = if idx == 0 {
+                        (1..=3).contains(&group.len())
+                    } else {
+                        group.len() == 3
+                    };
+                    if !valid || !group.bytes().all(|b| b.is_ascii_digit()) {
+                        return None;
+                    }
+                    normalized.push_str(group);
+                }
+            }
+            _ => normalized.push_str(int),
+        }
+        if let Some(frac) = frac {
+            normalized.push('.');
+            normalized.push_str(frac);
+        }
+        Some(normalized)
+    }
+
+    pub fn parse(&self, s: &str) -> Option<f64> {
+        self.normalize(s)?.parse().ok()
+    }
+
+    pub fn format(&self, value: Amount) -> String {
+        let s = value.to_string();
+        let (sign, digits) = match s.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", s.as_str()),
+        };
+        let (int, frac) = match digits.split_once('.') {
+            Some((int, frac)) => (int, Some(frac)),
+            None => (digits, None),
+        };
+
+        let mut formatted = sign.to_string();
+        for (idx, digit) in int.chars().enumerate() {
+            if idx > 0 && (int.len() - idx) % 3 == 0 {
+                if let Some(sep) = self.grouping {
+                    formatted.push(sep);
+                }
+            }
+            formatted.push(digit);
+        }
+        if let Some(frac) = frac {
+            formatted.push(self.decimal);
+            formatted.push_str(frac);
+        }
+        formatted
+    }
+
+    /// `format`, quoted if the result contains the CSV delimiter
+    pub fn format_csv(&self, value: Amount) -> String {
+        let formatted = self.format(value);
+        if formatted.contains(',') {
+            format!("\"{}\"", formatted)
+        } else {
+            formatted
+        }
+    }
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat::PLAIN
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = MyError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(NumberFormat::PLAIN),
+            "en" => Ok(NumberFormat::EN),
+            "de" => Ok(NumberFormat::DE),
+            "fr" => Ok(NumberFormat::FR),
+            "ch" => Ok(NumberFormat::CH),
+            _ => Err(MyError::Conversion(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            NumberFormat::PLAIN => "plain",
+            NumberFormat::EN => "en",
+            NumberFormat::DE => "de",
+            NumberFormat::FR => "fr",
+            NumberFormat::CH => "ch",
+            _ => return write!(f, "{:?}", self),
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// the unit of the input amounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmountUnit {
+    /// decimal amounts, ex: `12.34`
+    #[default]
+    Decimal,
+    /// integer hundredths, ex: `1234` for 12.34
+    Cents,
+    /// integer ten-thousandths, the engine's precision, ex: `123400` for 12.34
+    TenThousandths,
+}
+
+impl AmountUnit {
+    /// the amount column name that implies this unit, ex: `amount_cents`
+    pub fn from_header(header: &str) -> Option<Self> {
+        match header.trim() {
+            "amount_cents" => Some(AmountUnit::Cents),
+            "amount_ten_thousandths" => Some(AmountUnit::TenThousandths),
+            _ => None,
+        }
+    }
+
+    fn scale(&self) -> usize {
+        match self {
+            AmountUnit::Decimal => 0,
+            AmountUnit::Cents => 2,
+            AmountUnit::TenThousandths => 4,
+        }
+    }
+
+    /// rewrite an amount in this unit as a decimal. the digits are moved, not multiplied, so `1` cent is exactly
+    /// what `0.01` would be. None if a minor unit amount isn't an integer
+    pub fn to_decimal(&self, s: &str) -> Option<String> {
+        let s = s.trim();
+        let scale = self.scale();
+        if scale == 0 {
+            return Some(s.to_string());
+        }
+        let (sign, digits) = match s.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (int, frac) = padded.split_at(padded.len() - scale);
+        Some(format!("{}{}.{}", sign, int, frac))
+    }
+}
+
+impl FromStr for AmountUnit {
+    type Err = MyError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(AmountUnit::Decimal),
+            "cents" => Ok(AmountUnit::Cents),
+            "ten-thousandths" => Ok(AmountUnit::TenThousandths),
+            _ => Err(MyError::Conversion(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for AmountUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            AmountUnit::Decimal => "decimal",
+            AmountUnit::Cents => "cents",
+            AmountUnit::TenThousandths => "ten-thousandths",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(NumberFormat::DE.parse("1.234,56"), Some(1234.56));
+        assert_eq!(NumberFormat::DE.parse("1234,5"), Some(1234.5));
+        assert_eq!(NumberFormat::DE.parse("12.345.678"), Some(12345678.0));
+        assert_eq!(NumberFormat::FR.parse("1 234,56"), Some(1234.56));
+        assert_eq!(NumberFormat::CH.parse("-1'234.5"), Some(-1234.5));
+        assert_eq!(NumberFormat::EN.parse("1,234.56"), Some(1234.56));
+        assert_eq!(NumberFormat::PLAIN.parse("1234.56"), Some(1234.56));
+
+        // misplaced separators
+        assert_eq!(NumberFormat::EN.parse("1,5"), None);
+        assert_eq!(NumberFormat::DE.parse("1.2345,0"), None);
+        assert_eq!(NumberFormat::DE.parse("1234.56"), None);
+        assert_eq!(NumberFormat::PLAIN.parse("1,234.56"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(NumberFormat::DE.format(amt(1234.56)), "1.234,56");
+        assert_eq!(NumberFormat::DE.format(amt(-1234567.0)), "-1.234.567");
+        assert_eq!(NumberFormat::FR.format(amt(123.4)), "123,4");
+        assert_eq!(NumberFormat::PLAIN.format(amt(1234.5)), "1234.5");
+        assert_eq!(NumberFormat::DE.format_csv(amt(1.5)), "\"1,5\"");
+        assert_eq!(NumberFormat::CH.format_csv(amt(1234.5)), "1'234.5");
+
+        for format in [
+            NumberFormat::PLAIN,
+            NumberFormat::EN,
+            NumberFormat::DE,
+            NumberFormat::FR,
+            NumberFormat::CH,
+        ] {
+            assert_eq!(format.to_string().parse::<NumberFormat>().unwrap(), format);
+            assert_eq!(
+                format.parse(&format.format(amt(-9876543.2109))),
+                Some(-9876543.2109)
+            );
+        }
+    }
+
+    #[test]
+    fn test_amount_unit() {
+        assert_eq!(AmountUnit::Cents.to_decimal("1234").unwrap(), "12.34");
+        assert_eq!(AmountUnit::Cents.to_decimal("1").unwrap(), "0.01");
+        assert_eq!(AmountUnit::Cents.to_decimal("-5").unwrap(), "-0.05");
+        assert_eq!(
+            AmountUnit::TenThousandths.to_decimal("10001").unwrap(),
+            "1.0001"
+        );
+        assert_eq!(AmountUnit::Decimal.to_decimal("1.5").unwrap(), "1.5");
+        assert_eq!(AmountUnit::Cents.to_decimal("12.5"), None);
+        assert_eq!(AmountUnit::Cents.to_decimal("-"), None);
+        assert_eq!(
+            AmountUnit::from_header("amount_cents"),
+            Some(AmountUnit::Cents)
+        );
+        assert_eq!(AmountUnit::from_header("amount"), None);
+        for unit in [
+            AmountUnit::Decimal,
+            AmountUnit::Cents,
+            AmountUnit::TenThousandths,
+        ] {
+            assert_eq!(unit.to_string().parse::<AmountUnit>().unwrap(), unit);
+        }
+    }
+}
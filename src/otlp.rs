@@ -0,0 +1,185 @@
+//! OpenTelemetry export over OTLP/HTTP. the tracing spans (`process_csv`, `process`, the store calls) are exported as
+//! traces, and the `ProcessingStats` of the run as counters. configured with the standard `OTEL_EXPORTER_OTLP_*` and
+//! `OTEL_SERVICE_NAME` environment variables: nothing is exported unless `OTEL_EXPORTER_OTLP_ENDPOINT` is set
+use crate::{errors::*, fmt_error, stats::ProcessingStats};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use opentelemetry::{
+    metrics::{MeterProvider, ObservableCounter},
+    trace::TracerProvider,
+    KeyValue,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter};
+use opentelemetry_sdk::{metrics::SdkMeterProvider, trace::SdkTracerProvider, Resource};
+use std::sync::Mutex;
+use tracing_opentelemetry::OpenTelemetryLayer;
+
+const SCOPE: &str = "payments_engine";
+
+type StatsSource = Box<dyn Fn() -> ProcessingStats + Send>;
+
+// where the counters read the stats from. set by `observe_stats`
+static STATS: Mutex<Option<StatsSource>> = Mutex::new(None);
+
+/// the counters read `source` whenever they're exported (periodically, and once more at `Telemetry::shutdown`). ex:
+/// the stats of the finished run, or a closure that reads them from the processor of a server
+pub fn observe_stats(source: impl Fn() -> ProcessingStats + Send + 'static) {
+    if let Ok(mut stats) = STATS.lock() {
+        *stats = Some(Box::new(source));
+    }
+}
+
+fn current_stats() -> ProcessingStats {
+    match STATS.lock() {
+        Ok(source) => source.as_ref().map(|f| f()).unwrap_or_default(),
+        Err(_) => ProcessingStats::default(),
+    }
+}
+
+// the applied transactions, by type
+fn applied_by_type(stats: &ProcessingStats) -> [(&'static str, u64); 6] {
+    [
+        ("deposit", stats.deposits),
+        ("withdrawal", stats.withdrawals),
+        ("refund", stats.refunds),
+        ("dispute", stats.disputes),
+        ("resolve", stats.resolves),
+        ("chargeback", stats.chargebacks),
+    ]
+}
+
+/// the trace and metric pipelines. `shutdown` must be called before the process exits, or the last spans are lost
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+    _counters: Vec<ObservableCounter<u64>>,
+}
+
+impl Telemetry {
+    /// None if `OTEL_EXPORTER_OTLP_ENDPOINT` isn't set
+    pub fn from_env() -> Result<Option<Self>, MyError> {
+        if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+            return Ok(None);
+        }
+        let mut resource = Resource::builder();
+        if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+            resource = resource.with_service_name(SCOPE);
+        }
+        let resource = resource.build();
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .build()
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create the OTLP span exporter"))
+            .change_context(MyError::Config)?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .build()
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create the OTLP metric exporter"))
+            .change_context(MyError::Config)?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metrics)
+            .with_resource(resource)
+            .build();
+
+        let meter = meter_provider.meter(SCOPE);
+        let counters = vec![
+            meter
+                .u64_observable_counter("payments_engine.transactions.applied")
+                .with_description("applied transactions, by type")
+                .with_callback(|observer| {
+                    for (txn_type, count) in applied_by_type(&current_stats()) {
+                        observer.observe(count, &[KeyValue::new("type", txn_type)]);
+                    }
+                })
+                .build(),
+            meter
+                .u64_observable_counter("payments_engine.transactions.rejected")
+                .with_description("rejected transactions, by reason")
+                .with_callback(|observer| {
+                    for (reason, count) in current_stats().rejected {
+                        observer
+                            .observe(count, &[KeyValue::new("reason", format!("{:?}", reason))]);
+                    }
+                })
+                .build(),
+            meter
+                .u64_observable_counter("payments_engine.clients.created")
+                .with_description("accounts created")
+                .with_callback(|observer| observer.observe(current_stats().clients_created, &[]))
+                .build(),
+            meter
+                .u64_observable_counter("payments_engine.accounts.locked")
+                .with_description("accounts locked by a chargeback")
+                .with_callback(|observer| observer.observe(current_stats().accounts_locked, &[]))
+                .build(),
+        ];
+
+        Ok(Some(Telemetry {
+            tracer_provider,
+            meter_provider,
+            _counters: counters,
+        }))
+    }
+
+    /// the tracing layer that exports the spans
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer_provider.tracer(SCOPE))
+    }
+
+    /// export what's left: the pending spans and a last reading of the counters
+    pub fn shutdown(self) -> Result<(), MyError> {
+        let traces = self.tracer_provider.shutdown();
+        let metrics = self.meter_provider.shutdown();
+        for (pipeline, res) in [("traces", traces), ("metrics", metrics)] {
+            if let Err(e) = res {
+                return Err(report!(MyError::Output).attach_printable(fmt_error!(
+                    "failed to export the {}: {}",
+                    pipeline,
+                    e
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_observe_stats() {
+        assert_eq!(current_stats(), ProcessingStats::default());
+        let stats = ProcessingStats {
+            deposits: 3,
+            chargebacks: 1,
+            ..Default::default()
+        };
+        let observed = stats.clone();
+        observe_stats(move || observed.clone());
+        assert_eq!(current_stats(), stats);
+        assert_eq!(
+            applied_by_type(&current_stats()),
+            [
+                ("deposit", 3),
+                ("withdrawal", 0),
+                ("refund", 0),
+                ("dispute", 0),
+                ("resolve", 0),
+                ("chargeback", 1)
+            ]
+        );
+        // without an endpoint nothing is exported
+        std::env::remove_var("OTEL_EXPORTER_OTLP_ENDPOINT");
+        assert!(Telemetry::from_env().unwrap().is_none());
+    }
+}
@@ -0,0 +1,87 @@
+//! atomic file output. a report is written to a temporary file next to its destination and renamed over it once it's
+//! complete, so a reader never sees a half-written report and a failed run leaves the previous file in place
+use crate::{errors::*, fmt_error};
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// run `write` against a temporary file, then replace `path` with it. on failure `path` is left untouched
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<(), MyError>
+where
+    F: FnOnce(&mut io::BufWriter<fs::File>) -> Result<(), MyError>,
+{
+    let tmp = temp_path(path);
+    let res = write_file(&tmp, write).and_then(|_| {
+        fs::rename(&tmp, path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to rename to {}", path.display()))
+            .change_context(MyError::Output)
+    });
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+fn write_file<F>(tmp: &Path, write: F) -> Result<(), MyError>
+where
+    F: FnOnce(&mut io::BufWriter<fs::File>) -> Result<(), MyError>,
+{
+    let file = fs::File::create(tmp)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to create {}", tmp.display()))
+        .change_context(MyError::Output)?;
+    let mut writer = io::BufWriter::new(file);
+    write(&mut writer)?;
+    // the rename must not be seen before the contents are on disk
+    writer
+        .flush()
+        .and_then(|_| writer.get_ref().sync_all())
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to write {}", tmp.display()))
+        .change_context(MyError::Output)
+}
+
+// in the same directory, so the rename doesn't cross file systems. ex: `out/.report.csv.tmp`
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use error_stack::report;
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("pe-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+
+        write_atomically(&path, |w| {
+            w.write_all(b"first\n")
+                .report()
+                .change_context(MyError::Output)
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\n");
+
+        // a failed write keeps the previous file and cleans up
+        let res = write_atomically(&path, |w| {
+            w.write_all(b"partial").unwrap();
+            Err(report!(MyError::Output))
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\n");
+        assert!(!temp_path(&path).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,394 @@
+//! parallel processing. client accounts are independent of each other, so the transactions can be sharded by client
+//! id (`client_id % threads`) across worker threads, each with its own processor and store, and the reports merged at
+//! the end. the only state shared between clients is the txn_id of deposits and withdrawals, which must be unique
+//! across all of them: the dispatcher remembers which shard used each txn_id first and rejects reuses from other
+//! shards. this differs from a single processor in two ways: a txn_id is claimed by the first deposit or withdrawal
+//! with a positive amount even if the shard then rejects it, and a dispute of a transaction in another shard is
+//! rejected as an invalid dispute (it's still counted by `cross_client_disputes`). only
+//! `CrossClientDisputePolicy::Reject` is supported
+use crate::{
+    amount::RawAmount,
+    errors::*,
+    events::RejectReason,
+    fmt_error,
+    model::*,
+    output,
+    policy::CrossClientDisputePolicy,
+    stats::ProcessingStats,
+    transaction_processor::{read_record, CsvFormat, InputRow, TransactionProcessor},
+};
+use error_stack::{report, IntoReport, Result, ResultExt};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    path::Path,
+    sync::mpsc,
+    thread,
+};
+
+// transactions queued per worker before the dispatcher blocks
+const QUEUE_DEPTH: usize = 1024;
+
+type Worker = thread::JoinHandle<Result<TransactionProcessor, MyError>>;
+
+pub struct ParallelProcessor {
+    senders: Vec<mpsc::SyncSender<RawTxnInput>>,
+    // None once a worker that stopped on an error has been joined
+    workers: Vec<Option<Worker>>,
+    csv_format: CsvFormat,
+    // the shard of the first deposit or withdrawal with each txn_id
+    owners: HashMap<TransactionId, usize>,
+    // the clients of the transfers rejected by the dispatcher. a single processor creates the account of a client
+    // even when its transfer is rejected, so these are created at the end
+    rejected_clients: Vec<(usize, ClientId)>,
+    num_cross_client_disputes: u64,
+}
+
+impl ParallelProcessor {
+    /// `threads` workers, each with a processor from `make`. the processors should be configured the same way; the
+    /// CSV format (number format and amount unit) of the first one is used to read the input
+    pub fn new<F>(threads: usize, make: F) -> Result<Self, MyError>
+    where
+        F: Fn() -> Result<TransactionProcessor, MyError>,
+    {
+        if threads == 0 {
+            return Err(report!(MyError::Config)
+                .attach_printable(fmt_error!("at least one thread is required")));
+        }
+        let processors = (0..threads)
+            .map(|_| make())
+            .collect::<Result<Vec<_>, _>>()?;
+        if processors
+            .iter()
+            .any(|p| p.cross_client_dispute_policy() != CrossClientDisputePolicy::Reject)
+        {
+            return Err(report!(MyError::Config).attach_printable(fmt_error!(
+                "parallel processing only supports rejecting cross-client disputes"
+            )));
+        }
+        let csv_format = processors[0].csv_format();
+
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for (shard, mut processor) in processors.into_iter().enumerate() {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_DEPTH);
+            let worker = thread::Builder::new()
+                .name(format!("shard-{}", shard))
+                .spawn(move || {
+                    processor.process_stream(
+                        receiver.into_iter().map(|txn| (None, InputRow::Txn(txn))),
+                    )?;
+                    Ok(processor)
+                })
+                .report()
+                .attach_printable_lazy(|| fmt_error!("failed to start worker {}", shard))
+                .change_context(MyError::Worker)?;
+            senders.push(sender);
+            workers.push(Some(worker));
+        }
+        Ok(ParallelProcessor {
+            senders,
+            workers,
+            csv_format,
+            owners: HashMap::new(),
+            rejected_clients: Vec::new(),
+            num_cross_client_disputes: 0,
+        })
+    }
+
+    /// queue a transaction on the worker of its client. fails if that worker stopped on an error
+    pub fn process(&mut self, txn: RawTxnInput) -> Result<(), MyError> {
+        let shard = txn.client_id as usize % self.senders.len();
+        match txn.txn_type {
+            _ if takes_txn_id(&txn) => match self.owners.entry(txn.txn_id) {
+                Entry::Occupied(owner) if *owner.get() != shard => {
+                    tracing::debug!(txn_id = txn.txn_id, "txn_id already used by another shard");
+                    self.rejected_clients.push((shard, txn.client_id));
+                    return Ok(());
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(entry) => {
+                    entry.insert(shard);
+                }
+            },
+            TxnType::Dispute
+                if self
+                    .owners
+                    .get(&txn.txn_id)
+                    .is_some_and(|owner| *owner != shard) =>
+            {
+                self.num_cross_client_disputes += 1;
+            }
+            _ => {}
+        }
+        if self.senders[shard].send(txn).is_ok() {
+            return Ok(());
+        }
+        // the worker dropped its receiver: it stopped on an error
+        match self.workers[shard].take().map(join) {
+            Some(Err(e)) => Err(e),
+            _ => {
+                Err(report!(MyError::Worker)
+                    .attach_printable(fmt_error!("worker {} stopped", shard)))
+            }
+        }
+    }
+
+    /// process a CSV stream with a header row, skipping records with invalid formats
+    pub fn process_csv<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        let mut csv_reader = self.csv_format.reader(reader)?;
+        let columns = self.csv_format.columns_of(&mut csv_reader)?;
+        for record in csv_reader.records() {
+            if let Some(txn) = self.csv_format.deserialize(read_record(record)?, columns) {
+                self.process(txn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// process a Parquet file (feature "parquet"), skipping malformed rows
+    #[cfg(feature = "parquet")]
+    pub fn process_parquet<R: ::parquet::file::reader::ChunkReader + 'static>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), MyError> {
+        for row in crate::parquet::read_rows(reader)? {
+            if let InputRow::Txn(txn) = row? {
+                self.process(txn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// process an Avro container file (feature "avro"), skipping malformed rows
+    #[cfg(feature = "avro")]
+    pub fn process_avro<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        for row in crate::avro_container::read_rows(reader)? {
+            if let InputRow::Txn(txn) = row? {
+                self.process(txn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// process an Arrow record batch (feature "arrow"), skipping malformed rows
+    #[cfg(feature = "arrow")]
+    pub fn process_record_batch(
+        &mut self,
+        batch: &crate::arrow::RecordBatch,
+    ) -> Result<(), MyError> {
+        for row in crate::arrow::batch_rows(batch)? {
+            if let InputRow::Txn(txn) = row {
+                self.process(txn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// process a stream of length-delimited protobuf messages, skipping malformed ones
+    #[cfg(feature = "protobuf")]
+    pub fn process_protobuf<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        for row in crate::protobuf::read_rows(reader) {
+            if let InputRow::Txn(txn) = row? {
+                self.process(txn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// wait for the workers to finish the queued transactions
+    pub fn finish(self) -> Result<Shards, MyError> {
+        drop(self.senders);
+        let mut processors = Vec::new();
+        for (shard, worker) in self.workers.into_iter().enumerate() {
+            match worker {
+                Some(worker) => processors.push(join(worker)?),
+                None => {
+                    return Err(report!(MyError::Worker)
+                        .attach_printable(fmt_error!("worker {} stopped", shard)))
+                }
+            }
+        }
+        let num_rejected_transfers = self.rejected_clients.len() as u64;
+        for (shard, client_id) in self.rejected_clients {
+            processors[shard].ensure_client(client_id)?;
+        }
+        Ok(Shards {
+            processors,
+            num_cross_client_disputes: self.num_cross_client_disputes,
+            num_rejected_transfers,
+        })
+    }
+}
+
+// the deposits, withdrawals, and refunds use a new txn_id. malformed ones are left to the shard, which rejects them
+// without using it
+fn takes_txn_id(txn: &RawTxnInput) -> bool {
+    match txn.txn_type {
+        TxnType::Deposit | TxnType::Withdrawal => {
+            txn.amount.as_ref().is_some_and(RawAmount::is_positive)
+        }
+        TxnType::Refund => txn.amount.is_none() && txn.original_txn_id.is_some(),
+        _ => false,
+    }
+}
+
+fn join(worker: Worker) -> Result<TransactionProcessor, MyError> {
+    worker
+        .join()
+        .map_err(|_| report!(MyError::Worker).attach_printable(fmt_error!("a worker panicked")))?
+}
+
+/// the processors of a finished parallel run, one per shard
+pub struct Shards {
+    processors: Vec<TransactionProcessor>,
+    num_cross_client_disputes: u64,
+    // the transfers the dispatcher rejected for reusing another shard's txn_id
+    num_rejected_transfers: u64,
+}
+
+impl Shards {
+    pub fn processors(&self) -> &[TransactionProcessor] {
+        &self.processors
+    }
+
+    /// the stats of all the shards, with the transfers rejected by the dispatcher
+    pub fn stats(&self) -> ProcessingStats {
+        let mut stats = ProcessingStats::default();
+        for processor in &self.processors {
+            stats.merge(processor.stats());
+        }
+        if self.num_rejected_transfers > 0 {
+            *stats
+                .rejected
+                .entry(RejectReason::DuplicateTxnId)
+                .or_default() += self.num_rejected_transfers;
+        }
+        stats
+    }
+
+    /// the state of every client account, sorted by client id
+    pub fn client_states(&self) -> Result<Vec<ClientState>, MyError> {
+        let mut states = Vec::new();
+        for processor in &self.processors {
+            states.extend(processor.client_states()?);
+        }
+        states.sort_by_key(|state| state.client_id);
+        Ok(states)
+    }
+
+    /// the number of disputes that referenced a deposit or withdrawal of another client
+    pub fn cross_client_disputes(&self) -> u64 {
+        self.num_cross_client_disputes
+            + self
+                .processors
+                .iter()
+                .map(|p| p.cross_client_disputes())
+                .sum::<u64>()
+    }
+
+    /// write the merged client report, with the rows sorted by client id. in the report and number formats of the
+    /// first processor
+    pub fn write_report<W: io::Write>(&self, writer: W) -> Result<(), MyError> {
+        let rows = self.client_states()?;
+        self.processors[0]
+            .report_writer(writer)
+            .and_then(|mut report| {
+                rows.iter().try_for_each(|state| report.row(state))?;
+                report.finish()
+            })
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write report"))
+            .change_context(MyError::Output)
+    }
+
+    /// write_report to stdout
+    pub fn display(&self) -> Result<(), MyError> {
+        self.write_report(io::stdout().lock())
+    }
+
+    /// write the merged client report to `path` atomically
+    pub fn write_report_file(&self, path: &Path) -> Result<(), MyError> {
+        output::write_atomically(path, |writer| self.write_report(writer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::workload::{Workload, WorkloadConfig};
+
+    #[test]
+    fn test_parallel() {
+        let mut csv = Vec::new();
+        Workload::new(WorkloadConfig {
+            seed: 6,
+            clients: 20,
+            dispute_rate: 0.1,
+            settle_rate: 0.2,
+            ..Default::default()
+        })
+        .write_csv(&mut csv, 2000)
+        .unwrap();
+        let mut expected = TransactionProcessor::in_memory();
+        expected.process_csv(csv.as_slice()).unwrap();
+        let mut expected: Vec<String> = expected
+            .client_states()
+            .unwrap()
+            .iter()
+            .map(|state| state.to_string())
+            .collect();
+        expected.sort();
+
+        let mut parallel =
+            ParallelProcessor::new(4, || Ok(TransactionProcessor::in_memory())).unwrap();
+        parallel.process_csv(csv.as_slice()).unwrap();
+        let shards = parallel.finish().unwrap();
+        assert_eq!(shards.processors().len(), 4);
+        let mut states: Vec<String> = shards
+            .client_states()
+            .unwrap()
+            .iter()
+            .map(|state| state.to_string())
+            .collect();
+        states.sort();
+        assert_eq!(states, expected);
+    }
+
+    #[test]
+    fn test_txn_ids_across_shards() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        deposit,2,1,5.0
+                        deposit,3,2,1.0
+                        dispute,2,1,";
+        let mut parallel =
+            ParallelProcessor::new(2, || Ok(TransactionProcessor::in_memory())).unwrap();
+        parallel.process_csv(csv.as_bytes()).unwrap();
+        let shards = parallel.finish().unwrap();
+        let mut report = Vec::new();
+        shards.write_report(&mut report).unwrap();
+        // client 2's deposit reused txn 1, so its account is empty, and its dispute referenced client 1's deposit
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked
+1,10,0,10,false
+2,0,0,0,false
+3,1,0,1,false
+"
+        );
+        assert_eq!(shards.cross_client_disputes(), 1);
+        let stats = shards.stats();
+        assert_eq!(stats.deposits, 2);
+        assert_eq!(stats.rejected[&RejectReason::DuplicateTxnId], 1);
+        assert_eq!(stats.rejected[&RejectReason::InvalidDispute], 1);
+        assert_eq!(stats.clients_created, 3);
+
+        let owner = || {
+            let mut processor = TransactionProcessor::in_memory();
+            processor.set_cross_client_dispute_policy(CrossClientDisputePolicy::Owner);
+            Ok(processor)
+        };
+        assert!(ParallelProcessor::new(2, owner).is_err());
+    }
+}
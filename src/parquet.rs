@@ -0,0 +1,195 @@
+//! Parquet input (feature "parquet"): transaction files with the columns type, client, tx, amount, and optionally
+//! timestamp and original_tx, found by name. the files are read in record batches, and each row is mapped to a
+//! `RawTxnInput` the way a CSV row is (see arrow.rs)
+use crate::{
+    arrow::{check_schema, BatchColumns},
+    errors::*,
+    fmt_error,
+    transaction_processor::InputRow,
+};
+use ::parquet::{
+    arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
+    file::reader::ChunkReader,
+};
+use error_stack::{IntoReport, Result, ResultExt};
+
+/// the rows of a Parquet file, in order. a row with a null or a value that doesn't fit the model is malformed
+pub(crate) struct ParquetRows {
+    batches: ParquetRecordBatchReader,
+    batch: Option<BatchColumns>,
+    row: usize,
+}
+
+pub(crate) fn read_rows<R: ChunkReader + 'static>(reader: R) -> Result<ParquetRows, MyError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read the Parquet metadata"))
+        .change_context(MyError::FileReader)?;
+    check_schema(builder.schema())
+        .attach_printable_lazy(|| fmt_error!("failed to read the Parquet file"))?;
+    let batches = builder
+        .build()
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read the Parquet file"))
+        .change_context(MyError::FileReader)?;
+    Ok(ParquetRows {
+        batches,
+        batch: None,
+        row: 0,
+    })
+}
+
+impl Iterator for ParquetRows {
+    type Item = Result<InputRow, MyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(batch) = &self.batch {
+                if self.row < batch.len {
+                    self.row += 1;
+                    return Some(Ok(batch.row(self.row - 1)));
+                }
+            }
+            let batch = self
+                .batches
+                .next()?
+                .and_then(|batch| BatchColumns::new(&batch));
+            match batch {
+                Ok(batch) => {
+                    self.batch = Some(batch);
+                    self.row = 0;
+                }
+                Err(e) => {
+                    return Some(
+                        Err(e)
+                            .report()
+                            .attach_printable_lazy(|| fmt_error!("failed to read the Parquet file"))
+                            .change_context(MyError::FileReader),
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, model::TxnType, transaction_processor::TransactionProcessor};
+    use ::parquet::arrow::ArrowWriter;
+    use arrow_array::{
+        ArrayRef, Float64Array, Int32Array, Int64Array, RecordBatch, StringArray,
+        TimestampMillisecondArray,
+    };
+    use csv::StringRecord;
+    use std::{fs, path::PathBuf, sync::Arc};
+
+    // a file with shuffled columns, an unknown column, and a timestamp in milliseconds
+    fn write_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.parquet", name, std::process::id()));
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "amount",
+                Arc::new(Float64Array::from(vec![
+                    Some(10.0),
+                    Some(2.5),
+                    None,
+                    Some(1.0),
+                    Some(1.0),
+                ])) as ArrayRef,
+            ),
+            (
+                "memo",
+                Arc::new(StringArray::from(vec!["salary", "atm", "", "", ""])),
+            ),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 1, 3, 4]))),
+            (
+                "client",
+                Arc::new(Int32Array::from(vec![
+                    Some(1),
+                    Some(1),
+                    Some(1),
+                    None,
+                    Some(2),
+                ])),
+            ),
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "withdrawal",
+                    "dispute",
+                    "deposit",
+                    "transfer",
+                ])),
+            ),
+            (
+                "timestamp",
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    Some(1_704_067_200_500),
+                    None,
+                    None,
+                    None,
+                    None,
+                ])),
+            ),
+        ])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(fs::File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_rows() {
+        let path = write_file("read-rows");
+        let rows: Vec<InputRow> = read_rows(fs::File::open(&path).unwrap())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 5);
+        let InputRow::Txn(deposit) = &rows[0] else {
+            panic!("the deposit is malformed");
+        };
+        assert_eq!(deposit.txn_type, TxnType::Deposit);
+        assert_eq!((deposit.client_id, deposit.txn_id), (1, 1));
+        assert_eq!(deposit.amount, Some(10.0.into()));
+        assert_eq!(deposit.timestamp, Some(1704067200));
+        assert!(matches!(&rows[2], InputRow::Txn(txn) if txn.amount.is_none()));
+        // no client
+        let InputRow::Malformed(record) = &rows[3] else {
+            panic!("a row without a client isn't malformed");
+        };
+        assert_eq!(
+            record,
+            &StringRecord::from(vec!["deposit", "", "3", "1", "", ""])
+        );
+        assert!(matches!(&rows[4], InputRow::Txn(txn) if txn.txn_type == TxnType::Invalid));
+    }
+
+    #[test]
+    fn test_process_parquet() {
+        let path = write_file("process-parquet");
+        let mut tp = TransactionProcessor::in_memory();
+        tp.process_parquet(fs::File::open(&path).unwrap()).unwrap();
+        let state = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(state.available, amt(-2.5));
+        assert_eq!(state.held, amt(10.0));
+
+        // a file without the required columns
+        let batch = RecordBatch::try_from_iter(vec![(
+            "tx",
+            Arc::new(Int64Array::from(vec![1])) as ArrayRef,
+        )])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(fs::File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        assert!(tp.process_parquet(fs::File::open(&path).unwrap()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
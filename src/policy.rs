@@ -0,0 +1,267 @@
+//! per-deployment business rules for the cases the payments spec leaves open
+use crate::{amount::Amount, errors::*};
+use std::{fmt, str::FromStr};
+
+/// what happens to a dispute that references a deposit or withdrawal owned by a different client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossClientDisputePolicy {
+    /// reject it with `RejectReason::CrossClientDispute`
+    #[default]
+    Reject,
+    /// operator mode: apply the dispute to the client that owns the transfer
+    Owner,
+}
+
+impl FromStr for CrossClientDisputePolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "reject" => CrossClientDisputePolicy::Reject,
+            "owner" => CrossClientDisputePolicy::Owner,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for CrossClientDisputePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            CrossClientDisputePolicy::Reject => "reject",
+            CrossClientDisputePolicy::Owner => "owner",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// what happens to an input file whose content was already processed against the same store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateInputPolicy {
+    /// refuse to process it
+    #[default]
+    Reject,
+    /// log a warning and process it again
+    Warn,
+    /// log a warning and don't process it
+    Skip,
+}
+
+impl FromStr for DuplicateInputPolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "reject" => DuplicateInputPolicy::Reject,
+            "warn" => DuplicateInputPolicy::Warn,
+            "skip" => DuplicateInputPolicy::Skip,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for DuplicateInputPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            DuplicateInputPolicy::Reject => "reject",
+            DuplicateInputPolicy::Warn => "warn",
+            DuplicateInputPolicy::Skip => "skip",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// which balance transfers can be disputed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputePolicy {
+    /// deposits and withdrawals
+    #[default]
+    Both,
+    /// a dispute of a withdrawal is rejected with `RejectReason::DisputeNotAllowed`
+    DepositsOnly,
+    /// a dispute of a deposit is rejected with `RejectReason::DisputeNotAllowed`
+    WithdrawalsOnly,
+}
+
+impl DisputePolicy {
+    /// whether a transfer of `amount` (negative for a withdrawal) can be disputed
+    pub fn allows(&self, amount: Amount) -> bool {
+        match self {
+            DisputePolicy::Both => true,
+            DisputePolicy::DepositsOnly => !amount.is_negative(),
+            DisputePolicy::WithdrawalsOnly => amount.is_negative(),
+        }
+    }
+}
+
+impl FromStr for DisputePolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "both" => DisputePolicy::Both,
+            "deposits-only" => DisputePolicy::DepositsOnly,
+            "withdrawals-only" => DisputePolicy::WithdrawalsOnly,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for DisputePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            DisputePolicy::Both => "both",
+            DisputePolicy::DepositsOnly => "deposits-only",
+            DisputePolicy::WithdrawalsOnly => "withdrawals-only",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// how far a withdrawal can take the available funds below zero. a client's own limit (see
+/// `TransactionProcessor::set_overdraft_limit`) takes precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverdraftPolicy {
+    /// a withdrawal that exceeds the available funds is rejected with `RejectReason::InsufficientFunds`
+    #[default]
+    Reject,
+    /// the available funds can go down to minus the limit
+    AllowToLimit(Amount),
+    /// no limit
+    AllowUnlimited,
+}
+
+impl OverdraftPolicy {
+    /// the largest overdraft allowed, None if there is no limit
+    pub fn limit(&self) -> Option<Amount> {
+        match self {
+            OverdraftPolicy::Reject => Some(Amount::ZERO),
+            OverdraftPolicy::AllowToLimit(limit) => Some(*limit),
+            OverdraftPolicy::AllowUnlimited => None,
+        }
+    }
+}
+
+// `reject`, `allow-unlimited`, or `allow-to-limit:<amount>`
+impl FromStr for OverdraftPolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s.split_once(':') {
+            None if s == "reject" => OverdraftPolicy::Reject,
+            None if s == "allow-unlimited" => OverdraftPolicy::AllowUnlimited,
+            Some(("allow-to-limit", limit)) => match limit.parse::<Amount>() {
+                Ok(limit) if !limit.is_negative() => OverdraftPolicy::AllowToLimit(limit),
+                _ => return Err(MyError::Conversion(s.to_string())),
+            },
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for OverdraftPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OverdraftPolicy::Reject => write!(f, "reject"),
+            OverdraftPolicy::AllowToLimit(limit) => write!(f, "allow-to-limit:{}", limit),
+            OverdraftPolicy::AllowUnlimited => write!(f, "allow-unlimited"),
+        }
+    }
+}
+
+/// what happens to the transactions of an account locked by a chargeback. they are always rejected with
+/// `RejectReason::AccountLocked`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockedAccountPolicy {
+    /// the rejections are only in the rejects log and the audit log, if they're enabled
+    #[default]
+    Reject,
+    /// the rejections are appended to the audit log even if it isn't enabled for every transaction
+    Audit,
+    /// deposits are queued (`EngineEvent::DepositQueued`) and applied by `TransactionProcessor::unlock_account`
+    QueueDeposits,
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "reject" => LockedAccountPolicy::Reject,
+            "audit" => LockedAccountPolicy::Audit,
+            "queue-deposits" => LockedAccountPolicy::QueueDeposits,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for LockedAccountPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            LockedAccountPolicy::Reject => "reject",
+            LockedAccountPolicy::Audit => "audit",
+            LockedAccountPolicy::QueueDeposits => "queue-deposits",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for policy in [
+            CrossClientDisputePolicy::Reject,
+            CrossClientDisputePolicy::Owner,
+        ] {
+            assert_eq!(
+                policy
+                    .to_string()
+                    .parse::<CrossClientDisputePolicy>()
+                    .unwrap(),
+                policy
+            );
+        }
+        assert!("owners".parse::<CrossClientDisputePolicy>().is_err());
+        for policy in [
+            DuplicateInputPolicy::Reject,
+            DuplicateInputPolicy::Warn,
+            DuplicateInputPolicy::Skip,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<DuplicateInputPolicy>().unwrap(),
+                policy
+            );
+        }
+        for policy in [
+            DisputePolicy::Both,
+            DisputePolicy::DepositsOnly,
+            DisputePolicy::WithdrawalsOnly,
+        ] {
+            assert_eq!(policy.to_string().parse::<DisputePolicy>().unwrap(), policy);
+        }
+        for policy in [
+            OverdraftPolicy::Reject,
+            OverdraftPolicy::AllowToLimit(Amount::parse("100.5", Default::default()).unwrap()),
+            OverdraftPolicy::AllowUnlimited,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<OverdraftPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("allow-to-limit:-5".parse::<OverdraftPolicy>().is_err());
+        assert!("allow-to-limit".parse::<OverdraftPolicy>().is_err());
+        for policy in [
+            LockedAccountPolicy::Reject,
+            LockedAccountPolicy::Audit,
+            LockedAccountPolicy::QueueDeposits,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<LockedAccountPolicy>().unwrap(),
+                policy
+            );
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! the protobuf encoding of a transaction, the `Transaction` message of proto/transaction.proto. an input stream is a
+//! sequence of messages, each prefixed with its length as a varint. the message is derived with prost, so it needs no
+//! protoc or build step. enabled by the "protobuf" feature
+use crate::{amount::RawAmount, errors::*, fmt_error, model::*, transaction_processor::InputRow};
+use csv::StringRecord;
+use error_stack::{report, IntoReport, Result, ResultExt};
+use prost::Message;
+use std::io::{self, BufReader, Read};
+
+/// the `Transaction` message of proto/transaction.proto. fields with the default value are left out when encoding, as
+/// in proto3, and unknown fields are skipped when decoding
+#[derive(Clone, PartialEq, Message)]
+pub struct Transaction {
+    /// a `TxnType` enum value
+    #[prost(int32, tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint64, tag = "3")]
+    pub tx: u64,
+    #[prost(double, optional, tag = "4")]
+    pub amount: Option<f64>,
+    /// unix seconds
+    #[prost(int64, optional, tag = "5")]
+    pub timestamp: Option<i64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub original_tx: Option<u64>,
+}
+
+impl Transaction {
+    // an unknown type is invalid, as in CSV input
+    fn txn_type(&self) -> TxnType {
+        u8::try_from(self.r#type).map_or(TxnType::Invalid, TxnType::from)
+    }
+
+    // None if an id doesn't fit the model
+    fn transaction(&self) -> Option<RawTxnInput> {
+        let original_txn_id = match self.original_tx {
+            Some(id) => Some(TransactionId::try_from(id).ok()?),
+            None => None,
+        };
+        Some(RawTxnInput {
+            txn_type: self.txn_type(),
+            client_id: ClientId::try_from(self.client).ok()?,
+            txn_id: TransactionId::try_from(self.tx).ok()?,
+            amount: self.amount.map(RawAmount::from),
+            timestamp: self.timestamp,
+            original_txn_id,
+        })
+    }
+
+    // the fields as text, in the order of the CSV columns, for the rejects log
+    fn record(&self) -> StringRecord {
+        let text = |value: Option<String>| value.unwrap_or_default();
+        StringRecord::from(vec![
+            self.txn_type().to_string(),
+            self.client.to_string(),
+            self.tx.to_string(),
+            text(self.amount.map(|amount| amount.to_string())),
+            text(self.timestamp.map(|timestamp| timestamp.to_string())),
+            text(self.original_tx.map(|id| id.to_string())),
+        ])
+    }
+}
+
+impl From<&RawTxnInput> for Transaction {
+    // the ids are already a u32 and a u64 with the "wide-ids" feature
+    #[allow(clippy::useless_conversion)]
+    fn from(txn: &RawTxnInput) -> Self {
+        Transaction {
+            r#type: txn.txn_type.to_u8().into(),
+            client: txn.client_id.into(),
+            tx: u64::from(txn.txn_id),
+            amount: txn.amount.as_ref().map(RawAmount::to_f64),
+            timestamp: txn.timestamp,
+            original_tx: txn.original_txn_id.map(u64::from),
+        }
+    }
+}
+
+/// decode one `Transaction` message, without a length prefix
+pub fn decode_transaction(payload: &[u8]) -> Result<RawTxnInput, MyError> {
+    let message = Transaction::decode(payload)
+        .report()
+        .change_context(MyError::MalformedRecord)?;
+    message.transaction().ok_or_else(|| {
+        report!(MyError::MalformedRecord)
+            .attach_printable(fmt_error!("an id is out of range: {:?}", message.record()))
+    })
+}
+
+/// write a transaction as a `Transaction` message with its length prefix
+pub fn write_delimited<W: io::Write>(mut writer: W, txn: &RawTxnInput) -> io::Result<()> {
+    writer.write_all(&Transaction::from(txn).encode_length_delimited_to_vec())
+}
+
+/// the messages of a length-delimited stream, in order. a message with an id that doesn't fit the model is malformed.
+/// a message that doesn't decode or a truncated stream is an error, since the rest of the stream can't be trusted
+pub(crate) struct ProtobufRows<R: Read> {
+    reader: BufReader<R>,
+    // the length prefix and the message it's followed by
+    message: Vec<u8>,
+}
+
+pub(crate) fn read_rows<R: Read>(reader: R) -> ProtobufRows<R> {
+    ProtobufRows {
+        reader: BufReader::new(reader),
+        message: Vec::new(),
+    }
+}
+
+impl<R: Read> ProtobufRows<R> {
+    // reads the length prefix of the next message into `message`. None at the end of the stream
+    fn read_len(&mut self) -> io::Result<Option<usize>> {
+        let mut byte = [0];
+        while self.message.len() < 10 {
+            if self.reader.read(&mut byte)? == 0 {
+                if self.message.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.message.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        prost::decode_length_delimiter(self.message.as_slice())
+            .map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid length prefix"))
+    }
+
+    fn next_row(&mut self) -> Result<Option<InputRow>, MyError> {
+        self.message.clear();
+        let len = match self
+            .read_len()
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read the protobuf input"))
+            .change_context(MyError::FileReader)?
+        {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let prefix_len = self.message.len();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut self.message)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read the protobuf input"))
+            .change_context(MyError::FileReader)?;
+        if self.message.len() - prefix_len != len {
+            return Err(report!(MyError::FileReader)
+                .attach_printable(fmt_error!("the protobuf input is truncated")));
+        }
+        let message = Transaction::decode_length_delimited(self.message.as_slice())
+            .report()
+            .attach_printable_lazy(|| fmt_error!("invalid protobuf message"))
+            .change_context(MyError::FileReader)?;
+        Ok(Some(match message.transaction() {
+            Some(txn) => InputRow::Txn(txn),
+            None => InputRow::Malformed(message.record()),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for ProtobufRows<R> {
+    type Item = Result<InputRow, MyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, transaction_processor::TransactionProcessor};
+
+    fn txn(
+        txn_type: TxnType,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        amount: Option<f64>,
+    ) -> RawTxnInput {
+        RawTxnInput {
+            txn_type,
+            client_id,
+            txn_id,
+            amount: amount.map(RawAmount::from),
+            timestamp: None,
+            original_txn_id: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let refund = RawTxnInput {
+            timestamp: Some(-1),
+            original_txn_id: Some(1),
+            ..txn(TxnType::Refund, 1, 300, None)
+        };
+        let mut stream = Vec::new();
+        write_delimited(&mut stream, &refund).unwrap();
+        // the message is the bytes after the 1 byte prefix
+        assert_eq!(stream[0] as usize, stream.len() - 1);
+        assert_eq!(decode_transaction(&stream[1..]).unwrap(), refund);
+
+        // a gRPC Transaction with an unknown string field, and an unknown type
+        let message = [
+            &[0x08, 0x01, 0x10, 0x02, 0x18, 0x03, 0x21][..],
+            &2.5f64.to_le_bytes(),
+            &[0x3a, 0x02, b'h', b'i', 0x45, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(
+            decode_transaction(&message).unwrap(),
+            txn(TxnType::Deposit, 2, 3, Some(2.5))
+        );
+        assert_eq!(
+            decode_transaction(&[0x08, 0x63]).unwrap().txn_type,
+            TxnType::Invalid
+        );
+        // the amount as a varint and a truncated message
+        assert!(decode_transaction(&[0x20, 0x01]).is_err());
+        assert!(decode_transaction(&[0x18]).is_err());
+        // client 65536
+        #[cfg(not(feature = "wide-ids"))]
+        assert!(decode_transaction(&[0x10, 0x80, 0x80, 0x04]).is_err());
+    }
+
+    #[test]
+    fn test_process_protobuf() {
+        let mut stream = Vec::new();
+        for txn in [
+            txn(TxnType::Deposit, 1, 1, Some(10.0)),
+            txn(TxnType::Withdrawal, 1, 2, Some(2.5)),
+            txn(TxnType::Dispute, 1, 1, None),
+        ] {
+            write_delimited(&mut stream, &txn).unwrap();
+        }
+        // a client out of range is malformed
+        #[cfg(not(feature = "wide-ids"))]
+        {
+            stream.extend([6, 0x08, 0x01, 0x10, 0x80, 0x80, 0x04]);
+            let rows: Vec<InputRow> = read_rows(stream.as_slice()).map(Result::unwrap).collect();
+            assert_eq!(rows.len(), 4);
+            let InputRow::Malformed(record) = &rows[3] else {
+                panic!("a client out of range isn't malformed");
+            };
+            assert_eq!(
+                record,
+                &StringRecord::from(vec!["deposit", "65536", "0", "", "", ""])
+            );
+        }
+
+        let mut tp = TransactionProcessor::in_memory();
+        tp.process_protobuf(stream.as_slice()).unwrap();
+        let state = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(state.available, amt(-2.5));
+        assert_eq!(state.held, amt(10.0));
+
+        // a truncated stream
+        stream.pop();
+        assert!(tp.process_protobuf(stream.as_slice()).is_err());
+    }
+}
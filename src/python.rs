@@ -0,0 +1,114 @@
+// the pymethods macro expansion converts PyErr into PyErr
+#![allow(clippy::useless_conversion)]
+
+use crate::{amount::RawAmount, model::*, transaction_processor::TransactionProcessor};
+use pyo3::{
+    exceptions::{PyIOError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use std::{fs, io::BufReader, str::FromStr};
+
+// python only sees the message. the full report is logged
+fn to_py_err<C: error_stack::Context>(report: error_stack::Report<C>) -> PyErr {
+    let msg = format!("{:?}", report);
+    crate::errors::print_report(report);
+    PyRuntimeError::new_err(msg)
+}
+
+/// runs the same validation and balance logic as the payments_engine executable
+#[pyclass(name = "Engine")]
+pub struct Engine {
+    processor: TransactionProcessor,
+}
+
+#[pymethods]
+impl Engine {
+    /// uses an SQLite database unless `in_memory` is set (or the crate was built without the sqlite feature)
+    #[new]
+    #[pyo3(signature = (in_memory = false))]
+    fn new(in_memory: bool) -> PyResult<Self> {
+        #[cfg(feature = "sqlite")]
+        let processor = if in_memory {
+            TransactionProcessor::in_memory()
+        } else {
+            TransactionProcessor::new().map_err(to_py_err)?
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let processor = {
+            let _ = in_memory;
+            TransactionProcessor::in_memory()
+        };
+        Ok(Engine { processor })
+    }
+
+    /// process a CSV file with the columns `type,client,tx,amount`. invalid records are skipped
+    fn process_csv(&mut self, path: &str) -> PyResult<()> {
+        let file = fs::File::open(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        self.processor
+            .process_csv(BufReader::new(file))
+            .map_err(to_py_err)
+    }
+
+    /// process a single transaction given as a dict with the keys `type`, `client`, `tx`, and optionally `amount`
+    fn process(&mut self, txn: &Bound<'_, PyDict>) -> PyResult<()> {
+        let raw = raw_txn_from_dict(txn)?;
+        self.processor.process(raw).map(|_| ()).map_err(to_py_err)
+    }
+
+    /// a list of dicts with the keys `client`, `available`, `held`, `total`, and `locked`
+    fn accounts(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let states = self.processor.client_states().map_err(to_py_err)?;
+        states
+            .into_iter()
+            .map(|state| {
+                let dict = PyDict::new_bound(py);
+                dict.set_item("client", state.client_id)?;
+                dict.set_item("available", state.available.to_f64())?;
+                dict.set_item("held", state.held.to_f64())?;
+                dict.set_item("total", state.total.to_f64())?;
+                dict.set_item("locked", state.is_locked())?;
+                Ok(dict.into_py(py))
+            })
+            .collect()
+    }
+}
+
+fn raw_txn_from_dict(txn: &Bound<'_, PyDict>) -> PyResult<RawTxnInput> {
+    let get = |key: &str| {
+        txn.get_item(key)?
+            .ok_or_else(|| PyValueError::new_err(format!("transaction is missing \"{}\"", key)))
+    };
+    let txn_type: String = get("type")?.extract()?;
+    let txn_type = TxnType::from_str(&txn_type)
+        .map_err(|e| PyValueError::new_err(format!("invalid transaction type: {:?}", e)))?;
+    let amount = match txn.get_item("amount")? {
+        // a str is read as an exact decimal, ex: str(decimal.Decimal)
+        Some(amount) if !amount.is_none() => Some(match amount.extract::<String>() {
+            Ok(s) => RawAmount::parse(&s)
+                .ok_or_else(|| PyValueError::new_err(format!("invalid amount: {:?}", s)))?,
+            Err(_) => RawAmount::from(amount.extract::<f64>()?),
+        }),
+        _ => None,
+    };
+    Ok(RawTxnInput {
+        txn_type,
+        client_id: get("client")?.extract()?,
+        txn_id: get("tx")?.extract()?,
+        amount,
+        timestamp: match txn.get_item("timestamp")? {
+            Some(timestamp) if !timestamp.is_none() => Some(timestamp.extract()?),
+            _ => None,
+        },
+        original_txn_id: match txn.get_item("original_tx")? {
+            Some(original) if !original.is_none() => Some(original.extract()?),
+            _ => None,
+        },
+    })
+}
+
+#[pymodule]
+fn payments_engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Engine>()?;
+    Ok(())
+}
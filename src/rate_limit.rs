@@ -0,0 +1,304 @@
+//! ingestion rate limiting. a global limit protects the store, and a per-client limit keeps one busy producer from
+//! starving the other clients. both are token buckets: `rate` transactions per second on average, with bursts of
+//! up to `burst`. a transaction over a limit is either shed (rejected with `RejectReason::RateLimited`) or queued
+//! (the caller blocks until the limit allows it)
+use crate::{errors::*, model::*};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// `rate` transactions per second, with bursts of up to `burst`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+/// `<rate>` or `<rate>:<burst>`. ex: `1000` or `1000:5000`. the burst defaults to one second's worth
+impl FromStr for RateLimit {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MyError::Conversion(s.to_string());
+        let (rate, burst) = match s.split_once(':') {
+            Some((rate, burst)) => (rate, Some(burst)),
+            None => (s, None),
+        };
+        let rate: f64 = rate.parse().map_err(|_| err())?;
+        let burst: f64 = match burst {
+            Some(burst) => burst.parse().map_err(|_| err())?,
+            None => rate.max(1.0),
+        };
+        // a burst below 1 would never admit a transaction
+        if !rate.is_finite() || rate <= 0.0 || !burst.is_finite() || burst < 1.0 {
+            return Err(err());
+        }
+        Ok(RateLimit { rate, burst })
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.rate, self.burst)
+    }
+}
+
+/// what happens to a transaction over a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverloadPolicy {
+    /// reject it with `RejectReason::RateLimited`
+    #[default]
+    Shed,
+    /// wait until the limits allow it
+    Queue,
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "shed" => OverloadPolicy::Shed,
+            "queue" => OverloadPolicy::Queue,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for OverloadPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            OverloadPolicy::Shed => "shed",
+            OverloadPolicy::Queue => "queue",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    // how long until a token is available. zero if one is available now
+    fn wait(&self, limit: &RateLimit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / limit.rate)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    global: Option<(RateLimit, Option<Bucket>)>,
+    per_client: Option<RateLimit>,
+    clients: HashMap<ClientId, Bucket>,
+    policy: OverloadPolicy,
+    // the number of transactions shed or delayed, per client
+    limited: HashMap<ClientId, u64>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: OverloadPolicy) -> Self {
+        RateLimiter {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// the limit on all transactions, regardless of the client
+    pub fn set_global_limit(&mut self, limit: RateLimit) {
+        self.global = Some((limit, None));
+    }
+
+    /// the limit on the transactions of each client
+    pub fn set_client_limit(&mut self, limit: RateLimit) {
+        self.per_client = Some(limit);
+        self.clients.clear();
+    }
+
+    pub fn policy(&self) -> OverloadPolicy {
+        self.policy
+    }
+
+    /// take a token from the global bucket and the client's bucket. if either is empty, nothing is taken and the
+    /// result is how long the caller has to wait before trying again
+    pub fn try_acquire(&mut self, client_id: ClientId, now: Instant) -> Option<Duration> {
+        let mut wait = Duration::ZERO;
+        if let Some((limit, bucket)) = self.global.as_mut() {
+            let bucket = bucket.get_or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait(limit));
+        }
+        if let Some(limit) = &self.per_client {
+            let bucket = self
+                .clients
+                .entry(client_id)
+                .or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait(limit));
+        }
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+
+        if let Some((_, Some(bucket))) = self.global.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = self.clients.get_mut(&client_id) {
+            bucket.tokens -= 1.0;
+        }
+        None
+    }
+
+    /// admit a transaction of `client_id`. with `OverloadPolicy::Queue` this sleeps until the limits allow it and
+    /// always returns true. with `OverloadPolicy::Shed` it returns false if the transaction is over a limit
+    pub fn acquire(&mut self, client_id: ClientId) -> bool {
+        let mut limited = false;
+        while let Some(wait) = self.try_acquire(client_id, Instant::now()) {
+            if !limited {
+                limited = true;
+                *self.limited.entry(client_id).or_default() += 1;
+            }
+            match self.policy {
+                OverloadPolicy::Shed => return false,
+                OverloadPolicy::Queue => std::thread::sleep(wait),
+            }
+        }
+        true
+    }
+
+    /// forget the clients whose bucket has refilled. they're recreated full on their next transaction, so this
+    /// doesn't change what's admitted. returns the number of buckets dropped
+    pub fn shrink(&mut self, now: Instant) -> usize {
+        let before = self.clients.len();
+        if let Some(limit) = &self.per_client {
+            self.clients.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst
+            });
+        }
+        self.clients.shrink_to_fit();
+        before - self.clients.len()
+    }
+
+    /// the number of transactions that were shed or delayed, per client, in client order
+    pub fn limited(&self) -> Vec<(ClientId, u64)> {
+        let mut limited: Vec<(ClientId, u64)> =
+            self.limited.iter().map(|(k, v)| (*k, *v)).collect();
+        limited.sort_unstable();
+        limited
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "10".parse::<RateLimit>().unwrap(),
+            RateLimit {
+                rate: 10.0,
+                burst: 10.0
+            }
+        );
+        assert_eq!(
+            "0.5:3".parse::<RateLimit>().unwrap(),
+            RateLimit {
+                rate: 0.5,
+                burst: 3.0
+            }
+        );
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("10:0.5".parse::<RateLimit>().is_err());
+        assert!("ten".parse::<RateLimit>().is_err());
+        assert_eq!(
+            "queue".parse::<OverloadPolicy>().unwrap(),
+            OverloadPolicy::Queue
+        );
+        assert!("drop".parse::<OverloadPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_buckets() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(OverloadPolicy::Shed);
+        limiter.set_global_limit(RateLimit {
+            rate: 10.0,
+            burst: 3.0,
+        });
+        limiter.set_client_limit(RateLimit {
+            rate: 1.0,
+            burst: 2.0,
+        });
+
+        // client 1 uses its burst, and is then limited. client 2 still gets the rest of the global burst
+        assert!(limiter.try_acquire(1, start).is_none());
+        assert!(limiter.try_acquire(1, start).is_none());
+        assert_eq!(limiter.try_acquire(1, start), Some(Duration::from_secs(1)));
+        assert!(limiter.try_acquire(2, start).is_none());
+        // the global bucket is empty: a token every 100ms
+        assert_eq!(
+            limiter.try_acquire(2, start),
+            Some(Duration::from_millis(100))
+        );
+
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.try_acquire(2, later).is_none());
+        assert!(limiter.try_acquire(1, later).is_some());
+        // a limited attempt doesn't consume a token
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.try_acquire(1, later).is_none());
+
+        // client 2 has refilled, client 1 just used a token
+        assert_eq!(limiter.shrink(start + Duration::from_millis(2500)), 1);
+        assert!(limiter.clients.contains_key(&1));
+    }
+
+    #[test]
+    fn test_acquire() {
+        let mut limiter = RateLimiter::new(OverloadPolicy::Shed);
+        limiter.set_client_limit(RateLimit {
+            rate: 0.001,
+            burst: 1.0,
+        });
+        assert!(limiter.acquire(1));
+        assert!(!limiter.acquire(1));
+        assert!(!limiter.acquire(1));
+        assert!(limiter.acquire(2));
+        assert_eq!(limiter.limited(), vec![(1, 2)]);
+
+        let mut limiter = RateLimiter::new(OverloadPolicy::Queue);
+        limiter.set_global_limit(RateLimit {
+            rate: 100.0,
+            burst: 1.0,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.acquire(1));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(limiter.limited(), vec![(1, 2)]);
+    }
+}
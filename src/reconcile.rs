@@ -0,0 +1,187 @@
+//! run-level reconciliation: a cheap global consistency check. the change in the sum of the client totals over a
+//! run has to match what the applied transactions say it should be
+use crate::{amount::Amount, events::EngineEvent};
+use std::fmt;
+
+/// the amounts moved by the transactions applied during a run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunTotals {
+    pub deposits: Amount,
+    pub withdrawals: Amount,
+    /// refunded deposits are returned to the payer
+    pub refunds: Amount,
+    /// disputed withdrawals are provisionally credited back to the client (held)
+    pub disputed_withdrawals: Amount,
+    pub resolved_withdrawals: Amount,
+    /// charged back deposits are returned to the payer
+    pub charged_back_deposits: Amount,
+    /// the sum of the manual adjustments
+    pub adjustments: Amount,
+    /// the fees charged, net of the fees refunded on chargebacks
+    pub fees: Amount,
+    /// the interest accrued on the available funds
+    pub interest: Amount,
+}
+
+impl RunTotals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, events: &[EngineEvent]) {
+        for event in events {
+            // for disputes, resolutions, and chargebacks the amount is negative for a withdrawal
+            match event {
+                EngineEvent::FundsDeposited { amount, .. } => self.deposits += *amount,
+                EngineEvent::FundsWithdrawn { amount, .. } => self.withdrawals += *amount,
+                EngineEvent::FundsRefunded { amount, .. } => self.refunds += *amount,
+                EngineEvent::DisputeOpened { amount, .. }
+                | EngineEvent::DisputeReopened { amount, .. }
+                    if amount.is_negative() =>
+                {
+                    self.disputed_withdrawals -= *amount
+                }
+                EngineEvent::DisputeResolved { amount, .. } if amount.is_negative() => {
+                    self.resolved_withdrawals -= *amount
+                }
+                EngineEvent::ChargebackApplied { amount, .. } if amount.is_positive() => {
+                    self.charged_back_deposits += *amount
+                }
+                EngineEvent::BalanceAdjusted { amount, .. } => self.adjustments += *amount,
+                EngineEvent::FeeCharged { amount, .. } => self.fees += *amount,
+                EngineEvent::FeeRefunded { amount, .. } => self.fees -= *amount,
+                EngineEvent::InterestAccrued { amount, .. } => self.interest += *amount,
+                // a disputed deposit moves funds from available to held, and a charged back withdrawal moves them
+                // from held to available: the total doesn't change
+                _ => {}
+            }
+        }
+    }
+
+    /// how much the sum of the client totals should have changed
+    pub fn expected_change(&self) -> Amount {
+        self.deposits - self.withdrawals - self.refunds + self.disputed_withdrawals
+            - self.resolved_withdrawals
+            - self.charged_back_deposits
+            + self.adjustments
+            - self.fees
+            + self.interest
+    }
+}
+
+/// the outcome of reconciling a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    /// the sum of the client totals before the run
+    pub opening_total: Amount,
+    pub totals: RunTotals,
+    /// the sum of the client totals after the run
+    pub closing_total: Amount,
+}
+
+impl Reconciliation {
+    pub fn expected_total(&self) -> Amount {
+        self.opening_total + self.totals.expected_change()
+    }
+
+    pub fn difference(&self) -> Amount {
+        self.closing_total - self.expected_total()
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.difference().is_zero()
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let t = &self.totals;
+        write!(
+            f,
+            "opening total {} + deposits {} - withdrawals {} - refunds {} + disputed withdrawals {} - resolved withdrawals {} \
+             - charged back deposits {} + adjustments {} - fees {} + interest {} = expected {}, actual {}",
+            self.opening_total,
+            t.deposits,
+            t.withdrawals,
+            t.refunds,
+            t.disputed_withdrawals,
+            t.resolved_withdrawals,
+            t.charged_back_deposits,
+            t.adjustments,
+            t.fees,
+            t.interest,
+            self.expected_total(),
+            self.closing_total
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    #[test]
+    fn test_expected_change() {
+        let mut totals = RunTotals::new();
+        totals.observe(&[EngineEvent::FundsDeposited {
+            client_id: 1,
+            txn_id: 1,
+            amount: amt(10.0),
+        }]);
+        totals.observe(&[EngineEvent::FundsWithdrawn {
+            client_id: 1,
+            txn_id: 2,
+            amount: amt(4.0),
+        }]);
+        totals.observe(&[EngineEvent::DisputeOpened {
+            client_id: 1,
+            txn_id: 2,
+            amount: amt(-4.0),
+        }]);
+        assert_eq!(totals.expected_change(), 10.0);
+        totals.observe(&[EngineEvent::DisputeResolved {
+            client_id: 1,
+            txn_id: 2,
+            amount: amt(-4.0),
+        }]);
+        totals.observe(&[
+            EngineEvent::DisputeOpened {
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(10.0),
+            },
+            EngineEvent::ChargebackApplied {
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(10.0),
+            },
+        ]);
+        assert_eq!(totals.expected_change(), -4.0);
+        totals.observe(&[EngineEvent::FundsDeposited {
+            client_id: 1,
+            txn_id: 3,
+            amount: amt(2.0),
+        }]);
+        totals.observe(&[EngineEvent::FundsRefunded {
+            client_id: 1,
+            txn_id: 4,
+            original_txn_id: 3,
+            amount: amt(2.0),
+        }]);
+        assert_eq!(totals.expected_change(), -4.0);
+
+        let reconciliation = Reconciliation {
+            opening_total: amt(5.0),
+            totals,
+            closing_total: amt(1.0),
+        };
+        assert!(reconciliation.is_balanced());
+        let off = Reconciliation {
+            closing_total: amt(1.5),
+            ..reconciliation
+        };
+        assert!(!off.is_balanced());
+        assert_eq!(off.difference(), 0.5);
+    }
+}
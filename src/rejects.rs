@@ -0,0 +1,193 @@
+//! the rejects log: every transaction the processor rejected and every input row that failed to parse, with a reason
+//! code (a `RejectReason`, or `Malformed` for rows) and the original input, so nothing is dropped silently
+use crate::{errors::*, events::RejectReason, fmt_error, model::*};
+use csv::StringRecord;
+use error_stack::{IntoReport, Result, ResultExt};
+use serde_json::json;
+use std::{fs, io, path::Path};
+
+/// the header of the CSV rejects log. a malformed row has its original fields after the reason, however many
+pub const REJECTS_HEADER: &str = "reason,type,client,tx,amount";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectsFormat {
+    Csv,
+    /// one JSON object per line: `{"reason": ..., "input": ...}`. the input of a malformed row is its list of fields
+    Jsonl,
+}
+
+impl RejectsFormat {
+    /// JSONL for `.jsonl` files, CSV otherwise
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => RejectsFormat::Jsonl,
+            _ => RejectsFormat::Csv,
+        }
+    }
+}
+
+type Writer = Box<dyn io::Write + Send>;
+
+pub struct RejectsLog {
+    sink: Sink,
+}
+
+enum Sink {
+    // flexible: malformed rows have any number of fields
+    Csv(Box<csv::Writer<Writer>>),
+    Jsonl(Writer),
+}
+
+impl RejectsLog {
+    pub fn new<W: io::Write + Send + 'static>(
+        writer: W,
+        format: RejectsFormat,
+    ) -> Result<Self, MyError> {
+        let writer: Writer = Box::new(writer);
+        let sink = match format {
+            RejectsFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+                write_record(&mut writer, REJECTS_HEADER.split(','))?;
+                Sink::Csv(Box::new(writer))
+            }
+            RejectsFormat::Jsonl => Sink::Jsonl(writer),
+        };
+        Ok(RejectsLog { sink })
+    }
+
+    /// create the file at `path`, in the format of its extension
+    pub fn create(path: &Path) -> Result<Self, MyError> {
+        let file = fs::File::create(path)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create {}", path.display()))
+            .change_context(MyError::Output)?;
+        Self::new(io::BufWriter::new(file), RejectsFormat::of_path(path))
+    }
+
+    pub fn record_rejection(
+        &mut self,
+        input: &RawTxnInput,
+        reason: RejectReason,
+    ) -> Result<(), MyError> {
+        let reason = format!("{:?}", reason);
+        match &mut self.sink {
+            Sink::Csv(writer) => {
+                let amount = input
+                    .amount
+                    .as_ref()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                write_record(
+                    writer,
+                    [
+                        reason,
+                        input.txn_type.to_string(),
+                        input.client_id.to_string(),
+                        input.txn_id.to_string(),
+                        amount,
+                    ],
+                )
+            }
+            Sink::Jsonl(writer) => write_line(
+                writer,
+                json!({
+                    "reason": reason,
+                    "input": {
+                        "type": input.txn_type.to_string(),
+                        "client": input.client_id,
+                        "tx": input.txn_id,
+                        "amount": input.amount,
+                    },
+                }),
+            ),
+        }
+    }
+
+    /// an input row that didn't parse into a transaction
+    pub fn record_malformed(&mut self, row: &StringRecord) -> Result<(), MyError> {
+        match &mut self.sink {
+            Sink::Csv(writer) => {
+                write_record(writer, std::iter::once("Malformed").chain(row.iter()))
+            }
+            Sink::Jsonl(writer) => write_line(
+                writer,
+                json!({
+                    "reason": "Malformed",
+                    "input": row.iter().collect::<Vec<_>>(),
+                }),
+            ),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), MyError> {
+        let res = match &mut self.sink {
+            Sink::Csv(writer) => writer.flush(),
+            Sink::Jsonl(writer) => writer.flush(),
+        };
+        res.report()
+            .attach_printable_lazy(|| fmt_error!("failed to flush the rejects log"))
+            .change_context(MyError::Output)
+    }
+}
+
+fn write_record<I, T>(writer: &mut csv::Writer<Writer>, record: I) -> Result<(), MyError>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    writer
+        .write_record(record)
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to write to the rejects log"))
+        .change_context(MyError::Output)
+}
+
+fn write_line(writer: &mut Writer, line: serde_json::Value) -> Result<(), MyError> {
+    serde_json::to_writer(&mut *writer, &line)
+        .map_err(io::Error::from)
+        .and_then(|_| writeln!(writer))
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to write to the rejects log"))
+        .change_context(MyError::Output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rejects_log() {
+        let dir = std::env::temp_dir().join(format!("rejects-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let withdrawal = RawTxnInput {
+            txn_type: TxnType::Withdrawal,
+            client_id: 1,
+            txn_id: 2,
+            amount: Some(9.5.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let row = StringRecord::from(vec!["deposit", "x", "3"]);
+        for (file, expected) in [
+            (
+                "rejects.csv",
+                "reason,type,client,tx,amount\nInsufficientFunds,withdrawal,1,2,9.5\nMalformed,deposit,x,3\n",
+            ),
+            (
+                "rejects.jsonl",
+                r#"{"input":{"amount":9.5,"client":1,"tx":2,"type":"withdrawal"},"reason":"InsufficientFunds"}
+{"input":["deposit","x","3"],"reason":"Malformed"}
+"#,
+            ),
+        ] {
+            let path = dir.join(file);
+            let mut log = RejectsLog::create(&path).unwrap();
+            log.record_rejection(&withdrawal, RejectReason::InsufficientFunds)
+                .unwrap();
+            log.record_malformed(&row).unwrap();
+            log.flush().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+//! the client report: one row per client account, as CSV (the default) or as JSON for downstream services
+use crate::{errors::*, model::ClientState, number_format::NumberFormat, rounding::RoundingPolicy};
+use serde_json::{json, Value};
+use std::{fmt, io, str::FromStr};
+
+pub(crate) const REPORT_HEADER: &str = "client,available,held,total,locked";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// a header followed by one row per client
+    #[default]
+    Csv,
+    /// an array of client objects: `{"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false}`
+    Json,
+    /// one client object per line
+    Jsonl,
+}
+
+impl ReportFormat {
+    /// the file extension of a report in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+            ReportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let format = match s {
+            "csv" => ReportFormat::Csv,
+            "json" => ReportFormat::Json,
+            "jsonl" => ReportFormat::Jsonl,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(format)
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// the JSON object of a client account. the amounts are numbers with up to 4 decimal places
+pub fn client_json(state: &ClientState) -> Value {
+    json!({
+        "client": state.client_id,
+        "available": state.available.to_f64(),
+        "held": state.held.to_f64(),
+        "total": state.total.to_f64(),
+        "locked": state.is_locked(),
+    })
+}
+
+// how the report is written
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReportStyle {
+    pub(crate) format: ReportFormat,
+    // the number format of the CSV amounts
+    pub(crate) number_format: Option<NumberFormat>,
+    // the amounts are rounded to this many decimal places
+    pub(crate) precision: Option<usize>,
+    pub(crate) rounding: RoundingPolicy,
+}
+
+// writes the report one row at a time. `finish` must be called after the last row
+pub(crate) struct ReportWriter<W: io::Write> {
+    writer: W,
+    style: ReportStyle,
+    rows: u64,
+}
+
+impl<W: io::Write> ReportWriter<W> {
+    pub(crate) fn new(mut writer: W, style: ReportStyle) -> io::Result<Self> {
+        match style.format {
+            ReportFormat::Csv => writeln!(writer, "{}", REPORT_HEADER)?,
+            ReportFormat::Json => write!(writer, "[")?,
+            ReportFormat::Jsonl => {}
+        }
+        Ok(ReportWriter {
+            writer,
+            style,
+            rows: 0,
+        })
+    }
+
+    pub(crate) fn row(&mut self, client: &ClientState) -> io::Result<()> {
+        let separator = if self.rows == 0 { "" } else { "," };
+        self.rows += 1;
+        let mut client = client.clone();
+        if let Some(places) = self.style.precision {
+            for amount in [&mut client.available, &mut client.held, &mut client.total] {
+                *amount = amount.round_to(places, self.style.rounding);
+            }
+        }
+        let client = &client;
+        match (self.style.format, &self.style.number_format) {
+            (ReportFormat::Csv, Some(format)) => writeln!(
+                self.writer,
+                "{},{},{},{},{}",
+                client.client_id,
+                format.format_csv(client.available),
+                format.format_csv(client.held),
+                format.format_csv(client.total),
+                client.locked
+            ),
+            (ReportFormat::Csv, None) => writeln!(self.writer, "{}", client),
+            (ReportFormat::Json, _) => {
+                write!(self.writer, "{}\n{}", separator, client_json(client))
+            }
+            (ReportFormat::Jsonl, _) => writeln!(self.writer, "{}", client_json(client)),
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        match self.style.format {
+            ReportFormat::Json if self.rows > 0 => writeln!(self.writer, "\n]"),
+            ReportFormat::Json => writeln!(self.writer, "]"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn report(format: ReportFormat, clients: &[ClientState]) -> String {
+        let mut out = Vec::new();
+        let style = ReportStyle {
+            format,
+            ..Default::default()
+        };
+        let mut writer = ReportWriter::new(&mut out, style).unwrap();
+        for client in clients {
+            writer.row(client).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let mut client = ClientState::new(1);
+        client.available = amt(1.5);
+        client.total = amt(1.5);
+        let clients = [client, ClientState::new(2)];
+        assert_eq!(
+            report(ReportFormat::Csv, &clients),
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,0,0,0,false\n"
+        );
+        let json = report(ReportFormat::Json, &clients);
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            parsed,
+            json!([
+                {"client": 1, "available": 1.5, "held": 0.0, "total": 1.5, "locked": false},
+                {"client": 2, "available": 0.0, "held": 0.0, "total": 0.0, "locked": false},
+            ])
+        );
+        assert_eq!(report(ReportFormat::Json, &[]), "[]\n");
+        let jsonl = report(ReportFormat::Jsonl, &clients);
+        assert_eq!(jsonl.lines().count(), 2);
+        for (line, expected) in jsonl.lines().zip(parsed.as_array().unwrap()) {
+            assert_eq!(&serde_json::from_str::<Value>(line).unwrap(), expected);
+        }
+        assert_eq!(
+            "jsonl".parse::<ReportFormat>().unwrap(),
+            ReportFormat::Jsonl
+        );
+        assert_eq!(ReportFormat::Json.to_string(), "json");
+    }
+
+    #[test]
+    fn test_precision() {
+        let mut client = ClientState::new(1);
+        client.available = amt(1.2345);
+        client.held = amt(0.005);
+        client.total = amt(1.2395);
+        let mut out = Vec::new();
+        let style = ReportStyle {
+            precision: Some(2),
+            ..Default::default()
+        };
+        let mut writer = ReportWriter::new(&mut out, style).unwrap();
+        writer.row(&client).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,1.23,0,1.24,false\n"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! chargeback-ratio monitoring. tracks the chargebacks of each client as a fraction of its deposits over a rolling
+//! window and flags the clients above the configured thresholds.
+//! there are no timestamps in the input, so the window is the last `window` transactions processed (by every client)
+use crate::{amount::Amount, events::EngineEvent, model::*};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargebackThresholds {
+    /// the number of most recent transactions that are considered
+    pub window: u64,
+    /// flag a client whose chargeback count / deposit count exceeds this
+    pub max_count_ratio: f64,
+    /// flag a client whose chargeback value / deposit value exceeds this
+    pub max_value_ratio: f64,
+}
+
+impl Default for ChargebackThresholds {
+    fn default() -> Self {
+        ChargebackThresholds {
+            window: 1000,
+            max_count_ratio: 0.01,
+            max_value_ratio: 0.01,
+        }
+    }
+}
+
+/// a client's deposits and chargebacks within the window
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargebackRisk {
+    pub client_id: ClientId,
+    pub deposits: u64,
+    pub deposit_value: Amount,
+    pub chargebacks: u64,
+    pub chargeback_value: Amount,
+}
+
+impl ChargebackRisk {
+    /// infinite if there are chargebacks but no deposits in the window
+    pub fn count_ratio(&self) -> f64 {
+        ratio(self.chargebacks as f64, self.deposits as f64)
+    }
+
+    pub fn value_ratio(&self) -> f64 {
+        ratio(self.chargeback_value.to_f64(), self.deposit_value.to_f64())
+    }
+
+    pub fn exceeds(&self, thresholds: &ChargebackThresholds) -> bool {
+        self.chargebacks > 0
+            && (self.count_ratio() > thresholds.max_count_ratio
+                || self.value_ratio() > thresholds.max_value_ratio)
+    }
+}
+
+fn ratio(part: f64, whole: f64) -> f64 {
+    if part == 0.0 {
+        0.0
+    } else if whole == 0.0 {
+        f64::INFINITY
+    } else {
+        part / whole
+    }
+}
+
+impl fmt::Display for ChargebackRisk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {} of {} deposits charged back ({:.2}%), {} of {} by value ({:.2}%)",
+            self.client_id,
+            self.chargebacks,
+            self.deposits,
+            self.count_ratio() * 100.0,
+            self.chargeback_value,
+            self.deposit_value,
+            self.value_ratio() * 100.0
+        )
+    }
+}
+
+// (sequence number, amount, is a chargeback)
+type WindowEntry = (u64, Amount, bool);
+
+/// called when a client crosses a chargeback threshold
+pub type ChargebackAlert = Box<dyn FnMut(&ChargebackRisk) + Send>;
+
+#[derive(Debug, Default)]
+pub struct ChargebackMonitor {
+    thresholds: ChargebackThresholds,
+    // the number of transactions observed
+    seq: u64,
+    clients: BTreeMap<ClientId, VecDeque<WindowEntry>>,
+    // clients that were reported by `observe` and haven't dropped below the thresholds since
+    alerted: HashSet<ClientId>,
+}
+
+impl ChargebackMonitor {
+    pub fn new(thresholds: ChargebackThresholds) -> Self {
+        ChargebackMonitor {
+            thresholds,
+            ..Default::default()
+        }
+    }
+
+    pub fn thresholds(&self) -> &ChargebackThresholds {
+        &self.thresholds
+    }
+
+    /// applies from the next observed transaction. the activity already in the window is kept
+    pub fn set_thresholds(&mut self, thresholds: ChargebackThresholds) {
+        self.thresholds = thresholds;
+        self.evict();
+    }
+
+    /// record the events of one processed transaction (applied or rejected: both move the window).
+    /// returns the client if this transaction pushed it over a threshold
+    pub fn observe(&mut self, events: &[EngineEvent]) -> Option<ChargebackRisk> {
+        self.seq += 1;
+        let mut client = None;
+        for event in events {
+            match event {
+                EngineEvent::FundsDeposited {
+                    client_id, amount, ..
+                } => {
+                    self.push(*client_id, *amount, false);
+                    client = Some(*client_id);
+                }
+                // only a charged back deposit returns funds to the payer. amount is negative for withdrawals
+                EngineEvent::ChargebackApplied {
+                    client_id, amount, ..
+                } if amount.is_positive() => {
+                    self.push(*client_id, *amount, true);
+                    client = Some(*client_id);
+                }
+                _ => {}
+            }
+        }
+
+        let risk = self.risk(client?)?;
+        if risk.exceeds(&self.thresholds) {
+            self.alerted.insert(risk.client_id).then_some(risk)
+        } else {
+            self.alerted.remove(&risk.client_id);
+            None
+        }
+    }
+
+    fn push(&mut self, client_id: ClientId, amount: Amount, chargeback: bool) {
+        let (seq, window) = (self.seq, self.thresholds.window);
+        let entries = self.clients.entry(client_id).or_default();
+        entries.push_back((seq, amount, chargeback));
+        trim(entries, seq, window);
+    }
+
+    fn in_window(&self, seq: u64) -> bool {
+        seq + self.thresholds.window > self.seq
+    }
+
+    /// the client's activity within the window. None if it has none
+    pub fn risk(&self, client_id: ClientId) -> Option<ChargebackRisk> {
+        let mut risk = ChargebackRisk {
+            client_id,
+            deposits: 0,
+            deposit_value: Amount::ZERO,
+            chargebacks: 0,
+            chargeback_value: Amount::ZERO,
+        };
+        let entries = self.clients.get(&client_id)?;
+        for (_, amount, chargeback) in entries.iter().filter(|e| self.in_window(e.0)) {
+            if *chargeback {
+                risk.chargebacks += 1;
+                risk.chargeback_value += *amount;
+            } else {
+                risk.deposits += 1;
+                risk.deposit_value += *amount;
+            }
+        }
+        (risk.deposits > 0 || risk.chargebacks > 0).then_some(risk)
+    }
+
+    /// the risk report: every client currently above a threshold, in client order
+    pub fn flagged(&mut self) -> Vec<ChargebackRisk> {
+        self.evict();
+        self.clients
+            .keys()
+            .filter_map(|client_id| self.risk(*client_id))
+            .filter(|risk| risk.exceeds(&self.thresholds))
+            .collect()
+    }
+
+    /// forget the activity that left the window and release the memory it used
+    pub fn shrink(&mut self) {
+        self.evict();
+        for entries in self.clients.values_mut() {
+            entries.shrink_to_fit();
+        }
+    }
+
+    // forget the entries that left the window
+    fn evict(&mut self) {
+        let (seq, window) = (self.seq, self.thresholds.window);
+        self.clients.retain(|_, entries| {
+            trim(entries, seq, window);
+            !entries.is_empty()
+        });
+    }
+}
+
+fn trim(entries: &mut VecDeque<WindowEntry>, seq: u64, window: u64) {
+    while entries.front().is_some_and(|e| e.0 + window <= seq) {
+        entries.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn deposit(client_id: ClientId, amount: f64) -> Vec<EngineEvent> {
+        vec![EngineEvent::FundsDeposited {
+            client_id,
+            txn_id: 0,
+            amount: amt(amount),
+        }]
+    }
+
+    fn chargeback(client_id: ClientId, amount: f64) -> Vec<EngineEvent> {
+        vec![
+            EngineEvent::ChargebackApplied {
+                client_id,
+                txn_id: 0,
+                amount: amt(amount),
+            },
+            EngineEvent::AccountLocked { client_id },
+        ]
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let mut monitor = ChargebackMonitor::new(ChargebackThresholds {
+            window: 100,
+            max_count_ratio: 0.25,
+            max_value_ratio: 0.5,
+        });
+        for _ in 0..4 {
+            assert!(monitor.observe(&deposit(1, 10.0)).is_none());
+        }
+        // 1 of 4 is not above 25%, and 5 of 40 by value is below 50%
+        assert!(monitor.observe(&chargeback(1, 5.0)).is_none());
+        assert!(monitor.flagged().is_empty());
+
+        // a charged back withdrawal doesn't count
+        assert!(monitor.observe(&chargeback(1, -5.0)).is_none());
+        let risk = monitor.observe(&chargeback(1, 5.0)).unwrap();
+        assert_eq!((risk.chargebacks, risk.deposits), (2, 4));
+        assert_eq!(risk.count_ratio(), 0.5);
+        assert_eq!(risk.value_ratio(), 0.25);
+        // only reported once while it stays above the thresholds
+        assert!(monitor.observe(&chargeback(1, 5.0)).is_none());
+        assert_eq!(monitor.flagged().len(), 1);
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mut monitor = ChargebackMonitor::new(ChargebackThresholds {
+            window: 3,
+            ..Default::default()
+        });
+        monitor.observe(&deposit(1, 10.0));
+        assert!(monitor.observe(&chargeback(1, 10.0)).is_some());
+        assert_eq!(monitor.flagged()[0].client_id, 1);
+
+        // other clients' transactions move the window too
+        monitor.observe(&deposit(2, 1.0));
+        monitor.observe(&deposit(2, 1.0));
+        monitor.observe(&[]);
+        assert!(monitor.flagged().is_empty());
+        assert!(monitor.risk(1).is_none());
+        assert_eq!(monitor.risk(2).unwrap().deposits, 2);
+
+        // no deposits in the window: the ratio is infinite
+        let risk = monitor.observe(&chargeback(3, 1.0)).unwrap();
+        assert!(risk.count_ratio().is_infinite());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{amount::Amount, errors::*};
+use std::{fmt, str::FromStr};
+
+/// amounts are kept to this many decimal places
+pub const DECIMAL_PLACES: usize = 4;
+
+/// how amounts are rounded to `DECIMAL_PLACES` when they are combined or emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingPolicy {
+    /// round half to even (banker's rounding). ex: 1.00005 -> 1.0000, 1.00015 -> 1.0002
+    #[default]
+    HalfEven,
+    /// round half away from zero. ex: 1.00005 -> 1.0001
+    HalfUp,
+    /// drop the extra digits. ex: 1.00009 -> 1.0000
+    Truncate,
+}
+
+impl RoundingPolicy {
+    /// rounds based on the shortest decimal representation of `value` (what gets printed),
+    /// so 1.00005 is a tie even though the nearest f64 is slightly below it.
+    /// values too large for an `Amount` are returned unchanged
+    pub fn round(&self, value: f64) -> f64 {
+        Amount::from_f64(value, *self).map_or(value, |amount| amount.to_f64())
+    }
+
+    /// whether a magnitude whose digits past `DECIMAL_PLACES` are `dropped` rounds up (away from zero).
+    /// `odd` is whether the last kept digit is odd
+    pub(crate) fn rounds_up(&self, odd: bool, dropped: &str) -> bool {
+        let first_dropped = dropped.bytes().next().map_or(0, |b| b - b'0');
+        let rest_nonzero = dropped.bytes().skip(1).any(|b| b != b'0');
+        match self {
+            RoundingPolicy::Truncate => false,
+            RoundingPolicy::HalfUp => first_dropped >= 5,
+            RoundingPolicy::HalfEven => {
+                first_dropped > 5 || (first_dropped == 5 && (rest_nonzero || odd))
+            }
+        }
+    }
+
+    /// `numerator / denominator` rounded to a whole number. `denominator` is positive
+    pub(crate) fn round_div(&self, numerator: i128, denominator: i128) -> i128 {
+        let (quotient, remainder) = (numerator.abs() / denominator, numerator.abs() % denominator);
+        let up = match self {
+            RoundingPolicy::Truncate => false,
+            RoundingPolicy::HalfUp => 2 * remainder >= denominator,
+            RoundingPolicy::HalfEven => {
+                2 * remainder > denominator || (2 * remainder == denominator && quotient % 2 == 1)
+            }
+        };
+        let rounded = quotient + i128::from(up);
+        if numerator < 0 {
+            -rounded
+        } else {
+            rounded
+        }
+    }
+}
+
+impl FromStr for RoundingPolicy {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "half-even" => RoundingPolicy::HalfEven,
+            "half-up" => RoundingPolicy::HalfUp,
+            "truncate" => RoundingPolicy::Truncate,
+            _ => return Err(MyError::Conversion(s.to_string())),
+        };
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for RoundingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            RoundingPolicy::HalfEven => "half-even",
+            RoundingPolicy::HalfUp => "half-up",
+            RoundingPolicy::Truncate => "truncate",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_half_even() {
+        let policy = RoundingPolicy::HalfEven;
+        assert_eq!(policy.round(1.00005), 1.0);
+        assert_eq!(policy.round(1.00015), 1.0002);
+        assert_eq!(policy.round(1.00025), 1.0002);
+        assert_eq!(policy.round(1.000251), 1.0003);
+        assert_eq!(policy.round(1.99995), 2.0);
+        assert_eq!(policy.round(-1.00015), -1.0002);
+        assert_eq!(policy.round(0.00005), 0.0);
+        assert_eq!(policy.round(0.1 + 0.2), 0.3);
+    }
+
+    #[test]
+    fn test_half_up() {
+        let policy = RoundingPolicy::HalfUp;
+        assert_eq!(policy.round(1.00005), 1.0001);
+        assert_eq!(policy.round(1.00025), 1.0003);
+        assert_eq!(policy.round(1.000049), 1.0);
+        assert_eq!(policy.round(-1.00005), -1.0001);
+        assert_eq!(policy.round(0.00005), 0.0001);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let policy = RoundingPolicy::Truncate;
+        assert_eq!(policy.round(1.00009), 1.0);
+        assert_eq!(policy.round(-1.99999), -1.9999);
+        assert_eq!(policy.round(2.5), 2.5);
+    }
+
+    #[test]
+    fn test_unchanged() {
+        for policy in [
+            RoundingPolicy::HalfEven,
+            RoundingPolicy::HalfUp,
+            RoundingPolicy::Truncate,
+        ] {
+            assert_eq!(policy.round(1.2345), 1.2345);
+            assert_eq!(policy.round(100.0), 100.0);
+            assert_eq!(policy.round(1e20), 1e20);
+            assert!(policy.round(f64::NAN).is_nan());
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        for policy in [
+            RoundingPolicy::HalfEven,
+            RoundingPolicy::HalfUp,
+            RoundingPolicy::Truncate,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<RoundingPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("nearest".parse::<RoundingPolicy>().is_err());
+    }
+}
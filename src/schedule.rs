@@ -0,0 +1,438 @@
+//! fee and interest rates with effective dates. a schedule is a list of periods, each with the rates that were in
+//! force from one date until the next, so reprocessing an old file can use the rates of its time rather than today's.
+//! periods can't overlap; a date outside every period has no fees and no interest
+use crate::{amount::Amount, errors::*, fmt_error, rounding::RoundingPolicy};
+use error_stack::report;
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// a calendar day (UTC), stored as the number of days since 1970-01-01. parses and displays as `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(i64);
+
+impl Date {
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        // days from civil (proleptic gregorian), with the year starting in March so the leap day comes last
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = if y >= 0 { y } else { y - 399 } / 400;
+        let yoe = y - era * 400;
+        let mp = (month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let date = Date(era * 146_097 + doe - 719_468);
+        // rejects days past the end of the month, ex: 2023-02-29
+        (date.ymd() == (year, month, day)).then_some(date)
+    }
+
+    pub fn from_days_since_epoch(days: i64) -> Self {
+        Date(days)
+    }
+
+    /// the day of a unix timestamp in seconds
+    pub fn from_unix(seconds: i64) -> Self {
+        Date(seconds.div_euclid(86_400))
+    }
+
+    /// the current day (UTC)
+    pub fn today() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        Date::from_unix(seconds)
+    }
+
+    pub fn days_since_epoch(&self) -> i64 {
+        self.0
+    }
+
+    /// the next day
+    pub fn succ(&self) -> Self {
+        Date(self.0 + 1)
+    }
+
+    pub fn ymd(&self) -> (i64, u32, u32) {
+        let z = self.0 + 719_468;
+        let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MyError::Conversion(s.to_string());
+        let mut parts = s.trim().splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(err);
+        let (year, month, day) = (next()?, next()?, next()?);
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(err());
+        }
+        let (year, month, day) = match (year.parse(), month.parse(), day.parse()) {
+            (Ok(year), Ok(month), Ok(day)) => (year, month, day),
+            _ => return Err(err()),
+        };
+        Date::from_ymd(year, month, day).ok_or_else(err)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// an exact fraction, ex: a fee percentage or a yearly interest rate, as a whole number of billionths
+/// (`Rate::PLACES` decimal places). parses and displays as a decimal: 0.025 is 2.5%. a rate times an amount is
+/// computed in integers and rounded once, so it doesn't pick up the errors of f64
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(i64);
+
+impl Rate {
+    pub const ZERO: Rate = Rate(0);
+    pub const PLACES: usize = 9;
+    /// billionths per unit
+    pub const SCALE: i64 = 10i64.pow(Self::PLACES as u32);
+
+    pub const fn from_billionths(billionths: i64) -> Self {
+        Rate(billionths)
+    }
+
+    pub const fn billionths(&self) -> i64 {
+        self.0
+    }
+
+    /// `value` based on its shortest decimal representation (what gets printed), ex: from a config file. None for
+    /// NaN, infinities, values that don't fit, and digits past `PLACES`
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        // f64's Display never uses an exponent
+        value.to_string().parse().ok()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// `amount` times the rate, divided by `divisor` (positive, ex: the days of a year for a daily rate), rounded
+    /// once with `policy`. None if the result doesn't fit an `Amount`
+    pub fn apply(&self, amount: Amount, divisor: i64, policy: RoundingPolicy) -> Option<Amount> {
+        let product = i128::from(amount.minor_units()) * i128::from(self.0);
+        let units = policy.round_div(product, i128::from(Self::SCALE) * i128::from(divisor));
+        i64::try_from(units).ok().map(Amount::from_minor_units)
+    }
+}
+
+/// exact: fails on digits past `Rate::PLACES` instead of rounding them
+impl FromStr for Rate {
+    type Err = MyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MyError::Conversion(s.to_string());
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(err());
+        }
+        let frac = frac.trim_end_matches('0');
+        if frac.len() > Self::PLACES || int.trim_start_matches('0').len() > 18 {
+            return Err(err());
+        }
+        let int: i128 = if int.is_empty() {
+            0
+        } else {
+            int.parse().map_err(|_| err())?
+        };
+        let frac: i128 = format!("{:0<width$}", frac, width = Self::PLACES)
+            .parse()
+            .map_err(|_| err())?;
+        let billionths = int * i128::from(Self::SCALE) + frac;
+        let billionths = if negative { -billionths } else { billionths };
+        i64::try_from(billionths).map(Rate).map_err(|_| err())
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = Self::SCALE as u64;
+        let (int, frac) = (abs / scale, abs % scale);
+        if frac == 0 {
+            return write!(f, "{}{}", sign, int);
+        }
+        let frac = format!("{:0width$}", frac, width = Self::PLACES);
+        write!(f, "{}{}.{}", sign, int, frac.trim_end_matches('0'))
+    }
+}
+
+/// the rates in force on a given day. all zero outside the schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rates {
+    /// charged per deposit or withdrawal
+    pub flat_fee: Amount,
+    /// a fraction of the amount, charged per deposit or withdrawal. 0.01 is 1%
+    pub percent_fee: Rate,
+    /// yearly interest on the available funds. 0.02 is 2%
+    pub interest_rate: Rate,
+}
+
+impl Rates {
+    /// the fee of a deposit or withdrawal of `amount` (positive): the flat fee plus the percentage, rounded once
+    /// with `policy`. None if the fee doesn't fit an `Amount`
+    pub fn fee(&self, amount: Amount, policy: RoundingPolicy) -> Option<Amount> {
+        self.percent_fee
+            .apply(amount, 1, policy)?
+            .checked_add(self.flat_fee)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RatePeriod {
+    pub from: Date,
+    /// the first day the rates no longer apply. None if they apply until further notice
+    pub until: Option<Date>,
+    pub rates: Rates,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RateSchedule {
+    // sorted by start date, without overlaps
+    periods: Vec<RatePeriod>,
+}
+
+impl RateSchedule {
+    /// fails if a period ends before it starts, if two periods overlap, or if a rate is negative
+    pub fn new(mut periods: Vec<RatePeriod>) -> error_stack::Result<Self, MyError> {
+        periods.sort_by_key(|p| p.from);
+        for period in &periods {
+            if period.until.is_some_and(|until| until <= period.from) {
+                return Err(report!(MyError::Config).attach_printable(fmt_error!(
+                    "the rate period starting {} ends before it starts",
+                    period.from
+                )));
+            }
+            let rates = period.rates;
+            if rates.flat_fee.is_negative()
+                || rates.percent_fee.is_negative()
+                || rates.interest_rate.is_negative()
+            {
+                return Err(report!(MyError::Config).attach_printable(fmt_error!(
+                    "the rate period starting {} has a negative rate",
+                    period.from
+                )));
+            }
+        }
+        for pair in periods.windows(2) {
+            if pair[0].until.is_none_or(|until| until > pair[1].from) {
+                return Err(report!(MyError::Config).attach_printable(fmt_error!(
+                    "the rate periods starting {} and {} overlap",
+                    pair[0].from,
+                    pair[1].from
+                )));
+            }
+        }
+        Ok(RateSchedule { periods })
+    }
+
+    /// the same rates on every day
+    pub fn constant(rates: Rates) -> error_stack::Result<Self, MyError> {
+        RateSchedule::new(vec![RatePeriod {
+            from: Date::from_unix(0),
+            until: None,
+            rates,
+        }])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.periods.is_empty()
+    }
+
+    pub fn periods(&self) -> &[RatePeriod] {
+        &self.periods
+    }
+
+    /// the rates in force on `date`
+    pub fn rates_at(&self, date: Date) -> Rates {
+        let idx = self.periods.partition_point(|p| p.from <= date);
+        match idx.checked_sub(1).map(|idx| &self.periods[idx]) {
+            Some(period) if period.until.is_none_or(|until| date < until) => period.rates,
+            _ => Rates::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+
+    fn date(s: &str) -> Date {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(date("1970-01-01").days_since_epoch(), 0);
+        assert_eq!(date("2000-03-01").days_since_epoch(), 11_017);
+        assert_eq!(date("2024-02-29").succ(), date("2024-03-01"));
+        assert_eq!(date("1969-12-31").to_string(), "1969-12-31");
+        assert_eq!(Date::from_unix(1_700_000_000), date("2023-11-14"));
+        assert_eq!(Date::from_unix(-1), date("1969-12-31"));
+        for s in [
+            "2023-02-29",
+            "2023-13-01",
+            "2023-1-01",
+            "2023-01-01T00:00",
+            "",
+        ] {
+            assert!(s.parse::<Date>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_rates_at() {
+        let period = |from: &str, until: Option<&str>, flat_fee: f64| RatePeriod {
+            from: date(from),
+            until: until.map(date),
+            rates: Rates {
+                flat_fee: amt(flat_fee),
+                ..Default::default()
+            },
+        };
+        let schedule = RateSchedule::new(vec![
+            period("2024-07-01", None, 2.0),
+            period("2024-01-01", Some("2024-04-01"), 1.0),
+        ])
+        .unwrap();
+        assert_eq!(schedule.rates_at(date("2023-12-31")).flat_fee, amt(0.0));
+        assert_eq!(schedule.rates_at(date("2024-01-01")).flat_fee, amt(1.0));
+        assert_eq!(schedule.rates_at(date("2024-04-01")).flat_fee, amt(0.0));
+        assert_eq!(schedule.rates_at(date("2031-01-01")).flat_fee, amt(2.0));
+
+        assert!(RateSchedule::new(vec![
+            period("2024-01-01", None, 1.0),
+            period("2024-07-01", None, 2.0)
+        ])
+        .is_err());
+        assert!(RateSchedule::new(vec![period("2024-01-01", Some("2024-01-01"), 1.0)]).is_err());
+        assert!(RateSchedule::new(vec![period("2024-01-01", None, -1.0)]).is_err());
+    }
+
+    #[test]
+    fn test_rate() {
+        for (s, billionths, shown) in [
+            ("0.025", 25_000_000, "0.025"),
+            ("1", 1_000_000_000, "1"),
+            ("+.5", 500_000_000, "0.5"),
+            ("0.000000001", 1, "0.000000001"),
+            ("0.0100000000", 10_000_000, "0.01"),
+            ("-0.5", -500_000_000, "-0.5"),
+        ] {
+            let rate: Rate = s.parse().unwrap();
+            assert_eq!(rate, Rate::from_billionths(billionths), "{}", s);
+            assert_eq!(rate.to_string(), shown);
+        }
+        for s in ["0.0000000001", "", ".", "1e-3", "abc", "10000000000"] {
+            assert!(s.parse::<Rate>().is_err(), "{}", s);
+        }
+        assert_eq!(Rate::from_f64(0.015), "0.015".parse().ok());
+        assert_eq!(Rate::from_f64(1e-10), None);
+        assert_eq!(Rate::from_f64(f64::NAN), None);
+    }
+
+    #[test]
+    fn test_fee() {
+        let rates = Rates {
+            flat_fee: amt(0.25),
+            percent_fee: "0.001".parse().unwrap(),
+            ..Default::default()
+        };
+        let fee = |rates: Rates, amount: &str, policy: RoundingPolicy| {
+            rates
+                .fee(amount.parse().unwrap(), policy)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(fee(rates, "100", RoundingPolicy::HalfEven), "0.35");
+        assert_eq!(fee(rates, "0.5", RoundingPolicy::HalfEven), "0.2505");
+        // 0.000025 rounds to even
+        assert_eq!(fee(rates, "0.025", RoundingPolicy::HalfEven), "0.25");
+        assert_eq!(
+            Rates::default().fee(Amount::from_minor_units(10), RoundingPolicy::HalfEven),
+            Some(Amount::ZERO)
+        );
+        assert_eq!(
+            RateSchedule::constant(rates)
+                .unwrap()
+                .rates_at(Date::today())
+                .flat_fee,
+            amt(0.25)
+        );
+
+        // the half-way cases f64 rounded the wrong way: 0.05 * 0.025 = 0.00125 and 0.07 * 0.015 = 0.00105
+        let percent = |rate: &str| Rates {
+            percent_fee: rate.parse().unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fee(percent("0.025"), "0.05", RoundingPolicy::HalfEven),
+            "0.0012"
+        );
+        assert_eq!(
+            fee(percent("0.025"), "0.05", RoundingPolicy::HalfUp),
+            "0.0013"
+        );
+        assert_eq!(
+            fee(percent("0.015"), "0.07", RoundingPolicy::HalfEven),
+            "0.001"
+        );
+        assert_eq!(
+            fee(percent("0.015"), "0.07", RoundingPolicy::HalfUp),
+            "0.0011"
+        );
+        assert_eq!(
+            fee(percent("0.015"), "0.07", RoundingPolicy::Truncate),
+            "0.001"
+        );
+
+        // a fee too large for an Amount
+        let rates = Rates {
+            percent_fee: "1000".parse().unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            rates.fee(
+                Amount::from_minor_units(i64::MAX / 10),
+                RoundingPolicy::HalfEven
+            ),
+            None
+        );
+        let rates = Rates {
+            flat_fee: Amount::from_minor_units(i64::MAX),
+            percent_fee: "0.5".parse().unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(rates.fee(amt(1.0), RoundingPolicy::HalfEven), None);
+    }
+}
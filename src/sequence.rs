@@ -0,0 +1,116 @@
+use crate::model::TransactionId;
+use std::{collections::BTreeSet, fmt};
+
+/// a run of missing transaction ids, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub first: TransactionId,
+    pub last: TransactionId,
+}
+
+impl SequenceGap {
+    /// the number of missing ids
+    // TransactionId is already a u64 with the "wide-ids" feature
+    #[allow(clippy::useless_conversion)]
+    pub fn missing(&self) -> u64 {
+        u64::from(self.last - self.first) + 1
+    }
+}
+
+impl fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// records the transaction ids of deposits and withdrawals and reports the ids missing between the
+/// smallest and largest one seen. a gap usually means an upstream export dropped rows.
+/// ids don't need to arrive in order
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    seen: BTreeSet<TransactionId>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, txn_id: TransactionId) {
+        self.seen.insert(txn_id);
+    }
+
+    /// the gaps in ascending order
+    pub fn gaps(&self) -> Vec<SequenceGap> {
+        let mut gaps = Vec::new();
+        let mut prev: Option<TransactionId> = None;
+        for &txn_id in &self.seen {
+            if let Some(prev) = prev {
+                if txn_id > prev + 1 {
+                    gaps.push(SequenceGap {
+                        first: prev + 1,
+                        last: txn_id - 1,
+                    });
+                }
+            }
+            prev = Some(txn_id);
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gaps() {
+        let mut tracker = SequenceTracker::new();
+        for txn_id in [100, 101, 105, 103, 107, 108, 101] {
+            tracker.record(txn_id);
+        }
+        let gaps = tracker.gaps();
+        assert_eq!(
+            gaps,
+            vec![
+                SequenceGap {
+                    first: 102,
+                    last: 102
+                },
+                SequenceGap {
+                    first: 104,
+                    last: 104
+                },
+                SequenceGap {
+                    first: 106,
+                    last: 106
+                },
+            ]
+        );
+        assert_eq!(gaps[0].to_string(), "102");
+    }
+
+    #[test]
+    fn test_no_gaps() {
+        let mut tracker = SequenceTracker::new();
+        assert!(tracker.gaps().is_empty());
+        tracker.record(TransactionId::MAX);
+        tracker.record(TransactionId::MAX - 1);
+        assert!(tracker.gaps().is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tracker = SequenceTracker::new();
+        tracker.record(1);
+        tracker.record(10);
+        let gaps = tracker.gaps();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].missing(), 8);
+        assert_eq!(gaps[0].to_string(), "2-9");
+    }
+}
@@ -0,0 +1,226 @@
+//! HTTP ingestion. `POST /transactions` takes one transaction as JSON, with the names of the CSV columns
+//! (ex: `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}`), applies it to the shared processor, and answers
+//! with the outcome: `{"outcome": "applied"}` or `{"outcome": "rejected", "reason": "InsufficientFunds"}`. a rejection
+//! is a business outcome, not an HTTP error, so both are 200. `POST /transactions/batch` takes an array of them, applies
+//! them in one store transaction, and answers with their outcomes in order: `{"outcomes": [{"outcome": "applied"}, ...]}`.
+//! a store failure applies none of them. `GET /clients` and `GET /clients/{client}` return the
+//! accounts. a store failure is a 500 with the error report as JSON (see `report_to_json`). `GET /metrics` serves the
+//! Prometheus metrics of the processed transactions (see `Metrics`)
+use crate::{
+    async_processor::AsyncTransactionProcessor, errors::*, events::EngineEvent, fmt_error,
+    metrics::Metrics, model::*, report::client_json,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use error_stack::{IntoReport, Report, Result, ResultExt};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, time::Instant};
+
+type Response = (StatusCode, Json<Value>);
+
+#[derive(Clone)]
+struct AppState {
+    processor: AsyncTransactionProcessor,
+    metrics: Metrics,
+}
+
+/// the routes, sharing `processor`. the metrics count the transactions posted to this router
+pub fn router(processor: AsyncTransactionProcessor, metrics: Metrics) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transaction))
+        .route("/transactions/batch", post(post_batch))
+        .route("/clients", get(get_clients))
+        .route("/clients/{client}", get(get_client))
+        .route("/metrics", get(get_metrics))
+        .with_state(AppState { processor, metrics })
+}
+
+/// serve the routes on `addr` until the process is stopped
+pub async fn serve(addr: SocketAddr, processor: AsyncTransactionProcessor) -> Result<(), MyError> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to listen on {}", addr))
+        .change_context(MyError::Output)?;
+    tracing::info!(%addr, "listening");
+    axum::serve(listener, router(processor, Metrics::new()?))
+        .await
+        .report()
+        .attach_printable_lazy(|| fmt_error!("the server stopped"))
+        .change_context(MyError::Output)
+}
+
+async fn post_transaction(State(state): State<AppState>, Json(txn): Json<RawTxnInput>) -> Response {
+    let txn_type = txn.txn_type.clone();
+    let start = Instant::now();
+    let result = state.processor.process_async(txn).await;
+    state.metrics.observe(
+        txn_type,
+        result.as_ref().ok().map(Vec::as_slice),
+        start.elapsed(),
+    );
+    match result {
+        Ok(events) => (StatusCode::OK, Json(outcome_json(&events))),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn post_batch(State(state): State<AppState>, Json(txns): Json<Vec<RawTxnInput>>) -> Response {
+    let txn_types: Vec<TxnType> = txns.iter().map(|txn| txn.txn_type.clone()).collect();
+    let start = Instant::now();
+    let result = state.processor.process_batch_async(txns).await;
+    // the latency of the batch is shared by its transactions
+    let latency = start.elapsed() / txn_types.len().max(1) as u32;
+    match result {
+        Ok(batch) => {
+            for (txn_type, events) in txn_types.into_iter().zip(&batch.outcomes) {
+                state.metrics.observe(txn_type, Some(events), latency);
+            }
+            let outcomes: Vec<Value> = batch.outcomes.iter().map(|e| outcome_json(e)).collect();
+            (StatusCode::OK, Json(json!({ "outcomes": outcomes })))
+        }
+        Err(e) => {
+            for txn_type in txn_types {
+                state.metrics.observe(txn_type, None, latency);
+            }
+            internal_error(e)
+        }
+    }
+}
+
+// the outcome of one transaction
+fn outcome_json(events: &[EngineEvent]) -> Value {
+    match events.first() {
+        Some(EngineEvent::TransactionRejected { reason, .. }) => {
+            json!({"outcome": "rejected", "reason": format!("{:?}", reason)})
+        }
+        _ => json!({"outcome": "applied"}),
+    }
+}
+
+async fn get_clients(State(state): State<AppState>) -> Response {
+    match state.processor.client_states_async().await {
+        Ok(states) => (
+            StatusCode::OK,
+            Json(Value::Array(states.iter().map(client_json).collect())),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn get_client(State(state): State<AppState>, Path(client_id): Path<ClientId>) -> Response {
+    match state.processor.client_state_async(client_id).await {
+        Ok(Some(state)) => (StatusCode::OK, Json(client_json(&state))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("client {} not found", client_id)})),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn get_metrics(State(state): State<AppState>) -> axum::response::Response {
+    match state.metrics.encode() {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+fn internal_error(e: Report<MyError>) -> Response {
+    let body = report_to_json(&e);
+    print_report(e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(body))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction_processor::TransactionProcessor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // one HTTP/1.1 request on its own connection. returns the status code and the body
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serve() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let processor = AsyncTransactionProcessor::new(TransactionProcessor::in_memory());
+        let metrics = Metrics::new().unwrap();
+        let app = router(processor, metrics.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5}"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", deposit).await,
+            (200, json!({"outcome": "applied"}))
+        );
+        let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": 9.0}"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", withdrawal).await,
+            (
+                200,
+                json!({"outcome": "rejected", "reason": "InsufficientFunds"})
+            )
+        );
+        let (status, _) = request(addr, "POST", "/transactions", "{").await;
+        assert_eq!(status, 400);
+        let batch = format!(
+            "[{}, {}]",
+            withdrawal,
+            deposit.replace("\"tx\": 1", "\"tx\": 3")
+        );
+        assert_eq!(
+            request(addr, "POST", "/transactions/batch", &batch).await,
+            (
+                200,
+                json!({"outcomes": [
+                    {"outcome": "rejected", "reason": "InsufficientFunds"},
+                    {"outcome": "applied"}
+                ]})
+            )
+        );
+
+        let account =
+            json!({"client": 1, "available": 5.0, "held": 0.0, "total": 5.0, "locked": false});
+        assert_eq!(
+            request(addr, "GET", "/clients/1", "").await,
+            (200, account.clone())
+        );
+        assert_eq!(
+            request(addr, "GET", "/clients", "").await,
+            (200, json!([account]))
+        );
+        assert_eq!(request(addr, "GET", "/clients/2", "").await.0, 404);
+
+        // the malformed body never reached the processor
+        assert_eq!(request(addr, "GET", "/metrics", "").await.0, 200);
+        let text = metrics.encode().unwrap();
+        assert!(text
+            .contains(r#"payments_engine_transactions_total{outcome="applied",type="deposit"} 2"#));
+        assert!(text.contains(r#"payments_engine_rejections_total{reason="InsufficientFunds"} 2"#));
+        assert!(text.contains(
+            r#"payments_engine_transaction_duration_seconds_count{type="withdrawal"} 2"#
+        ));
+    }
+}
@@ -0,0 +1,322 @@
+//! signed run manifests (feature "signing"), so downstream consumers can check that a results file came from an
+//! untampered engine run. the manifest records the sha256 of the input and of the results and is signed with
+//! HMAC-SHA256 (a shared secret) or Ed25519 (a key pair)
+use crate::{errors::*, fmt_error};
+use ed25519_dalek::{Signer, Verifier};
+use error_stack::{bail, report, IntoReport, Result, ResultExt};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureAlgorithm {
+    HmacSha256,
+    Ed25519,
+}
+
+impl FromStr for SignatureAlgorithm {
+    type Err = MyError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "hmac-sha256" => Ok(SignatureAlgorithm::HmacSha256),
+            "ed25519" => Ok(SignatureAlgorithm::Ed25519),
+            _ => Err(MyError::Conversion(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SignatureAlgorithm::HmacSha256 => "hmac-sha256",
+            SignatureAlgorithm::Ed25519 => "ed25519",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+pub enum SigningKey {
+    Hmac(Vec<u8>),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SigningKey {
+    /// HMAC keys are used as is. Ed25519 keys are a hex encoded 32 byte secret key
+    pub fn from_key_file(algorithm: SignatureAlgorithm, contents: &[u8]) -> Result<Self, MyError> {
+        match algorithm {
+            SignatureAlgorithm::HmacSha256 => Ok(SigningKey::Hmac(contents.to_vec())),
+            SignatureAlgorithm::Ed25519 => Ok(SigningKey::Ed25519(
+                ed25519_dalek::SigningKey::from_bytes(&decode_key(contents)?),
+            )),
+        }
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            SigningKey::Hmac(_) => SignatureAlgorithm::HmacSha256,
+            SigningKey::Ed25519(_) => SignatureAlgorithm::Ed25519,
+        }
+    }
+
+    /// the key that checks this key's signatures
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            SigningKey::Hmac(key) => VerifyingKey::Hmac(key.clone()),
+            SigningKey::Ed25519(key) => VerifyingKey::Ed25519(key.verifying_key()),
+        }
+    }
+
+    /// hex encoded
+    pub fn sign(&self, message: &[u8]) -> String {
+        match self {
+            SigningKey::Hmac(key) => {
+                let mut mac = hmac(key);
+                mac.update(message);
+                encode_hex(&mac.finalize().into_bytes())
+            }
+            SigningKey::Ed25519(key) => encode_hex(&key.sign(message).to_bytes()),
+        }
+    }
+}
+
+pub enum VerifyingKey {
+    Hmac(Vec<u8>),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl VerifyingKey {
+    /// HMAC keys are the shared secret. Ed25519 keys are a hex encoded 32 byte public key
+    pub fn from_key_file(algorithm: SignatureAlgorithm, contents: &[u8]) -> Result<Self, MyError> {
+        match algorithm {
+            SignatureAlgorithm::HmacSha256 => Ok(VerifyingKey::Hmac(contents.to_vec())),
+            SignatureAlgorithm::Ed25519 => {
+                let key = ed25519_dalek::VerifyingKey::from_bytes(&decode_key(contents)?)
+                    .report()
+                    .attach_printable_lazy(|| fmt_error!("invalid Ed25519 public key"))
+                    .change_context(MyError::Signature)?;
+                Ok(VerifyingKey::Ed25519(key))
+            }
+        }
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &str) -> Result<(), MyError> {
+        let signature = match decode_hex(signature) {
+            Some(s) => s,
+            None => bail!(MyError::Signature),
+        };
+        let valid = match self {
+            VerifyingKey::Hmac(key) => {
+                let mut mac = hmac(key);
+                mac.update(message);
+                mac.verify_slice(&signature).is_ok()
+            }
+            VerifyingKey::Ed25519(key) => match ed25519_dalek::Signature::from_slice(&signature) {
+                Ok(signature) => key.verify(message, &signature).is_ok(),
+                Err(_) => false,
+            },
+        };
+        if !valid {
+            bail!(MyError::Signature);
+        }
+        Ok(())
+    }
+}
+
+/// describes one engine run. `signature` covers every other field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub engine_version: String,
+    pub input: String,
+    pub input_sha256: String,
+    pub results_sha256: String,
+    pub algorithm: Option<SignatureAlgorithm>,
+    pub signature: Option<String>,
+}
+
+impl RunManifest {
+    pub fn new(input: &str, input_contents: &[u8], results: &[u8]) -> Self {
+        RunManifest {
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            input: input.to_string(),
+            input_sha256: sha256_hex(input_contents),
+            results_sha256: sha256_hex(results),
+            algorithm: None,
+            signature: None,
+        }
+    }
+
+    // the manifest without its signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = RunManifest {
+            signature: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.algorithm = Some(key.algorithm());
+        self.signature = Some(key.sign(&self.signed_bytes()));
+    }
+
+    /// check that `results` are the results this manifest describes and that the manifest was signed by `key`
+    pub fn verify(&self, results: &[u8], key: &VerifyingKey) -> Result<(), MyError> {
+        if sha256_hex(results) != self.results_sha256 {
+            return invalid(fmt_error!("the results don't match the manifest"));
+        }
+        let signature = match &self.signature {
+            Some(s) => s,
+            None => return invalid(fmt_error!("the manifest isn't signed")),
+        };
+        self.verify_signature(signature, key)
+    }
+
+    fn verify_signature(&self, signature: &str, key: &VerifyingKey) -> Result<(), MyError> {
+        let algorithm = match key {
+            VerifyingKey::Hmac(_) => SignatureAlgorithm::HmacSha256,
+            VerifyingKey::Ed25519(_) => SignatureAlgorithm::Ed25519,
+        };
+        if self.algorithm != Some(algorithm) {
+            return invalid(fmt_error!(
+                "the manifest was signed with {:?}, not {}",
+                self.algorithm,
+                algorithm
+            ));
+        }
+        key.verify(&self.signed_bytes(), signature)
+            .attach_printable_lazy(|| fmt_error!("the manifest signature is invalid"))
+    }
+}
+
+fn invalid<T>(msg: String) -> Result<T, MyError> {
+    Err(report!(MyError::Signature).attach_printable(msg))
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    encode_hex(&Sha256::digest(bytes))
+}
+
+/// like sha256_hex, without reading the whole input into memory
+pub fn sha256_hex_reader<R: std::io::Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(encode_hex(&hasher.finalize())),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length
+    <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length")
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.trim()
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => u8::from_str_radix(std::str::from_utf8(&[*hi, *lo]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn decode_key(contents: &[u8]) -> Result<[u8; 32], MyError> {
+    let key = String::from_utf8_lossy(contents);
+    match decode_hex(&key).and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) {
+        Some(key) => Ok(key),
+        None => invalid(fmt_error!("expected a hex encoded 32 byte key")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ED25519_SECRET: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    fn manifest() -> RunManifest {
+        RunManifest::new(
+            "input.csv",
+            b"type,client,tx,amount\n",
+            b"client,available\n",
+        )
+    }
+
+    #[test]
+    fn test_hmac() {
+        let key = SigningKey::from_key_file(SignatureAlgorithm::HmacSha256, b"secret").unwrap();
+        let mut manifest = manifest();
+        manifest.sign(&key);
+        assert_eq!(manifest.algorithm, Some(SignatureAlgorithm::HmacSha256));
+        manifest
+            .verify(b"client,available\n", &key.verifying_key())
+            .unwrap();
+
+        let wrong = VerifyingKey::from_key_file(SignatureAlgorithm::HmacSha256, b"guess").unwrap();
+        assert!(manifest.verify(b"client,available\n", &wrong).is_err());
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let key = SigningKey::from_key_file(SignatureAlgorithm::Ed25519, ED25519_SECRET.as_bytes())
+            .unwrap();
+        let mut manifest = manifest();
+        manifest.sign(&key);
+
+        // consumers only need the public key
+        let public = match key.verifying_key() {
+            VerifyingKey::Ed25519(k) => encode_hex(k.as_bytes()),
+            VerifyingKey::Hmac(_) => unreachable!(),
+        };
+        let public =
+            VerifyingKey::from_key_file(SignatureAlgorithm::Ed25519, public.as_bytes()).unwrap();
+        manifest.verify(b"client,available\n", &public).unwrap();
+
+        // an HMAC key can't verify an Ed25519 signature
+        let hmac = VerifyingKey::from_key_file(SignatureAlgorithm::HmacSha256, b"secret").unwrap();
+        assert!(manifest.verify(b"client,available\n", &hmac).is_err());
+        assert!(SigningKey::from_key_file(SignatureAlgorithm::Ed25519, b"abcd").is_err());
+    }
+
+    #[test]
+    fn test_tampering() {
+        let key = SigningKey::from_key_file(SignatureAlgorithm::HmacSha256, b"secret").unwrap();
+        let mut manifest = manifest();
+        manifest.sign(&key);
+        let key = key.verifying_key();
+
+        // modified results
+        assert!(manifest.verify(b"client,available\n1,100\n", &key).is_err());
+
+        // modified manifest
+        let mut modified = manifest.clone();
+        modified.input = "other.csv".to_string();
+        assert!(modified.verify(b"client,available\n", &key).is_err());
+
+        // unsigned manifest
+        let mut unsigned = manifest.clone();
+        unsigned.signature = None;
+        assert!(unsigned.verify(b"client,available\n", &key).is_err());
+    }
+
+    #[test]
+    fn test_algorithm_from_str() {
+        for algorithm in [SignatureAlgorithm::HmacSha256, SignatureAlgorithm::Ed25519] {
+            assert_eq!(
+                algorithm.to_string().parse::<SignatureAlgorithm>().unwrap(),
+                algorithm
+            );
+        }
+        assert!("rsa".parse::<SignatureAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! periodic snapshots of the client accounts. when a long run produces a wrong final balance, the numbered snapshots
+//! narrow down where it went wrong ("the balances were still right at snapshot 12") without replaying the whole input
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+    every: u64,
+    // the number of transactions processed since snapshots were enabled
+    seen: u64,
+}
+
+impl Snapshots {
+    /// a snapshot every `every` transactions (applied or rejected), written to `dir`
+    pub fn new(dir: &Path, every: u64) -> Self {
+        Snapshots {
+            dir: dir.to_path_buf(),
+            every: every.max(1),
+            seen: 0,
+        }
+    }
+
+    /// count a processed transaction. returns the file the next snapshot goes to if one is due
+    pub fn observe(&mut self) -> Option<PathBuf> {
+        self.seen += 1;
+        self.seen
+            .is_multiple_of(self.every)
+            .then(|| self.path(self.seen / self.every))
+    }
+
+    /// the file of snapshot `number`, ex: `snapshot-000012.csv`. zero padded so the files sort in order
+    pub fn path(&self, number: u64) -> PathBuf {
+        snapshot_path(&self.dir, number)
+    }
+
+    /// the number of transactions that were processed when snapshot `number` was taken
+    pub fn transactions_at(&self, number: u64) -> u64 {
+        number * self.every
+    }
+}
+
+/// snapshots taken on a timer instead of a transaction count, for consumers of unbounded streams
+#[derive(Debug)]
+pub struct PeriodicSnapshots {
+    dir: PathBuf,
+    interval: Duration,
+    last: Instant,
+    taken: u64,
+}
+
+impl PeriodicSnapshots {
+    /// a snapshot every `interval`, written to `dir`. the first one is due one interval from now
+    pub fn new(dir: &Path, interval: Duration) -> Self {
+        PeriodicSnapshots {
+            dir: dir.to_path_buf(),
+            interval,
+            last: Instant::now(),
+            taken: 0,
+        }
+    }
+
+    /// the file the next snapshot goes to if one is due. restarts the interval
+    pub fn due(&mut self) -> Option<PathBuf> {
+        if self.last.elapsed() < self.interval {
+            return None;
+        }
+        self.last = Instant::now();
+        self.taken += 1;
+        Some(snapshot_path(&self.dir, self.taken))
+    }
+}
+
+fn snapshot_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("snapshot-{:06}.csv", number))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let mut snapshots = Snapshots::new(Path::new("out"), 2);
+        assert_eq!(snapshots.observe(), None);
+        assert_eq!(
+            snapshots.observe(),
+            Some(PathBuf::from("out/snapshot-000001.csv"))
+        );
+        assert_eq!(snapshots.observe(), None);
+        assert_eq!(snapshots.observe(), Some(snapshots.path(2)));
+        assert_eq!(snapshots.transactions_at(2), 4);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let mut snapshots = PeriodicSnapshots::new(Path::new("out"), Duration::from_secs(3600));
+        assert_eq!(snapshots.due(), None);
+        let mut snapshots = PeriodicSnapshots::new(Path::new("out"), Duration::ZERO);
+        assert_eq!(
+            snapshots.due(),
+            Some(PathBuf::from("out/snapshot-000001.csv"))
+        );
+        assert_eq!(
+            snapshots.due(),
+            Some(PathBuf::from("out/snapshot-000002.csv"))
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! per-client account statements, built from the postings journal: the opening balance, every applied operation in
+//! order with the running balances, and the closing balance. rejected transactions aren't in the journal, so they
+//! don't appear
+use crate::{
+    amount::Amount,
+    ledger::{LedgerAccount, Posting, SEALED_TXN_ID},
+    model::*,
+};
+use std::{collections::HashSet, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementEvent {
+    Deposit,
+    Withdrawal,
+    /// a refund of a deposit. the journal records it like a withdrawal: see `TransactionProcessor::statement`
+    Refund,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Adjustment,
+    /// the fee of a deposit or withdrawal
+    Fee,
+    /// the interest accrued on the available funds
+    Interest,
+    /// the fee of a charged back deposit or withdrawal, refunded
+    FeeRefund,
+    /// the summary left by `forget_client` in place of the erased history
+    Sealed,
+}
+
+impl fmt::Display for StatementEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            StatementEvent::Deposit => "deposit",
+            StatementEvent::Withdrawal => "withdrawal",
+            StatementEvent::Refund => "refund",
+            StatementEvent::Dispute => "dispute",
+            StatementEvent::Resolve => "resolve",
+            StatementEvent::Chargeback => "chargeback",
+            StatementEvent::Adjustment => "adjustment",
+            StatementEvent::Fee => "fee",
+            StatementEvent::Interest => "interest",
+            StatementEvent::FeeRefund => "fee_refund",
+            StatementEvent::Sealed => "sealed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// one applied operation and the balances right after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    pub event: StatementEvent,
+    /// 0 for adjustments, interest, and sealed summaries
+    pub txn_id: TransactionId,
+    /// the amount moved, always positive
+    pub amount: Amount,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// when the deposit or withdrawal happened, in unix seconds. None if it isn't known, and for the other events.
+    /// the journal doesn't keep it: see `TransactionProcessor::statement`
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct Statement {
+    pub client_id: ClientId,
+    /// the balances carried over by `--initial-balances`. zero for a client that started in this store
+    pub opening: ClientState,
+    pub lines: Vec<StatementLine>,
+    pub closing: ClientState,
+}
+
+impl Statement {
+    /// `postings` in the order they were recorded. the ones that don't involve the client are skipped.
+    /// the closing state is unlocked: the lock isn't in the journal, so the caller sets it from the client state
+    pub fn new(client_id: ClientId, postings: impl IntoIterator<Item = Posting>) -> Self {
+        let mut opening = ClientState::new(client_id);
+        let mut state = ClientState::new(client_id);
+        let mut lines = Vec::new();
+        // a released hold on a withdrawal is a chargeback, on a deposit a resolve
+        let mut withdrawals = HashSet::new();
+        for posting in postings {
+            if !posting.involves_client(client_id) {
+                continue;
+            }
+            posting.apply_to(&mut state);
+            state.total = state.available + state.held;
+            if [posting.debit, posting.credit].contains(&LedgerAccount::OpeningBalances) {
+                opening = state.clone();
+                continue;
+            }
+
+            let event = match (posting.debit, posting.credit) {
+                (LedgerAccount::Adjustments, _) | (_, LedgerAccount::Adjustments) => {
+                    StatementEvent::Adjustment
+                }
+                (LedgerAccount::Interest, _) => StatementEvent::Interest,
+                _ if posting.txn_id == SEALED_TXN_ID => StatementEvent::Sealed,
+                (_, LedgerAccount::Fees) => StatementEvent::Fee,
+                (LedgerAccount::Fees, _) => StatementEvent::FeeRefund,
+                (LedgerAccount::OperatorCash, _) => StatementEvent::Deposit,
+                (LedgerAccount::ClientHeld(_), LedgerAccount::OperatorCash) => {
+                    StatementEvent::Chargeback
+                }
+                (_, LedgerAccount::OperatorCash) => {
+                    withdrawals.insert(posting.txn_id);
+                    StatementEvent::Withdrawal
+                }
+                (_, LedgerAccount::ClientHeld(_)) => StatementEvent::Dispute,
+                (LedgerAccount::ClientHeld(_), LedgerAccount::ClientAvailable(_))
+                    if withdrawals.contains(&posting.txn_id) =>
+                {
+                    StatementEvent::Chargeback
+                }
+                _ => StatementEvent::Resolve,
+            };
+            lines.push(StatementLine {
+                event,
+                txn_id: posting.txn_id,
+                amount: posting.amount,
+                available: state.available,
+                held: state.held,
+                total: state.total,
+                timestamp: None,
+            });
+        }
+        Statement {
+            client_id,
+            opening,
+            lines,
+            closing: state,
+        }
+    }
+}
+
+/// CSV: an opening row, one row per line, and a closing row with the lock state
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "event,tx,amount,available,held,total,locked,timestamp")?;
+        writeln!(
+            f,
+            "opening,,,{},{},{},,",
+            self.opening.available, self.opening.held, self.opening.total
+        )?;
+        for line in &self.lines {
+            writeln!(
+                f,
+                "{},{},{},{},{},{},,{}",
+                line.event,
+                line.txn_id,
+                line.amount,
+                line.available,
+                line.held,
+                line.total,
+                line.timestamp.map(|t| t.to_string()).unwrap_or_default()
+            )?;
+        }
+        writeln!(
+            f,
+            "closing,,,{},{},{},{},",
+            self.closing.available, self.closing.held, self.closing.total, self.closing.locked
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{amount::amt, ledger::*};
+
+    fn transfer(txn_id: TransactionId, amount: f64) -> BalanceTransfer {
+        BalanceTransfer {
+            client_id: 1,
+            txn_id,
+            amount: amt(amount),
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let deposit = transfer(1, 10.0);
+        let withdrawal = transfer(2, -4.0);
+        let other = BalanceTransfer {
+            client_id: 2,
+            ..transfer(3, 5.0)
+        };
+        let postings = [
+            balance_transfer_postings(&deposit),
+            balance_transfer_postings(&other),
+            balance_transfer_postings(&withdrawal),
+            dispute_postings(&deposit),
+            resolve_postings(&deposit),
+            dispute_postings(&withdrawal),
+            chargeback_postings(&withdrawal),
+        ]
+        .concat();
+        let statement = Statement::new(1, postings);
+        let events: Vec<(StatementEvent, TransactionId, f64, f64)> = statement
+            .lines
+            .iter()
+            .map(|l| (l.event, l.txn_id, l.available.to_f64(), l.held.to_f64()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (StatementEvent::Deposit, 1, 10.0, 0.0),
+                (StatementEvent::Withdrawal, 2, 6.0, 0.0),
+                (StatementEvent::Dispute, 1, -4.0, 10.0),
+                (StatementEvent::Resolve, 1, 6.0, 0.0),
+                (StatementEvent::Dispute, 2, 6.0, 4.0),
+                (StatementEvent::Chargeback, 2, 10.0, 0.0),
+            ]
+        );
+        assert_eq!(statement.closing.total, 10.0);
+    }
+
+    #[test]
+    fn test_opening() {
+        let mut seed = ClientState::new(1);
+        seed.available = amt(3.0);
+        seed.held = amt(1.0);
+        let postings = [
+            opening_postings(&seed),
+            balance_transfer_postings(&transfer(1, 2.0)),
+        ]
+        .concat();
+        let statement = Statement::new(1, postings);
+        assert_eq!(statement.opening.total, 4.0);
+        assert_eq!(statement.lines.len(), 1);
+        assert_eq!(
+            statement.to_string(),
+            "event,tx,amount,available,held,total,locked,timestamp
+opening,,,3,1,4,,
+deposit,1,2,5,1,6,,
+closing,,,5,1,6,false,
+"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! counts of what a processor did, for a processing summary. built from the events of `process`, so a transaction
+//! counts once whether it came from a CSV file, a server, or a library call
+use crate::{
+    amount::Amount,
+    events::{EngineEvent, RejectReason},
+};
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessingStats {
+    /// applied deposits
+    pub deposits: u64,
+    /// applied withdrawals
+    pub withdrawals: u64,
+    /// refunded deposits
+    pub refunds: u64,
+    /// disputes opened
+    pub disputes: u64,
+    /// disputes resolved
+    pub resolves: u64,
+    /// disputes charged back
+    pub chargebacks: u64,
+    /// rejected transactions, by reason
+    pub rejected: BTreeMap<RejectReason, u64>,
+    /// accounts created for clients seen for the first time (or seeded with initial balances)
+    pub clients_created: u64,
+    /// accounts locked by a chargeback
+    pub accounts_locked: u64,
+    /// deposits to locked accounts queued by `LockedAccountPolicy::QueueDeposits`
+    pub deposits_queued: u64,
+    /// transfers skipped because an earlier run applied them (`TransactionProcessor::set_skip_seen_txns`)
+    pub seen: u64,
+    /// the fees charged on deposits and withdrawals
+    pub fees_charged: Amount,
+    /// the fees refunded on chargebacks
+    pub fees_refunded: Amount,
+}
+
+impl ProcessingStats {
+    /// count the events of one processed transaction
+    pub fn observe(&mut self, events: &[EngineEvent]) {
+        for event in events {
+            match event {
+                EngineEvent::FundsDeposited { .. } => self.deposits += 1,
+                EngineEvent::FundsWithdrawn { .. } => self.withdrawals += 1,
+                EngineEvent::FundsRefunded { .. } => self.refunds += 1,
+                EngineEvent::DisputeOpened { .. } => self.disputes += 1,
+                EngineEvent::DisputeResolved { .. } => self.resolves += 1,
+                EngineEvent::ChargebackApplied { .. } => self.chargebacks += 1,
+                EngineEvent::AccountLocked { .. } => self.accounts_locked += 1,
+                EngineEvent::DepositQueued { .. } => self.deposits_queued += 1,
+                EngineEvent::FeeCharged { amount, .. } => self.fees_charged += *amount,
+                EngineEvent::FeeRefunded { amount, .. } => self.fees_refunded += *amount,
+                EngineEvent::TransactionRejected { reason, .. } => {
+                    *self.rejected.entry(*reason).or_default() += 1
+                }
+                // operator actions, not transactions
+                EngineEvent::DisputeReopened { .. }
+                | EngineEvent::BalanceAdjusted { .. }
+                | EngineEvent::InterestAccrued { .. }
+                | EngineEvent::AccountUnlocked { .. } => {}
+            }
+        }
+    }
+
+    /// the number of transactions that were applied
+    pub fn applied(&self) -> u64 {
+        self.deposits
+            + self.withdrawals
+            + self.refunds
+            + self.disputes
+            + self.resolves
+            + self.chargebacks
+    }
+
+    /// the fees charged net of the refunds: what the fee account collected
+    pub fn fees_collected(&self) -> Amount {
+        self.fees_charged - self.fees_refunded
+    }
+
+    /// the number of transactions that were rejected, for any reason
+    pub fn rejections(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+
+    /// the number of transactions processed: applied, rejected, or queued
+    pub fn processed(&self) -> u64 {
+        self.applied() + self.rejections() + self.deposits_queued
+    }
+
+    /// the end-of-run summary of a run that took `elapsed`, with its throughput
+    pub fn summary(&self, elapsed: Duration) -> Summary<'_> {
+        Summary {
+            stats: self,
+            elapsed,
+        }
+    }
+
+    /// add the counts of `other`, ex: another shard of a parallel run
+    pub fn merge(&mut self, other: &ProcessingStats) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.refunds += other.refunds;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        for (reason, count) in &other.rejected {
+            *self.rejected.entry(*reason).or_default() += count;
+        }
+        self.clients_created += other.clients_created;
+        self.accounts_locked += other.accounts_locked;
+        self.deposits_queued += other.deposits_queued;
+        self.seen += other.seen;
+        self.fees_charged += other.fees_charged;
+        self.fees_refunded += other.fees_refunded;
+    }
+}
+
+/// a human-readable summary of the stats (see `ProcessingStats::summary`), one line per group of counts
+pub struct Summary<'a> {
+    stats: &'a ProcessingStats,
+    elapsed: Duration,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = self.stats;
+        let seconds = self.elapsed.as_secs_f64();
+        let throughput = if seconds > 0.0 {
+            stats.processed() as f64 / seconds
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "processed {} transaction(s) in {:.3}s ({:.0} per second)",
+            stats.processed(),
+            seconds,
+            throughput
+        )?;
+        writeln!(
+            f,
+            "applied: {} deposit(s), {} withdrawal(s), {} refund(s), {} dispute(s), {} resolve(s), {} chargeback(s)",
+            stats.deposits,
+            stats.withdrawals,
+            stats.refunds,
+            stats.disputes,
+            stats.resolves,
+            stats.chargebacks
+        )?;
+        write!(f, "rejected: {}", stats.rejections())?;
+        let reasons: Vec<String> = stats
+            .rejected
+            .iter()
+            .map(|(reason, count)| format!("{:?} {}", reason, count))
+            .collect();
+        if !reasons.is_empty() {
+            write!(f, " ({})", reasons.join(", "))?;
+        }
+        writeln!(f)?;
+        if stats.deposits_queued > 0 {
+            writeln!(f, "queued: {} deposit(s)", stats.deposits_queued)?;
+        }
+        if stats.seen > 0 {
+            writeln!(
+                f,
+                "skipped: {} transfer(s) seen in an earlier run",
+                stats.seen
+            )?;
+        }
+        write!(
+            f,
+            "clients created: {}, accounts locked: {}",
+            stats.clients_created, stats.accounts_locked
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let stats = ProcessingStats {
+            deposits: 3,
+            withdrawals: 1,
+            rejected: BTreeMap::from([
+                (RejectReason::InsufficientFunds, 2),
+                (RejectReason::Malformed, 1),
+            ]),
+            clients_created: 2,
+            ..Default::default()
+        };
+        assert_eq!(stats.processed(), 7);
+        assert_eq!(
+            stats.summary(Duration::from_millis(500)).to_string(),
+            "processed 7 transaction(s) in 0.500s (14 per second)
+applied: 3 deposit(s), 1 withdrawal(s), 0 refund(s), 0 dispute(s), 0 resolve(s), 0 chargeback(s)
+rejected: 3 (Malformed 1, InsufficientFunds 2)
+clients created: 2, accounts locked: 0"
+        );
+        assert!(ProcessingStats::default()
+            .summary(Duration::ZERO)
+            .to_string()
+            .starts_with("processed 0 transaction(s) in 0.000s (0 per second)"));
+    }
+}
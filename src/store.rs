@@ -0,0 +1,237 @@
+use crate::{
+    adjustment::Adjustment, amount::Amount, audit::AuditEntry, errors::*, event_log::LoggedEvent,
+    ledger::Posting, limits::Limits, model::*, schedule::Date,
+};
+use error_stack::Result;
+
+/// the storage operations needed by the `TransactionProcessor`.
+/// the try_* functions rely on the store to enforce the data integrity constraints described in the README:
+/// they return Ok(false) when an operation is rejected and reserve errors for storage failures.
+pub trait TxnStore {
+    // call this if get_client_state returns None
+    fn create_client_state(&mut self, client_id: ClientId) -> Result<ClientState, MyError>;
+
+    // search for a client state (an account) by client ID
+    // return None if not found
+    fn get_client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError>;
+
+    // used to display client account information. in client_id order, so reports from two runs can be diffed
+    fn process_all_clients(&self, f: &mut dyn FnMut(ClientState)) -> Result<(), MyError>;
+
+    fn update_client_state(&mut self, client_state: &ClientState) -> Result<(), MyError>;
+
+    // fails if the transaction id is already in use or the client doesn't exist
+    fn try_insert_balance_transfer(&mut self, txn: BalanceTransfer) -> Result<bool, MyError>;
+
+    // fails if the balance transfer doesn't exist (for this client), was already disputed, or is a refund or a
+    // refunded deposit
+    fn try_insert_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+
+    // fails if the dispute doesn't exist or was already resolved/charged back
+    fn try_resolve_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+
+    // fails if the dispute doesn't exist or was already resolved/charged back
+    fn try_chargeback_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+
+    // fails unless the dispute was resolved (a chargeback is final). the resolution is moved to the dispute history,
+    // which leaves the dispute open again
+    fn try_reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<bool, MyError>;
+
+    // the number of times the dispute was reopened
+    fn count_dispute_reopens(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<u64, MyError>;
+
+    // return None if not found
+    fn get_balance_transfer(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError>;
+
+    // look up a balance transfer by its (globally unique) transaction id, regardless of the client
+    fn get_balance_transfer_by_id(
+        &self,
+        txn_id: TransactionId,
+    ) -> Result<Option<BalanceTransfer>, MyError>;
+
+    // the balance transfers of a client that are disputed but not resolved or charged back
+    fn get_open_disputes(&self, client_id: ClientId) -> Result<Vec<BalanceTransfer>, MyError>;
+
+    // visits every balance transfer with its dispute status, in client_id then txn_id order
+    fn process_all_balance_transfers(
+        &self,
+        f: &mut dyn FnMut(TransferRecord),
+    ) -> Result<(), MyError>;
+
+    // the client's own overdraft limit. None if it has none, and the processor's `OverdraftPolicy` applies
+    fn get_overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError>;
+
+    // sets the client's overdraft limit, or removes it with None. does nothing if the client doesn't exist
+    fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError>;
+
+    // the client's own limits. None if it has none, and the processor's limits apply
+    fn get_client_limits(&self, client_id: ClientId) -> Result<Option<Limits>, MyError>;
+
+    // sets the client's own limits, or removes them with None. does nothing if the client doesn't exist
+    fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError>;
+
+    // records the fee charged on the balance transfer `txn_id`
+    fn insert_fee(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        fee: Amount,
+    ) -> Result<(), MyError>;
+
+    // the fee charged on a balance transfer. None if it had none
+    fn get_fee(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Amount>, MyError>;
+
+    // records a refund. fails if the deposit was disputed or already refunded, or the refund id is already used by
+    // another refund
+    fn try_insert_refund(&mut self, refund: &Refund) -> Result<bool, MyError>;
+
+    // the refund with the transaction id `txn_id`. None if it isn't a refund
+    fn get_refund(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<Refund>, MyError>;
+
+    // queues a deposit to a locked account, to be applied when it's unlocked. fails if the transaction id is already
+    // queued
+    fn try_queue_deposit(&mut self, txn: BalanceTransfer) -> Result<bool, MyError>;
+
+    // removes and returns the deposits queued for a client, in the order they were queued
+    fn take_queued_deposits(
+        &mut self,
+        client_id: ClientId,
+    ) -> Result<Vec<BalanceTransfer>, MyError>;
+
+    // erases a client's history: deletes its balance transfers (and their disputes and resolutions), adjustments, and
+    // logged events, and replaces the postings that involve its accounts with `summary`. returns the number of balance
+    // transfers deleted
+    fn forget_client(&mut self, client_id: ClientId, summary: &[Posting])
+        -> Result<usize, MyError>;
+
+    // postings are only ever appended
+    fn insert_posting(&mut self, posting: &Posting) -> Result<(), MyError>;
+
+    // visits the postings in the order they were inserted
+    fn process_all_postings(&self, f: &mut dyn FnMut(Posting)) -> Result<(), MyError>;
+
+    // visits the postings that involve the accounts of a client, in the order they were inserted
+    fn process_client_postings(
+        &self,
+        client_id: ClientId,
+        f: &mut dyn FnMut(Posting),
+    ) -> Result<(), MyError> {
+        self.process_all_postings(&mut |posting| {
+            if posting.involves_client(client_id) {
+                f(posting);
+            }
+        })
+    }
+
+    // adjustments are only ever appended
+    fn insert_adjustment(&mut self, adjustment: &Adjustment) -> Result<(), MyError>;
+
+    // visits the adjustments in the order they were inserted
+    fn process_all_adjustments(&self, f: &mut dyn FnMut(Adjustment)) -> Result<(), MyError>;
+
+    // the audit log is append-only
+    fn append_audit_entry(&mut self, entry: &AuditEntry) -> Result<(), MyError>;
+
+    // visits the audit entries in order
+    fn process_all_audit_entries(&self, f: &mut dyn FnMut(AuditEntry)) -> Result<(), MyError>;
+
+    // events are only ever appended. `forget_client` is the only way to remove them
+    fn append_event(&mut self, event: &LoggedEvent) -> Result<(), MyError>;
+
+    // visits the logged events in the order they were appended
+    fn process_all_events(&self, f: &mut dyn FnMut(LoggedEvent)) -> Result<(), MyError>;
+
+    // groups the store operations for one input row so they are applied atomically.
+    // stores that can't roll back keep the default no-ops and may leave a partially applied row behind on failure
+    fn begin(&mut self) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    // the number of input rows of a run that were committed. None if the run never started
+    fn get_checkpoint(&self, _run_id: &str) -> Result<Option<u64>, MyError> {
+        Ok(None)
+    }
+
+    // called inside the same unit of work as the row it counts, so a crash can't separate the two
+    fn set_checkpoint(&mut self, _run_id: &str, _rows: u64) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    // the earliest run that processed an input file with this content. None if there is none
+    fn get_input_run(&self, _input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        Ok(None)
+    }
+
+    // keeps the earliest run if the content was already recorded
+    fn insert_input_run(&mut self, _run: &InputRun) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    // records the content hash of an input record. fails if it was already recorded
+    fn try_insert_record_hash(&mut self, _sha256: &str) -> Result<bool, MyError> {
+        Ok(true)
+    }
+
+    // the last day interest was accrued through. None if interest was never accrued
+    fn get_interest_accrued_through(&self) -> Result<Option<Date>, MyError> {
+        Ok(None)
+    }
+
+    // called inside the same unit of work as the interest postings
+    fn set_interest_accrued_through(&mut self, _date: Date) -> Result<(), MyError> {
+        Ok(())
+    }
+
+    // whether the txn_id belonged to a balance transfer deleted by data retention. it can't be used again
+    fn is_purged_txn_id(&self, _txn_id: TransactionId) -> Result<bool, MyError> {
+        Ok(false)
+    }
+}
@@ -1,562 +1,4960 @@
-use crate::{db::TxnDb, errors::*, fmt_error, model::*};
-use error_stack::{bail, Result, ResultExt};
+#[cfg(feature = "sqlite")]
+use crate::db::TxnDb;
+#[cfg(feature = "encryption")]
+use crate::encryption::FieldKey;
+use crate::{
+    adjustment::Adjustment,
+    amount::{Amount, RawAmount},
+    audit::{self, AuditChain, AuditVerifier},
+    columns::Columns,
+    config::{ConfigWatcher, EngineConfig},
+    dialect::CsvDialect,
+    duplicates::{DuplicateTracker, DuplicateTxn},
+    errors::*,
+    event_log::{self, LoggedEvent},
+    events::*,
+    export::ExportWriter,
+    fmt_error,
+    hooks::{self, ProcessorHooks},
+    interest, invariants,
+    latency::{self, LatencyHistogram, StoreTimings, TimedStore},
+    ledger,
+    ledger::{Ledger, Posting},
+    limits::Limits,
+    memory::{ByteSize, MemoryMonitor, MemoryPressure},
+    memory_db::MemoryDb,
+    model::*,
+    number_format::{AmountUnit, NumberFormat},
+    output,
+    policy::{
+        CrossClientDisputePolicy, DisputePolicy, DuplicateInputPolicy, LockedAccountPolicy,
+        OverdraftPolicy,
+    },
+    rate_limit::RateLimiter,
+    reconcile::{Reconciliation, RunTotals},
+    rejects::RejectsLog,
+    report::{ReportFormat, ReportStyle, ReportWriter},
+    risk::{ChargebackAlert, ChargebackMonitor, ChargebackRisk, ChargebackThresholds},
+    rounding::{RoundingPolicy, DECIMAL_PLACES},
+    schedule::{Date, RateSchedule, Rates},
+    sequence::*,
+    snapshot::Snapshots,
+    statement::{Statement, StatementEvent},
+    stats::ProcessingStats,
+    store::TxnStore,
+};
+use csv::{ReaderBuilder, StringRecord};
+use error_stack::{report, IntoReport, Result, ResultExt};
+#[cfg(feature = "sqlite")]
 use random_string::generate;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+/// owns its store, so a processor can be moved onto a worker thread or into a blocking task.
+/// rusqlite::Connection is Send but not Sync: share a processor between threads with a Mutex, not a bare Arc.
 pub struct TransactionProcessor {
-    db: TxnDb,
-    /// this field is mainly for unit testing
-    num_processed: u64,
+    db: Box<dyn TxnStore + Send>,
+    stats: ProcessingStats,
+    rounding: RoundingPolicy,
+    cross_client_disputes: CrossClientDisputePolicy,
+    disputes: DisputePolicy,
+    // in days. None: a transfer can be disputed at any time
+    dispute_window: Option<u32>,
+    overdraft: OverdraftPolicy,
+    // the limits of the clients without their own
+    limits: Limits,
+    locked_accounts: LockedAccountPolicy,
+    // the number of disputes that referenced another client's transfer
+    num_cross_client_disputes: u64,
+    number_format: Option<NumberFormat>,
+    amount_unit: AmountUnit,
+    csv_dialect: CsvDialect,
+    report_format: ReportFormat,
+    report_precision: Option<usize>,
+    sequence: Option<SequenceTracker>,
+    duplicates: Option<DuplicateTracker>,
+    // the sum of the client totals when reconciliation was enabled, and what has been applied since
+    reconciliation: Option<(Amount, RunTotals)>,
+    check_invariants: bool,
+    audit: Option<AuditChain>,
+    // whether every transaction is audited. if not, the chain is loaded for LockedAccountPolicy::Audit
+    audit_all: bool,
+    chargeback_monitor: Option<ChargebackMonitor>,
+    chargeback_alert: Option<ChargebackAlert>,
+    hooks: Vec<Box<dyn ProcessorHooks>>,
+    config_watcher: Option<ConfigWatcher>,
+    // the version of the last configuration applied
+    config_version: Option<String>,
+    // encrypts the input records of the audit entries
+    #[cfg(feature = "encryption")]
+    field_key: Option<FieldKey>,
+    rate_limiter: Option<RateLimiter>,
+    // the held funds carried over by load_initial_balances. they aren't backed by disputes in this store
+    opening_held: HashMap<ClientId, Amount>,
+    snapshots: Option<Snapshots>,
+    // how many times process_csv_resumable retries a batch that failed with MyError::Busy
+    busy_retries: u32,
+    // the number of input rows applied per store transaction
+    commit_every: u64,
+    latency: Option<LatencyHistogram>,
+    // transactions slower than this are logged with the timings of their store calls
+    slow_threshold: Option<(Duration, StoreTimings)>,
+    memory: Option<MemoryMonitor>,
+    duplicate_inputs: DuplicateInputPolicy,
+    record_hashes: bool,
+    rate_schedule: RateSchedule,
+    rejects: Option<RejectsLog>,
+    strict: bool,
+    // persistent mode: transfers an earlier run already stored are skipped
+    skip_seen_txns: bool,
+    // in persistent mode, the ids of the transfers applied by this run. a repeat of one of them is a duplicate
+    run_txn_ids: HashSet<TransactionId>,
+    // inside dry_run: everything is rolled back at the end, so nothing else begins or commits a store transaction
+    in_dry_run: bool,
 }
 
+// a CSV row: a transaction, or a row that failed to parse, kept for the rejects log
+pub(crate) enum InputRow {
+    Txn(RawTxnInput),
+    Malformed(StringRecord),
+}
+
+// stands in for a record the CSV reader couldn't split into fields (ex: a row with the wrong number of fields). it
+// doesn't deserialize, so the row is skipped, and the rejects log gets the reader's error as its input
+fn unreadable(e: csv::Error) -> StringRecord {
+    let mut record = StringRecord::from(vec![e.to_string()]);
+    record.set_position(e.position().cloned());
+    record
+}
+
+// a record from the CSV reader. an I/O error (ex: a truncated compressed input) stops processing, while a record
+// the reader couldn't split is a malformed row
+pub(crate) fn read_record(record: csv::Result<StringRecord>) -> Result<StringRecord, MyError> {
+    match record {
+        Err(e) if matches!(e.kind(), csv::ErrorKind::Io(_)) => Err(e)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read the input"))
+            .change_context(MyError::FileReader),
+        record => Ok(record.unwrap_or_else(unreadable)),
+    }
+}
+
+// the line of the input a record was read from
+fn line_of(record: &StringRecord) -> Option<u64> {
+    record.position().map(|pos| pos.line())
+}
+
+// where a strict mode error happened
+fn at_line(line: Option<u64>) -> String {
+    match line {
+        Some(line) => format!("line {}", line),
+        None => "an input row".to_string(),
+    }
+}
+
+// how the input CSV is read: the configured dialect, number format and amount unit
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CsvFormat {
+    dialect: CsvDialect,
+    number_format: Option<NumberFormat>,
+    amount_unit: AmountUnit,
+}
+
+impl CsvFormat {
+    pub(crate) fn reader<R: io::Read>(
+        &self,
+        reader: R,
+    ) -> Result<csv::Reader<io::BufReader<R>>, MyError> {
+        self.dialect.reader(reader)
+    }
+
+    // the columns of a CSV file, named by its header. fails if the header can't be read
+    pub(crate) fn columns_of<R: io::Read>(
+        &self,
+        csv_reader: &mut csv::Reader<R>,
+    ) -> Result<Columns, MyError> {
+        let headers = read_record(csv_reader.headers().cloned())?;
+        Ok(Columns::from_header(&headers))
+    }
+
+    // trim, arrange and deserialize a CSV record. the amounts are in the unit named by the header, or the configured
+    // one. None for invalid formats
+    pub(crate) fn deserialize(
+        &self,
+        mut record: StringRecord,
+        columns: Columns,
+    ) -> Option<RawTxnInput> {
+        record.trim();
+        let mut record = columns.arrange(record);
+        let unit = columns.amount_unit().unwrap_or(self.amount_unit);
+        let format = self.number_format.filter(|_| unit == AmountUnit::Decimal);
+        if format.is_some() || unit != AmountUnit::Decimal {
+            if let Some(amount) = record.get(3).filter(|amount| !amount.is_empty()) {
+                let amount = match format {
+                    Some(format) => format.normalize(amount)?,
+                    None => unit.to_decimal(amount)?,
+                };
+                let normalized: StringRecord = record
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, field)| if idx == 3 { amount.as_str() } else { field })
+                    .collect();
+                record = normalized;
+            }
+        }
+        let mut txn: RawTxnInput = record.deserialize(None).ok()?;
+        // serde infers the amount field as an f64, which loses the digits of large amounts: parse its text instead
+        txn.amount = match record.get(3).filter(|amount| !amount.is_empty()) {
+            Some(amount) => Some(RawAmount::parse(amount)?),
+            None => None,
+        };
+        Some(txn)
+    }
+}
+
+// compile time check: the processor must stay Send so it can run on worker threads
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<TransactionProcessor>();
+};
+
 impl TransactionProcessor {
+    #[cfg(feature = "sqlite")]
     pub fn new() -> Result<Self, MyError> {
         // use a different name for the database. allows the unit tests to continue when the next test executes before the existing database is deleted.
         let charset = "abcdefghijklmnopqrstuvwxyz";
-        Ok(TransactionProcessor {
-            db: TxnDb::new(&format!("{}.db", generate(6, charset)))
-                .attach_printable_lazy(|| fmt_error!("database failure"))?,
-            num_processed: 0,
+        let db = TxnDb::new(&format!("{}.db", generate(6, charset)))
+            .attach_printable_lazy(|| fmt_error!("database failure"))?;
+        Ok(Self::with_store(db))
+    }
+
+    /// keeps all state in memory. doesn't require SQLite or a file system
+    pub fn in_memory() -> Self {
+        Self::with_store(MemoryDb::new())
+    }
+
+    pub fn with_store<S: TxnStore + Send + 'static>(store: S) -> Self {
+        Self::with_boxed_store(Box::new(store))
+    }
+
+    pub(crate) fn with_boxed_store(db: Box<dyn TxnStore + Send>) -> Self {
+        TransactionProcessor {
+            db,
+            stats: ProcessingStats::default(),
+            rounding: RoundingPolicy::default(),
+            cross_client_disputes: CrossClientDisputePolicy::default(),
+            disputes: DisputePolicy::default(),
+            dispute_window: None,
+            overdraft: OverdraftPolicy::default(),
+            limits: Limits::default(),
+            locked_accounts: LockedAccountPolicy::default(),
+            num_cross_client_disputes: 0,
+            number_format: None,
+            amount_unit: AmountUnit::default(),
+            csv_dialect: CsvDialect::default(),
+            report_format: ReportFormat::default(),
+            report_precision: None,
+            sequence: None,
+            duplicates: None,
+            reconciliation: None,
+            check_invariants: false,
+            audit: None,
+            audit_all: false,
+            chargeback_monitor: None,
+            chargeback_alert: None,
+            hooks: Vec::new(),
+            config_watcher: None,
+            config_version: None,
+            #[cfg(feature = "encryption")]
+            field_key: None,
+            rate_limiter: None,
+            opening_held: HashMap::new(),
+            snapshots: None,
+            busy_retries: 0,
+            commit_every: 1,
+            latency: None,
+            slow_threshold: None,
+            memory: None,
+            duplicate_inputs: DuplicateInputPolicy::default(),
+            record_hashes: false,
+            rate_schedule: RateSchedule::default(),
+            rejects: None,
+            strict: false,
+            skip_seen_txns: false,
+            run_txn_ids: HashSet::new(),
+            in_dry_run: false,
+        }
+    }
+
+    /// how amounts are rounded to 4 decimal places. defaults to half-even (banker's rounding)
+    pub fn set_rounding_policy(&mut self, policy: RoundingPolicy) {
+        self.rounding = policy;
+    }
+
+    pub fn rounding_policy(&self) -> RoundingPolicy {
+        self.rounding
+    }
+
+    /// what happens to a dispute of another client's deposit or withdrawal. defaults to rejecting it
+    pub fn set_cross_client_dispute_policy(&mut self, policy: CrossClientDisputePolicy) {
+        self.cross_client_disputes = policy;
+    }
+
+    pub fn cross_client_dispute_policy(&self) -> CrossClientDisputePolicy {
+        self.cross_client_disputes
+    }
+
+    /// which transfers can be disputed: deposits, withdrawals, or both (the default). a dispute of any other
+    /// transfer is rejected with `RejectReason::DisputeNotAllowed`
+    pub fn set_dispute_policy(&mut self, policy: DisputePolicy) {
+        self.disputes = policy;
+    }
+
+    /// how many days after a deposit or withdrawal it can be disputed, or None (the default) for no limit. a dispute
+    /// filed later is rejected with `RejectReason::DisputeWindowExpired`. the days are counted from the `timestamp`
+    /// of the transfer to the `timestamp` of the dispute, or to the time it's processed if it has none; a transfer
+    /// without a timestamp can always be disputed
+    pub fn set_dispute_window(&mut self, days: Option<u32>) {
+        self.dispute_window = days;
+    }
+
+    pub fn dispute_window(&self) -> Option<u32> {
+        self.dispute_window
+    }
+
+    // whether a dispute filed at `filed` (unix seconds, now if None) is too late for `transfer`
+    fn dispute_window_expired(&self, transfer: &BalanceTransfer, filed: Option<i64>) -> bool {
+        let (days, made) = match (self.dispute_window, transfer.timestamp) {
+            (Some(days), Some(made)) => (days, made),
+            _ => return false,
+        };
+        let filed = filed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64)
+        });
+        filed - made > i64::from(days) * 86_400
+    }
+
+    /// how far a withdrawal can overdraw the available funds, for the clients without their own limit. defaults to
+    /// rejecting any overdraft
+    pub fn set_overdraft_policy(&mut self, policy: OverdraftPolicy) {
+        self.overdraft = policy;
+    }
+
+    pub fn overdraft_policy(&self) -> OverdraftPolicy {
+        self.overdraft
+    }
+
+    /// the balance, deposit, and withdrawal limits of the clients without their own (`set_client_limits`). defaults
+    /// to no limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// what happens to the transactions of a locked account: they're rejected (the default), rejected and audited, or
+    /// deposits are queued until `unlock_account`
+    pub fn set_locked_account_policy(&mut self, policy: LockedAccountPolicy) {
+        self.locked_accounts = policy;
+    }
+
+    pub fn locked_account_policy(&self) -> LockedAccountPolicy {
+        self.locked_accounts
+    }
+
+    /// the number of disputes that referenced a deposit or withdrawal of another client, rejected or not
+    pub fn cross_client_disputes(&self) -> u64 {
+        self.num_cross_client_disputes
+    }
+
+    /// admit transactions through `limiter`. with `OverloadPolicy::Shed`, a transaction over a limit is rejected
+    /// with `RejectReason::RateLimited`; with `OverloadPolicy::Queue`, `process` blocks until it's allowed
+    pub fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// the number of transactions shed or delayed per client. None unless set_rate_limiter was called
+    pub fn rate_limited(&self) -> Option<Vec<(ClientId, u64)>> {
+        self.rate_limiter.as_ref().map(|limiter| limiter.limited())
+    }
+
+    /// retry a batch of process_csv_resumable up to `retries` times, with exponential backoff, when the store reports
+    /// that it's busy (`MyError::Busy`). the batch is rolled back before each retry. defaults to 0
+    pub fn set_busy_retries(&mut self, retries: u32) {
+        self.busy_retries = retries;
+    }
+
+    /// apply the rows of process_csv and process_csv_resumable in store transactions of up to `rows` rows instead of
+    /// one each, which is much faster with SQLite. a failure rolls back the whole batch, and a retry or a resumed run
+    /// replays it. defaults to 1
+    pub fn set_commit_every(&mut self, rows: u64) {
+        self.commit_every = rows.max(1);
+    }
+
+    /// strict mode: process_csv and process_csv_resumable stop at the first input row that doesn't parse
+    /// (`MyError::MalformedRecord`) or transaction that's rejected (`MyError::Rejected`), and the report names its line.
+    /// the unfinished batch is rolled back. off by default: such rows are skipped
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// persistent mode, for a store that outlives the run (ex: `TxnDb` with `--db --persist`): a deposit, withdrawal,
+    /// or refund that an earlier run already stored (same txn_id, client, and amount, or the same refunded deposit) is
+    /// skipped instead of being rejected as a duplicate. it isn't audited or logged as a reject, and counts in
+    /// `ProcessingStats::seen`. a reused id with another client or amount, or a repeat within the run, is still
+    /// rejected as a duplicate. off by default
+    pub fn set_skip_seen_txns(&mut self, skip: bool) {
+        self.skip_seen_txns = skip;
+    }
+
+    /// what check_input does with an input file whose content was already processed. defaults to rejecting it
+    pub fn set_duplicate_input_policy(&mut self, policy: DuplicateInputPolicy) {
+        self.duplicate_inputs = policy;
+    }
+
+    /// call before processing an input file, with the sha256 of its content. if the store already processed the same
+    /// content, returns that earlier run: an error with `DuplicateInputPolicy::Reject`, a warning with `Warn` and
+    /// `Skip` (the caller then doesn't process the file). a run that stopped part way isn't recorded, so resuming it
+    /// is allowed
+    pub fn check_input(&self, input_sha256: &str) -> Result<Option<InputRun>, MyError> {
+        let run = match self.db.get_input_run(input_sha256)? {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+        match self.duplicate_inputs {
+            DuplicateInputPolicy::Reject => {
+                return Err(
+                    report!(MyError::DuplicateInput).attach_printable(fmt_error!(
+                        "this input was already processed: {} had the same content (sha256 {})",
+                        run.input,
+                        run.input_sha256
+                    )),
+                )
+            }
+            DuplicateInputPolicy::Warn => tracing::warn!(
+                input = %run.input,
+                sha256 = %run.input_sha256,
+                "this input was already processed, processing it again"
+            ),
+            DuplicateInputPolicy::Skip => tracing::warn!(
+                input = %run.input,
+                sha256 = %run.input_sha256,
+                "this input was already processed, skipping it"
+            ),
+        }
+        Ok(Some(run))
+    }
+
+    /// per record idempotency: the content hash of every processed record (`RawTxnInput::content_hash`) is kept in the
+    /// store, and a record with the same content is skipped with a warning and rejected as
+    /// `RejectReason::DuplicateRecord`. a record shed by the rate limiter isn't kept, so it can be sent again. off by
+    /// default
+    pub fn set_record_hashes(&mut self, enabled: bool) {
+        self.record_hashes = enabled;
+    }
+
+    /// call after an input file was processed to the end, so check_input recognizes its content from now on
+    pub fn record_input(&mut self, input: &str, input_sha256: &str) -> Result<(), MyError> {
+        let processed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.db.insert_input_run(&InputRun {
+            input_sha256: input_sha256.to_string(),
+            input: input.to_string(),
+            processed_at,
         })
     }
 
-    pub fn display(&self) -> Result<(), MyError> {
-        // display the result
-        println!("client,available,held,total,locked");
-        self.db
-            .process_all_clients(|client| println!("{}", client))?;
+    /// start recording how long each transaction takes. with `slow_threshold`, a transaction that takes longer is
+    /// logged as a warning with the time spent in each store call (which adds a little overhead to every call)
+    pub fn enable_latency_tracking(&mut self, slow_threshold: Option<Duration>) {
+        self.latency.get_or_insert_with(LatencyHistogram::new);
+        match (slow_threshold, self.slow_threshold.as_mut()) {
+            (Some(threshold), Some((current, _))) => *current = threshold,
+            (Some(threshold), None) => {
+                let timings = StoreTimings::default();
+                let db = std::mem::replace(&mut self.db, Box::new(MemoryDb::new()));
+                self.db = Box::new(TimedStore::new(db, timings.clone()));
+                self.slow_threshold = Some((threshold, timings));
+            }
+            (None, _) => {}
+        }
+    }
 
-        Ok(())
+    /// the distribution of the processing times. None unless enable_latency_tracking was called
+    pub fn latency(&self) -> Option<&LatencyHistogram> {
+        self.latency.as_ref()
     }
 
-    pub fn process(&mut self, raw_input: RawTxnInput) -> Result<(), MyError> {
-        // ignore invalid transactions
-        let txn = match self.validate_raw_input(&raw_input) {
-            Some(r) => r,
+    /// sample the memory used by the process while processing (on Linux) and record the peak. with a `ceiling`,
+    /// the processor drops the state it can rebuild when usage reaches 90% of it, and `process` fails with
+    /// MyError::Memory if usage is still above the ceiling afterwards. the in-memory store only grows: use the SQLite
+    /// store for inputs that don't fit
+    pub fn enable_memory_tracking(&mut self, ceiling: Option<ByteSize>) {
+        self.memory = Some(MemoryMonitor::new(ceiling));
+    }
+
+    /// the highest memory usage sampled. None unless enable_memory_tracking was called, or if it can't be measured
+    pub fn peak_memory(&self) -> Option<ByteSize> {
+        self.memory.as_ref().and_then(|monitor| monitor.peak())
+    }
+
+    fn check_memory(&mut self) -> Result<(), MyError> {
+        let monitor = match self.memory.as_mut() {
+            Some(monitor) => monitor,
+            None => return Ok(()),
+        };
+        let bytes = match monitor.observe() {
+            Some(bytes) => bytes,
             None => return Ok(()),
         };
+        if monitor.pressure(bytes) == MemoryPressure::Normal {
+            return Ok(());
+        }
 
-        // obtain the customer state - create new if needed
-        let mut state = match self.db.get_client_state(raw_input.client_id)? {
-            Some(s) => s,
-            None => self.db.create_client_state(raw_input.client_id)?,
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            limiter.shrink(Instant::now());
+        }
+        if let Some(monitor) = self.chargeback_monitor.as_mut() {
+            monitor.shrink();
+        }
+        let monitor = match self.memory.as_mut() {
+            Some(monitor) => monitor,
+            None => return Ok(()),
         };
+        let bytes = monitor.sample().unwrap_or(bytes);
+        tracing::warn!(
+            used = %ByteSize(bytes),
+            ceiling = ?monitor.ceiling().map(|c| c.to_string()),
+            "memory usage is close to the ceiling"
+        );
+        if monitor.pressure(bytes) == MemoryPressure::Exceeded {
+            let ceiling = monitor.ceiling().unwrap_or(ByteSize(0));
+            return Err(report!(MyError::Memory).attach_printable(fmt_error!(
+                "using {} with a ceiling of {}",
+                ByteSize(bytes),
+                ceiling
+            )));
+        }
+        Ok(())
+    }
 
-        // ignore transactions once the account is locked/frozen
-        if state.is_locked() {
-            return Ok(());
+    /// write the report of every client account to a numbered file in `dir` (`snapshot-000001.csv`, ...) after every
+    /// `every` transactions, applied or rejected. creates `dir` if needed
+    pub fn enable_snapshots(&mut self, dir: &Path, every: u64) -> Result<(), MyError> {
+        fs::create_dir_all(dir)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to create {}", dir.display()))
+            .change_context(MyError::Output)?;
+        self.snapshots = Some(Snapshots::new(dir, every));
+        Ok(())
+    }
+
+    /// the format of the client report: CSV (the default), a JSON array, or JSON lines. the JSON amounts are numbers,
+    /// regardless of the number format
+    pub fn set_report_format(&mut self, format: ReportFormat) {
+        self.report_format = format;
+    }
+
+    pub fn report_format(&self) -> ReportFormat {
+        self.report_format
+    }
+
+    /// round the amounts of the client report to `places` decimal places (at most 4) with the rounding policy. the
+    /// balances themselves keep 4, so a rounded total can differ from the sum of the rounded available and held
+    pub fn set_report_precision(&mut self, places: usize) {
+        self.report_precision = Some(places.min(DECIMAL_PLACES));
+    }
+
+    /// parse the amounts in CSV input, and format the amounts in the report, with `format` (ex: `1.234,56`).
+    /// amounts that contain the delimiter must be quoted in the input, and are quoted in the report
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        self.number_format = Some(format);
+    }
+
+    /// the unit of the amounts in CSV input. a file whose amount column is named `amount_cents` (or
+    /// `amount_ten_thousandths`) is read in that unit regardless. minor unit amounts are plain integers: the number
+    /// format doesn't apply to them
+    pub fn set_amount_unit(&mut self, unit: AmountUnit) {
+        self.amount_unit = unit;
+    }
+
+    /// the delimiter and quoting of CSV input. a comma by default, with `"` quotes
+    pub fn set_csv_dialect(&mut self, dialect: CsvDialect) {
+        self.csv_dialect = dialect;
+    }
+
+    /// start recording deposit and withdrawal ids, including rejected ones, to detect gaps in the txn_id sequence
+    pub fn enable_sequence_check(&mut self) {
+        self.sequence.get_or_insert_with(SequenceTracker::new);
+    }
+
+    /// the gaps in the txn_id sequence. None unless enable_sequence_check was called
+    pub fn sequence_gaps(&self) -> Option<Vec<SequenceGap>> {
+        self.sequence.as_ref().map(|tracker| tracker.gaps())
+    }
+
+    /// record every rejected transaction, and every CSV row that fails to parse, in `log`. rows of a batch that is
+    /// rolled back and retried can be recorded twice
+    pub fn set_rejects_log(&mut self, log: RejectsLog) {
+        self.rejects = Some(log);
+    }
+
+    /// flush the rejects log, if there is one
+    pub fn flush_rejects_log(&mut self) -> Result<(), MyError> {
+        match self.rejects.as_mut() {
+            Some(log) => log.flush(),
+            None => Ok(()),
         }
+    }
 
-        match txn {
-            Txn::BalanceTransfer(transfer) => {
-                // ignore withdrawals that exceed account balance
-                // in the event of a dispute, available funds may be negative. allow deposits in this case.
-                if transfer.amount < 0.0 && state.available + transfer.amount < 0.0 {
-                    return Ok(());
-                }
+    /// start recording deposits and withdrawals rejected for reusing a txn_id, next to the transfer that was applied
+    pub fn enable_duplicate_report(&mut self) {
+        self.duplicates.get_or_insert_with(DuplicateTracker::new);
+    }
 
-                // verify transaction_id is unique
-                if self.db.try_insert_balance_transfer(transfer)? {
-                    // update client state
-                    state.available += transfer.amount;
-                    self.num_processed += 1;
-                }
-            }
-            Txn::Dispute { client_id, txn_id } => {
-                // validate txn_id and client_id using the database relations
-                if self.db.try_insert_dispute(client_id, txn_id)? {
-                    let opt = self
-                        .db
-                        .get_balance_transfer(client_id, txn_id)
-                        .attach_printable_lazy(|| fmt_error!("process dispute failed"))?;
+    /// the txn_ids that appeared more than once. None unless enable_duplicate_report was called
+    pub fn duplicate_report(&self) -> Option<Vec<DuplicateTxn>> {
+        self.duplicates.as_ref().map(|tracker| tracker.report())
+    }
 
-                    let balance_transfer = match opt {
-                        Some(b) => b,
-                        None => bail!(MyError::GenericFmt(fmt_error!(
-                            "inserted dispute but get_balance_transfer returned None"
-                        ))),
-                    };
-
-                    // if it was a withdrawal, increase held by the amount but to not increase available funds
-                    if balance_transfer.amount < 0.0 {
-                        // because here balance_transfer is negative, this operation increases state.held
-                        state.held -= balance_transfer.amount;
-                    } else {
-                        // if it was a deposit, hold the funds and don't let them be spent -> decrease available funds
-                        state.held += balance_transfer.amount;
-                        state.available -= balance_transfer.amount;
-                    }
-                    self.num_processed += 1;
-                }
+    /// start totalling the transactions applied from now on, so `reconcile` can check them against the client totals
+    pub fn enable_reconciliation(&mut self) -> Result<(), MyError> {
+        let opening_total = self.sum_of_totals()?;
+        self.reconciliation = Some((opening_total, RunTotals::new()));
+        Ok(())
+    }
+
+    /// compare the change in the sum of the client totals with the transactions applied since enable_reconciliation.
+    /// None unless enable_reconciliation was called. check the result with `Reconciliation::is_balanced`
+    pub fn reconcile(&self) -> Result<Option<Reconciliation>, MyError> {
+        let (opening_total, totals) = match &self.reconciliation {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        Ok(Some(Reconciliation {
+            opening_total: *opening_total,
+            totals: totals.clone(),
+            closing_total: self.sum_of_totals()?,
+        }))
+    }
+
+    fn sum_of_totals(&self) -> Result<Amount, MyError> {
+        let mut sum = Amount::ZERO;
+        self.db
+            .process_all_clients(&mut |client| sum += client.total)?;
+        Ok(sum)
+    }
+
+    /// re-verify the client account after every applied transaction: total == available + held, held >= 0, and held
+    /// matches the open disputes. a violation is returned as a MyError::Invariant error
+    pub fn enable_invariant_checks(&mut self) {
+        self.check_invariants = true;
+    }
+
+    /// track each client's chargebacks as a fraction of its deposits over a rolling window of transactions
+    pub fn enable_chargeback_monitor(&mut self, thresholds: ChargebackThresholds) {
+        self.chargeback_monitor = Some(ChargebackMonitor::new(thresholds));
+    }
+
+    /// called when a transaction pushes a client over a chargeback threshold. it's called again only after the client
+    /// dropped below the thresholds. requires enable_chargeback_monitor
+    pub fn set_chargeback_alert<F: FnMut(&ChargebackRisk) + Send + 'static>(&mut self, alert: F) {
+        self.chargeback_alert = Some(Box::new(alert));
+    }
+
+    /// called with every transaction processed from now on, after it's applied or rejected. hooks are called in the
+    /// order they were added
+    pub fn add_hooks<H: ProcessorHooks + 'static>(&mut self, hooks: H) {
+        self.add_boxed_hooks(Box::new(hooks));
+    }
+
+    pub(crate) fn add_boxed_hooks(&mut self, hooks: Box<dyn ProcessorHooks>) {
+        self.hooks.push(hooks);
+    }
+
+    /// the clients currently above a chargeback threshold. None unless enable_chargeback_monitor was called
+    pub fn chargeback_risk_report(&mut self) -> Option<Vec<ChargebackRisk>> {
+        self.chargeback_monitor
+            .as_mut()
+            .map(|monitor| monitor.flagged())
+    }
+
+    /// append an entry to the store's hash-chained audit log for every transaction, applied or rejected.
+    /// continues the chain if the store already has a log
+    pub fn enable_audit_log(&mut self) -> Result<(), MyError> {
+        self.audit = Some(self.load_audit_chain()?);
+        self.audit_all = true;
+        Ok(())
+    }
+
+    fn load_audit_chain(&self) -> Result<AuditChain, MyError> {
+        let mut last = None;
+        self.db
+            .process_all_audit_entries(&mut |entry| last = Some(entry))?;
+        let mut chain = AuditChain::resume(last.as_ref());
+        chain.set_config_version(self.config_version.clone());
+        #[cfg(feature = "encryption")]
+        chain.set_field_key(self.field_key.clone());
+        Ok(chain)
+    }
+
+    /// the audit entries of transactions written from now on also record the input record, encrypted with `key`.
+    /// the ciphertext is part of the hash chain, so the log can still be verified without the key. `TxnDb::set_field_key`
+    /// encrypts the operators and reasons of the adjustments
+    #[cfg(feature = "encryption")]
+    pub fn set_field_key(&mut self, key: Option<FieldKey>) {
+        if let Some(chain) = self.audit.as_mut() {
+            chain.set_field_key(key.clone());
+        }
+        self.field_key = key;
+    }
+
+    /// apply the settings of `config` that are set. the audit entries written from now on record its version
+    pub fn apply_config(&mut self, config: &EngineConfig) {
+        tracing::info!(version = %config.version, "applying configuration");
+        if let Some(rounding) = config.rounding {
+            self.rounding = rounding;
+        }
+        if let Some(policy) = config.cross_client_disputes {
+            self.cross_client_disputes = policy;
+        }
+        if let Some(policy) = config.disputes {
+            self.disputes = policy;
+        }
+        if let Some(policy) = config.overdraft {
+            self.overdraft = policy;
+        }
+        self.limits = config.limits.or(self.limits);
+        if let Some(policy) = config.locked_accounts {
+            self.locked_accounts = policy;
+        }
+        if let Some(days) = config.dispute_window_days {
+            self.dispute_window = Some(days);
+        }
+        if let Some(schedule) = &config.rate_schedule {
+            self.rate_schedule = schedule.clone();
+        }
+        if let Some(thresholds) = config.chargeback_thresholds {
+            match self.chargeback_monitor.as_mut() {
+                Some(monitor) => monitor.set_thresholds(thresholds),
+                None => self.enable_chargeback_monitor(thresholds),
             }
-            Txn::Resolve { client_id, txn_id } => {
-                // validate txn_id and client_id using the database relations
-                if self.db.try_resolve_dispute(client_id, txn_id)? {
-                    let opt = self
-                        .db
-                        .get_balance_transfer(client_id, txn_id)
-                        .attach_printable_lazy(|| fmt_error!("resolved dispute failed"))?;
+        }
+        self.config_version = Some(config.version.clone());
+        if let Some(chain) = self.audit.as_mut() {
+            chain.set_config_version(self.config_version.clone());
+        }
+    }
 
-                    let balance_transfer = match opt {
-                        Some(b) => b,
-                        None => bail!(MyError::GenericFmt(fmt_error!(
-                            "resolved dispute but get_balance_transfer returned None"
-                        ))),
-                    };
-
-                    // the withdrawal was cleared
-                    if balance_transfer.amount < 0.0 {
-                        // because here balance_transfer is negative, this operation decreases state.held
-                        state.held += balance_transfer.amount;
-                    } else {
-                        // the deposit was cleared
-                        state.held -= balance_transfer.amount;
-                        state.available += balance_transfer.amount;
-                    }
-                    self.num_processed += 1;
-                }
+    /// the fee and interest rates with their effective dates. empty (no fees, no interest) by default. a deposit or
+    /// withdrawal is charged the fees in force on the day of its timestamp (or the day it's processed without one),
+    /// taken from the available funds and credited to `LedgerAccount::Fees`
+    pub fn set_rate_schedule(&mut self, schedule: RateSchedule) {
+        self.rate_schedule = schedule;
+    }
+
+    /// the rates in force on `date` according to the rate schedule
+    pub fn rates_at(&self, date: Date) -> Rates {
+        self.rate_schedule.rates_at(date)
+    }
+
+    // the fee of a deposit or withdrawal, at the rates of the day it happened (today if it has no timestamp). a
+    // deposit's fee is capped at its amount: a deposit never lowers the available funds. None if the fee doesn't
+    // fit an Amount
+    fn fee_of(&self, transfer: &BalanceTransfer) -> Option<Amount> {
+        if self.rate_schedule.is_empty() {
+            return Some(Amount::ZERO);
+        }
+        let date = transfer.timestamp.map_or_else(Date::today, Date::from_unix);
+        let fee = self
+            .rates_at(date)
+            .fee(transfer.amount.abs(), self.rounding)?;
+        if transfer.amount.is_negative() {
+            Some(fee)
+        } else {
+            Some(fee.min(transfer.amount))
+        }
+    }
+
+    /// hot reload: check `watcher` before every transaction and apply a changed configuration. the change takes
+    /// effect between two transactions, never in the middle of one
+    pub fn watch_config(&mut self, watcher: ConfigWatcher) {
+        self.config_watcher = Some(watcher);
+    }
+
+    /// the version of the last configuration applied. None if there wasn't one
+    pub fn config_version(&self) -> Option<&str> {
+        self.config_version.as_deref()
+    }
+
+    /// check that the audit log in the store hasn't been modified. returns the verifier, which holds the entry count and head hash
+    pub fn verify_audit_log(&self) -> Result<AuditVerifier, MyError> {
+        let mut verifier = AuditVerifier::new();
+        let mut res = Ok(());
+        self.db.process_all_audit_entries(&mut |entry| {
+            if res.is_ok() {
+                res = verifier.check(&entry);
             }
-            Txn::Chargeback { client_id, txn_id } => {
-                // validate txn_id and client_id using the database relations
-                if self.db.try_chargeback_dispute(client_id, txn_id)? {
-                    let opt = self
-                        .db
-                        .get_balance_transfer(client_id, txn_id)
-                        .attach_printable_lazy(|| fmt_error!("charged back dispute failed"))?;
+        })?;
+        res.map(|_| verifier)
+    }
 
-                    let balance_transfer = match opt {
-                        Some(b) => b,
-                        None => bail!(MyError::GenericFmt(fmt_error!(
-                            "charged back dispute but get_balance_transfer returned None"
-                        ))),
-                    };
-
-                    // the withdrawal was charged back. decrease state.held and increase state.available
-                    if balance_transfer.amount < 0.0 {
-                        // because here balance_transfer is negative, this operation decreases state.held
-                        state.held += balance_transfer.amount;
-                        state.available -= balance_transfer.amount;
-                    } else {
-                        // a deposit was charged back. decrease state.held but not state.available
-                        state.held -= balance_transfer.amount;
-                        // state.available was already deducted at the time of the dispute. don't need to deduct it here.
-                    }
-                    state.locked = LockedState::Locked;
-                    self.num_processed += 1;
-                }
+    /// export the audit log as JSON lines. `audit::verify_file` checks the export
+    pub fn write_audit_log<W: io::Write>(&self, mut writer: W) -> Result<(), MyError> {
+        let mut res = Ok(());
+        self.db.process_all_audit_entries(&mut |entry| {
+            if res.is_ok() {
+                res = audit::write_entries(&mut writer, [&entry]);
             }
-        }
+        })?;
+        res
+    }
 
-        state.total = state.available + state.held;
-        self.db.update_client_state(&state)?;
+    /// process a Parquet file (feature "parquet") with the columns type, client, tx, amount, and optionally timestamp
+    /// and original_tx, in any order. a row with a null or a value that doesn't fit the model is malformed, like a CSV
+    /// record with an invalid format. with set_commit_every, the transactions are grouped as in process_csv
+    #[cfg(feature = "parquet")]
+    pub fn process_parquet<R: ::parquet::file::reader::ChunkReader + 'static>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), MyError> {
+        let span = tracing::info_span!("process_parquet");
+        let _guard = span.enter();
+        self.process_rows(crate::parquet::read_rows(reader)?)
+    }
 
-        Ok(())
+    /// process an Avro object container file. the writer's schema must be a record with the fields type, client, and
+    /// tx, and optionally amount, timestamp, and original_tx; other fields are skipped. a value that doesn't fit the
+    /// model makes the row malformed, as in process_parquet
+    #[cfg(feature = "avro")]
+    pub fn process_avro<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        let span = tracing::info_span!("process_avro");
+        let _guard = span.enter();
+        self.process_rows(crate::avro_container::read_rows(reader)?)
     }
 
-    pub fn validate_raw_input(&self, txn: &RawTxnInput) -> Option<Txn> {
-        match txn.txn_type {
-            TxnType::Invalid => None,
-            TxnType::Deposit => {
-                let amount = txn.amount.unwrap_or(-1.0);
-                if amount <= 0.0 {
-                    return None;
+    /// process an Arrow record batch (feature "arrow") with the columns type, client, tx, amount, and optionally
+    /// timestamp and original_tx, in any order. each column is cast once, without copying a column that already has the
+    /// type of its field (Utf8, Int64, Float64). rows are malformed as in process_parquet. the accounts carry over from
+    /// one batch to the next
+    #[cfg(feature = "arrow")]
+    pub fn process_record_batch(
+        &mut self,
+        batch: &crate::arrow::RecordBatch,
+    ) -> Result<(), MyError> {
+        let span = tracing::info_span!("process_record_batch", rows = batch.num_rows());
+        let _guard = span.enter();
+        self.process_rows(crate::arrow::batch_rows(batch)?.map(Ok))
+    }
+
+    /// process a stream of length-delimited protobuf `Transaction` messages (see proto/transaction.proto). a message
+    /// with an id that doesn't fit the model is malformed, as in process_parquet
+    #[cfg(feature = "protobuf")]
+    pub fn process_protobuf<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        let span = tracing::info_span!("process_protobuf");
+        let _guard = span.enter();
+        self.process_rows(crate::protobuf::read_rows(reader))
+    }
+
+    // process the rows of a file that isn't CSV. a read error stops the processing after the rows before it
+    #[cfg(any(feature = "arrow", feature = "avro", feature = "protobuf"))]
+    fn process_rows(
+        &mut self,
+        rows: impl Iterator<Item = Result<InputRow, MyError>>,
+    ) -> Result<(), MyError> {
+        let keep_malformed = self.rejects.is_some() || self.strict;
+        let mut read_error = None;
+        let rows = rows
+            .map_while(|row| row.map_err(|e| read_error = Some(e)).ok())
+            .filter(|row| keep_malformed || matches!(row, InputRow::Txn(_)))
+            .map(|row| (None, row));
+        self.process_stream(rows)?;
+        match read_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// process a CSV stream with a header row, skipping records with invalid formats (see set_strict). with
+    /// set_commit_every, the transactions are grouped into store transactions and a failure rolls back the unfinished
+    /// batch
+    pub fn process_csv<R: io::Read>(&mut self, reader: R) -> Result<(), MyError> {
+        let span = tracing::info_span!("process_csv");
+        let _guard = span.enter();
+        let start = self.stats.clone();
+        let format = self.csv_format();
+        let mut csv_reader = format.reader(reader)?;
+        let columns = format.columns_of(&mut csv_reader)?;
+        // deserialize the records. invalid formats are skipped, or passed on for the rejects log and strict mode
+        let keep_malformed = self.rejects.is_some() || self.strict;
+        let mut read_error = None;
+        let records = csv_reader
+            .records()
+            .map_while(|record| read_record(record).map_err(|e| read_error = Some(e)).ok());
+        let rows = records.filter_map(|record| {
+            let line = line_of(&record);
+            let original = keep_malformed.then(|| record.clone());
+            match format.deserialize(record, columns) {
+                Some(txn) => Some((line, InputRow::Txn(txn))),
+                None => {
+                    tracing::debug!(line, "malformed row");
+                    original.map(|record| (line, InputRow::Malformed(record)))
                 }
-                Some(Txn::BalanceTransfer(BalanceTransfer {
-                    client_id: txn.client_id,
-                    txn_id: txn.txn_id,
-                    amount,
-                }))
             }
-            TxnType::Withdrawal => {
-                let amount = txn.amount.unwrap_or(-1.0);
-                if amount <= 0.0 {
-                    return None;
+        });
+        self.process_stream(rows)?;
+        tracing::debug!(
+            applied = self.stats.applied() - start.applied(),
+            rejected = self.stats.rejections() - start.rejections(),
+            "read the input"
+        );
+        match read_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // applies the transactions in store transactions of commit_every. a failure rolls back the unfinished batch. the
+    // rows come with the line they were read from, if known
+    pub(crate) fn process_stream(
+        &mut self,
+        rows: impl IntoIterator<Item = (Option<u64>, InputRow)>,
+    ) -> Result<(), MyError> {
+        // a batch of one is left to the store's autocommit
+        let batched = self.commit_every > 1;
+        let mut pending = 0;
+        for (line, row) in rows {
+            let txn = match row {
+                InputRow::Txn(txn) => txn,
+                InputRow::Malformed(record) => {
+                    let res = self.record_malformed(&record);
+                    if let Err(e) = res.and_then(|_| self.check_malformed(line, &record)) {
+                        if batched && pending > 0 {
+                            self.rollback();
+                        }
+                        return Err(e);
+                    }
+                    continue;
                 }
-                Some(Txn::BalanceTransfer(BalanceTransfer {
-                    client_id: txn.client_id,
-                    txn_id: txn.txn_id,
-                    amount: -amount,
-                }))
+            };
+            if batched && pending == 0 {
+                self.db.begin()?;
             }
-            TxnType::Dispute => {
-                if txn.amount.is_some() {
-                    return None;
+            let res = self.process(txn);
+            if let Err(e) = res.and_then(|events| self.check_rejected(line, &events)) {
+                if batched {
+                    self.rollback();
                 }
-                Some(Txn::Dispute {
-                    client_id: txn.client_id,
-                    txn_id: txn.txn_id,
-                })
+                return Err(e);
             }
-            TxnType::Resolve => {
-                if txn.amount.is_some() {
-                    return None;
-                }
-                Some(Txn::Resolve {
-                    client_id: txn.client_id,
-                    txn_id: txn.txn_id,
+            pending += 1;
+            if batched && pending == self.commit_every {
+                pending = 0;
+                self.commit_batch()?;
+            }
+        }
+        if batched && pending > 0 {
+            self.commit_batch()?;
+        }
+        Ok(())
+    }
+
+    fn record_malformed(&mut self, record: &StringRecord) -> Result<(), MyError> {
+        match self.rejects.as_mut() {
+            Some(log) => log.record_malformed(record),
+            None => Ok(()),
+        }
+    }
+
+    // in strict mode, an input row that didn't parse is an error
+    fn check_malformed(&self, line: Option<u64>, record: &StringRecord) -> Result<(), MyError> {
+        if !self.strict {
+            return Ok(());
+        }
+        let fields: Vec<&str> = record.iter().collect();
+        Err(
+            report!(MyError::MalformedRecord).attach_printable(fmt_error!(
+                "{}: invalid input row {:?}",
+                at_line(line),
+                fields
+            )),
+        )
+    }
+
+    // in strict mode, a rejected transaction is an error
+    fn check_rejected(&self, line: Option<u64>, events: &[EngineEvent]) -> Result<(), MyError> {
+        match events.first() {
+            Some(EngineEvent::TransactionRejected {
+                client_id,
+                txn_id,
+                txn_type,
+                reason,
+            }) if self.strict => Err(report!(MyError::Rejected).attach_printable(fmt_error!(
+                "{}: {} {} of client {} rejected: {:?}",
+                at_line(line),
+                txn_type,
+                txn_id,
+                client_id,
+                reason
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn commit_batch(&mut self) -> Result<(), MyError> {
+        let res = self.db.commit();
+        if res.is_err() {
+            self.rollback();
+        }
+        res
+    }
+
+    // how this processor reads CSV input
+    pub(crate) fn csv_format(&self) -> CsvFormat {
+        CsvFormat {
+            dialect: self.csv_dialect,
+            number_format: self.number_format,
+            amount_unit: self.amount_unit,
+        }
+    }
+
+    /// like process_csv, but crash safe. each row (or batch of rows, with set_commit_every) is applied in its own store
+    /// transaction together with a checkpoint counting the rows of `run_id` that are done. if an earlier run with the
+    /// same id stopped part way (a crash, a storage error), its committed rows are skipped and processing continues
+    /// where it stopped: the input is the journal. returns the number of rows that were skipped
+    pub fn process_csv_resumable<R: io::Read>(
+        &mut self,
+        reader: R,
+        run_id: &str,
+    ) -> Result<u64, MyError> {
+        let span = tracing::info_span!("process_csv_resumable", run_id);
+        let _guard = span.enter();
+        let done = self.db.get_checkpoint(run_id)?.unwrap_or(0);
+        if done > 0 {
+            tracing::info!(run_id, rows = done, "resuming a partially processed run");
+        }
+
+        let format = self.csv_format();
+        let mut csv_reader = format.reader(reader)?;
+        let columns = format.columns_of(&mut csv_reader)?;
+        // rows with invalid formats are counted too, so the row numbers stay the same across runs
+        let mut batch = Vec::new();
+        for (idx, record) in csv_reader.records().enumerate() {
+            let row = idx as u64 + 1;
+            if row <= done {
+                continue;
+            }
+            batch.push((row, read_record(record)?));
+            if batch.len() as u64 >= self.commit_every {
+                self.apply_batch(&batch, columns, run_id)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.apply_batch(&batch, columns, run_id)?;
+        }
+        Ok(done)
+    }
+
+    // applies the rows in one store transaction, retrying the whole batch while the store is busy
+    fn apply_batch(
+        &mut self,
+        batch: &[(u64, StringRecord)],
+        columns: Columns,
+        run_id: &str,
+    ) -> Result<(), MyError> {
+        let mut attempt = 0;
+        loop {
+            let res = self
+                .db
+                .begin()
+                .and_then(|_| {
+                    batch.iter().try_for_each(|(row, record)| {
+                        self.apply_row(record.clone(), columns, run_id, *row)
+                    })
                 })
+                .and_then(|_| self.db.commit());
+            let e = match res {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            self.rollback();
+            if !matches!(e.current_context(), MyError::Busy) || attempt >= self.busy_retries {
+                return Err(e);
             }
-            TxnType::Chargeback => {
-                if txn.amount.is_some() {
-                    return None;
+            attempt += 1;
+            tracing::debug!(row = batch[0].0, attempt, "store busy, retrying the batch");
+            std::thread::sleep(std::time::Duration::from_millis(1 << attempt.min(10)));
+        }
+    }
+
+    // rolls back the open store transaction. the rolled back rows may have been audited, so the audit chain is reloaded
+    fn rollback(&mut self) {
+        // the error being returned is more useful than a rollback failure
+        let _ = self.db.rollback();
+        if self.audit.is_some() {
+            if let Ok(chain) = self.load_audit_chain() {
+                self.audit = Some(chain);
+            }
+        }
+    }
+
+    fn apply_row(
+        &mut self,
+        record: StringRecord,
+        columns: Columns,
+        run_id: &str,
+        row: u64,
+    ) -> Result<(), MyError> {
+        // deserialize it, skip invalid formats
+        let format = self.csv_format();
+        let line = line_of(&record);
+        let original = (self.rejects.is_some() || self.strict).then(|| record.clone());
+        match (format.deserialize(record, columns), original) {
+            (Some(txn), _) => {
+                let events = self.process(txn)?;
+                self.check_rejected(line, &events)?;
+            }
+            (None, Some(original)) => {
+                self.record_malformed(&original)?;
+                self.check_malformed(line, &original)?;
+            }
+            (None, None) => {}
+        }
+        self.db.set_checkpoint(run_id, row)
+    }
+
+    /// counts of the transactions processed so far: applied by type, rejected by reason, and the accounts created
+    /// and locked
+    pub fn stats(&self) -> &ProcessingStats {
+        &self.stats
+    }
+
+    /// the current state of one client account. None if the client has never been seen
+    pub fn client_state(&mut self, client_id: ClientId) -> Result<Option<ClientState>, MyError> {
+        self.db.get_client_state(client_id)
+    }
+
+    /// the double-entry ledger built from every posting in the store
+    pub fn ledger(&self) -> Result<Ledger, MyError> {
+        let mut ledger = Ledger::new();
+        self.db
+            .process_all_postings(&mut |posting| ledger.post(&posting))?;
+        Ok(ledger)
+    }
+
+    /// the statement of one client account, built from the stored postings. None if the client has never been seen
+    pub fn statement(&mut self, client_id: ClientId) -> Result<Option<Statement>, MyError> {
+        let state = match self.db.get_client_state(client_id)? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let mut postings = Vec::new();
+        self.db
+            .process_client_postings(client_id, &mut |posting| postings.push(posting))?;
+        let mut statement = Statement::new(client_id, postings);
+        statement.closing.locked = state.locked;
+        for line in &mut statement.lines {
+            if matches!(
+                line.event,
+                StatementEvent::Deposit | StatementEvent::Withdrawal
+            ) {
+                line.timestamp = self
+                    .db
+                    .get_balance_transfer(client_id, line.txn_id)?
+                    .and_then(|transfer| transfer.timestamp);
+            }
+            // the journal can't tell a refund from a withdrawal
+            if line.event == StatementEvent::Withdrawal
+                && self.db.get_refund(client_id, line.txn_id)?.is_some()
+            {
+                line.event = StatementEvent::Refund;
+            }
+        }
+        Ok(Some(statement))
+    }
+
+    /// gives the client its own overdraft limit, which takes precedence over the `OverdraftPolicy`, or with None
+    /// goes back to the policy. the account is created if the client is new. stored in the "Clients" table with
+    /// SQLite, and appended to the audit log if it's enabled
+    pub fn set_overdraft_limit(
+        &mut self,
+        client_id: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), MyError> {
+        if limit.is_some_and(|limit| limit.is_negative()) {
+            return Err(report!(MyError::InvalidRequest)
+                .attach_printable(fmt_error!("an overdraft limit can't be negative")));
+        }
+        self.admin_action(|tp| {
+            tp.ensure_client(client_id)?;
+            tp.db.set_overdraft_limit(client_id, limit)?;
+            let outcome = match limit {
+                Some(limit) => format!("overdraft limit set to {}", limit),
+                None => "overdraft limit removed".to_string(),
+            };
+            tp.audit_action("set_overdraft_limit", client_id, 0, &outcome)
+        })
+    }
+
+    /// the overdraft limit of the client: its own, or the policy's. None if there is no limit
+    pub fn overdraft_limit(&self, client_id: ClientId) -> Result<Option<Amount>, MyError> {
+        match self.db.get_overdraft_limit(client_id)? {
+            Some(limit) => Ok(Some(limit)),
+            None => Ok(self.overdraft.limit()),
+        }
+    }
+
+    /// gives the client its own limits, which take precedence over the processor's one limit at a time, or with None
+    /// goes back to the processor's. the account is created if the client is new. stored in the "Limits" table with
+    /// SQLite, and appended to the audit log if it's enabled
+    pub fn set_client_limits(
+        &mut self,
+        client_id: ClientId,
+        limits: Option<Limits>,
+    ) -> Result<(), MyError> {
+        if let Some(limits) = limits {
+            let limits = [
+                limits.max_balance,
+                limits.max_deposit,
+                limits.max_withdrawal,
+            ];
+            if limits
+                .into_iter()
+                .flatten()
+                .any(|limit| limit.is_negative())
+            {
+                return Err(report!(MyError::InvalidRequest)
+                    .attach_printable(fmt_error!("a limit can't be negative")));
+            }
+        }
+        self.admin_action(|tp| {
+            tp.ensure_client(client_id)?;
+            tp.db.set_client_limits(client_id, limits)?;
+            let outcome = match limits {
+                Some(limits) => format!("limits set to {}", limits),
+                None => "limits removed".to_string(),
+            };
+            tp.audit_action("set_limits", client_id, 0, &outcome)
+        })
+    }
+
+    /// the limits that apply to the client: each of its own that is set, otherwise the processor's
+    pub fn client_limits(&self, client_id: ClientId) -> Result<Limits, MyError> {
+        Ok(self
+            .db
+            .get_client_limits(client_id)?
+            .unwrap_or_default()
+            .or(self.limits))
+    }
+
+    /// unlocks an account locked by a chargeback, then applies the deposits queued by
+    /// `LockedAccountPolicy::QueueDeposits` in the order they arrived. a queued deposit can still be rejected, ex:
+    /// if its id was used since. if the audit log is enabled the unlocking is appended to it.
+    /// returns AccountUnlocked followed by the events of the queued deposits
+    pub fn unlock_account(&mut self, client_id: ClientId) -> Result<Vec<EngineEvent>, MyError> {
+        let mut state = match self.db.get_client_state(client_id)? {
+            Some(s) => s,
+            None => {
+                return Err(report!(MyError::UnknownClient)
+                    .attach_printable(fmt_error!("unknown client {}", client_id)))
+            }
+        };
+        if !state.is_locked() {
+            return Err(report!(MyError::InvalidRequest)
+                .attach_printable(fmt_error!("client {} isn't locked", client_id)));
+        }
+
+        self.admin_action(|tp| {
+            state.locked = LockedState::Unlocked;
+            tp.db.update_client_state(&state)?;
+            let queued = tp.db.take_queued_deposits(client_id)?;
+            let outcome = format!("unlocked, {} queued deposit(s)", queued.len());
+            tp.audit_action("unlock_account", client_id, 0, &outcome)?;
+
+            let mut events = vec![EngineEvent::AccountUnlocked { client_id }];
+            tp.log_events(&events)?;
+            // the queued deposits were admitted when they arrived: they aren't rate limited or deduplicated again
+            let limiter = tp.rate_limiter.take();
+            let record_hashes = std::mem::take(&mut tp.record_hashes);
+            let mut res = Ok(());
+            for deposit in queued {
+                match tp.process(RawTxnInput {
+                    txn_type: TxnType::Deposit,
+                    client_id,
+                    txn_id: deposit.txn_id,
+                    amount: Some(deposit.amount.into()),
+                    timestamp: deposit.timestamp,
+                    original_txn_id: None,
+                }) {
+                    Ok(applied) => events.extend(applied),
+                    Err(e) => {
+                        res = Err(e);
+                        break;
+                    }
                 }
-                Some(Txn::Chargeback {
-                    client_id: txn.client_id,
-                    txn_id: txn.txn_id,
-                })
             }
+            tp.rate_limiter = limiter;
+            tp.record_hashes = record_hashes;
+            res.map(|_| events)
+        })
+    }
+
+    /// GDPR-style erasure of a client's transaction history. its deposits, withdrawals, and disputes are deleted and
+    /// its postings are replaced with a sealed summary (`ledger::seal`), so every ledger balance and the account
+    /// itself are unchanged. refused while the client has open disputes.
+    /// if the audit log is enabled the erasure is appended to it. existing audit entries are kept: removing them
+    /// would break the hash chain. returns the number of balance transfers deleted
+    pub fn forget_client(&mut self, client_id: ClientId) -> Result<usize, MyError> {
+        let state = match self.db.get_client_state(client_id)? {
+            Some(s) => s,
+            None => {
+                return Err(report!(MyError::UnknownClient)
+                    .attach_printable(fmt_error!("unknown client {}", client_id)))
+            }
+        };
+        let open: Vec<TransactionId> = self
+            .db
+            .get_open_disputes(client_id)?
+            .iter()
+            .map(|txn| txn.txn_id)
+            .collect();
+        if !open.is_empty() {
+            return Err(report!(MyError::OpenDisputes).attach_printable(fmt_error!(
+                "client {} has open disputes: {:?}",
+                client_id,
+                open
+            )));
         }
+
+        let mut postings = Vec::new();
+        self.db
+            .process_client_postings(client_id, &mut |posting| postings.push(posting))?;
+        let summary = ledger::seal(&postings);
+
+        self.admin_action(|tp| {
+            let deleted = tp.db.forget_client(client_id, &summary)?;
+            // like the postings, the erased events are replaced by the balances they left
+            for event in LoggedEvent::opening(&state) {
+                tp.db.append_event(&event)?;
+            }
+            let outcome = format!(
+                "forgot {} balance transfer(s), sealed {} posting(s) into {}",
+                deleted,
+                postings.len(),
+                summary.len()
+            );
+            tp.audit_action("forget_client", client_id, 0, &outcome)?;
+            Ok(deleted)
+        })
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// administrative reopening of a resolved dispute, ex: when new evidence arrives. the funds are held again, as
+    /// if the transaction had been disputed a second time, and the resolution is moved to the dispute history
+    /// (`TxnStore::count_dispute_reopens`). refused for locked accounts and for disputes that aren't resolved: a
+    /// chargeback is final. if the audit log is enabled the reopening is appended to it
+    pub fn reopen_dispute(
+        &mut self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Vec<EngineEvent>, MyError> {
+        let mut state = match self.db.get_client_state(client_id)? {
+            Some(s) => s,
+            None => {
+                return Err(report!(MyError::UnknownClient)
+                    .attach_printable(fmt_error!("unknown client {}", client_id)))
+            }
+        };
+        if state.is_locked() {
+            return Err(report!(MyError::AccountLocked)
+                .attach_printable(fmt_error!("client {} is locked", client_id)));
+        }
 
-    fn init() -> TransactionProcessor {
-        let _ = env_logger::builder().is_test(true).try_init();
-        TransactionProcessor::new().unwrap()
+        let events = self.admin_action(|tp| {
+            if !tp.db.try_reopen_dispute(client_id, txn_id)? {
+                return Err(
+                    report!(MyError::UnknownTransfer).attach_printable(fmt_error!(
+                        "txn {} of client {} doesn't have a resolved dispute",
+                        txn_id,
+                        client_id
+                    )),
+                );
+            }
+            let balance_transfer = match tp.db.get_balance_transfer(client_id, txn_id)? {
+                Some(b) => b,
+                None => {
+                    return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                        "reopened dispute but get_balance_transfer returned None"
+                    )))
+                }
+            };
+            tp.post(&mut state, &ledger::dispute_postings(&balance_transfer))?;
+            tp.audit_action("reopen_dispute", client_id, txn_id, "reopened")?;
+            let events = vec![EngineEvent::DisputeReopened {
+                client_id,
+                txn_id,
+                amount: balance_transfer.amount,
+            }];
+            tp.log_events(&events)?;
+            Ok(events)
+        })?;
+
+        if let Some((_, totals)) = self.reconciliation.as_mut() {
+            totals.observe(&events);
+        }
+        Ok(events)
     }
 
-    fn apply_transactions(csv: &str, processor: &mut TransactionProcessor) {
-        let mut csv_reader = csv::Reader::from_reader(csv.as_bytes());
-        for mut string_record in csv_reader.records().flatten() {
-            string_record.trim();
-            // deserialize it, skip invalid formats
-            if let Ok(txn) = string_record.deserialize(None) {
-                processor.process(txn).unwrap();
+    /// manually adjust a client's available funds. the adjustment is stored with its reason code and operator and is
+    /// posted against `LedgerAccount::Adjustments`, keeping it apart from the client transactions.
+    /// a negative adjustment can't take the available funds below zero unless `allow_overdraft` is set.
+    /// unlike transactions, adjustments apply to locked accounts. if the audit log is enabled the adjustment is
+    /// appended to it
+    pub fn adjust(
+        &mut self,
+        adjustment: Adjustment,
+        allow_overdraft: bool,
+    ) -> Result<Vec<EngineEvent>, MyError> {
+        let client_id = adjustment.client_id;
+        let amount = adjustment.amount;
+        if amount.is_zero() {
+            return Err(report!(MyError::InvalidRequest)
+                .attach_printable(fmt_error!("invalid adjustment amount {}", amount)));
+        }
+        if adjustment.operator.trim().is_empty() {
+            return Err(report!(MyError::InvalidRequest)
+                .attach_printable(fmt_error!("an adjustment needs an operator")));
+        }
+        let mut state = match self.db.get_client_state(client_id)? {
+            Some(s) => s,
+            None => {
+                return Err(report!(MyError::UnknownClient)
+                    .attach_printable(fmt_error!("unknown client {}", client_id)))
+            }
+        };
+        if !allow_overdraft && (state.available + amount).is_negative() {
+            return Err(
+                report!(MyError::InsufficientFunds).attach_printable(fmt_error!(
+                    "adjustment of {} exceeds the available funds of client {} ({})",
+                    amount,
+                    client_id,
+                    state.available
+                )),
+            );
+        }
+
+        let events = self.admin_action(|tp| {
+            tp.db.insert_adjustment(&adjustment)?;
+            tp.post(&mut state, &ledger::adjustment_postings(&adjustment))?;
+            let outcome = format!(
+                "adjusted by {} ({} by {})",
+                amount, adjustment.reason, adjustment.operator
+            );
+            tp.audit_action("adjust", client_id, 0, &outcome)?;
+            let events = vec![EngineEvent::BalanceAdjusted {
+                client_id,
+                amount,
+                reason: adjustment.reason,
+            }];
+            tp.log_events(&events)?;
+            Ok(events)
+        })?;
+
+        if let Some((_, totals)) = self.reconciliation.as_mut() {
+            totals.observe(&events);
+        }
+        Ok(events)
+    }
+
+    /// accrue daily interest on the positive available balances, at the yearly `interest_rate` of the rate schedule,
+    /// from the day after interest was last accrued through `through` (only `through` the first time). the interest
+    /// is posted to each client as a synthetic transaction against `LedgerAccount::Interest`, as one unit of work
+    /// that also records `through`, so accruing twice for the same day pays nothing. applies to locked accounts like
+    /// adjustments do. if the audit log is enabled each accrual is appended to it
+    pub fn accrue_interest(&mut self, through: Date) -> Result<Vec<EngineEvent>, MyError> {
+        let from = match self.db.get_interest_accrued_through()? {
+            Some(accrued) if accrued >= through => return Ok(Vec::new()),
+            Some(accrued) => accrued.succ(),
+            None => through,
+        };
+        let states = self.client_states()?;
+
+        let events = self.admin_action(|tp| {
+            let mut events = Vec::new();
+            for mut state in states {
+                let amount = interest::accrue(
+                    state.available,
+                    &tp.rate_schedule,
+                    from,
+                    through,
+                    tp.rounding,
+                )
+                .ok_or_else(|| {
+                    report!(MyError::InvalidRequest).attach_printable(fmt_error!(
+                        "the interest of client {} is too large for an amount",
+                        state.client_id
+                    ))
+                })?;
+                if !amount.is_positive() {
+                    continue;
+                }
+                let client_id = state.client_id;
+                tp.post(&mut state, &ledger::interest_postings(client_id, amount))?;
+                let outcome = format!("accrued {} from {} through {}", amount, from, through);
+                tp.audit_action("accrue_interest", client_id, 0, &outcome)?;
+                events.push(EngineEvent::InterestAccrued { client_id, amount });
+            }
+            tp.log_events(&events)?;
+            tp.db.set_interest_accrued_through(through)?;
+            Ok(events)
+        })?;
+
+        if let Some((_, totals)) = self.reconciliation.as_mut() {
+            totals.observe(&events);
+        }
+        Ok(events)
+    }
+
+    /// seed the client accounts from the report of a previous run (`client,available,held,total,locked`), so daily
+    /// batches can chain without keeping the earlier transactions online. the balances are posted against
+    /// `LedgerAccount::OpeningBalances`. every client must be new to the store, and the file is loaded as one unit
+    /// of work: a bad row loads nothing. the held funds of the previous run aren't backed by disputes in this store,
+    /// so they can't be resolved or charged back. returns the number of clients seeded
+    pub fn load_initial_balances<R: io::Read>(&mut self, reader: R) -> Result<usize, MyError> {
+        let mut csv_reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut states = Vec::new();
+        for (idx, record) in csv_reader.records().enumerate() {
+            let record = record
+                .report()
+                .attach_printable_lazy(|| fmt_error!("invalid initial balances"))
+                .change_context(MyError::FileReader)?;
+            let state = self.parse_client_state(&record).ok_or_else(|| {
+                report!(MyError::FileReader).attach_printable(fmt_error!(
+                    "invalid initial balance on line {}: {:?}",
+                    idx + 2,
+                    record.iter().collect::<Vec<_>>()
+                ))
+            })?;
+            states.push(state);
+        }
+
+        let seeded = self.admin_action(|tp| {
+            let mut seeded_total = Amount::ZERO;
+            for seed in &states {
+                if tp.db.get_client_state(seed.client_id)?.is_some() {
+                    return Err(report!(MyError::ClientExists)
+                        .attach_printable(fmt_error!("client {} already exists", seed.client_id)));
+                }
+                let mut state = tp.db.create_client_state(seed.client_id)?;
+                state.locked = seed.locked.clone();
+                tp.post(&mut state, &ledger::opening_postings(seed))?;
+                for event in LoggedEvent::opening(&state) {
+                    tp.db.append_event(&event)?;
+                }
+                let outcome = format!("seeded with {}", state);
+                tp.audit_action("seed", seed.client_id, 0, &outcome)?;
+                seeded_total += state.total;
+            }
+            Ok(seeded_total)
+        })?;
+
+        self.stats.clients_created += states.len() as u64;
+        for state in &states {
+            if !state.held.is_zero() {
+                self.opening_held.insert(state.client_id, state.held);
+            }
+        }
+        // the seeded funds were there before the run started
+        if let Some((opening_total, _)) = self.reconciliation.as_mut() {
+            *opening_total += seeded;
+        }
+        Ok(states.len())
+    }
+
+    // a row of the report. None if it's invalid
+    fn parse_client_state(&self, record: &StringRecord) -> Option<ClientState> {
+        if record.len() != 5 {
+            return None;
+        }
+        let amount = |idx: usize| -> Option<Amount> {
+            let field = record.get(idx)?;
+            match &self.number_format {
+                Some(format) => Amount::parse(&format.normalize(field)?, self.rounding),
+                None => Amount::parse(field, self.rounding),
+            }
+        };
+        let state = ClientState {
+            client_id: record.get(0)?.parse().ok()?,
+            available: amount(1)?,
+            held: amount(2)?,
+            total: amount(3)?,
+            locked: match record.get(4)? {
+                "true" => LockedState::Locked,
+                "false" => LockedState::Unlocked,
+                _ => return None,
+            },
+        };
+        (!state.held.is_negative() && state.total == state.available + state.held).then_some(state)
+    }
+
+    /// recompute every client account from the event log alone, ex: to recover from a corrupted Clients table. the
+    /// accounts that don't match the log are overwritten as one unit of work and returned, sorted by client id.
+    /// accounts without events are reset to empty, unlocked accounts. refused if the log is empty while some account
+    /// has funds: the store was written before the log existed. if the audit log is enabled each correction is
+    /// appended to it
+    pub fn rebuild(&mut self) -> Result<Vec<ClientState>, MyError> {
+        let mut events = Vec::new();
+        self.db
+            .process_all_events(&mut |event| events.push(event))?;
+        let current = self.client_states()?;
+        if events.is_empty() && current.iter().any(|s| !s.total.is_zero()) {
+            return Err(
+                report!(MyError::InvalidRequest).attach_printable(fmt_error!(
+                    "the event log is empty but the accounts have funds: it can't rebuild them"
+                )),
+            );
+        }
+
+        let mut rebuilt = event_log::rebuild(events);
+        for state in &current {
+            rebuilt
+                .entry(state.client_id)
+                .or_insert_with(|| ClientState::new(state.client_id));
+        }
+        let current: HashMap<ClientId, ClientState> =
+            current.into_iter().map(|s| (s.client_id, s)).collect();
+        let corrected: Vec<ClientState> = rebuilt
+            .into_values()
+            .filter(|state| match current.get(&state.client_id) {
+                Some(s) => {
+                    (s.available, s.held, s.total, s.locked.to_u8())
+                        != (
+                            state.available,
+                            state.held,
+                            state.total,
+                            state.locked.to_u8(),
+                        )
+                }
+                None => true,
+            })
+            .collect();
+
+        self.admin_action(|tp| {
+            for state in &corrected {
+                tp.ensure_client(state.client_id)?;
+                tp.db.update_client_state(state)?;
+                let outcome = format!("rebuilt as {}", state);
+                tp.audit_action("rebuild", state.client_id, 0, &outcome)?;
+            }
+            Ok(())
+        })?;
+        Ok(corrected)
+    }
+
+    /// every manual adjustment, oldest first
+    pub fn adjustments(&self) -> Result<Vec<Adjustment>, MyError> {
+        let mut adjustments = Vec::new();
+        self.db
+            .process_all_adjustments(&mut |adjustment| adjustments.push(adjustment))?;
+        Ok(adjustments)
+    }
+
+    // runs an administrative action as one unit of work. on failure the store is rolled back and the audit chain is
+    // reloaded, because the rolled back entry may have advanced it
+    fn admin_action<T>(
+        &mut self,
+        action: impl FnOnce(&mut Self) -> Result<T, MyError>,
+    ) -> Result<T, MyError> {
+        if self.in_dry_run {
+            return action(self);
+        }
+        self.db.begin()?;
+        match action(self) {
+            Ok(res) => {
+                self.db.commit()?;
+                Ok(res)
+            }
+            Err(e) => {
+                self.rollback();
+                Err(e)
+            }
+        }
+    }
+
+    fn audit_action(
+        &mut self,
+        action: &str,
+        client_id: ClientId,
+        txn_id: TransactionId,
+        outcome: &str,
+    ) -> Result<(), MyError> {
+        self.load_locked_audit_chain()?;
+        if let Some(chain) = self.audit.as_mut() {
+            let entry = chain.next_action_entry(action, client_id, txn_id, outcome);
+            self.db.append_audit_entry(&entry)?;
+            chain.advance(&entry);
+        }
+        Ok(())
+    }
+
+    /// the current state of every client account, sorted by client id
+    pub fn client_states(&self) -> Result<Vec<ClientState>, MyError> {
+        let mut states = Vec::new();
+        self.db
+            .process_all_clients(&mut |client| states.push(client))?;
+        Ok(states)
+    }
+
+    /// write_report to stdout, for the executable. library users call write_report with their own writer, or read
+    /// the accounts with client_states
+    pub fn display(&self) -> Result<(), MyError> {
+        self.write_report(io::stdout().lock())
+    }
+
+    /// write the client report, one row per client sorted by client id, in the report format (CSV by default, see set_report_format)
+    pub fn write_report<W: io::Write>(&self, writer: W) -> Result<(), MyError> {
+        let mut res = self.report_writer(writer);
+        self.db.process_all_clients(&mut |client| {
+            if let Ok(report) = res.as_mut() {
+                if let Err(e) = report.row(&client) {
+                    res = Err(e);
+                }
+            }
+        })?;
+        res.and_then(|report| report.finish())
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write report"))
+            .change_context(MyError::Output)
+    }
+
+    /// write every stored deposit, withdrawal, and refund with its dispute status, sorted by client and transaction id,
+    /// as CSV (`export::EXPORT_HEADER`), a JSON array, or JSON lines. the amounts are positive, as in the input.
+    /// returns the number of transfers written
+    pub fn export_ledger<W: io::Write>(
+        &self,
+        writer: W,
+        format: ReportFormat,
+    ) -> Result<u64, MyError> {
+        let mut res = ExportWriter::new(writer, format);
+        self.db.process_all_balance_transfers(&mut |record| {
+            if let Ok(export) = res.as_mut() {
+                if let Err(e) = export.row(&record) {
+                    res = Err(e);
+                }
+            }
+        })?;
+        res.and_then(|export| export.finish())
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to export the ledger"))
+            .change_context(MyError::Output)
+    }
+
+    // starts a client report in this processor's report and number formats
+    pub(crate) fn report_writer<W: io::Write>(&self, writer: W) -> io::Result<ReportWriter<W>> {
+        ReportWriter::new(
+            writer,
+            ReportStyle {
+                format: self.report_format,
+                number_format: self.number_format,
+                precision: self.report_precision,
+                rounding: self.rounding,
+            },
+        )
+    }
+
+    // creates the account if the client has never been seen
+    pub(crate) fn ensure_client(&mut self, client_id: ClientId) -> Result<(), MyError> {
+        if self.db.get_client_state(client_id)?.is_none() {
+            self.db.create_client_state(client_id)?;
+            self.stats.clients_created += 1;
+        }
+        Ok(())
+    }
+
+    /// apply a transaction. returns the events it produced: either a TransactionRejected event or the changes that were applied
+    pub fn process(&mut self, raw_input: RawTxnInput) -> Result<Vec<EngineEvent>, MyError> {
+        let span = tracing::debug_span!(
+            "process",
+            client_id = raw_input.client_id,
+            txn_id = raw_input.txn_id,
+            txn_type = ?raw_input.txn_type,
+            outcome = tracing::field::Empty,
+        );
+        let _guard = span.enter();
+        let start = self.latency.is_some().then(Instant::now);
+        if let Some((_, timings)) = &self.slow_threshold {
+            if let Ok(mut timings) = timings.lock() {
+                timings.clear();
+            }
+        }
+
+        if let Some(config) = self.config_watcher.as_mut().and_then(|w| w.poll()) {
+            self.apply_config(&config);
+        }
+
+        let is_transfer = matches!(
+            raw_input.txn_type,
+            TxnType::Deposit | TxnType::Withdrawal | TxnType::Refund
+        );
+        if self.skip_seen_txns && is_transfer && self.applied_by_earlier_run(&raw_input)? {
+            span.record("outcome", "seen");
+            self.stats.seen += 1;
+            return Ok(Vec::new());
+        }
+        let txn_id = raw_input.txn_id;
+
+        // disputes, resolves, and chargebacks refer to existing ids
+        if let Some(tracker) = self.sequence.as_mut() {
+            if matches!(
+                raw_input.txn_type,
+                TxnType::Deposit | TxnType::Withdrawal | TxnType::Refund
+            ) {
+                tracker.record(raw_input.txn_id);
+            }
+        }
+
+        let logged_input = (self.audit_all
+            || self.rejects.is_some()
+            || self.locked_accounts == LockedAccountPolicy::Audit)
+            .then(|| raw_input.clone());
+        let res = self.process_txn(raw_input);
+        match &res {
+            Ok(events) => match events.first() {
+                Some(EngineEvent::TransactionRejected { reason, .. }) => {
+                    span.record("outcome", tracing::field::debug(reason));
+                }
+                _ => {
+                    span.record("outcome", "applied");
+                }
+            },
+            Err(_) => {
+                span.record("outcome", "error");
+            }
+        }
+        tracing::trace!("processed");
+
+        if let (Some(raw_input), Ok(events)) = (logged_input, &res) {
+            self.append_audit_entry(&raw_input, events)?;
+            if let (Some(log), Some(EngineEvent::TransactionRejected { reason, .. })) =
+                (self.rejects.as_mut(), events.first())
+            {
+                log.record_rejection(&raw_input, *reason)?;
+            }
+        }
+        if let (true, true, Ok(events)) = (self.skip_seen_txns, is_transfer, &res) {
+            if !matches!(
+                events.first(),
+                Some(EngineEvent::TransactionRejected { .. })
+            ) {
+                self.run_txn_ids.insert(txn_id);
+            }
+        }
+        if let Ok(events) = &res {
+            self.stats.observe(events);
+            for hooks in self.hooks.iter_mut() {
+                hooks::notify(hooks.as_mut(), events);
+            }
+        }
+        if let (Some((_, totals)), Ok(events)) = (self.reconciliation.as_mut(), &res) {
+            totals.observe(events);
+        }
+        if let (Some(monitor), Ok(events)) = (self.chargeback_monitor.as_mut(), &res) {
+            if let Some(risk) = monitor.observe(events) {
+                tracing::warn!(
+                    client_id = risk.client_id,
+                    "chargeback ratio above threshold: {}",
+                    risk
+                );
+                if let Some(alert) = self.chargeback_alert.as_mut() {
+                    alert(&risk);
+                }
+            }
+        }
+        if let Some(path) = self.snapshots.as_mut().and_then(|s| s.observe()) {
+            self.write_report_file(&path.with_extension(self.report_format.extension()))?;
+        }
+        self.check_memory()?;
+        if let (Some(start), Some(histogram)) = (start, self.latency.as_mut()) {
+            let elapsed = start.elapsed();
+            histogram.record(elapsed);
+            if let Some((threshold, timings)) = &self.slow_threshold {
+                if elapsed > *threshold {
+                    let breakdown = timings
+                        .lock()
+                        .map(|timings| latency::describe_timings(&timings))
+                        .unwrap_or_default();
+                    tracing::warn!(?elapsed, "slow transaction. {}", breakdown);
+                }
+            }
+        }
+        res
+    }
+
+    /// process many transactions in a single store transaction, which is much faster with SQLite than a commit for
+    /// each. a rejected transaction is an outcome, like with process. a store failure rolls back the whole batch: none
+    /// of it is applied
+    pub fn process_batch(
+        &mut self,
+        txns: impl IntoIterator<Item = RawTxnInput>,
+    ) -> Result<BatchResult, MyError> {
+        self.admin_action(|tp| {
+            let outcomes = txns
+                .into_iter()
+                .map(|txn| tp.process(txn))
+                .collect::<Result<Vec<_>, MyError>>()?;
+            Ok(BatchResult { outcomes })
+        })
+    }
+
+    /// run `f`, then roll back every change it made to the store, ex: to see what an input would do to a persistent
+    /// ledger without applying it. the stats and the rejects log keep what `f` did. `f` runs in a single store
+    /// transaction, whatever set_commit_every says, so it can't call process_csv_resumable. a custom store that can't
+    /// roll back keeps the changes: use a scratch processor with it
+    pub fn dry_run<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, MyError>,
+    ) -> Result<T, MyError> {
+        self.db.begin()?;
+        let commit_every = std::mem::replace(&mut self.commit_every, 1);
+        self.in_dry_run = true;
+        let res = f(self);
+        self.in_dry_run = false;
+        self.commit_every = commit_every;
+        self.rollback();
+        res
+    }
+
+    /// write_report to the file at `path`, atomically: a reader sees the previous file or the complete report
+    pub fn write_report_file(&self, path: &Path) -> Result<(), MyError> {
+        output::write_atomically(path, |writer| self.write_report(writer))?;
+        tracing::debug!(report = %path.display(), "wrote a report");
+        Ok(())
+    }
+
+    fn append_audit_entry(
+        &mut self,
+        raw_input: &RawTxnInput,
+        events: &[EngineEvent],
+    ) -> Result<(), MyError> {
+        let outcome = match events.first() {
+            Some(EngineEvent::TransactionRejected { reason, .. }) => format!("{:?}", reason),
+            Some(EngineEvent::DepositQueued { .. }) => "queued".to_string(),
+            _ => "applied".to_string(),
+        };
+        let locked = matches!(
+            events.first(),
+            Some(EngineEvent::TransactionRejected {
+                reason: RejectReason::AccountLocked,
+                ..
+            })
+        );
+        if !self.audit_all && !locked {
+            return Ok(());
+        }
+        self.load_locked_audit_chain()?;
+        if let Some(chain) = self.audit.as_mut() {
+            let entry = chain.next_entry(raw_input, &outcome);
+            self.db.append_audit_entry(&entry)?;
+            chain.advance(&entry);
+        }
+        Ok(())
+    }
+
+    // appends a rejection to the audit log, loading the chain if the log isn't enabled
+    fn audit_rejection(
+        &mut self,
+        raw_input: &RawTxnInput,
+        reason: RejectReason,
+    ) -> Result<(), MyError> {
+        let chain = match self.audit.as_mut() {
+            Some(chain) => chain,
+            None => self.audit.insert(self.load_audit_chain()?),
+        };
+        let entry = chain.next_entry(raw_input, &format!("{:?}", reason));
+        self.db.append_audit_entry(&entry)?;
+        chain.advance(&entry);
+        Ok(())
+    }
+
+    // with LockedAccountPolicy::Audit the audit log isn't necessarily enabled: the chain is loaded when the first
+    // entry is appended
+    fn load_locked_audit_chain(&mut self) -> Result<(), MyError> {
+        if self.audit.is_none() && self.locked_accounts == LockedAccountPolicy::Audit {
+            self.audit = Some(self.load_audit_chain()?);
+        }
+        Ok(())
+    }
+
+    // whether the transfer is the one an earlier run stored under its txn_id: same client and amount (for a refund,
+    // same refunded deposit), and not applied by this run
+    fn applied_by_earlier_run(&self, raw_input: &RawTxnInput) -> Result<bool, MyError> {
+        if self.run_txn_ids.contains(&raw_input.txn_id) {
+            return Ok(false);
+        }
+        let stored = match self.db.get_balance_transfer_by_id(raw_input.txn_id)? {
+            Some(stored) => stored,
+            None => return Ok(false),
+        };
+        Ok(match self.validate_raw_input(raw_input) {
+            Some(Txn::BalanceTransfer(transfer)) => {
+                transfer.client_id == stored.client_id && transfer.amount == stored.amount
+            }
+            Some(Txn::Refund { refund, .. }) => {
+                refund.client_id == stored.client_id
+                    && self
+                        .db
+                        .get_refund(refund.client_id, refund.txn_id)?
+                        .is_some_and(|r| r.original_txn_id == refund.original_txn_id)
+            }
+            _ => false,
+        })
+    }
+
+    fn process_txn(&mut self, raw_input: RawTxnInput) -> Result<Vec<EngineEvent>, MyError> {
+        let reject = |reason: RejectReason| {
+            Ok(vec![EngineEvent::TransactionRejected {
+                client_id: raw_input.client_id,
+                txn_id: raw_input.txn_id,
+                txn_type: raw_input.txn_type.clone(),
+                reason,
+            }])
+        };
+
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            if !limiter.acquire(raw_input.client_id) {
+                return reject(RejectReason::RateLimited);
+            }
+        }
+
+        if self.record_hashes && !self.db.try_insert_record_hash(&raw_input.content_hash())? {
+            tracing::warn!("this record was already processed, skipping it");
+            return reject(RejectReason::DuplicateRecord);
+        }
+
+        // ignore invalid transactions
+        let txn = match self.validate_raw_input(&raw_input) {
+            Some(r) => r,
+            None => return reject(RejectReason::Malformed),
+        };
+
+        // operator mode: a dispute of another client's transfer is applied to the owner
+        let txn = match txn {
+            Txn::Dispute { client_id, txn_id }
+                if self.cross_client_disputes == CrossClientDisputePolicy::Owner =>
+            {
+                match self.cross_client_owner(client_id, txn_id)? {
+                    Some(owner) => {
+                        tracing::info!(owner, "dispute applied to the owner of the transfer");
+                        self.num_cross_client_disputes += 1;
+                        Txn::Dispute {
+                            client_id: owner,
+                            txn_id,
+                        }
+                    }
+                    None => txn,
+                }
+            }
+            _ => txn,
+        };
+        let client_id = match &txn {
+            Txn::Dispute { client_id, .. } => *client_id,
+            _ => raw_input.client_id,
+        };
+
+        // obtain the customer state - create new if needed
+        let mut state = match self.db.get_client_state(client_id)? {
+            Some(s) => s,
+            None => {
+                self.stats.clients_created += 1;
+                self.db.create_client_state(client_id)?
+            }
+        };
+
+        // ignore transactions once the account is locked/frozen, unless deposits are queued until it's unlocked
+        if state.is_locked() {
+            if let Txn::BalanceTransfer(transfer) = txn {
+                if self.locked_accounts == LockedAccountPolicy::QueueDeposits
+                    && transfer.amount.is_positive()
+                {
+                    // the id of a queued deposit can't be used by a balance transfer (even a purged one) or another
+                    // queued deposit
+                    if self
+                        .db
+                        .get_balance_transfer_by_id(transfer.txn_id)?
+                        .is_some()
+                        || self.db.is_purged_txn_id(transfer.txn_id)?
+                        || !self.db.try_queue_deposit(transfer)?
+                    {
+                        return reject(RejectReason::DuplicateTxnId);
+                    }
+                    return Ok(vec![EngineEvent::DepositQueued {
+                        client_id,
+                        txn_id: transfer.txn_id,
+                        amount: transfer.amount,
+                    }]);
+                }
+            }
+            return reject(RejectReason::AccountLocked);
+        }
+
+        let mut events = Vec::new();
+        let postings = match txn {
+            Txn::BalanceTransfer(transfer) => {
+                // a fee that can't be represented, like a balance below
+                let fee = match self.fee_of(&transfer) {
+                    Some(fee) => fee,
+                    None => return reject(RejectReason::Malformed),
+                };
+                // ignore withdrawals that exceed account balance (with the fee), past the overdraft limit if there
+                // is one. in the event of a dispute, available funds may be negative. allow deposits in this case.
+                if transfer.amount.is_negative()
+                    && (state.available + transfer.amount - fee).is_negative()
+                {
+                    let overdrawn = -(state.available + transfer.amount - fee);
+                    if self
+                        .overdraft_limit(client_id)?
+                        .is_some_and(|limit| overdrawn > limit)
+                    {
+                        return reject(RejectReason::InsufficientFunds);
+                    }
+                }
+                // a deposit that would take the balance past the largest amount can't be represented
+                if state.total.checked_add(transfer.amount).is_none() {
+                    return reject(RejectReason::Malformed);
+                }
+                if let Some(reason) = self
+                    .client_limits(client_id)?
+                    .check(state.total, transfer.amount)
+                {
+                    // audited whether or not the audit log is enabled
+                    if !self.audit_all {
+                        self.audit_rejection(&raw_input, reason)?;
+                    }
+                    return reject(reason);
+                }
+
+                // verify transaction_id is unique
+                if !self.db.try_insert_balance_transfer(transfer)? {
+                    if self.duplicates.is_some() {
+                        let original = self.db.get_balance_transfer_by_id(transfer.txn_id)?;
+                        if let Some(tracker) = self.duplicates.as_mut() {
+                            tracker.record(original, transfer);
+                        }
+                    }
+                    return reject(RejectReason::DuplicateTxnId);
+                }
+                events.push(if transfer.amount.is_negative() {
+                    EngineEvent::FundsWithdrawn {
+                        client_id: transfer.client_id,
+                        txn_id: transfer.txn_id,
+                        amount: -transfer.amount,
+                    }
+                } else {
+                    EngineEvent::FundsDeposited {
+                        client_id: transfer.client_id,
+                        txn_id: transfer.txn_id,
+                        amount: transfer.amount,
+                    }
+                });
+                let mut postings = ledger::balance_transfer_postings(&transfer);
+                if fee.is_positive() {
+                    self.db
+                        .insert_fee(transfer.client_id, transfer.txn_id, fee)?;
+                    events.push(EngineEvent::FeeCharged {
+                        client_id: transfer.client_id,
+                        txn_id: transfer.txn_id,
+                        amount: fee,
+                    });
+                    postings.extend(ledger::fee_postings(
+                        transfer.client_id,
+                        transfer.txn_id,
+                        fee,
+                    ));
+                }
+                postings
+            }
+            Txn::Dispute { client_id, txn_id } => {
+                if self.disputes != DisputePolicy::Both || self.dispute_window.is_some() {
+                    if let Some(transfer) = self.db.get_balance_transfer(client_id, txn_id)? {
+                        if !self.disputes.allows(transfer.amount) {
+                            return reject(RejectReason::DisputeNotAllowed);
+                        }
+                        if self.dispute_window_expired(&transfer, raw_input.timestamp) {
+                            return reject(RejectReason::DisputeWindowExpired);
+                        }
+                    }
+                }
+                // validate txn_id and client_id using the database relations
+                if !self.db.try_insert_dispute(client_id, txn_id)? {
+                    if self.cross_client_owner(client_id, txn_id)?.is_some() {
+                        self.num_cross_client_disputes += 1;
+                        return reject(RejectReason::CrossClientDispute);
+                    }
+                    return reject(RejectReason::InvalidDispute);
+                }
+                let opt = self
+                    .db
+                    .get_balance_transfer(client_id, txn_id)
+                    .attach_printable_lazy(|| fmt_error!("process dispute failed"))?;
+
+                let balance_transfer = match opt {
+                    Some(b) => b,
+                    None => {
+                        return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                            "inserted dispute but get_balance_transfer returned None"
+                        )))
+                    }
+                };
+
+                // if it was a withdrawal, increase held by the amount but to not increase available funds
+                // if it was a deposit, hold the funds and don't let them be spent -> decrease available funds
+                events.push(EngineEvent::DisputeOpened {
+                    client_id,
+                    txn_id,
+                    amount: balance_transfer.amount,
+                });
+                ledger::dispute_postings(&balance_transfer)
+            }
+            Txn::Resolve { client_id, txn_id } => {
+                // validate txn_id and client_id using the database relations
+                if !self.db.try_resolve_dispute(client_id, txn_id)? {
+                    return reject(RejectReason::NotDisputed);
+                }
+                let opt = self
+                    .db
+                    .get_balance_transfer(client_id, txn_id)
+                    .attach_printable_lazy(|| fmt_error!("resolved dispute failed"))?;
+
+                let balance_transfer = match opt {
+                    Some(b) => b,
+                    None => {
+                        return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                            "resolved dispute but get_balance_transfer returned None"
+                        )))
+                    }
+                };
+
+                // the withdrawal or deposit was cleared: undo the dispute
+                events.push(EngineEvent::DisputeResolved {
+                    client_id,
+                    txn_id,
+                    amount: balance_transfer.amount,
+                });
+                ledger::resolve_postings(&balance_transfer)
+            }
+            Txn::Chargeback { client_id, txn_id } => {
+                // validate txn_id and client_id using the database relations
+                if !self.db.try_chargeback_dispute(client_id, txn_id)? {
+                    return reject(RejectReason::NotDisputed);
+                }
+                let opt = self
+                    .db
+                    .get_balance_transfer(client_id, txn_id)
+                    .attach_printable_lazy(|| fmt_error!("charged back dispute failed"))?;
+
+                let balance_transfer = match opt {
+                    Some(b) => b,
+                    None => {
+                        return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                            "charged back dispute but get_balance_transfer returned None"
+                        )))
+                    }
+                };
+
+                // the withdrawal was charged back. decrease state.held and increase state.available
+                // a deposit was charged back. decrease state.held but not state.available: it was already deducted at the time of the dispute
+                state.locked = LockedState::Locked;
+                events.push(EngineEvent::ChargebackApplied {
+                    client_id,
+                    txn_id,
+                    amount: balance_transfer.amount,
+                });
+                let mut postings = ledger::chargeback_postings(&balance_transfer);
+                // the transfer is reversed, and so is its fee
+                if let Some(fee) = self.db.get_fee(client_id, txn_id)? {
+                    events.push(EngineEvent::FeeRefunded {
+                        client_id,
+                        txn_id,
+                        amount: fee,
+                    });
+                    postings.extend(ledger::fee_refund_postings(client_id, txn_id, fee));
+                }
+                events.push(EngineEvent::AccountLocked { client_id });
+                postings
+            }
+            Txn::Refund { refund, timestamp } => {
+                // only a deposit of the same client can be refunded
+                let deposit = match self
+                    .db
+                    .get_balance_transfer(client_id, refund.original_txn_id)?
+                {
+                    Some(deposit) if deposit.amount.is_positive() => deposit,
+                    _ => return reject(RejectReason::InvalidRefund),
+                };
+                // the refund is a withdrawal of the deposited amount, without a fee
+                let transfer = BalanceTransfer {
+                    client_id,
+                    txn_id: refund.txn_id,
+                    amount: -deposit.amount,
+                    timestamp,
+                };
+                if (state.available + transfer.amount).is_negative() {
+                    let overdrawn = -(state.available + transfer.amount);
+                    if self
+                        .overdraft_limit(client_id)?
+                        .is_some_and(|limit| overdrawn > limit)
+                    {
+                        return reject(RejectReason::InsufficientFunds);
+                    }
+                }
+                if self.db.get_balance_transfer_by_id(refund.txn_id)?.is_some()
+                    || self.db.is_purged_txn_id(refund.txn_id)?
+                {
+                    return reject(RejectReason::DuplicateTxnId);
+                }
+                // fails if the deposit is disputed or was already refunded
+                if !self.db.try_insert_refund(&refund)? {
+                    return reject(RejectReason::InvalidRefund);
+                }
+                if !self.db.try_insert_balance_transfer(transfer)? {
+                    return Err(report!(MyError::Db).attach_printable(fmt_error!(
+                        "inserted refund {} but not its balance transfer",
+                        refund.txn_id
+                    )));
+                }
+                events.push(EngineEvent::FundsRefunded {
+                    client_id,
+                    txn_id: refund.txn_id,
+                    original_txn_id: refund.original_txn_id,
+                    amount: deposit.amount,
+                });
+                ledger::balance_transfer_postings(&transfer)
+            }
+        };
+
+        self.post(&mut state, &postings)?;
+
+        if self.check_invariants {
+            self.verify_client(&raw_input, &state)?;
+        }
+
+        self.log_events(&events)?;
+        Ok(events)
+    }
+
+    // the owner of the transfer `txn_id` if it isn't `client_id`
+    fn cross_client_owner(
+        &self,
+        client_id: ClientId,
+        txn_id: TransactionId,
+    ) -> Result<Option<ClientId>, MyError> {
+        Ok(self
+            .db
+            .get_balance_transfer_by_id(txn_id)?
+            .map(|txn| txn.client_id)
+            .filter(|owner| *owner != client_id))
+    }
+
+    // appends the balance changes among `events` to the event log
+    fn log_events(&mut self, events: &[EngineEvent]) -> Result<(), MyError> {
+        for event in events.iter().filter_map(LoggedEvent::from_engine_event) {
+            self.db.append_event(&event)?;
+        }
+        Ok(())
+    }
+
+    // the client balances are derived from the postings
+    fn post(&mut self, state: &mut ClientState, postings: &[Posting]) -> Result<(), MyError> {
+        for posting in postings {
+            posting.apply_to(state);
+            self.db.insert_posting(posting)?;
+        }
+        state.total = state.available + state.held;
+        self.db.update_client_state(state)
+    }
+
+    fn verify_client(&self, raw_input: &RawTxnInput, state: &ClientState) -> Result<(), MyError> {
+        let open_disputes = self.db.get_open_disputes(state.client_id)?;
+        // the held funds carried over from a previous run aren't part of the open disputes
+        let mut checked = state.clone();
+        if let Some(held) = self.opening_held.get(&state.client_id) {
+            checked.held -= *held;
+            checked.total -= *held;
+        }
+        let violations = invariants::check_client(&checked, &open_disputes);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut report = error_stack::report!(MyError::Invariant).attach_printable(fmt_error!(
+            "invariant violated after {:?} txn {} for client {}",
+            raw_input.txn_type,
+            raw_input.txn_id,
+            raw_input.client_id
+        ));
+        for violation in &violations {
+            report = report.attach_printable(violation.to_string());
+        }
+        let open: Vec<TransactionId> = open_disputes.iter().map(|txn| txn.txn_id).collect();
+        Err(report
+            .attach_printable(format!("client state: {}", state))
+            .attach_printable(format!("open disputes: {:?}", open)))
+    }
+
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    pub fn validate_raw_input(&self, txn: &RawTxnInput) -> Option<Txn> {
+        // the ids have to fit in an SQLite INTEGER. only u64 transaction ids (feature "wide-ids") can overflow it
+        if i64::try_from(txn.txn_id).is_err() {
+            return None;
+        }
+        match txn.txn_type {
+            TxnType::Invalid => None,
+            TxnType::Deposit => {
+                // NaN and inf parse as valid floats. amounts that round to zero are rejected too
+                let amount = txn.amount.as_ref()?.to_amount(self.rounding)?;
+                if !amount.is_positive() {
+                    return None;
+                }
+                Some(Txn::BalanceTransfer(BalanceTransfer {
+                    client_id: txn.client_id,
+                    txn_id: txn.txn_id,
+                    amount,
+                    timestamp: txn.timestamp,
+                }))
+            }
+            TxnType::Withdrawal => {
+                let amount = txn.amount.as_ref()?.to_amount(self.rounding)?;
+                if !amount.is_positive() {
+                    return None;
+                }
+                Some(Txn::BalanceTransfer(BalanceTransfer {
+                    client_id: txn.client_id,
+                    txn_id: txn.txn_id,
+                    amount: -amount,
+                    timestamp: txn.timestamp,
+                }))
+            }
+            TxnType::Dispute => {
+                if txn.amount.is_some() {
+                    return None;
+                }
+                Some(Txn::Dispute {
+                    client_id: txn.client_id,
+                    txn_id: txn.txn_id,
+                })
+            }
+            TxnType::Resolve => {
+                if txn.amount.is_some() {
+                    return None;
+                }
+                Some(Txn::Resolve {
+                    client_id: txn.client_id,
+                    txn_id: txn.txn_id,
+                })
+            }
+            TxnType::Chargeback => {
+                if txn.amount.is_some() {
+                    return None;
+                }
+                Some(Txn::Chargeback {
+                    client_id: txn.client_id,
+                    txn_id: txn.txn_id,
+                })
+            }
+            TxnType::Refund => {
+                // the amount is the amount of the refunded deposit
+                if txn.amount.is_some() {
+                    return None;
+                }
+                let original_txn_id = txn.original_txn_id?;
+                if original_txn_id == txn.txn_id || i64::try_from(original_txn_id).is_err() {
+                    return None;
+                }
+                Some(Txn::Refund {
+                    refund: Refund {
+                        client_id: txn.client_id,
+                        txn_id: txn.txn_id,
+                        original_txn_id,
+                    },
+                    timestamp: txn.timestamp,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::amt;
+    use crate::{ledger::LedgerAccount, schedule::RatePeriod};
+    use tracing_subscriber::EnvFilter;
+
+    #[cfg(feature = "sqlite")]
+    fn init() -> TransactionProcessor {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_test_writer()
+            .try_init();
+        TransactionProcessor::new().unwrap()
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn init() -> TransactionProcessor {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_test_writer()
+            .try_init();
+        TransactionProcessor::in_memory()
+    }
+
+    fn apply_transactions(csv: &str, processor: &mut TransactionProcessor) {
+        let mut csv_reader = csv::Reader::from_reader(csv.as_bytes());
+        for mut string_record in csv_reader.records().flatten() {
+            string_record.trim();
+            // deserialize it, skip invalid formats
+            if let Ok(txn) = string_record.deserialize(None) {
+                processor.process(txn).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_deposit_withdraw() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0
+                        deposit,2,2,2.0
+                        deposit,1,3,100
+                        withdrawal,1,4,50
+                        withdrawal,2,5,3";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 51.0);
+        assert_eq!(client1.total, 51.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(!client1.is_locked());
+
+        let client2 = tp.db.get_client_state(2).unwrap().unwrap();
+        assert_eq!(client2.available, 2.0);
+        assert_eq!(client2.total, 2.0);
+        assert_eq!(client2.held, 0.0);
+        assert!(!client2.is_locked());
+
+        //  txn 5 was invalid because client 2 had insufficient funds
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_process_on_worker_thread() {
+        let mut tp = init();
+        let handle = std::thread::spawn(move || {
+            let csv = "type,client,tx,amount
+                        deposit,1,1,1.0
+                        deposit,1,2,2.0";
+            apply_transactions(csv, &mut tp);
+            tp
+        });
+        let mut tp = handle.join().unwrap();
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 3.0);
+        assert_eq!(tp.stats().applied(), 2);
+    }
+
+    #[test]
+    fn test_in_memory_store() {
+        let mut tp = TransactionProcessor::in_memory();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,2.0
+                        withdrawal,1,11,1.0
+                        dispute,1,10,
+                        chargeback,1,10,
+                        deposit,1,12,1.0";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, -1.0);
+        assert_eq!(client1.total, -1.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    // the stores are interchangeable: the same input gives the same events and report
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_store_parity() {
+        use crate::workload::{Workload, WorkloadConfig};
+
+        let workload = Workload::new(WorkloadConfig {
+            seed: 7,
+            clients: 10,
+            dispute_rate: 0.2,
+            ..Default::default()
+        });
+        let mut sqlite = TransactionProcessor::new().unwrap();
+        let mut memory = TransactionProcessor::in_memory();
+        for txn in workload.take(500) {
+            assert_eq!(
+                sqlite.process(txn.clone()).unwrap(),
+                memory.process(txn).unwrap()
+            );
+        }
+        let report = |tp: &TransactionProcessor| {
+            let mut out = Vec::new();
+            tp.write_report(&mut out).unwrap();
+            out
+        };
+        assert_eq!(report(&sqlite), report(&memory));
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[test]
+    fn test_wide_ids() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,70000,5000000000,1.0
+                        deposit,70000,18446744073709551615,1.0";
+        apply_transactions(csv, &mut tp);
+        // the second id doesn't fit in an SQLite INTEGER
+        assert_eq!(tp.stats().applied(), 1);
+        assert_eq!(tp.db.get_client_state(70000).unwrap().unwrap().total, 1.0);
+    }
+
+    #[test]
+    fn test_storage_errors_are_reported() {
+        use crate::fake_store::{FakeStore, StoreOp};
+
+        let mut tp =
+            TransactionProcessor::with_store(FakeStore::new().fail(StoreOp::InsertDispute));
+        let deposit = RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id: 1,
+            txn_id: 1,
+            amount: Some(1.0.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let dispute = RawTxnInput {
+            txn_type: TxnType::Dispute,
+            amount: None,
+            timestamp: None,
+            original_txn_id: None,
+            ..deposit.clone()
+        };
+        assert!(tp.process(deposit).is_ok());
+        assert!(tp.process(dispute).is_err());
+        assert_eq!(tp.stats().applied(), 1);
+
+        // a simulated constraint violation is an ignored transaction, not an error
+        let mut tp = TransactionProcessor::with_store(
+            FakeStore::new().reject(StoreOp::InsertBalanceTransfer),
+        );
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_process_batch() {
+        let mut tp = init();
+        let txn = |txn_type, txn_id, amount| RawTxnInput {
+            txn_type,
+            client_id: 1,
+            txn_id,
+            amount,
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let batch = tp
+            .process_batch([
+                txn(TxnType::Deposit, 1, Some(5.0.into())),
+                txn(TxnType::Withdrawal, 2, Some(9.0.into())),
+                txn(TxnType::Dispute, 1, None),
+            ])
+            .unwrap();
+        assert_eq!((batch.applied(), batch.rejected()), (2, 1));
+        assert_eq!(
+            batch.outcomes[2],
+            [EngineEvent::DisputeOpened {
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(5.0)
+            }]
+        );
+        let state = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((state.available, state.held), (Amount::ZERO, amt(5.0)));
+        assert_eq!(tp.process_batch([]).unwrap(), BatchResult::default());
+    }
+
+    #[test]
+    fn test_failed_batch_is_rolled_back() {
+        use crate::fake_store::{FakeStore, StoreOp};
+
+        let txn = |txn_type, txn_id, amount| RawTxnInput {
+            txn_type,
+            client_id: 1,
+            txn_id,
+            amount,
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let mut tp =
+            TransactionProcessor::with_store(FakeStore::new().fail(StoreOp::InsertDispute));
+        tp.process(txn(TxnType::Deposit, 1, Some(5.0.into())))
+            .unwrap();
+
+        // the dispute fails after the withdrawal was applied
+        assert!(tp
+            .process_batch([
+                txn(TxnType::Withdrawal, 2, Some(2.0.into())),
+                txn(TxnType::Dispute, 1, None),
+            ])
+            .is_err());
+        let state = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((state.available, state.held), (amt(5.0), Amount::ZERO));
+        assert!(tp.db.get_balance_transfer(1, 2).unwrap().is_none());
+
+        // the same for the batches of process_csv
+        tp.set_commit_every(10);
+        let csv = "type,client,tx,amount
+                        deposit,1,3,1.0
+                        dispute,1,1,";
+        assert!(tp.process_csv(csv.as_bytes()).is_err());
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(5.0));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut tp = init();
+        tp.set_commit_every(10);
+        let csv = "type,client,tx,amount
+                        deposit,1,1,5.0
+                        withdrawal,1,2,9.0
+                        dispute,1,1,";
+        let applied = tp
+            .dry_run(|tp| {
+                tp.process_csv(csv.as_bytes())?;
+                tp.set_overdraft_limit(1, Some(amt(1.0)))?;
+                Ok(tp.client_state(1)?.unwrap().held)
+            })
+            .unwrap();
+        assert_eq!(applied, amt(5.0));
+        assert_eq!(tp.stats().applied(), 2);
+        assert_eq!(tp.stats().rejections(), 1);
+        // nothing was kept
+        assert!(tp.client_states().unwrap().is_empty());
+        assert!(tp.db.get_balance_transfer(1, 1).unwrap().is_none());
+
+        // the processor works as before
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().held, amt(5.0));
+    }
+
+    #[test]
+    fn test_events() {
+        let mut tp = init();
+        let mut process = |txn_type: TxnType, txn_id: TransactionId, amount: Option<f64>| {
+            tp.process(RawTxnInput {
+                txn_type,
+                client_id: 1,
+                txn_id,
+                amount: amount.map(RawAmount::from),
+                timestamp: None,
+                original_txn_id: None,
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            process(TxnType::Deposit, 1, Some(2.0)),
+            vec![EngineEvent::FundsDeposited {
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(2.0)
+            }]
+        );
+        assert_eq!(
+            process(TxnType::Withdrawal, 2, Some(1.0)),
+            vec![EngineEvent::FundsWithdrawn {
+                client_id: 1,
+                txn_id: 2,
+                amount: amt(1.0)
+            }]
+        );
+        assert_eq!(
+            process(TxnType::Withdrawal, 3, Some(5.0)),
+            vec![EngineEvent::TransactionRejected {
+                client_id: 1,
+                txn_id: 3,
+                txn_type: TxnType::Withdrawal,
+                reason: RejectReason::InsufficientFunds
+            }]
+        );
+        assert_eq!(
+            process(TxnType::Dispute, 2, None),
+            vec![EngineEvent::DisputeOpened {
+                client_id: 1,
+                txn_id: 2,
+                amount: amt(-1.0)
+            }]
+        );
+        assert_eq!(
+            process(TxnType::Resolve, 1, None),
+            vec![EngineEvent::TransactionRejected {
+                client_id: 1,
+                txn_id: 1,
+                txn_type: TxnType::Resolve,
+                reason: RejectReason::NotDisputed
+            }]
+        );
+        assert_eq!(
+            process(TxnType::Chargeback, 2, None),
+            vec![
+                EngineEvent::ChargebackApplied {
+                    client_id: 1,
+                    txn_id: 2,
+                    amount: amt(-1.0)
+                },
+                EngineEvent::AccountLocked { client_id: 1 }
+            ]
+        );
+        assert_eq!(
+            process(TxnType::Deposit, 4, Some(1.0)),
+            vec![EngineEvent::TransactionRejected {
+                client_id: 1,
+                txn_id: 4,
+                txn_type: TxnType::Deposit,
+                reason: RejectReason::AccountLocked
+            }]
+        );
+
+        let stats = tp.stats();
+        assert_eq!(
+            (
+                stats.deposits,
+                stats.withdrawals,
+                stats.disputes,
+                stats.resolves,
+                stats.chargebacks
+            ),
+            (1, 1, 1, 0, 1)
+        );
+        assert_eq!(stats.applied(), 4);
+        assert_eq!(stats.rejections(), 3);
+        assert_eq!(stats.rejected[&RejectReason::InsufficientFunds], 1);
+        assert_eq!(stats.rejected[&RejectReason::NotDisputed], 1);
+        assert_eq!(stats.rejected[&RejectReason::AccountLocked], 1);
+        assert_eq!(stats.clients_created, 1);
+        assert_eq!(stats.accounts_locked, 1);
+    }
+
+    #[test]
+    fn test_many_accounts() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0
+                        deposit,2,2,2
+                        deposit,3,3,3.0000
+                        deposit,4,4,4.00
+                        deposit,5,5,5.000
+                        deposit,6,6,6
+                        deposit,7,7,7
+                        deposit,8,8,8.0";
+        apply_transactions(csv, &mut tp);
+
+        for i in 1..9 {
+            let client = tp.db.get_client_state(i).unwrap().unwrap();
+            assert_eq!(client.available, i as f64);
+            assert_eq!(client.total, i as f64);
+            assert_eq!(client.held, 0.0);
+            assert!(!client.is_locked());
+        }
+
+        assert_eq!(tp.stats().applied(), 8);
+    }
+
+    #[test]
+    fn test_dispute_deposit() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        dispute,1,10,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 0.0);
+        assert_eq!(client1.total, 1.0);
+        assert_eq!(client1.held, 1.0);
+        assert!(!client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 2);
+    }
+
+    #[test]
+    fn test_dispute_deposit2() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        withdrawal,1,11,1.0
+                        dispute,1,10,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, -1.0);
+        assert_eq!(client1.total, 0.0);
+        assert_eq!(client1.held, 1.0);
+        assert!(!client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_chargeback_deposit() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        dispute,1,10,
+                        chargeback,1,10,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 0.0);
+        assert_eq!(client1.total, 0.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_chargeback_deposit2() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        withdrawal,1,11,1.0
+                        dispute,1,10,
+                        chargeback,1,10,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, -1.0);
+        assert_eq!(client1.total, -1.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        withdrawal,1,11,1.0
+                        dispute,1,11,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 0.0);
+        assert_eq!(client1.total, 1.0);
+        assert_eq!(client1.held, 1.0);
+        assert!(!client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_resolve_withdrawal() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        withdrawal,1,11,1.0
+                        dispute,1,11,
+                        resolve,1,11,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 0.0);
+        assert_eq!(client1.total, 0.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(!client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        withdrawal,1,11,1.0
+                        dispute,1,11,
+                        chargeback,1,11,";
+        apply_transactions(csv, &mut tp);
+        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, 1.0);
+        assert_eq!(client1.total, 1.0);
+        assert_eq!(client1.held, 0.0);
+        assert!(client1.is_locked());
+
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_invalid_txns_for_new_account() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        withdrawal,1,11,1.0
+                        dispute,2,12,
+                        chargeback,3,13,
+                        resolve,4,14,";
+        apply_transactions(csv, &mut tp);
+
+        for i in 1..5 {
+            let client = tp.db.get_client_state(i).unwrap().unwrap();
+            assert_eq!(client.available, 0.0);
+            assert_eq!(client.total, 0.0);
+            assert_eq!(client.held, 0.0);
+            assert!(!client.is_locked());
+        }
+
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_duplicate_dispute() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        deposit,2,11,1.0
+                        dispute,1,10,
+                        dispute,1,10,";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_duplicate_chargeback() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        deposit,2,11,1.0
+                        dispute,1,10,
+                        chargeback,1,10,
+                        chargeback,1,10,";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_duplicate_resolve() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        deposit,2,11,1.0
+                        dispute,1,10,
+                        resolve,1,10,
+                        resolve,1,10,";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 4);
+    }
+
+    #[test]
+    fn test_duplicate_txn_id() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        deposit,2,10,1.0";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 1);
+    }
+
+    #[test]
+    fn test_negative_balance_transfer() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,-1.0
+                        deposit,2,11,1.0
+                        withdrawal,2,12,-1.0";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 1);
+    }
+
+    #[test]
+    fn test_non_finite_balance_transfer() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,NaN
+                        deposit,1,11,inf
+                        deposit,1,12,1.0
+                        withdrawal,1,13,NaN";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 1);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 1.0);
+    }
+
+    #[test]
+    fn test_rounding_policy() {
+        let csv = "type,client,tx,amount
+                        deposit,1,10,0.1
+                        deposit,1,11,0.2
+                        deposit,1,12,1.00005
+                        deposit,1,13,0.00001
+                        withdrawal,1,14,0.00015";
+
+        let mut tp = init();
+        apply_transactions(csv, &mut tp);
+        // 0.00001 rounds to zero and is rejected
+        assert_eq!(tp.stats().applied(), 4);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 1.2998);
+        assert_eq!(client.total, 1.2998);
+
+        let mut tp = init();
+        tp.set_rounding_policy(RoundingPolicy::HalfUp);
+        apply_transactions(csv, &mut tp);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 1.2999);
+
+        let mut tp = init();
+        tp.set_rounding_policy(RoundingPolicy::Truncate);
+        apply_transactions(csv, &mut tp);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 1.2999);
+    }
+
+    #[test]
+    fn test_exact_amounts() {
+        let mut csv = "type,client,tx,amount".to_string();
+        for txn_id in 1..=1000 {
+            csv.push_str(&format!("\ndeposit,1,{},0.1", txn_id));
+        }
+        csv.push_str("\nwithdrawal,1,1001,100");
+        let mut tp = init();
+        apply_transactions(&csv, &mut tp);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, Amount::ZERO);
+        assert_eq!(tp.stats().applied(), 1001);
+    }
+
+    #[test]
+    fn test_large_amounts() {
+        // more significant digits than an f64 holds: they must reach the balance as written
+        let csv = "type,client,tx,amount
+                        deposit,1,1,12345678901.2345
+                        deposit,1,2,12345678901234.5678
+                        withdrawal,1,3,0.0001";
+        let mut tp = init();
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tp.stats().applied(), 3);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(
+            client.available,
+            Amount::from_minor_units(123_580_245_801_358_022)
+        );
+        assert_eq!(client.available.to_string(), "12358024580135.8022");
+    }
+
+    #[test]
+    fn test_sequence_gaps() {
+        let csv = "type,client,tx,amount
+                        deposit,1,100,1.0
+                        deposit,1,101,1.0
+                        withdrawal,1,105,5.0
+                        dispute,1,107,
+                        deposit,2,106,1.0";
+
+        let mut tp = init();
+        apply_transactions(csv, &mut tp);
+        assert!(tp.sequence_gaps().is_none());
+
+        let mut tp = init();
+        tp.enable_sequence_check();
+        apply_transactions(csv, &mut tp);
+        // the rejected withdrawal still counts, the dispute doesn't
+        assert_eq!(
+            tp.sequence_gaps().unwrap(),
+            vec![SequenceGap {
+                first: 102,
+                last: 104
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resume_after_failure() {
+        use crate::fake_store::FakeStore;
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0
+                        deposit,1,2,2.0
+                        withdrawal,1,3,0.5";
+
+        // the first row takes 7 store calls. the 8th call, the start of the second row, fails
+        let mut tp = TransactionProcessor::with_store(FakeStore::new().fail_nth_call(8));
+        assert!(tp.process_csv_resumable(csv.as_bytes(), "run").is_err());
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 1.0);
+
+        // the failure isn't repeated. the first row is skipped instead of being applied twice
+        assert_eq!(tp.process_csv_resumable(csv.as_bytes(), "run").unwrap(), 1);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 2.5);
+
+        // a completed run is a no-op. a different run id starts over
+        assert_eq!(tp.process_csv_resumable(csv.as_bytes(), "run").unwrap(), 3);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 2.5);
+        assert_eq!(
+            tp.process_csv_resumable(csv.as_bytes(), "other").unwrap(),
+            0
+        );
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_statement() {
+        let mut tp = TransactionProcessor::in_memory();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,5.0
+                        deposit,2,2,1.0
+                        withdrawal,1,3,9.0
+                        dispute,1,1,
+                        chargeback,1,1,";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert!(tp.statement(3).unwrap().is_none());
+
+        // the rejected withdrawal isn't in the journal
+        let statement = tp.statement(1).unwrap().unwrap();
+        let events: Vec<StatementEvent> = statement.lines.iter().map(|l| l.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                StatementEvent::Deposit,
+                StatementEvent::Dispute,
+                StatementEvent::Chargeback
+            ]
+        );
+        assert_eq!(statement.closing.total, 0.0);
+        assert!(statement.closing.is_locked());
+    }
+
+    #[test]
+    fn test_duplicate_input() {
+        let mut tp = TransactionProcessor::in_memory();
+        assert_eq!(tp.check_input("abc").unwrap(), None);
+        tp.record_input("monday.csv", "abc").unwrap();
+        tp.record_input("copy.csv", "abc").unwrap();
+        let err = tp.check_input("abc").unwrap_err();
+        assert!(matches!(err.current_context(), MyError::DuplicateInput));
+        assert_eq!(tp.check_input("def").unwrap(), None);
+
+        // the earliest run is kept
+        tp.set_duplicate_input_policy(DuplicateInputPolicy::Warn);
+        assert_eq!(tp.check_input("abc").unwrap().unwrap().input, "monday.csv");
+        tp.set_duplicate_input_policy(DuplicateInputPolicy::Skip);
+        assert!(tp.check_input("abc").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_record_hashes() {
+        let monday = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,3.0";
+        // repeats monday's records, with another formatting of the amount
+        let tuesday = "type,client,tx,amount
+                        deposit,1,1,10
+                        withdrawal,1,2,3.0
+                        deposit,1,3,1.0";
+        let mut tp = init();
+        tp.set_record_hashes(true);
+        tp.process_csv(monday.as_bytes()).unwrap();
+        tp.process_csv(tuesday.as_bytes()).unwrap();
+        assert_eq!(tp.stats().deposits, 2);
+        assert_eq!(tp.stats().withdrawals, 1);
+        assert_eq!(tp.stats().rejected[&RejectReason::DuplicateRecord], 2);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(8.0));
+
+        // a different record with a reused txn_id is still a duplicate txn_id
+        apply_transactions("type,client,tx,amount\ndeposit,1,1,5.0", &mut tp);
+        assert_eq!(tp.stats().rejected[&RejectReason::DuplicateTxnId], 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_resume_after_restart() {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let file_name = format!("{}.db", generate(6, charset));
+        let csv = "type,client,tx,amount
+                        deposit,1,1,1.0
+                        abcdefg
+                        deposit,1,2,2.0
+                        dispute,1,1,
+                        withdrawal,1,3,0.5";
+
+        // the first process stops after 3 rows
+        {
+            let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+            let partial: String = csv.lines().take(4).collect::<Vec<_>>().join("\n");
+            assert_eq!(
+                tp.process_csv_resumable(partial.as_bytes(), "run").unwrap(),
+                0
+            );
+        }
+
+        let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+        assert_eq!(tp.process_csv_resumable(csv.as_bytes(), "run").unwrap(), 3);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 1.5);
+        assert_eq!(client.held, 1.0);
+        drop(tp);
+        let _ = std::fs::remove_file(&file_name);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_persistent_runs() {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let file_name = format!("{}.db", generate(6, charset));
+        let monday = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,3.0";
+        // repeats monday's transfers, and disputes one of them
+        let tuesday = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,3.0
+                        deposit,1,3,1.0
+                        dispute,1,1,";
+
+        {
+            let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+            tp.set_skip_seen_txns(true);
+            tp.process_csv(monday.as_bytes()).unwrap();
+        }
+
+        let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+        tp.set_skip_seen_txns(true);
+        tp.process_csv(tuesday.as_bytes()).unwrap();
+        assert_eq!(tp.stats().seen, 2);
+        assert_eq!(tp.stats().rejections(), 0);
+        assert_eq!((tp.stats().deposits, tp.stats().disputes), (1, 1));
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, amt(-2.0));
+        assert_eq!(client.held, amt(10.0));
+        assert_eq!(client.total, amt(8.0));
+        drop(tp);
+        let _ = std::fs::remove_file(&file_name);
+    }
+
+    // only a repeat of the same transfer from an earlier run is skipped
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_persistent_runs_duplicates() {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let file_name = format!("{}.db", generate(6, charset));
+        let monday = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,3.0";
+        // repeats txn 1, reuses txn 2 for another amount and txn 1 for another client, and repeats txn 3 in the run
+        let tuesday = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        deposit,2,1,10.0
+                        deposit,1,3,1.0
+                        deposit,1,3,1.0";
+
+        {
+            let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+            tp.set_skip_seen_txns(true);
+            tp.process_csv(monday.as_bytes()).unwrap();
+        }
+
+        let mut tp = TransactionProcessor::with_store(TxnDb::open(&file_name).unwrap());
+        tp.set_skip_seen_txns(true);
+        tp.enable_duplicate_report();
+        tp.process_csv(tuesday.as_bytes()).unwrap();
+        assert_eq!(tp.stats().seen, 1);
+        assert_eq!(tp.stats().deposits, 1);
+        assert_eq!(tp.stats().rejected[&RejectReason::DuplicateTxnId], 3);
+        let report = tp.duplicate_report().unwrap();
+        let ids: Vec<TransactionId> = report.iter().map(|d| d.txn_id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(report[0].differs() && report[1].differs() && !report[2].differs());
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, amt(8.0));
+        assert!(tp.client_state(2).unwrap().unwrap().available.is_zero());
+        drop(tp);
+        let _ = std::fs::remove_file(&file_name);
+    }
+
+    // a run against a store that fails at random must end with the same accounts as a run that didn't fail
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_chaos() {
+        use crate::{
+            chaos_store::{ChaosStore, Fault},
+            workload::{Workload, WorkloadConfig},
+        };
+        let mut csv = Vec::new();
+        Workload::new(WorkloadConfig {
+            seed: 3,
+            clients: 10,
+            dispute_rate: 0.1,
+            settle_rate: 0.2,
+            ..Default::default()
+        })
+        .write_csv(&mut csv, 300)
+        .unwrap();
+        let mut expected = TransactionProcessor::in_memory();
+        expected.process_csv(csv.as_slice()).unwrap();
+        let expected = expected.client_states().unwrap();
+        let same = |tp: &TransactionProcessor| {
+            let states = tp.client_states().unwrap();
+            states.len() == expected.len()
+                && states
+                    .iter()
+                    .zip(&expected)
+                    .all(|(a, b)| a.to_string() == b.to_string())
+        };
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+
+        // busy errors are retried
+        let db = TxnDb::new(&format!("{}.db", generate(6, charset))).unwrap();
+        let mut tp = TransactionProcessor::with_store(
+            ChaosStore::new(db, 1).rate(0.02).faults(&[Fault::Busy]),
+        );
+        tp.set_busy_retries(20);
+        tp.process_csv_resumable(csv.as_slice(), "run").unwrap();
+        assert!(same(&tp));
+
+        // other failures stop the run. a failed row is rolled back, and resuming completes the run
+        let db = TxnDb::new(&format!("{}.db", generate(6, charset))).unwrap();
+        let mut tp = TransactionProcessor::with_store(
+            ChaosStore::new(db, 2).faults(&[Fault::Constraint, Fault::Io]),
+        );
+        let mut failures = 0;
+        while tp.process_csv_resumable(csv.as_slice(), "run").is_err() {
+            failures += 1;
+        }
+        assert!(failures > 0);
+        assert!(same(&tp));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_commit_every() {
+        use crate::{
+            chaos_store::{ChaosStore, Fault},
+            workload::{Workload, WorkloadConfig},
+        };
+        let mut csv = Vec::new();
+        Workload::new(WorkloadConfig {
+            seed: 4,
+            clients: 10,
+            dispute_rate: 0.1,
+            settle_rate: 0.2,
+            ..Default::default()
+        })
+        .write_csv(&mut csv, 300)
+        .unwrap();
+        let mut expected = TransactionProcessor::in_memory();
+        expected.process_csv(csv.as_slice()).unwrap();
+        let report = |tp: &TransactionProcessor| {
+            let mut out = Vec::new();
+            tp.write_report(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut tp = init();
+        tp.set_commit_every(64);
+        tp.process_csv(csv.as_slice()).unwrap();
+        assert_eq!(report(&tp), report(&expected));
+
+        // a failed batch is rolled back as a whole, and resuming replays it
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        let db = TxnDb::new(&format!("{}.db", generate(6, charset))).unwrap();
+        let mut tp = TransactionProcessor::with_store(
+            ChaosStore::new(db, 5).faults(&[Fault::Constraint, Fault::Io]),
+        );
+        tp.set_commit_every(8);
+        let mut failures = 0;
+        while tp.process_csv_resumable(csv.as_slice(), "run").is_err() {
+            failures += 1;
+        }
+        assert!(failures > 0);
+        assert_eq!(report(&tp), report(&expected));
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut tp = init();
+        tp.enable_latency_tracking(Some(Duration::ZERO));
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0";
+        apply_transactions(csv, &mut tp);
+        let latency = tp.latency().unwrap();
+        assert_eq!(latency.count(), 2);
+        assert!(latency.quantile(0.5) <= latency.max());
+        // the store still works through the timing wrapper
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 6.0);
+        let timings = tp
+            .slow_threshold
+            .as_ref()
+            .unwrap()
+            .1
+            .lock()
+            .unwrap()
+            .clone();
+        assert!(timings
+            .iter()
+            .any(|(op, _)| *op == "try_insert_balance_transfer"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_memory_ceiling() {
+        let txn = RawTxnInput {
+            txn_type: TxnType::Deposit,
+            client_id: 1,
+            txn_id: 1,
+            amount: Some(1.0.into()),
+            timestamp: None,
+            original_txn_id: None,
+        };
+        let mut tp = init();
+        tp.enable_memory_tracking(None);
+        tp.process(txn.clone()).unwrap();
+        assert!(tp.peak_memory().unwrap().0 > 0);
+
+        let mut tp = init();
+        tp.enable_memory_tracking(Some(ByteSize(1 << 10)));
+        let err = tp.process(txn).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::Memory));
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        deposit,2,3,2.5
+                        dispute,1,2,
+                        dispute,2,3,
+                        chargeback,1,2,
+                        withdrawal,2,4,1.0
+                        deposit,3,5,1.0
+                        dispute,3,5,
+                        resolve,3,5,";
+        apply_transactions(csv, &mut tp);
+
+        let ledger = tp.ledger().unwrap();
+        let (debits, credits) = ledger.totals();
+        assert_eq!(debits, credits);
+        let mut liabilities = Amount::ZERO;
+        for client in tp.client_states().unwrap() {
+            let (available, held) = ledger.client_balances(client.client_id);
+            assert_eq!(available, client.available);
+            assert_eq!(held, client.held);
+            liabilities += available + held;
+        }
+        // what the operator owes its clients is covered by its cash plus the chargebacks it paid for
+        let cash = ledger.balance(LedgerAccount::OperatorCash);
+        let expense = ledger.balance(LedgerAccount::ChargebackExpense);
+        assert_eq!(cash, 9.5);
+        assert_eq!(expense, 4.0);
+        assert_eq!(cash + expense, liabilities);
+    }
+
+    #[test]
+    fn test_invariant_checks() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        dispute,1,1,
+                        dispute,1,2,
+                        resolve,1,1,
+                        chargeback,1,2,";
+        let mut tp = init();
+        tp.enable_invariant_checks();
+        apply_transactions(csv, &mut tp);
+
+        // corrupt the stored state: held no longer matches the open disputes
+        let mut db = MemoryDb::new();
+        let mut client = db.create_client_state(1).unwrap();
+        client.held = amt(1.0);
+        client.total = amt(1.0);
+        db.update_client_state(&client).unwrap();
+        let mut tp = TransactionProcessor::with_store(db);
+        tp.enable_invariant_checks();
+        let err = tp.process_csv(csv.as_bytes()).unwrap_err();
+        let json = report_to_json(&err);
+        assert_eq!(json["error"], "Invariant");
+        assert!(json
+            .to_string()
+            .contains("held (1) != the sum of the open disputes (0)"));
+        // the run stopped at the first transaction, which failed instead of being applied
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,40.0
+                        dispute,1,1,";
+        let mut tp = init();
+        tp.enable_audit_log().unwrap();
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 3);
+
+        let mut file = Vec::new();
+        tp.write_audit_log(&mut file).unwrap();
+        let file = String::from_utf8(file).unwrap();
+        assert!(file.contains("InsufficientFunds"));
+        let verifier = audit::verify_file(file.as_bytes()).unwrap();
+        assert_eq!(verifier.verified(), 3);
+        assert_eq!(verifier.head(), tp.verify_audit_log().unwrap().head());
+
+        let tampered = file.replacen("10.0", "100.0", 1);
+        assert!(audit::verify_file(tampered.as_bytes()).is_err());
+
+        // enabling it again continues the chain
+        tp.enable_audit_log().unwrap();
+        apply_transactions("type,client,tx,amount\ndeposit,1,3,1.0", &mut tp);
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 4);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_audit_input() {
+        use crate::encryption::{self, FieldKey};
+        let key =
+            FieldKey::from_hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+                .unwrap();
+        let csv = "type,client,tx,amount,timestamp
+deposit,7,1,10.5,1700000000
+withdrawal,7,2,40.0,1700000060";
+        let mut tp = TransactionProcessor::in_memory();
+        tp.set_field_key(Some(key.clone()));
+        tp.enable_audit_log().unwrap();
+        tp.process_csv(csv.as_bytes()).unwrap();
+
+        let mut file = Vec::new();
+        tp.write_audit_log(&mut file).unwrap();
+        let file = String::from_utf8(file).unwrap();
+        assert!(!file.contains("1700000000"));
+        let inputs: Vec<String> = file
+            .lines()
+            .map(|line| serde_json::from_str::<audit::AuditEntry>(line).unwrap())
+            .map(|entry| entry.input.unwrap())
+            .collect();
+        assert!(inputs.iter().all(|input| encryption::is_encrypted(input)));
+        assert_eq!(
+            key.decrypt("input", &inputs[1]).unwrap(),
+            "withdrawal,7,2,40,1700000060,"
+        );
+        // the chain covers the ciphertext and verifies without the key
+        assert_eq!(audit::verify_file(file.as_bytes()).unwrap().verified(), 2);
+        let tampered = file.replacen(&inputs[0], &key.encrypt("input", "deposit,7,1,1.0,,"), 1);
+        assert!(audit::verify_file(tampered.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_negative_client_id() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,-1,10,1.0";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_negative_txn_id() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,-10,1.0";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_extra_newlines() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,10,1.0
+                        
+                        deposit,1,11,1.0
+                        
+                        ";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 2);
+        let client = tp.db.get_client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, 2.0);
+    }
+
+    #[test]
+    fn test_invalid_input1() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        abcdefg,1,10,1.0
+                        deposit,1,11,1.0
+                        dispute,1,11,
+                        resolve,1,11,";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_invalid_input2() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        abcdefg
+                        too,many,columns,a,b,c,d
+                        deposit,1,11,1.0
+                        dispute,1,11,
+                        resolve,1,11,";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 3);
+    }
+
+    #[test]
+    fn test_missing_comma() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,11,1.0
+                        dispute,1,11,
+                        resolve,1,11";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 2);
+    }
+
+    #[test]
+    fn test_missing_balance_transfer() {
+        let mut tp = init();
+        let csv = "type,client,tx,amount
+                        deposit,1,11,
+
+                        ";
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.stats().applied(), 0);
+    }
+
+    #[test]
+    fn test_forget_client() {
+        let mut tp = init();
+        tp.enable_audit_log().unwrap();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        dispute,1,2,
+                        resolve,1,2,
+                        deposit,2,3,2.5
+                        dispute,2,3,";
+        apply_transactions(csv, &mut tp);
+        let before = tp.ledger().unwrap();
+        let client = tp.client_state(1).unwrap().unwrap().to_string();
+
+        assert_eq!(tp.forget_client(1).unwrap(), 2);
+        let after = tp.ledger().unwrap();
+        for account in before.accounts() {
+            assert_eq!(before.balance(account), after.balance(account));
+        }
+        assert_eq!(tp.client_state(1).unwrap().unwrap().to_string(), client);
+        assert!(tp.db.get_balance_transfer(1, 1).unwrap().is_none());
+        // the other client keeps its history
+        assert!(tp.db.get_balance_transfer(2, 3).unwrap().is_some());
+
+        // the erasure is the last entry of an intact audit log
+        let verifier = tp.verify_audit_log().unwrap();
+        assert_eq!(verifier.verified(), 7);
+        let mut last = None;
+        tp.db
+            .process_all_audit_entries(&mut |entry| last = Some(entry))
+            .unwrap();
+        assert_eq!(last.unwrap().txn_type, "forget_client");
+
+        // open disputes and unknown clients are refused
+        let err = tp.forget_client(2).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::OpenDisputes));
+        let err = tp.forget_client(9).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::UnknownClient));
+        assert!(tp.db.get_balance_transfer(2, 3).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_reopen_dispute() {
+        let mut tp = init();
+        tp.enable_audit_log().unwrap();
+        tp.enable_invariant_checks();
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        dispute,1,1,
+                        resolve,1,1,
+                        deposit,2,2,5.0
+                        dispute,2,2,
+                        chargeback,2,2,";
+        apply_transactions(csv, &mut tp);
+
+        let events = tp.reopen_dispute(1, 1).unwrap();
+        assert_eq!(
+            events,
+            vec![EngineEvent::DisputeReopened {
+                client_id: 1,
+                txn_id: 1,
+                amount: amt(10.0)
+            }]
+        );
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(
+            (client.available, client.held, client.total),
+            (amt(0.0), amt(10.0), amt(10.0))
+        );
+        assert_eq!(tp.db.count_dispute_reopens(1, 1).unwrap(), 1);
+        // the reopened dispute can't be reopened again until it's settled
+        assert!(tp.reopen_dispute(1, 1).is_err());
+
+        let csv = "type,client,tx,amount
+                        chargeback,1,1,";
+        apply_transactions(csv, &mut tp);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.total, 0.0);
+        assert!(client.is_locked());
+
+        // charged back, locked, or unknown
+        assert!(tp.reopen_dispute(2, 2).is_err());
+        let err = tp.reopen_dispute(1, 1).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::AccountLocked));
+        let err = tp.reopen_dispute(9, 1).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::UnknownClient));
+
+        let verifier = tp.verify_audit_log().unwrap();
+        assert_eq!(verifier.verified(), 8);
+    }
+
+    #[test]
+    fn test_cross_client_disputes() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        dispute,2,1,
+                        dispute,2,9,";
+        let mut tp = init();
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.cross_client_disputes(), 1);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().held, 0.0);
+        let events = tp
+            .process(RawTxnInput {
+                txn_type: TxnType::Dispute,
+                client_id: 3,
+                txn_id: 1,
+                amount: None,
+                timestamp: None,
+                original_txn_id: None,
+            })
+            .unwrap();
+        assert!(matches!(
+            events[0],
+            EngineEvent::TransactionRejected {
+                reason: RejectReason::CrossClientDispute,
+                ..
+            }
+        ));
+
+        // operator mode: the dispute is applied to the owner
+        let mut tp = init();
+        tp.set_cross_client_dispute_policy(CrossClientDisputePolicy::Owner);
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.cross_client_disputes(), 1);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((client.available, client.held), (amt(0.0), amt(10.0)));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().held, 0.0);
+    }
+
+    #[test]
+    fn test_dispute_policy() {
+        let csv = "type,client,tx,amount
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        dispute,1,2,
+                        dispute,1,1,";
+        // the default: the withdrawal's amount is held too
+        let mut tp = init();
+        apply_transactions(csv, &mut tp);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((client.available, client.held), (amt(-4.0), amt(14.0)));
+
+        let mut tp = init();
+        tp.set_dispute_policy(DisputePolicy::DepositsOnly);
+        apply_transactions(csv, &mut tp);
+        // only the deposit is held
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((client.available, client.held), (amt(-4.0), amt(10.0)));
+        let events = tp
+            .process(RawTxnInput {
+                txn_type: TxnType::Resolve,
+                client_id: 1,
+                txn_id: 2,
+                amount: None,
+                timestamp: None,
+                original_txn_id: None,
+            })
+            .unwrap();
+        assert!(matches!(
+            events[0],
+            EngineEvent::TransactionRejected {
+                reason: RejectReason::NotDisputed,
+                ..
+            }
+        ));
+
+        let mut tp = init();
+        tp.set_dispute_policy(DisputePolicy::WithdrawalsOnly);
+        apply_transactions(csv, &mut tp);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!((client.available, client.held), (amt(6.0), amt(4.0)));
+        let events = tp
+            .process(RawTxnInput {
+                txn_type: TxnType::Dispute,
+                client_id: 1,
+                txn_id: 1,
+                amount: None,
+                timestamp: None,
+                original_txn_id: None,
+            })
+            .unwrap();
+        assert!(matches!(
+            events[0],
+            EngineEvent::TransactionRejected {
+                reason: RejectReason::DisputeNotAllowed,
+                ..
             }
-        }
+        ));
     }
 
     #[test]
-    fn test_deposit_withdraw() {
-        let mut tp = init();
+    fn test_overdraft_policy() {
         let csv = "type,client,tx,amount
-                        deposit,1,1,1.0
-                        deposit,2,2,2.0
-                        deposit,1,3,100
-                        withdrawal,1,4,50
-                        withdrawal,2,5,3";
+                        deposit,1,1,10.0
+                        withdrawal,1,2,50.0
+                        withdrawal,1,3,20.0
+                        deposit,2,4,10.0
+                        withdrawal,2,5,11.0";
+        let mut tp = init();
         apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 51.0);
-        assert_eq!(client1.total, 51.0);
-        assert_eq!(client1.held, 0.0);
-        assert!(!client1.is_locked());
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(10.0));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, amt(10.0));
 
-        let client2 = tp.db.get_client_state(2).unwrap().unwrap();
-        assert_eq!(client2.available, 2.0);
-        assert_eq!(client2.total, 2.0);
-        assert_eq!(client2.held, 0.0);
-        assert!(!client2.is_locked());
+        // the second withdrawal would overdraw by 60
+        let mut tp = init();
+        tp.set_overdraft_policy(OverdraftPolicy::AllowToLimit(amt(50.0)));
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(-40.0));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, amt(-1.0));
 
-        //  txn 5 was invalid because client 2 had insufficient funds
-        assert_eq!(tp.num_processed, 4);
+        // a client's own limit takes precedence, and a new client gets an account
+        let mut tp = init();
+        tp.set_overdraft_policy(OverdraftPolicy::AllowUnlimited);
+        tp.set_overdraft_limit(2, Some(Amount::ZERO)).unwrap();
+        assert_eq!(tp.overdraft_limit(2).unwrap(), Some(Amount::ZERO));
+        assert_eq!(tp.overdraft_limit(1).unwrap(), None);
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(-60.0));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, amt(10.0));
+        tp.set_overdraft_limit(2, None).unwrap();
+        assert_eq!(tp.overdraft_limit(2).unwrap(), None);
+
+        let err = tp.set_overdraft_limit(1, Some(amt(-1.0))).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::InvalidRequest));
     }
 
     #[test]
-    fn test_many_accounts() {
-        let mut tp = init();
+    fn test_limits() {
         let csv = "type,client,tx,amount
-                        deposit,1,1,1.0
-                        deposit,2,2,2
-                        deposit,3,3,3.0000
-                        deposit,4,4,4.00
-                        deposit,5,5,5.000
-                        deposit,6,6,6
-                        deposit,7,7,7
-                        deposit,8,8,8.0";
+                        deposit,1,1,60.0
+                        deposit,1,2,60.0
+                        deposit,1,3,40.0
+                        withdrawal,1,4,30.0
+                        deposit,2,5,500.0
+                        withdrawal,2,6,300.0";
+        let mut tp = init();
+        tp.set_limits(Limits {
+            max_balance: Some(amt(100.0)),
+            max_deposit: Some(amt(80.0)),
+            max_withdrawal: Some(amt(20.0)),
+        });
+        // client 2 has its own deposit limit and no withdrawal limit. its balance limit is the processor's
+        tp.set_client_limits(
+            2,
+            Some(Limits {
+                max_deposit: Some(amt(1000.0)),
+                max_withdrawal: Some(amt(1000.0)),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
         apply_transactions(csv, &mut tp);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().total, amt(100.0));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().total, Amount::ZERO);
+        let stats = tp.stats();
+        assert_eq!(stats.rejected[&RejectReason::BalanceLimitExceeded], 2);
+        assert_eq!(stats.rejected[&RejectReason::WithdrawalLimitExceeded], 1);
+        assert_eq!(tp.client_limits(2).unwrap().max_balance, Some(amt(100.0)));
 
-        for i in 1..9 {
-            let client = tp.db.get_client_state(i).unwrap().unwrap();
-            assert_eq!(client.available, i as f64);
-            assert_eq!(client.total, i as f64);
-            assert_eq!(client.held, 0.0);
-            assert!(!client.is_locked());
-        }
+        // the rejections are audited though the audit log isn't enabled
+        let mut outcomes = Vec::new();
+        tp.db
+            .process_all_audit_entries(&mut |entry| outcomes.push(entry.outcome))
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                "BalanceLimitExceeded",
+                "WithdrawalLimitExceeded",
+                "BalanceLimitExceeded"
+            ]
+        );
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 3);
 
-        assert_eq!(tp.num_processed, 8);
+        tp.set_client_limits(2, None).unwrap();
+        assert_eq!(tp.client_limits(2).unwrap(), tp.limits());
+        let err = tp
+            .set_client_limits(
+                1,
+                Some(Limits {
+                    max_deposit: Some(amt(-1.0)),
+                    ..Default::default()
+                }),
+            )
+            .unwrap_err();
+        assert!(matches!(err.current_context(), MyError::InvalidRequest));
     }
 
     #[test]
-    fn test_dispute_deposit() {
-        let mut tp = init();
+    fn test_fees() {
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        dispute,1,10,";
+                        deposit,1,1,100.0
+                        withdrawal,1,2,50.0
+                        withdrawal,1,3,46.0
+                        deposit,2,4,0.5
+                        dispute,1,1,
+                        chargeback,1,1,";
+        let mut tp = init();
+        tp.set_rate_schedule(
+            RateSchedule::constant(Rates {
+                flat_fee: amt(1.0),
+                percent_fee: "0.01".parse().unwrap(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        tp.enable_reconciliation().unwrap();
         apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 0.0);
-        assert_eq!(client1.total, 1.0);
-        assert_eq!(client1.held, 1.0);
-        assert!(!client1.is_locked());
 
-        assert_eq!(tp.num_processed, 2);
+        // 100 - 2 - 50 - 1.5: the second withdrawal can't pay its fee. the chargeback refunds the deposit's fee
+        let client1 = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, amt(-51.5));
+        assert_eq!(tp.stats().rejected[&RejectReason::InsufficientFunds], 1);
+        // the fee of a deposit is at most its amount
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, Amount::ZERO);
+        assert_eq!(tp.stats().fees_charged, amt(4.0));
+        assert_eq!(tp.stats().fees_refunded, amt(2.0));
+
+        let ledger = tp.ledger().unwrap();
+        assert_eq!(ledger.balance(LedgerAccount::Fees), amt(2.0));
+        assert!(ledger.trial_balance(false).is_balanced());
+        let reconciliation = tp.reconcile().unwrap().unwrap();
+        assert!(reconciliation.is_balanced(), "{}", reconciliation);
+
+        let statement = tp.statement(1).unwrap().unwrap();
+        let events: Vec<StatementEvent> = statement.lines.iter().map(|l| l.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                StatementEvent::Deposit,
+                StatementEvent::Fee,
+                StatementEvent::Withdrawal,
+                StatementEvent::Fee,
+                StatementEvent::Dispute,
+                StatementEvent::Chargeback,
+                StatementEvent::FeeRefund
+            ]
+        );
     }
 
     #[test]
-    fn test_dispute_deposit2() {
+    fn test_timestamps() {
+        // the fifth column is optional
+        let csv = "type,client,tx,amount,timestamp
+                        deposit,1,1,100.0,1704067200
+                        deposit,1,2,100.0,1719792000
+                        withdrawal,1,3,10.0,
+                        dispute,1,1,,1719792000";
         let mut tp = init();
-        let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        withdrawal,1,11,1.0
-                        dispute,1,10,";
-        apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, -1.0);
-        assert_eq!(client1.total, 0.0);
-        assert_eq!(client1.held, 1.0);
-        assert!(!client1.is_locked());
+        tp.set_rate_schedule(
+            RateSchedule::new(vec![RatePeriod {
+                from: "2024-07-01".parse().unwrap(),
+                until: None,
+                rates: Rates {
+                    flat_fee: amt(1.0),
+                    ..Default::default()
+                },
+            }])
+            .unwrap(),
+        );
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tp.stats().deposits, 2);
+        assert_eq!(tp.stats().disputes, 1);
+        // only the deposit made after the rates took effect pays a fee, and the withdrawal without a timestamp
+        // pays today's rates
+        assert_eq!(tp.stats().fees_charged, amt(2.0));
 
-        assert_eq!(tp.num_processed, 3);
+        let statement = tp.statement(1).unwrap().unwrap();
+        let timestamps: Vec<(StatementEvent, Option<i64>)> = statement
+            .lines
+            .iter()
+            .map(|line| (line.event, line.timestamp))
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![
+                (StatementEvent::Deposit, Some(1_704_067_200)),
+                (StatementEvent::Deposit, Some(1_719_792_000)),
+                (StatementEvent::Fee, None),
+                (StatementEvent::Withdrawal, None),
+                (StatementEvent::Fee, None),
+                (StatementEvent::Dispute, None),
+            ]
+        );
+        assert!(statement
+            .to_string()
+            .contains("deposit,1,100,100,0,100,,1704067200\n"));
     }
 
     #[test]
-    fn test_chargeback_deposit() {
+    fn test_refund() {
+        let csv = "type,client,tx,amount,timestamp,original_tx
+                        deposit,1,1,10.0,,
+                        deposit,1,2,5.0,,
+                        deposit,1,3,1.0,,
+                        deposit,1,13,100.0,,
+                        refund,1,4,,,1
+                        refund,1,5,,,1
+                        dispute,1,1,,,
+                        dispute,1,4,,,
+                        dispute,1,2,,,
+                        refund,1,6,,,2
+                        withdrawal,1,7,100.25,,
+                        refund,1,8,,,7
+                        refund,2,9,,,3
+                        refund,1,10,,,3
+                        deposit,1,11,1.0,,
+                        refund,1,3,,,3
+                        refund,1,2,,,3
+                        refund,1,12,1.0,,3
+                        refund,1,12,,,3";
         let mut tp = init();
-        let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        dispute,1,10,
-                        chargeback,1,10,";
-        apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 0.0);
-        assert_eq!(client1.total, 0.0);
-        assert_eq!(client1.held, 0.0);
-        assert!(client1.is_locked());
+        tp.enable_reconciliation().unwrap();
+        tp.process_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(tp.stats().refunds, 2);
+        // tx 1 was already refunded, tx 2 is disputed, tx 7 is a withdrawal, and tx 3 belongs to client 1
+        assert_eq!(tp.stats().rejected[&RejectReason::InvalidRefund], 4);
+        // neither a refund nor a refunded deposit can be disputed
+        assert_eq!(tp.stats().rejected[&RejectReason::InvalidDispute], 2);
+        assert_eq!(tp.stats().rejected[&RejectReason::InsufficientFunds], 1);
+        assert_eq!(tp.stats().rejected[&RejectReason::DuplicateTxnId], 1);
+        assert_eq!(tp.stats().rejected[&RejectReason::Malformed], 2);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.available, amt(0.75));
+        assert_eq!(client.held, amt(5.0));
+
+        assert!(tp.ledger().unwrap().trial_balance(false).is_balanced());
+        let reconciliation = tp.reconcile().unwrap().unwrap();
+        assert!(reconciliation.is_balanced(), "{}", reconciliation);
 
-        assert_eq!(tp.num_processed, 3);
+        let statement = tp.statement(1).unwrap().unwrap();
+        let events: Vec<StatementEvent> = statement.lines.iter().map(|l| l.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                StatementEvent::Deposit,
+                StatementEvent::Deposit,
+                StatementEvent::Deposit,
+                StatementEvent::Deposit,
+                StatementEvent::Refund,
+                StatementEvent::Dispute,
+                StatementEvent::Withdrawal,
+                StatementEvent::Deposit,
+                StatementEvent::Refund
+            ]
+        );
     }
 
     #[test]
-    fn test_chargeback_deposit2() {
+    fn test_dispute_window() {
+        // 2024-01-01, and 10 and 91 days later
+        let csv = "type,client,tx,amount,timestamp
+                        deposit,1,1,10.0,1704067200
+                        deposit,1,2,10.0,
+                        deposit,1,3,10.0,1704067200
+                        dispute,1,1,,1704931200
+                        resolve,1,1,,1704931200
+                        dispute,1,3,,1711929600
+                        dispute,1,2,,1711929600";
+        let mut tp = init();
+        tp.set_dispute_window(Some(90));
+        tp.enable_audit_log().unwrap();
+        tp.process_csv(csv.as_bytes()).unwrap();
+        // the dispute of tx 3 is too late. tx 2 has no timestamp, so its window can't be checked
+        assert_eq!(tp.stats().disputes, 2);
+        assert_eq!(tp.stats().rejected[&RejectReason::DisputeWindowExpired], 1);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client.held, amt(10.0));
+        assert_eq!(client.available, amt(20.0));
+        // the rejection is audited with its own reason
+        let mut log = Vec::new();
+        tp.write_audit_log(&mut log).unwrap();
+        assert_eq!(
+            String::from_utf8(log)
+                .unwrap()
+                .matches("DisputeWindowExpired")
+                .count(),
+            1
+        );
+
+        // without a timestamp, a dispute is filed when it's processed
         let mut tp = init();
+        tp.set_dispute_window(Some(90));
+        apply_transactions(
+            "type,client,tx,amount,timestamp
+                deposit,1,1,10.0,1704067200
+                dispute,1,1,,",
+            &mut tp,
+        );
+        assert_eq!(tp.stats().rejected[&RejectReason::DisputeWindowExpired], 1);
+        assert!(tp.client_state(1).unwrap().unwrap().held.is_zero());
+    }
+
+    #[test]
+    fn test_accrue_interest() {
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        withdrawal,1,11,1.0
-                        dispute,1,10,
-                        chargeback,1,10,";
+                        deposit,1,1,1000.0
+                        deposit,2,2,10.0
+                        withdrawal,2,3,10.0";
+        let mut tp = init();
+        tp.set_rate_schedule(
+            RateSchedule::constant(Rates {
+                interest_rate: "0.0365".parse().unwrap(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        tp.enable_reconciliation().unwrap();
         apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, -1.0);
-        assert_eq!(client1.total, -1.0);
-        assert_eq!(client1.held, 0.0);
-        assert!(client1.is_locked());
 
-        assert_eq!(tp.num_processed, 4);
+        let day: Date = "2024-01-01".parse().unwrap();
+        let events = tp.accrue_interest(day).unwrap();
+        assert_eq!(
+            events,
+            vec![EngineEvent::InterestAccrued {
+                client_id: 1,
+                amount: amt(0.1)
+            }]
+        );
+        // already accrued through that day
+        assert!(tp.accrue_interest(day).unwrap().is_empty());
+        // two more days, compounded
+        tp.accrue_interest(day.succ().succ()).unwrap();
+        let client1 = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(client1.available, amt(1000.3));
+        assert_eq!(client1.total, amt(1000.3));
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, Amount::ZERO);
+
+        let ledger = tp.ledger().unwrap();
+        assert_eq!(ledger.balance(LedgerAccount::Interest), amt(0.3));
+        assert!(ledger.trial_balance(false).is_balanced());
+        let reconciliation = tp.reconcile().unwrap().unwrap();
+        assert!(reconciliation.is_balanced(), "{}", reconciliation);
+        let statement = tp.statement(1).unwrap().unwrap();
+        assert_eq!(
+            statement.lines.last().map(|l| l.event),
+            Some(StatementEvent::Interest)
+        );
     }
 
     #[test]
-    fn test_dispute_withdrawal() {
+    fn test_rebuild() {
+        use crate::adjustment::AdjustmentReason;
         let mut tp = init();
-        let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        withdrawal,1,11,1.0
-                        dispute,1,11,";
-        apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 0.0);
-        assert_eq!(client1.total, 1.0);
-        assert_eq!(client1.held, 1.0);
-        assert!(!client1.is_locked());
+        tp.set_rate_schedule(
+            RateSchedule::constant(Rates {
+                flat_fee: amt(0.5),
+                interest_rate: "0.0365".parse().unwrap(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let prior = "client,available,held,total,locked
+                          3,4,1,5,false";
+        tp.load_initial_balances(prior.as_bytes()).unwrap();
+        let csv = "type,client,tx,amount,timestamp,original_tx
+                        deposit,1,1,100.0,,
+                        deposit,1,2,20.0,,
+                        withdrawal,1,3,10.0,,
+                        refund,1,4,,,2
+                        dispute,1,1,,,
+                        resolve,1,1,,,
+                        deposit,2,5,50.0,,
+                        withdrawal,2,6,5.0,,
+                        dispute,2,6,,,
+                        chargeback,2,6,,,
+                        withdrawal,3,7,1.0,,";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        tp.adjust(
+            Adjustment {
+                client_id: 1,
+                amount: amt(-1.0),
+                reason: AdjustmentReason::Correction,
+                operator: "ops-7".to_string(),
+            },
+            false,
+        )
+        .unwrap();
+        tp.accrue_interest("2024-01-01".parse().unwrap()).unwrap();
+        tp.forget_client(3).unwrap();
+        let expected: Vec<String> = tp
+            .client_states()
+            .unwrap()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        // the accounts match the log
+        assert!(tp.rebuild().unwrap().is_empty());
 
-        assert_eq!(tp.num_processed, 3);
+        let mut corrupted = tp.client_state(1).unwrap().unwrap();
+        corrupted.available = Amount::ZERO;
+        tp.db.update_client_state(&corrupted).unwrap();
+        let mut corrupted = tp.client_state(2).unwrap().unwrap();
+        corrupted.locked = LockedState::Unlocked;
+        tp.db.update_client_state(&corrupted).unwrap();
+        let corrected: Vec<ClientId> = tp.rebuild().unwrap().iter().map(|s| s.client_id).collect();
+        assert_eq!(corrected, vec![1, 2]);
+        let rebuilt: Vec<String> = tp
+            .client_states()
+            .unwrap()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(rebuilt, expected);
+
+        // without a log there's nothing to rebuild from
+        let mut tp = TransactionProcessor::in_memory();
+        let mut state = tp.db.create_client_state(1).unwrap();
+        state.available = amt(1.0);
+        state.total = amt(1.0);
+        tp.db.update_client_state(&state).unwrap();
+        assert!(tp.rebuild().is_err());
     }
 
     #[test]
-    fn test_resolve_withdrawal() {
+    fn test_export_ledger() {
+        let csv = "type,client,tx,amount,timestamp,original_tx
+                        deposit,1,1,10.0,1704067200,
+                        withdrawal,1,2,4.0,,
+                        dispute,1,2,,,
+                        resolve,1,2,,,
+                        deposit,1,3,1.0,,
+                        refund,1,4,,,3
+                        deposit,2,5,2.5,,
+                        dispute,2,5,,,
+                        deposit,3,6,1.0,,
+                        dispute,3,6,,,
+                        chargeback,3,6,,,";
         let mut tp = init();
+        tp.process_csv(csv.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(tp.export_ledger(&mut out, ReportFormat::Csv).unwrap(), 6);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type,client,tx,amount,timestamp,status,original_tx
+deposit,1,1,10,1704067200,undisputed,
+withdrawal,1,2,4,,resolved,
+deposit,1,3,1,,undisputed,
+refund,1,4,1,,undisputed,3
+deposit,2,5,2.5,,disputed,
+deposit,3,6,1,,charged_back,
+"
+        );
+    }
+
+    #[test]
+    fn test_locked_account_policy() {
+        // client 1 is locked by the chargeback
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        withdrawal,1,11,1.0
-                        dispute,1,11,
-                        resolve,1,11,";
+                        deposit,1,1,10.0
+                        dispute,1,1,
+                        chargeback,1,1,
+                        deposit,1,2,5.0
+                        withdrawal,1,3,1.0
+                        deposit,1,4,2.5
+                        deposit,1,2,5.0";
+        let mut tp = init();
         apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 0.0);
-        assert_eq!(client1.total, 0.0);
-        assert_eq!(client1.held, 0.0);
-        assert!(!client1.is_locked());
+        assert_eq!(tp.stats().rejected[&RejectReason::AccountLocked], 4);
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 0);
+        let err = tp.unlock_account(2).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::UnknownClient));
+
+        // only the rejections are audited
+        let mut tp = init();
+        tp.set_locked_account_policy(LockedAccountPolicy::Audit);
+        apply_transactions(csv, &mut tp);
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 4);
+        let mut file = Vec::new();
+        tp.write_audit_log(&mut file).unwrap();
+        assert_eq!(
+            String::from_utf8(file)
+                .unwrap()
+                .matches("AccountLocked")
+                .count(),
+            4
+        );
+
+        let mut tp = init();
+        tp.set_locked_account_policy(LockedAccountPolicy::QueueDeposits);
+        apply_transactions(csv, &mut tp);
+        // the withdrawal is rejected, and the deposit with a queued id is a duplicate
+        assert_eq!(tp.stats().deposits_queued, 2);
+        assert_eq!(tp.stats().rejected[&RejectReason::AccountLocked], 1);
+        assert_eq!(tp.stats().rejected[&RejectReason::DuplicateTxnId], 1);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert!(client.is_locked());
+        assert_eq!(client.total, Amount::ZERO);
 
-        assert_eq!(tp.num_processed, 4);
+        let events = tp.unlock_account(1).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], EngineEvent::AccountUnlocked { client_id: 1 });
+        assert_eq!(
+            events[2],
+            EngineEvent::FundsDeposited {
+                client_id: 1,
+                txn_id: 4,
+                amount: amt(2.5)
+            }
+        );
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert!(!client.is_locked());
+        assert_eq!(client.available, amt(7.5));
+        // the queue was emptied
+        let err = tp.unlock_account(1).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::InvalidRequest));
     }
 
     #[test]
-    fn test_chargeback_withdrawal() {
+    fn test_rate_limit() {
+        use crate::rate_limit::{OverloadPolicy, RateLimit};
+
+        let mut limiter = RateLimiter::new(OverloadPolicy::Shed);
+        limiter.set_client_limit(RateLimit {
+            rate: 0.001,
+            burst: 2.0,
+        });
         let mut tp = init();
+        tp.set_rate_limiter(limiter);
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        withdrawal,1,11,1.0
-                        dispute,1,11,
-                        chargeback,1,11,";
+                        deposit,1,1,10.0
+                        deposit,1,2,10.0
+                        deposit,1,3,10.0
+                        deposit,2,4,10.0";
         apply_transactions(csv, &mut tp);
-        let client1 = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client1.available, 1.0);
-        assert_eq!(client1.total, 1.0);
-        assert_eq!(client1.held, 0.0);
-        assert!(client1.is_locked());
-
-        assert_eq!(tp.num_processed, 4);
+        // the third deposit of client 1 is shed. client 2 isn't affected
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 20.0);
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, 10.0);
+        assert_eq!(tp.rate_limited(), Some(vec![(1, 1)]));
+        let events = tp
+            .process(RawTxnInput {
+                txn_type: TxnType::Withdrawal,
+                client_id: 1,
+                txn_id: 5,
+                amount: Some(1.0.into()),
+                timestamp: None,
+                original_txn_id: None,
+            })
+            .unwrap();
+        assert!(matches!(
+            events[0],
+            EngineEvent::TransactionRejected {
+                reason: RejectReason::RateLimited,
+                ..
+            }
+        ));
     }
 
     #[test]
-    fn test_invalid_txns_for_new_account() {
+    fn test_initial_balances() {
+        let prior = "client,available,held,total,locked
+                          1,10.5,2,12.5,false
+                          2,0,0,0,true";
         let mut tp = init();
+        tp.enable_invariant_checks();
+        tp.enable_reconciliation().unwrap();
+        assert_eq!(tp.load_initial_balances(prior.as_bytes()).unwrap(), 2);
         let csv = "type,client,tx,amount
-                        withdrawal,1,11,1.0
-                        dispute,2,12,
-                        chargeback,3,13,
-                        resolve,4,14,";
+                        withdrawal,1,1,10.5
+                        deposit,2,2,1.0";
         apply_transactions(csv, &mut tp);
+        let client = tp.client_state(1).unwrap().unwrap();
+        assert_eq!(
+            (client.available, client.held, client.total),
+            (amt(0.0), amt(2.0), amt(2.0))
+        );
+        assert!(tp.client_state(2).unwrap().unwrap().is_locked());
+        assert!(tp.reconcile().unwrap().unwrap().is_balanced());
+        let ledger = tp.ledger().unwrap();
+        assert_eq!(ledger.balance(LedgerAccount::OpeningBalances), 12.5);
+        assert!(ledger.trial_balance(false).is_balanced());
 
-        for i in 1..5 {
-            let client = tp.db.get_client_state(i).unwrap().unwrap();
-            assert_eq!(client.available, 0.0);
-            assert_eq!(client.total, 0.0);
-            assert_eq!(client.held, 0.0);
-            assert!(!client.is_locked());
-        }
-
-        assert_eq!(tp.num_processed, 0);
+        // existing clients and bad rows are refused, and nothing is loaded
+        assert!(tp.load_initial_balances(prior.as_bytes()).is_err());
+        let mut tp = init();
+        let bad = "client,available,held,total,locked
+                        3,1,0,1,false
+                        4,1,1,1,false";
+        assert!(tp.load_initial_balances(bad.as_bytes()).is_err());
+        assert!(tp.client_state(3).unwrap().is_none());
     }
 
     #[test]
-    fn test_duplicate_dispute() {
+    fn test_snapshots() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", std::process::id()));
         let mut tp = init();
+        tp.enable_snapshots(&dir, 2).unwrap();
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        deposit,2,11,1.0
-                        dispute,1,10,
-                        dispute,1,10,";
+                        deposit,1,1,10.0
+                        withdrawal,1,2,4.0
+                        withdrawal,1,3,100.0
+                        deposit,2,4,1.0
+                        deposit,2,5,1.0";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 3);
+        let read =
+            |number: u64| fs::read_to_string(dir.join(format!("snapshot-{:06}.csv", number)));
+        assert_eq!(
+            read(1).unwrap(),
+            "client,available,held,total,locked\n1,6,0,6,false\n"
+        );
+        // the rejected withdrawal counts too
+        assert_eq!(
+            read(2).unwrap(),
+            "client,available,held,total,locked\n1,6,0,6,false\n2,1,0,1,false\n"
+        );
+        assert!(read(3).is_err());
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_duplicate_chargeback() {
+    fn test_json_report() {
         let mut tp = init();
+        tp.set_number_format(NumberFormat::DE);
+        tp.set_report_format(ReportFormat::Json);
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        deposit,2,11,1.0
-                        dispute,1,10,
-                        chargeback,1,10,
-                        chargeback,1,10,";
-        apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 4);
+deposit,2,1,\"1234,5\"
+deposit,1,2,1";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        let mut report = Vec::new();
+        tp.write_report(&mut report).unwrap();
+        let mut report: Vec<serde_json::Value> = serde_json::from_slice(&report).unwrap();
+        report.sort_by_key(|client| client["client"].as_u64());
+        assert_eq!(
+            report[1],
+            serde_json::json!({"client": 2, "available": 1234.5, "held": 0.0, "total": 1234.5, "locked": false})
+        );
     }
 
     #[test]
-    fn test_duplicate_resolve() {
+    fn test_rejects_log() {
+        let path = std::env::temp_dir().join(format!("rejects-{}.csv", std::process::id()));
         let mut tp = init();
+        tp.set_rejects_log(RejectsLog::create(&path).unwrap());
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        deposit,2,11,1.0
-                        dispute,1,10,
-                        resolve,1,10,
-                        resolve,1,10,";
-        apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 4);
+deposit,1,1,10.0
+withdrawal,1,2,100.0
+deposit,1,x,1.0
+deposit,1,3,1.0,2.0
+dispute,1,9,";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        tp.flush_rejects_log().unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "reason,type,client,tx,amount");
+        assert_eq!(lines[1], "InsufficientFunds,withdrawal,1,2,100");
+        assert_eq!(lines[2], "Malformed,deposit,1,x,1.0");
+        // the reader can't split a row with an extra field, so its error stands in for the input
+        assert!(lines[3].starts_with("Malformed,") && lines[3].contains("5 fields"));
+        assert_eq!(lines[4], "InvalidDispute,dispute,1,9,");
+        fs::remove_file(&path).unwrap();
     }
 
     #[test]
-    fn test_duplicate_txn_id() {
+    fn test_strict() {
+        let malformed = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,x,1.0
+deposit,1,2,1.0";
+        let rejected = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,3,1.0
+withdrawal,1,2,100.0";
+        for commit_every in [1, 10] {
+            let mut tp = init();
+            tp.set_commit_every(commit_every);
+            tp.set_strict(true);
+            let err = tp.process_csv(malformed.as_bytes()).unwrap_err();
+            assert!(matches!(err.current_context(), MyError::MalformedRecord));
+            assert!(report_to_json(&err).to_string().contains("line 3"));
+
+            let mut tp = init();
+            tp.set_commit_every(commit_every);
+            tp.set_strict(true);
+            let err = tp.process_csv(rejected.as_bytes()).unwrap_err();
+            assert!(matches!(err.current_context(), MyError::Rejected));
+            assert!(report_to_json(&err)
+                .to_string()
+                .contains("line 4: withdrawal 2 of client 1 rejected: InsufficientFunds"));
+        }
+
         let mut tp = init();
-        let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        deposit,2,10,1.0";
-        apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 1);
+        tp.set_strict(true);
+        let err = tp
+            .process_csv_resumable(rejected.as_bytes(), "strict")
+            .unwrap_err();
+        assert!(matches!(err.current_context(), MyError::Rejected));
+
+        // without strict mode, the same input is processed
+        let mut tp = init();
+        tp.process_csv(malformed.as_bytes()).unwrap();
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, amt(11.0));
     }
 
     #[test]
-    fn test_negative_balance_transfer() {
+    fn test_adjust() {
+        use crate::adjustment::AdjustmentReason;
+
         let mut tp = init();
+        tp.enable_audit_log().unwrap();
+        tp.enable_reconciliation().unwrap();
         let csv = "type,client,tx,amount
-                        deposit,1,10,-1.0
-                        deposit,2,11,1.0
-                        withdrawal,2,12,-1.0";
+                        deposit,1,1,10.0";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 1);
+        let adjustment = |amount: f64| Adjustment {
+            client_id: 1,
+            amount: amt(amount),
+            reason: AdjustmentReason::Correction,
+            operator: "ops-7".to_string(),
+        };
+
+        let events = tp.adjust(adjustment(2.5), false).unwrap();
+        assert_eq!(
+            events,
+            vec![EngineEvent::BalanceAdjusted {
+                client_id: 1,
+                amount: amt(2.5),
+                reason: AdjustmentReason::Correction
+            }]
+        );
+        // the insufficient-funds check only applies without allow_overdraft
+        let err = tp.adjust(adjustment(-20.0), false).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::InsufficientFunds));
+        tp.adjust(adjustment(-20.0), true).unwrap();
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, -7.5);
+
+        // an operator is required, and the client has to exist
+        let anonymous = Adjustment {
+            operator: " ".to_string(),
+            ..adjustment(1.0)
+        };
+        assert!(tp.adjust(anonymous, false).is_err());
+        assert!(tp
+            .adjust(
+                Adjustment {
+                    client_id: 9,
+                    ..adjustment(1.0)
+                },
+                false
+            )
+            .is_err());
+        let err = tp.adjust(adjustment(0.0), false).unwrap_err();
+        assert!(matches!(err.current_context(), MyError::InvalidRequest));
+
+        assert_eq!(tp.adjustments().unwrap().len(), 2);
+        let ledger = tp.ledger().unwrap();
+        assert_eq!(ledger.balance(LedgerAccount::Adjustments), -17.5);
+        assert_eq!(ledger.balance(LedgerAccount::OperatorCash), 10.0);
+        assert!(tp.reconcile().unwrap().unwrap().is_balanced());
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 3);
     }
 
     #[test]
-    fn test_negative_client_id() {
+    fn test_apply_config() {
         let mut tp = init();
+        tp.enable_audit_log().unwrap();
         let csv = "type,client,tx,amount
-                        deposit,-1,10,1.0";
+                        deposit,1,1,10.0
+                        dispute,2,1,";
+        apply_transactions(csv, &mut tp);
+
+        let config = EngineConfig::parse(br#"{"cross_client_disputes": "owner"}"#).unwrap();
+        tp.apply_config(&config);
+        assert_eq!(tp.config_version(), Some(config.version.as_str()));
+        let csv = "type,client,tx,amount
+                        dispute,2,1,";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 0);
+        assert_eq!(tp.client_state(1).unwrap().unwrap().held, 10.0);
+
+        // only the entries written after the change carry the version
+        let mut versions = Vec::new();
+        tp.db
+            .process_all_audit_entries(&mut |entry| versions.push(entry.config_version))
+            .unwrap();
+        assert_eq!(versions, vec![None, None, Some(config.version.clone())]);
+        assert_eq!(tp.verify_audit_log().unwrap().verified(), 3);
     }
 
     #[test]
-    fn test_negative_txn_id() {
+    fn test_chargeback_monitor() {
         let mut tp = init();
+        assert!(tp.chargeback_risk_report().is_none());
+        tp.enable_chargeback_monitor(ChargebackThresholds {
+            window: 100,
+            max_count_ratio: 0.4,
+            max_value_ratio: 0.4,
+        });
+        let alerts = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = alerts.clone();
+        tp.set_chargeback_alert(move |risk| sink.lock().unwrap().push(risk.client_id));
+
         let csv = "type,client,tx,amount
-                        deposit,1,-10,1.0";
+                        deposit,1,1,10.0
+                        deposit,1,2,10.0
+                        deposit,2,3,10.0
+                        deposit,2,4,10.0
+                        deposit,2,5,10.0
+                        dispute,1,1,
+                        chargeback,1,1,
+                        dispute,2,3,
+                        chargeback,2,3,";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 0);
+
+        // client 1: 1 of 2 deposits (50%). client 2: 1 of 3 (33%)
+        assert_eq!(*alerts.lock().unwrap(), vec![1]);
+        let report = tp.chargeback_risk_report().unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].client_id, 1);
+        assert_eq!(report[0].count_ratio(), 0.5);
     }
 
     #[test]
-    fn test_extra_newlines() {
+    fn test_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        // counts deposits and records the locked accounts and the rejections
+        #[derive(Default)]
+        struct Counter {
+            deposits: u64,
+            locked: Vec<ClientId>,
+            rejected: Vec<(TransactionId, RejectReason)>,
+        }
+        impl ProcessorHooks for Arc<Mutex<Counter>> {
+            fn on_deposit(&mut self, _: ClientId, _: TransactionId, _: Amount) {
+                self.lock().unwrap().deposits += 1;
+            }
+            fn on_account_locked(&mut self, client_id: ClientId) {
+                self.lock().unwrap().locked.push(client_id);
+            }
+            fn on_reject(
+                &mut self,
+                _: ClientId,
+                txn_id: TransactionId,
+                _: &TxnType,
+                reason: RejectReason,
+            ) {
+                self.lock().unwrap().rejected.push((txn_id, reason));
+            }
+        }
+
         let mut tp = init();
+        tp.set_locked_account_policy(LockedAccountPolicy::QueueDeposits);
+        let counter = Arc::new(Mutex::new(Counter::default()));
+        tp.add_hooks(counter.clone());
         let csv = "type,client,tx,amount
-                        deposit,1,10,1.0
-                        
-                        deposit,1,11,1.0
-                        
-                        ";
+                        deposit,1,1,10.0
+                        withdrawal,1,2,50.0
+                        dispute,1,1,
+                        chargeback,1,1,
+                        deposit,1,3,5.0";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 2);
-        let client = tp.db.get_client_state(1).unwrap().unwrap();
-        assert_eq!(client.available, 2.0);
+        {
+            let counter = counter.lock().unwrap();
+            assert_eq!(counter.deposits, 1);
+            assert_eq!(counter.locked, [1]);
+            assert_eq!(counter.rejected, [(2, RejectReason::InsufficientFunds)]);
+        }
+
+        // the queued deposit is applied by the unlock
+        tp.unlock_account(1).unwrap();
+        assert_eq!(counter.lock().unwrap().deposits, 2);
     }
 
     #[test]
-    fn test_invalid_input1() {
+    fn test_duplicate_report() {
         let mut tp = init();
+        assert!(tp.duplicate_report().is_none());
+        tp.enable_duplicate_report();
         let csv = "type,client,tx,amount
-                        abcdefg,1,10,1.0
-                        deposit,1,11,1.0
-                        dispute,1,11,
-                        resolve,1,11,";
+                        deposit,1,1,10.0
+                        deposit,1,2,5.0
+                        deposit,1,1,10.0
+                        deposit,2,2,7.0";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 3);
+
+        let report = tp.duplicate_report().unwrap();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].txn_id, 1);
+        assert!(!report[0].differs());
+        assert_eq!(report[1].txn_id, 2);
+        assert_eq!(report[1].original.unwrap().client_id, 1);
+        assert!(report[1].differs());
     }
 
     #[test]
-    fn test_invalid_input2() {
+    fn test_reconcile() {
         let mut tp = init();
+        assert!(tp.reconcile().unwrap().is_none());
+        apply_transactions(
+            "type,client,tx,amount
+                        deposit,1,1,3.0",
+            &mut tp,
+        );
+        // only what happens after this is counted
+        tp.enable_reconciliation().unwrap();
         let csv = "type,client,tx,amount
-                        abcdefg
-                        too,many,columns,a,b,c,d
-                        deposit,1,11,1.0
-                        dispute,1,11,
-                        resolve,1,11,";
+                        deposit,1,2,10.0
+                        withdrawal,1,3,4.0
+                        dispute,1,3,
+                        deposit,2,4,2.5
+                        dispute,2,4,
+                        chargeback,2,4,
+                        withdrawal,2,5,1.0";
         apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 3);
+
+        let reconciliation = tp.reconcile().unwrap().unwrap();
+        assert_eq!(reconciliation.opening_total, 3.0);
+        assert_eq!(reconciliation.expected_total(), 13.0);
+        assert!(reconciliation.is_balanced(), "{}", reconciliation);
+
+        // a balance changed behind the processor's back
+        let mut state = tp.client_state(1).unwrap().unwrap();
+        state.total += amt(1.0);
+        tp.db.update_client_state(&state).unwrap();
+        let reconciliation = tp.reconcile().unwrap().unwrap();
+        assert!(!reconciliation.is_balanced());
+        assert_eq!(reconciliation.difference(), 1.0);
     }
 
     #[test]
-    fn test_missing_comma() {
+    fn test_number_format() {
         let mut tp = init();
+        tp.set_number_format(NumberFormat::DE);
         let csv = "type,client,tx,amount
-                        deposit,1,11,1.0
-                        dispute,1,11,
-                        resolve,1,11";
-        apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 2);
+                        deposit,1,1,\"1.234,56\"
+                        withdrawal,1,2,\"0,5\"
+                        dispute,1,2,
+                        deposit,1,3,1234.56";
+        tp.process_csv(csv.as_bytes()).unwrap();
+
+        let mut report = Vec::new();
+        tp.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n1,\"1.234,06\",\"0,5\",\"1.234,56\",false\n"
+        );
     }
 
     #[test]
-    fn test_missing_balance_transfer() {
-        let mut tp = init();
+    fn test_amount_unit() {
+        let mut tp = TransactionProcessor::in_memory();
+        // named by the header
+        let csv = "type,client,tx,amount_cents
+                        deposit,1,1,1001
+                        withdrawal,1,2,1
+                        deposit,1,3,1.5";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 10.0);
+
+        tp.set_amount_unit(AmountUnit::TenThousandths);
         let csv = "type,client,tx,amount
-                        deposit,1,11,
+                        deposit,2,4,5";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tp.client_state(2).unwrap().unwrap().available, 0.0005);
+    }
 
-                        ";
-        apply_transactions(csv, &mut tp);
-        assert_eq!(tp.num_processed, 0);
+    #[test]
+    fn test_csv_dialect() {
+        let mut tp = TransactionProcessor::in_memory();
+        tp.set_number_format(NumberFormat::DE);
+        tp.set_csv_dialect(CsvDialect {
+            delimiter: Some(b';'),
+            quote: Some(b'\''),
+        });
+        // the decimal commas don't need quotes
+        let csv = "type;client;tx;amount
+                        deposit;1;1;1.234,5
+                        withdrawal;1;2;'0,5'";
+        tp.process_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tp.client_state(1).unwrap().unwrap().available, 1234.0);
+    }
+
+    #[test]
+    fn test_shuffled_columns() {
+        let report = |csv: &str| {
+            let mut tp = TransactionProcessor::in_memory();
+            tp.process_csv(csv.as_bytes()).unwrap();
+            let mut report = Vec::new();
+            tp.write_report(&mut report).unwrap();
+            String::from_utf8(report).unwrap()
+        };
+        let expected = report(
+            "type,client,tx,amount
+                deposit,1,1,10.0
+                withdrawal,1,2,2.5
+                deposit,2,3,1.0
+                dispute,2,3,",
+        );
+        // reordered, with unknown columns
+        let shuffled = report(
+            "tx,memo,amount,client,type,channel
+                1,salary,10.0,1,deposit,web
+                2,atm,2.5,1,withdrawal,
+                3,,1.0,2,deposit,
+                3,,,2,dispute,phone",
+        );
+        assert_eq!(shuffled, expected);
+        assert!(expected.contains("2,0,1,1,false"));
+
+        // the unit of a renamed amount column applies wherever it is
+        let cents = report(
+            "client,amount_cents,type,tx
+                1,1000,deposit,1
+                1,250,withdrawal,2
+                2,100,deposit,3
+                2,,dispute,3",
+        );
+        assert_eq!(cents, expected);
     }
 }
@@ -0,0 +1,317 @@
+//! per-line diagnostics for an input file (`validate`). every record is checked the way the processor parses and
+//! validates it, without applying anything, so the problems of a file can be fixed before it's processed. the checks
+//! that need the accounts (insufficient funds, disputes of unknown transactions) are left to `--dry-run`
+use crate::{
+    amount::Amount, columns::Columns, dialect::CsvDialect, errors::*, fmt_error, model::*,
+    number_format::AmountUnit, rounding::RoundingPolicy,
+};
+use csv::{ReaderBuilder, StringRecord};
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{collections::HashMap, fmt, io, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// the record doesn't have the number of fields of the header
+    FieldCount {
+        found: usize,
+        expected: usize,
+    },
+    UnknownType(String),
+    BadClient(String),
+    BadTxnId(String),
+    /// a deposit or withdrawal without an amount
+    MissingAmount,
+    /// not a number, not positive, or zero once rounded
+    BadAmount(String),
+    /// a dispute, resolve, chargeback, or refund with an amount
+    UnexpectedAmount,
+    BadTimestamp(String),
+    /// a refund without the deposit it reverses
+    MissingOriginalTxn,
+    BadOriginalTxn(String),
+    /// the id of a deposit, withdrawal, or refund was already used on an earlier line
+    DuplicateTxnId {
+        first_line: u64,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::FieldCount { found, expected } => {
+                write!(f, "{} field(s), the header has {}", found, expected)
+            }
+            Problem::UnknownType(s) => write!(f, "unknown type \"{}\"", s),
+            Problem::BadClient(s) => write!(f, "bad client \"{}\"", s),
+            Problem::BadTxnId(s) => write!(f, "bad tx \"{}\"", s),
+            Problem::MissingAmount => write!(f, "missing amount"),
+            Problem::BadAmount(s) => write!(f, "bad amount \"{}\"", s),
+            Problem::UnexpectedAmount => write!(f, "unexpected amount"),
+            Problem::BadTimestamp(s) => write!(f, "bad timestamp \"{}\"", s),
+            Problem::MissingOriginalTxn => write!(f, "missing original_tx"),
+            Problem::BadOriginalTxn(s) => write!(f, "bad original_tx \"{}\"", s),
+            Problem::DuplicateTxnId { first_line } => {
+                write!(f, "duplicate tx, first used on line {}", first_line)
+            }
+        }
+    }
+}
+
+/// a record that failed validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: u64,
+    /// the trimmed fields of the record
+    pub fields: Vec<String>,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}: {}",
+            self.line,
+            self.fields.join(","),
+            self.problem
+        )
+    }
+}
+
+/// checks records one at a time, remembering the transaction ids seen so far
+#[derive(Debug, Default)]
+pub struct Validator {
+    rounding: RoundingPolicy,
+    unit: AmountUnit,
+    // the line of each deposit, withdrawal, and refund id
+    transfers: HashMap<TransactionId, u64>,
+}
+
+impl Validator {
+    /// `rounding`: how the amounts would be rounded by the processor. `unit`: the unit of the amount column
+    pub fn new(rounding: RoundingPolicy, unit: AmountUnit) -> Self {
+        Validator {
+            rounding,
+            unit,
+            transfers: HashMap::new(),
+        }
+    }
+
+    /// the first problem of the record on `line`, with its fields in the order of `columns::COLUMNS`. None if the
+    /// processor would accept it
+    pub fn check(&mut self, line: u64, record: &StringRecord) -> Option<Problem> {
+        let field = |idx: usize| record.get(idx).unwrap_or("");
+        let txn_type = match field(0).parse::<TxnType>() {
+            Ok(txn_type) => txn_type,
+            Err(_) => return Some(Problem::UnknownType(field(0).to_string())),
+        };
+        if parse::<ClientId>(field(1)).is_none() {
+            return Some(Problem::BadClient(field(1).to_string()));
+        }
+        let txn_id = match parse_txn_id(field(2)) {
+            Some(txn_id) => txn_id,
+            None => return Some(Problem::BadTxnId(field(2).to_string())),
+        };
+        let amount = field(3);
+        match txn_type {
+            TxnType::Deposit | TxnType::Withdrawal if amount.is_empty() => {
+                return Some(Problem::MissingAmount)
+            }
+            TxnType::Deposit | TxnType::Withdrawal => {
+                let valid = self
+                    .unit
+                    .to_decimal(amount)
+                    .and_then(|amount| parse::<f64>(&amount))
+                    .and_then(|amount| Amount::from_f64(amount, self.rounding))
+                    .is_some_and(|amount| amount.is_positive());
+                if !valid {
+                    return Some(Problem::BadAmount(amount.to_string()));
+                }
+            }
+            _ if !amount.is_empty() => return Some(Problem::UnexpectedAmount),
+            _ => {}
+        }
+        if !field(4).is_empty() && parse::<i64>(field(4)).is_none() {
+            return Some(Problem::BadTimestamp(field(4).to_string()));
+        }
+        if txn_type == TxnType::Refund {
+            match parse_txn_id(field(5)) {
+                _ if field(5).is_empty() => return Some(Problem::MissingOriginalTxn),
+                Some(original) if original != txn_id => {}
+                _ => return Some(Problem::BadOriginalTxn(field(5).to_string())),
+            }
+        }
+        if matches!(
+            txn_type,
+            TxnType::Deposit | TxnType::Withdrawal | TxnType::Refund
+        ) {
+            if let Some(first_line) = self.transfers.get(&txn_id) {
+                return Some(Problem::DuplicateTxnId {
+                    first_line: *first_line,
+                });
+            }
+            self.transfers.insert(txn_id, line);
+        }
+        None
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Option<T> {
+    s.parse().ok()
+}
+
+// the ids have to fit in an SQLite INTEGER as well as in a TransactionId
+fn parse_txn_id(s: &str) -> Option<TransactionId> {
+    parse::<i64>(s).and_then(|id| TransactionId::try_from(id).ok())
+}
+
+/// check every record of a CSV input in `dialect` with a header row, mapping the columns by name. the amounts are in
+/// the unit named by the header, decimal by default. returns the number of records and their problems, in order
+pub fn validate_csv<R: io::Read>(
+    reader: R,
+    dialect: CsvDialect,
+    rounding: RoundingPolicy,
+) -> Result<(u64, Vec<Diagnostic>), MyError> {
+    let mut builder = ReaderBuilder::new();
+    builder.flexible(true);
+    let mut reader = dialect.reader_from(builder, reader)?;
+    let headers = reader
+        .headers()
+        .report()
+        .attach_printable_lazy(|| fmt_error!("failed to read the header"))
+        .change_context(MyError::FileReader)?;
+    let expected = headers.len();
+    let columns = Columns::from_header(headers);
+    let unit = columns.amount_unit().unwrap_or_default();
+    let mut validator = Validator::new(rounding, unit);
+    let mut records = 0;
+    let mut diagnostics = Vec::new();
+    for record in reader.records() {
+        let mut record = record
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to read the input"))
+            .change_context(MyError::FileReader)?;
+        record.trim();
+        records += 1;
+        let line = record.position().map_or(0, |pos| pos.line());
+        let problem = if record.len() != expected {
+            Some(Problem::FieldCount {
+                found: record.len(),
+                expected,
+            })
+        } else {
+            validator.check(line, &columns.arrange(record.clone()))
+        };
+        if let Some(problem) = problem {
+            diagnostics.push(Diagnostic {
+                line,
+                fields: record.iter().map(str::to_string).collect(),
+                problem,
+            });
+        }
+    }
+    Ok((records, diagnostics))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_csv() {
+        let csv = "type,client,tx,amount
+deposit,1,1,1.5
+deposit, 1, 2,
+withdrawal,1,3,abc
+transfer,1,4,1.0
+deposit,1,1,2.0
+dispute,1,1,1.0
+withdrawal,1,5,0.00001
+deposit,x,6,1.0
+dispute,1,1
+resolve,1,1,";
+        let (records, diagnostics) = validate_csv(
+            csv.as_bytes(),
+            CsvDialect::default(),
+            RoundingPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(records, 10);
+        let problems: Vec<(u64, Problem)> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.problem.clone()))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (3, Problem::MissingAmount),
+                (4, Problem::BadAmount("abc".to_string())),
+                (5, Problem::UnknownType("transfer".to_string())),
+                (6, Problem::DuplicateTxnId { first_line: 2 }),
+                (7, Problem::UnexpectedAmount),
+                (8, Problem::BadAmount("0.00001".to_string())),
+                (9, Problem::BadClient("x".to_string())),
+                (
+                    10,
+                    Problem::FieldCount {
+                        found: 3,
+                        expected: 4
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "line 3: deposit,1,2,: missing amount"
+        );
+
+        let cents = "type,client,tx,amount_cents\ndeposit,1,1,150\ndeposit,1,2,1.5\n";
+        let (_, diagnostics) = validate_csv(
+            cents.as_bytes(),
+            CsvDialect::default(),
+            RoundingPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].problem,
+            Problem::BadAmount("1.5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_refund() {
+        let mut validator = Validator::default();
+        let check = |validator: &mut Validator, line, fields: &[&str]| {
+            validator.check(line, &StringRecord::from(fields.to_vec()))
+        };
+        assert_eq!(
+            check(&mut validator, 2, &["deposit", "1", "1", "2.0", "", ""]),
+            None
+        );
+        assert_eq!(
+            check(&mut validator, 3, &["refund", "1", "2", "", "", ""]),
+            Some(Problem::MissingOriginalTxn)
+        );
+        assert_eq!(
+            check(&mut validator, 4, &["refund", "1", "2", "", "", "2"]),
+            Some(Problem::BadOriginalTxn("2".to_string()))
+        );
+        assert_eq!(
+            check(
+                &mut validator,
+                5,
+                &["refund", "1", "2", "", "1704067200", "1"]
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &mut validator,
+                6,
+                &["withdrawal", "1", "2", "1.0", "soon", ""]
+            ),
+            Some(Problem::BadTimestamp("soon".to_string()))
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! synthetic workloads for benchmarks and property tests. a `Workload` is a seeded, reproducible stream of
+//! transactions: client popularity follows a Zipf distribution (a few clients send most of the traffic), amounts
+//! follow a configurable distribution, and disputes can be correlated (clients that disputed before are more likely
+//! to dispute again). the stream only uses the standard library, so the same seed gives the same stream everywhere
+use crate::{errors::*, fmt_error, model::*};
+use error_stack::{IntoReport, Result, ResultExt};
+use std::{collections::HashSet, io};
+
+/// the amount of a deposit or withdrawal. amounts are rounded to 4 decimal places, and at least 0.0001
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountDistribution {
+    Fixed(f64),
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    /// heavy tailed, like real payments: exp(N(mu, sigma)). mu = 3, sigma = 1 gives a median around 20
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkloadConfig {
+    pub seed: u64,
+    /// the number of clients. client ids are 1..=clients, in order of popularity
+    pub clients: ClientId,
+    /// the Zipf exponent of client popularity. 0 is uniform, around 1 is typical
+    pub zipf_exponent: f64,
+    pub amounts: AmountDistribution,
+    /// the fraction of the deposits and withdrawals that are withdrawals
+    pub withdrawal_ratio: f64,
+    /// the probability that a transaction disputes an earlier deposit
+    pub dispute_rate: f64,
+    /// the probability that a dispute comes from a client that already disputed something
+    pub dispute_correlation: f64,
+    /// the probability that a transaction settles an open dispute
+    pub settle_rate: f64,
+    /// the fraction of the settled disputes that are charged back rather than resolved
+    pub chargeback_ratio: f64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            seed: 0,
+            clients: 1000,
+            zipf_exponent: 1.0,
+            amounts: AmountDistribution::LogNormal {
+                mu: 3.0,
+                sigma: 1.0,
+            },
+            withdrawal_ratio: 0.3,
+            dispute_rate: 0.01,
+            dispute_correlation: 0.5,
+            settle_rate: 0.01,
+            chargeback_ratio: 0.2,
+        }
+    }
+}
+
+// splitmix64: small, fast, and good enough for workloads and fault injection. not for anything security related
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+
+    // standard normal, Box-Muller
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+}
+
+/// a seeded stream of transactions. never ends: take as many as needed
+#[derive(Debug, Clone)]
+pub struct Workload {
+    config: WorkloadConfig,
+    rng: Rng,
+    // the cumulative probability of each client, by popularity rank
+    cdf: Vec<f64>,
+    next_txn_id: TransactionId,
+    // the undisputed deposits of each client, by rank
+    deposits: Vec<Vec<TransactionId>>,
+    open_disputes: Vec<(ClientId, TransactionId)>,
+    // the clients that have disputed something, in the order they first did
+    disputers: Vec<ClientId>,
+    disputer_set: HashSet<ClientId>,
+}
+
+impl Workload {
+    pub fn new(config: WorkloadConfig) -> Self {
+        let weights: Vec<f64> = (1..=config.clients.max(1))
+            .map(|rank| 1.0 / (rank as f64).powf(config.zipf_exponent))
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        let cdf = weights
+            .iter()
+            .scan(0.0, |acc, w| {
+                *acc += w / sum;
+                Some(*acc)
+            })
+            .collect::<Vec<f64>>();
+        Workload {
+            rng: Rng::new(config.seed),
+            deposits: vec![Vec::new(); cdf.len()],
+            cdf,
+            config,
+            next_txn_id: 1,
+            open_disputes: Vec::new(),
+            disputers: Vec::new(),
+            disputer_set: HashSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> &WorkloadConfig {
+        &self.config
+    }
+
+    // a client id, by Zipf popularity
+    fn client(&mut self) -> ClientId {
+        let p = self.rng.next_f64();
+        let rank = self.cdf.partition_point(|c| *c < p).min(self.cdf.len() - 1);
+        (rank + 1) as ClientId
+    }
+
+    fn amount(&mut self) -> f64 {
+        let amount = match self.config.amounts {
+            AmountDistribution::Fixed(amount) => amount,
+            AmountDistribution::Uniform { min, max } => min + self.rng.next_f64() * (max - min),
+            AmountDistribution::LogNormal { mu, sigma } => (mu + sigma * self.rng.normal()).exp(),
+        };
+        ((amount * 10_000.0).round() / 10_000.0).max(0.0001)
+    }
+
+    fn transfer(&mut self) -> RawTxnInput {
+        let client_id = self.client();
+        let txn_id = self.next_txn_id;
+        self.next_txn_id += 1;
+        let txn_type = if self.rng.next_f64() < self.config.withdrawal_ratio {
+            TxnType::Withdrawal
+        } else {
+            self.deposits[client_id as usize - 1].push(txn_id);
+            TxnType::Deposit
+        };
+        RawTxnInput {
+            txn_type,
+            client_id,
+            txn_id,
+            amount: Some(self.amount().into()),
+            timestamp: None,
+            original_txn_id: None,
+        }
+    }
+
+    fn dispute(&mut self) -> Option<RawTxnInput> {
+        let client_id = if !self.disputers.is_empty()
+            && self.rng.next_f64() < self.config.dispute_correlation
+        {
+            self.disputers[self.rng.below(self.disputers.len())]
+        } else {
+            self.client()
+        };
+        let deposits = &mut self.deposits[client_id as usize - 1];
+        if deposits.is_empty() {
+            return None;
+        }
+        let txn_id = deposits.swap_remove(self.rng.below(deposits.len()));
+        self.open_disputes.push((client_id, txn_id));
+        if self.disputer_set.insert(client_id) {
+            self.disputers.push(client_id);
+        }
+        Some(RawTxnInput {
+            txn_type: TxnType::Dispute,
+            client_id,
+            txn_id,
+            amount: None,
+            timestamp: None,
+            original_txn_id: None,
+        })
+    }
+
+    fn settle(&mut self) -> RawTxnInput {
+        let (client_id, txn_id) = self
+            .open_disputes
+            .swap_remove(self.rng.below(self.open_disputes.len()));
+        let txn_type = if self.rng.next_f64() < self.config.chargeback_ratio {
+            TxnType::Chargeback
+        } else {
+            TxnType::Resolve
+        };
+        RawTxnInput {
+            txn_type,
+            client_id,
+            txn_id,
+            amount: None,
+            timestamp: None,
+            original_txn_id: None,
+        }
+    }
+
+    /// write `count` transactions as CSV input for the executable
+    pub fn write_csv<W: io::Write>(&mut self, writer: W, count: usize) -> Result<(), MyError> {
+        self.write_rows(writer, count)
+            .report()
+            .attach_printable_lazy(|| fmt_error!("failed to write the workload"))
+            .change_context(MyError::Output)
+    }
+
+    fn write_rows<W: io::Write>(&mut self, mut writer: W, count: usize) -> io::Result<()> {
+        writeln!(writer, "type,client,tx,amount")?;
+        for txn in self.by_ref().take(count) {
+            let txn_type = match txn.txn_type {
+                TxnType::Deposit => "deposit",
+                TxnType::Withdrawal => "withdrawal",
+                TxnType::Dispute => "dispute",
+                TxnType::Resolve => "resolve",
+                TxnType::Chargeback => "chargeback",
+                // never generated
+                TxnType::Refund | TxnType::Invalid => continue,
+            };
+            match txn.amount {
+                Some(amount) => writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    txn_type, txn.client_id, txn.txn_id, amount
+                )?,
+                None => writeln!(writer, "{},{},{},", txn_type, txn.client_id, txn.txn_id)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Workload {
+    type Item = RawTxnInput;
+
+    fn next(&mut self) -> Option<RawTxnInput> {
+        if !self.open_disputes.is_empty() && self.rng.next_f64() < self.config.settle_rate {
+            return Some(self.settle());
+        }
+        if self.rng.next_f64() < self.config.dispute_rate {
+            if let Some(dispute) = self.dispute() {
+                return Some(dispute);
+            }
+        }
+        Some(self.transfer())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_reproducible() {
+        let config = WorkloadConfig {
+            seed: 7,
+            ..Default::default()
+        };
+        let a: Vec<RawTxnInput> = Workload::new(config.clone()).take(500).collect();
+        let b: Vec<RawTxnInput> = Workload::new(config).take(500).collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut workload = Workload::new(WorkloadConfig {
+            clients: 100,
+            amounts: AmountDistribution::Uniform { min: 1.0, max: 2.0 },
+            dispute_rate: 0.05,
+            settle_rate: 0.5,
+            ..Default::default()
+        });
+        let mut per_client: HashMap<ClientId, usize> = HashMap::new();
+        let mut disputed = HashSet::new();
+        for txn in workload.by_ref().take(20_000) {
+            *per_client.entry(txn.client_id).or_default() += 1;
+            match txn.txn_type {
+                TxnType::Deposit | TxnType::Withdrawal => {
+                    let amount = txn.amount.unwrap().to_f64();
+                    assert!((1.0..=2.0).contains(&amount));
+                }
+                // only undisputed deposits are disputed, and only open disputes are settled
+                TxnType::Dispute => assert!(disputed.insert(txn.txn_id)),
+                _ => assert!(disputed.contains(&txn.txn_id)),
+            }
+        }
+        // with a Zipf exponent of 1 the most popular client sends about 19% of the traffic
+        let top = per_client[&1] as f64 / 20_000.0;
+        assert!((0.15..0.25).contains(&top), "{}", top);
+        assert!(per_client[&1] > per_client[&10]);
+
+        let mut csv = Vec::new();
+        Workload::new(WorkloadConfig {
+            amounts: AmountDistribution::Fixed(2.5),
+            dispute_rate: 0.0,
+            withdrawal_ratio: 0.0,
+            ..Default::default()
+        })
+        .write_csv(&mut csv, 2)
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("type,client,tx,amount\ndeposit,"));
+        assert!(csv.ends_with(",2,2.5\n"));
+    }
+}
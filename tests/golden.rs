@@ -0,0 +1,66 @@
+//! golden-file tests. each directory in tests/golden holds an `input.csv` and the `expected.csv` report it should produce.
+//! run with UPDATE_GOLDEN=1 to rewrite the expected files, then review the diff.
+use payments_engine::transaction_processor::TransactionProcessor;
+use std::{fs, io::BufReader, path::Path};
+
+// the full pipeline: CSV input, processing, and the CSV report with client rows sorted by client id
+fn run_case(input: &Path) -> String {
+    let mut processor = TransactionProcessor::in_memory();
+    let file = fs::File::open(input).unwrap();
+    processor.process_csv(BufReader::new(file)).unwrap();
+
+    let mut report = Vec::new();
+    processor.write_report(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+
+    let mut lines = report.lines();
+    let header = lines.next().unwrap();
+    let mut rows: Vec<(u16, &str)> = lines
+        .map(|line| (line.split(',').next().unwrap().parse().unwrap(), line))
+        .collect();
+    rows.sort_by_key(|(client_id, _)| *client_id);
+
+    let mut out = format!("{}\n", header);
+    for (_, row) in rows {
+        out.push_str(row);
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut cases: Vec<_> = fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no golden cases in {}", root.display());
+
+    let mut failures = Vec::new();
+    for case in cases {
+        let actual = run_case(&case.join("input.csv"));
+        let expected_path = case.join("expected.csv");
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                case.display(),
+                expected,
+                actual
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "golden mismatches:\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,133 @@
+//! property tests: random transaction streams must preserve the account invariants after every step
+use payments_engine::{
+    model::{ClientId, ClientState, RawTxnInput, TransactionId, TxnType},
+    transaction_processor::TransactionProcessor,
+    workload::{Workload, WorkloadConfig},
+};
+use proptest::prelude::*;
+use std::{collections::HashMap, ops::Range};
+
+// any amount up to 400 at 4 decimal places. balances are exact, so the invariants can be checked with ==
+fn amount() -> impl Strategy<Value = f64> {
+    (1u32..4_000_000).prop_map(|units| units as f64 / 10_000.0)
+}
+
+// few clients and transaction ids, so disputes, resolutions, and duplicates actually hit existing transfers
+fn txn(types: &'static [TxnType]) -> impl Strategy<Value = RawTxnInput> {
+    let client_ids: Range<ClientId> = 1..4;
+    let txn_ids: Range<TransactionId> = 1..16;
+    (prop::sample::select(types), client_ids, txn_ids, amount()).prop_map(
+        |(txn_type, client_id, txn_id, amount)| {
+            let amount = match txn_type {
+                TxnType::Deposit | TxnType::Withdrawal => Some(amount.into()),
+                _ => None,
+            };
+            RawTxnInput {
+                txn_type,
+                client_id,
+                txn_id,
+                amount,
+                timestamp: None,
+                original_txn_id: None,
+            }
+        },
+    )
+}
+
+const ALL_TYPES: &[TxnType] = &[
+    TxnType::Deposit,
+    TxnType::Withdrawal,
+    TxnType::Dispute,
+    TxnType::Resolve,
+    TxnType::Chargeback,
+];
+
+const DEPOSIT_ONLY_TYPES: &[TxnType] = &[
+    TxnType::Deposit,
+    TxnType::Dispute,
+    TxnType::Resolve,
+    TxnType::Chargeback,
+];
+
+fn snapshot(processor: &TransactionProcessor) -> HashMap<ClientId, ClientState> {
+    processor
+        .client_states()
+        .unwrap()
+        .into_iter()
+        .map(|state| (state.client_id, state))
+        .collect()
+}
+
+fn same_balances(a: &ClientState, b: &ClientState) -> bool {
+    a.available == b.available && a.held == b.held && a.total == b.total
+}
+
+proptest! {
+    #[test]
+    fn total_is_available_plus_held(txns in prop::collection::vec(txn(ALL_TYPES), 1..64)) {
+        let mut processor = TransactionProcessor::in_memory();
+        for txn in txns {
+            processor.process(txn).unwrap();
+            for state in processor.client_states().unwrap() {
+                prop_assert_eq!(state.total, state.available + state.held);
+            }
+        }
+    }
+
+    #[test]
+    fn held_is_non_negative_for_deposits(txns in prop::collection::vec(txn(DEPOSIT_ONLY_TYPES), 1..64)) {
+        let mut processor = TransactionProcessor::in_memory();
+        for txn in txns {
+            processor.process(txn).unwrap();
+            for state in processor.client_states().unwrap() {
+                prop_assert!(!state.held.is_negative(), "client {} held {}", state.client_id, state.held);
+            }
+        }
+    }
+
+    #[test]
+    fn locked_accounts_never_change(txns in prop::collection::vec(txn(ALL_TYPES), 1..64)) {
+        let mut processor = TransactionProcessor::in_memory();
+        for txn in txns {
+            let before = snapshot(&processor);
+            processor.process(txn).unwrap();
+            let after = snapshot(&processor);
+            for (client_id, state) in before.iter().filter(|(_, state)| state.is_locked()) {
+                prop_assert!(same_balances(state, &after[client_id]), "locked client {} changed", client_id);
+                prop_assert!(after[client_id].is_locked());
+            }
+        }
+    }
+
+    #[test]
+    fn duplicate_txns_never_double_apply(txns in prop::collection::vec(txn(ALL_TYPES), 1..64)) {
+        let mut processor = TransactionProcessor::in_memory();
+        for txn in txns {
+            processor.process(txn.clone()).unwrap();
+            let once = snapshot(&processor);
+            // replaying the exact same record must be a no-op
+            processor.process(txn).unwrap();
+            let twice = snapshot(&processor);
+            for (client_id, state) in once.iter() {
+                prop_assert!(same_balances(state, &twice[client_id]), "client {} changed on replay", client_id);
+            }
+        }
+    }
+
+    #[test]
+    fn realistic_workloads_keep_the_invariants(seed in any::<u64>()) {
+        // few clients, frequent disputes: the disputes and settlements hit funded accounts
+        let workload = Workload::new(WorkloadConfig {
+            seed,
+            clients: 20,
+            dispute_rate: 0.1,
+            settle_rate: 0.2,
+            ..Default::default()
+        });
+        let mut processor = TransactionProcessor::in_memory();
+        processor.enable_invariant_checks();
+        for txn in workload.take(300) {
+            processor.process(txn).unwrap();
+        }
+    }
+}